@@ -12,6 +12,7 @@ use version_compare::Version;
 fn main() {
     let crate_version = Version::from(env!("CARGO_PKG_VERSION")).unwrap();
 
+    println!(r#"cargo:rustc-check-cfg=cfg(crate_major_version, values(any()))"#);
     println!(
         r#"cargo:rustc-cfg=crate_major_version="{}""#,
         crate_version.part(0).unwrap()
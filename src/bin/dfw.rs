@@ -9,24 +9,39 @@
 
 //! # DFW - binary
 
-use bollard::{system::EventsOptions, Docker, API_DEFAULT_VERSION};
-use clap::{crate_version, Parser};
+use bollard::{Docker, API_DEFAULT_VERSION};
+use clap::{crate_version, Parser, Subcommand};
 use crossbeam_channel::{select, Receiver, Sender};
 use dfw::{
-    process::{ContainerFilter, Process, ProcessContext, ProcessingOptions},
+    diff::write_diffs,
+    migrate,
+    process::{
+        self, AppliedRules, ContainerFilter, OutputFormat, Process, ProcessContext,
+        ProcessingOptions, RulesetOutput,
+    },
+    runtime::{ContainerRuntime, InMemoryRuntime},
     types::DFW,
     util::*,
+    validate::validate,
+    Ruleset,
 };
-use failure::{bail, format_err};
-use futures::{future, stream::StreamExt};
-use maplit::hashmap;
-use slog::{debug, error, info, o, trace, Logger};
+use failure::{bail, format_err, ResultExt};
+use glob::glob;
+use slog::{debug, error, info, o, trace, warn, Logger};
 use sloggers::{
     terminal::{Destination, TerminalLoggerBuilder},
     types::Severity,
     Build,
 };
 use std::{
+    fmt,
+    fs::{self, File, Permissions},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    os::unix::{
+        fs::{FileTypeExt, PermissionsExt},
+        net::{UnixListener, UnixStream},
+    },
+    path::Path,
     thread,
     time::{Duration, Instant},
 };
@@ -48,11 +63,41 @@ enum FirewallBackend {
     Iptables,
 }
 
-impl ToString for FirewallBackend {
-    fn to_string(&self) -> String {
+impl fmt::Display for FirewallBackend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Self::Nftables => "nftables".to_owned(),
-            Self::Iptables => "iptables".to_owned(),
+            Self::Nftables => write!(f, "nftables"),
+            Self::Iptables => write!(f, "iptables"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, clap::ArgEnum)]
+enum Runtime {
+    Docker,
+    Podman,
+}
+
+impl From<&Runtime> for process::Runtime {
+    fn from(runtime: &Runtime) -> Self {
+        match runtime {
+            Runtime::Docker => process::Runtime::Docker,
+            Runtime::Podman => process::Runtime::Podman,
+        }
+    }
+}
+
+#[derive(Debug, Clone, clap::ArgEnum)]
+enum Format {
+    Text,
+    Json,
+}
+
+impl From<&Format> for OutputFormat {
+    fn from(format: &Format) -> Self {
+        match format {
+            Format::Text => OutputFormat::Text,
+            Format::Json => OutputFormat::Json,
         }
     }
 }
@@ -63,6 +108,12 @@ enum LoadMode {
     Always,
 }
 
+#[derive(Debug, Clone, clap::ArgEnum)]
+enum ApplyMode {
+    Full,
+    Incremental,
+}
+
 fn container_filter_try_from_str(s: &str) -> Result<ContainerFilter> {
     match &*s.to_ascii_lowercase() {
         "all" => Ok(ContainerFilter::All),
@@ -71,6 +122,28 @@ fn container_filter_try_from_str(s: &str) -> Result<ContainerFilter> {
     }
 }
 
+fn duration_try_from_str(s: &str) -> Result<Duration> {
+    let (value, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => s.split_at(index),
+        None => (s, "s"),
+    };
+    let value: u64 = value
+        .parse()
+        .map_err(|_| format_err!("Invalid duration '{}'", s))?;
+    let duration = match unit {
+        "ms" => Duration::from_millis(value),
+        "s" => Duration::from_secs(value),
+        "m" => Duration::from_secs(value * 60),
+        "h" => Duration::from_secs(value * 60 * 60),
+        _ => bail!("Unknown unit in duration '{}', expected ms, s, m or h", s),
+    };
+    if duration.is_zero() {
+        bail!("Duration '{}' has to be greater than zero", s);
+    }
+
+    Ok(duration)
+}
+
 fn load_config<B>(args: &Args) -> Result<DFW<B>>
 where
     B: dfw::FirewallBackend,
@@ -90,6 +163,310 @@ where
     Ok(toml)
 }
 
+fn migrate_config(path: &str, in_place: bool, backend: migrate::Backend) -> Result<()> {
+    let path = Path::new(path);
+    let files = if path.is_dir() {
+        let mut files = glob(&format!("{}/*", path.display()))?
+            .collect::<::std::result::Result<Vec<_>, _>>()?;
+        files.sort();
+        files
+    } else {
+        vec![path.to_path_buf()]
+    };
+
+    for file in files {
+        // Only the files `--config-path` loads from a directory are migrated.
+        let is_config_file = file
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| CONFIG_EXTENSIONS.contains(&extension));
+        if path.is_dir() && !is_config_file {
+            if !file.is_dir() {
+                eprintln!("{}: skipped, not a configuration file", file.display());
+            }
+            continue;
+        }
+
+        let contents = fs::read_to_string(&file)?;
+        let migrated = migrate::migrate_file(&file, &contents, backend)
+            .map_err(|e| format_err!("{}: {}", file.display(), e))?;
+        for change in &migrated.changes {
+            eprintln!("{}: {}", file.display(), change);
+        }
+        for note in &migrated.notes {
+            eprintln!("{}: warning: {}", file.display(), note);
+        }
+
+        if in_place {
+            if migrated.is_changed() {
+                fs::write(&file, &migrated.contents)?;
+            }
+        } else if path.is_dir() {
+            if migrated.is_changed() {
+                println!("==> {} <==\n{}", file.display(), migrated.contents);
+            }
+        } else {
+            print!("{}", migrated.contents);
+        }
+    }
+
+    Ok(())
+}
+
+fn connect(docker_url: Option<&str>, runtime: process::Runtime, timeout: u64) -> Result<Docker> {
+    Ok(match docker_url {
+        Some(docker_url) => Docker::connect_with_http(docker_url, timeout, API_DEFAULT_VERSION),
+        None if runtime == process::Runtime::Docker => Docker::connect_with_unix_defaults(),
+        None => Docker::connect_with_socket(runtime.default_socket(), timeout, API_DEFAULT_VERSION),
+    }?)
+}
+
+fn snapshot(args: &Args, output: Option<&str>) -> Result<()> {
+    let runtime = process::Runtime::from(&args.runtime);
+    let docker = connect(args.docker_url.as_deref(), runtime, 120)?
+        .negotiate_version()
+        .sync()?;
+    let snapshot = InMemoryRuntime::capture(&docker, &args.container_filter)?;
+
+    match output {
+        Some(output) => {
+            let mut writer = BufWriter::new(
+                File::create(output)
+                    .with_context(|_| format!("failed to create snapshot file {}", output))?,
+            );
+            snapshot.to_json(&mut writer)?;
+            writeln!(writer)?;
+        }
+        None => {
+            let mut stdout = io::stdout().lock();
+            snapshot.to_json(&mut stdout)?;
+            writeln!(stdout)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn render<B>(args: &Args, state: &str, logger: &Logger) -> Result<()>
+where
+    B: dfw::FirewallBackend,
+    DFW<B>: Process<B>,
+{
+    let toml = load_config::<B>(args)?;
+    let state = InMemoryRuntime::from_json(BufReader::new(
+        File::open(state).with_context(|_| format!("failed to open state file {}", state))?,
+    ))?;
+    let processing_options = ProcessingOptions {
+        container_filter: args.container_filter.clone(),
+        runtime: process::Runtime::from(&args.runtime),
+        dry_run_output: ruleset_output(args),
+    };
+
+    let rulesets =
+        ProcessContext::new(&state, &toml, &processing_options, logger, true)?.render()?;
+    processing_options.dry_run_output.write(&rulesets)
+}
+
+/// Compare the rules currently applied with the rules DFW would apply, returning whether they
+/// differ.
+fn diff<B>(args: &Args, logger: &Logger) -> Result<bool>
+where
+    B: dfw::FirewallBackend,
+    DFW<B>: Process<B>,
+{
+    let toml = load_config::<B>(args)?;
+    let runtime = process::Runtime::from(&args.runtime);
+    let docker = connect(args.docker_url.as_deref(), runtime, 120)?
+        .negotiate_version()
+        .sync()?;
+    let processing_options = ProcessingOptions {
+        container_filter: args.container_filter.clone(),
+        runtime,
+        dry_run_output: ruleset_output(args),
+    };
+
+    let diffs = ProcessContext::new(&docker, &toml, &processing_options, logger, true)?.diff()?;
+    write_diffs(
+        &diffs,
+        OutputFormat::from(&args.output_format),
+        io::stdout().lock(),
+    )?;
+
+    Ok(diffs.iter().any(|diff| !diff.is_empty()))
+}
+
+fn ruleset_output(args: &Args) -> RulesetOutput {
+    RulesetOutput {
+        file: args.output.as_ref().map(Into::into),
+        format: OutputFormat::from(&args.output_format),
+    }
+}
+
+/// Ask the DFW instance listening on the control socket to keep the rules it applied last.
+fn confirm(control_socket: &str) -> Result<()> {
+    let mut stream = UnixStream::connect(control_socket)
+        .with_context(|_| format!("failed to connect to control socket {}", control_socket))?;
+    writeln!(stream, "confirm")?;
+
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply)?;
+    match reply.trim_end() {
+        "ok" => Ok(()),
+        reply => bail!("{}", reply.trim_start_matches("error: ")),
+    }
+}
+
+/// Rules applied in commit-confirm mode, that get reverted unless they are confirmed in time.
+struct Confirmation {
+    timeout: Option<Duration>,
+    snapshot: Option<Vec<Ruleset>>,
+    deadline: Receiver<Instant>,
+    dummy: Receiver<Instant>,
+}
+
+impl Confirmation {
+    fn new(timeout: Option<Duration>) -> Confirmation {
+        let dummy: Receiver<Instant> = {
+            let (s_dummy, r_dummy) = crossbeam_channel::bounded(0);
+            // Leak the send-channel so that it never gets closed and `recv` never synchronizes.
+            ::std::mem::forget(s_dummy);
+            r_dummy
+        };
+
+        Confirmation {
+            timeout,
+            snapshot: None,
+            deadline: dummy.clone(),
+            dummy,
+        }
+    }
+
+    fn is_pending(&self) -> bool {
+        self.snapshot.is_some()
+    }
+
+    /// Apply all rules through `process`, saving the rules applied before if they have to be
+    /// confirmed.
+    ///
+    /// If rules applied previously weren't confirmed yet, the rules applied before them are kept,
+    /// such that a revert always returns to the last confirmed rules.
+    fn process<B>(
+        &mut self,
+        process: &mut dyn FnMut(bool) -> Result<()>,
+        logger: &Logger,
+    ) -> Result<()>
+    where
+        B: dfw::FirewallBackend,
+        DFW<B>: Process<B>,
+    {
+        let timeout = match self.timeout {
+            Some(timeout) => timeout,
+            None => return process(true),
+        };
+
+        if self.snapshot.is_none() {
+            self.snapshot =
+                Some(B::snapshot(logger).context("failed to save the rules currently applied")?);
+        }
+        process(true)?;
+
+        info!(logger, "Rules applied, reverting them in {:?} unless they are confirmed", timeout;
+              o!("confirm_timeout" => format!("{:?}", timeout)));
+        self.deadline = crossbeam_channel::after(timeout);
+
+        Ok(())
+    }
+
+    /// Keep the rules applied, returning whether there were rules to confirm.
+    fn confirm(&mut self, logger: &Logger) -> bool {
+        self.deadline = self.dummy.clone();
+        if self.snapshot.take().is_some() {
+            info!(logger, "Rules confirmed");
+            true
+        } else {
+            info!(
+                logger,
+                "Received confirmation, but there are no rules to confirm"
+            );
+            false
+        }
+    }
+
+    /// Restore the rules that were applied before the unconfirmed rules.
+    fn revert<B>(&mut self, logger: &Logger) -> Result<()>
+    where
+        B: dfw::FirewallBackend,
+        DFW<B>: Process<B>,
+    {
+        self.deadline = self.dummy.clone();
+        if let Some(snapshot) = self.snapshot.take() {
+            warn!(logger, "Rules weren't confirmed in time, reverting them");
+            B::restore_snapshot(&snapshot, logger)
+                .context("failed to revert the unconfirmed rules")?;
+        }
+
+        Ok(())
+    }
+}
+
+fn spawn_control_socket(
+    path: &str,
+    s_confirm: Sender<Sender<bool>>,
+    logger: &Logger,
+) -> Result<thread::JoinHandle<()>> {
+    let logger = logger.new(o!("thread" => "control_socket"));
+
+    // Remove the socket left behind by a previous instance, binding fails otherwise.
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if metadata.file_type().is_socket() {
+            fs::remove_file(path)?;
+        }
+    }
+    let listener = UnixListener::bind(path)
+        .with_context(|_| format!("failed to bind to control socket {}", path))?;
+    fs::set_permissions(path, Permissions::from_mode(0o600))?;
+
+    Ok(thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!(logger, "Failed to accept connection"; o!("error" => e.to_string()));
+                    continue;
+                }
+            };
+            let mut request = String::new();
+            if let Err(e) = stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .and_then(|_| BufReader::new(&stream).read_line(&mut request))
+            {
+                warn!(logger, "Failed to read request"; o!("error" => e.to_string()));
+                continue;
+            }
+
+            trace!(logger, "Received request";
+                   o!("request" => request.trim_end().to_owned()));
+            let reply = match request.trim_end() {
+                "confirm" => {
+                    let (s_reply, r_reply) = crossbeam_channel::bounded(1);
+                    s_confirm
+                        .send(s_reply)
+                        .expect("Failed to send confirmation");
+                    if r_reply.recv().unwrap_or(false) {
+                        "ok".to_owned()
+                    } else {
+                        "error: there are no rules to confirm".to_owned()
+                    }
+                }
+                request => format!("error: unknown request '{}'", request),
+            };
+            if let Err(e) = writeln!(stream, "{}", reply) {
+                warn!(logger, "Failed to write reply"; o!("error" => e.to_string()));
+            }
+        }
+    }))
+}
+
 fn spawn_burst_monitor(
     burst_timeout: u64,
     s_trigger: Sender<()>,
@@ -148,47 +525,31 @@ fn spawn_burst_monitor(
 
 fn spawn_event_monitor(
     docker_url: Option<String>,
+    runtime: process::Runtime,
     s_event: Sender<()>,
     logger: &Logger,
 ) -> thread::JoinHandle<()> {
     let logger = logger.new(o!("thread" => "event_monitor"));
     thread::spawn(move || {
-        let docker = match docker_url {
-            Some(docker_url) => Docker::connect_with_http(&docker_url, 120, API_DEFAULT_VERSION),
-            None => Docker::connect_with_unix_defaults(),
-        }
-        .expect("Failed to setup connection to Docker")
-        .negotiate_version()
-        .sync()
-        .expect("Failed to negotiate version with Docker");
+        let docker = connect(docker_url.as_deref(), runtime, 120)
+            .expect("Failed to setup connection to Docker")
+            .negotiate_version()
+            .sync()
+            .expect("Failed to negotiate version with Docker");
         loop {
             trace!(logger, "Waiting for events");
-            docker
-                .events(Some(EventsOptions {
-                    filters: hashmap! { "type" => vec!["container"] },
-                    ..Default::default()
-                }))
-                .for_each({
-                    let logger = logger.clone();
-                    let s_event = s_event.clone();
-                    move |event| {
-                        let event = event.expect("failure in getting Docker event");
-                        trace!(logger, "Received event";
-                               o!("event" => format!("{:?}", &event)));
-                        if let Some(action) = &event.action {
-                            match &**action {
-                                "create" | "destroy" | "start" | "restart" | "die" | "stop" => {
-                                    trace!(logger, "Trigger channel about event";
-                                           o!("event" => format!("{:?}", event)));
-                                    s_event.send(()).expect("Failed to send trigger event");
-                                }
-                                _ => {}
-                            }
-                        }
-                        future::ready(())
+            for event in ContainerRuntime::events(&docker, runtime.event_filters()) {
+                let event = event.expect("failure in getting Docker event");
+                trace!(logger, "Received event";
+                       o!("event" => format!("{:?}", &event)));
+                if let Some(action) = &event.action {
+                    if runtime.container_events().contains(&&**action) {
+                        trace!(logger, "Trigger channel about event";
+                               o!("event" => format!("{:?}", event)));
+                        s_event.send(()).expect("Failed to send trigger event");
                     }
-                })
-                .sync();
+                }
+            }
         }
     })
 }
@@ -202,19 +563,27 @@ where
 {
     let toml = load_config(args);
     if args.check_config {
-        return toml.map(|_| ());
+        let toml = toml.map_err(|e| {
+            // Print the error on its own, since the location and snippet span multiple lines.
+            eprintln!("{}", e);
+            e
+        })?;
+        let report = validate(&toml);
+        eprint!("{}", report);
+        if report.has_errors() {
+            bail!("configuration is invalid");
+        }
+        return Ok(());
     }
 
     let toml = toml?;
     debug!(root_logger, "Initial configuration loaded";
            o!("config" => format!("{:#?}", toml)));
 
-    let docker = match args.docker_url {
-        Some(ref docker_url) => Docker::connect_with_http(docker_url, 120, API_DEFAULT_VERSION),
-        None => Docker::connect_with_unix_defaults(),
-    }?
-    .negotiate_version()
-    .sync()?;
+    let runtime = process::Runtime::from(&args.runtime);
+    let docker = connect(args.docker_url.as_deref(), runtime, 120)?
+        .negotiate_version()
+        .sync()?;
     // Check if the docker instance is reachable
     trace!(root_logger, "Pinging docker");
     docker.ping().sync()?;
@@ -223,7 +592,7 @@ where
     let load_interval_chan = {
         let load_interval: u64 = args.load_interval;
 
-        if load_interval > 0 {
+        if load_interval > 0 && !args.run_once {
             // If the load interval is greater than zero, we use a tick-channel
             trace!(root_logger, "Creating tick channel";
                    o!("load_interval" => load_interval));
@@ -243,6 +612,8 @@ where
 
     let processing_options = ProcessingOptions {
         container_filter: args.container_filter.clone(),
+        runtime,
+        dry_run_output: ruleset_output(args),
     };
 
     let monitor_events = !args.disable_event_monitoring;
@@ -257,39 +628,60 @@ where
     trace!(root_logger, "Dry run: {}", dry_run;
            o!("dry_run" => dry_run));
 
+    let confirm_timeout = if dry_run { None } else { args.confirm_timeout };
+    trace!(root_logger, "Confirm timeout: {:?}", confirm_timeout;
+           o!("confirm_timeout" => format!("{:?}", confirm_timeout)));
+
+    let incremental = matches!(args.apply_mode, ApplyMode::Incremental);
+    trace!(root_logger, "Apply mode: {:?}", args.apply_mode;
+           o!("apply_mode" => format!("{:?}", args.apply_mode)));
+
     let processing_logger = root_logger.new(o!());
-    let mut process: Box<dyn FnMut() -> Result<()>> = match args.load_mode {
+    let mut applied = AppliedRules::new();
+    let mut process: Box<dyn FnMut(bool) -> Result<()>> = match args.load_mode {
         LoadMode::Once => {
             trace!(root_logger, "Creating process closure according to load mode";
                    o!("load_mode" => "once"));
-            Box::new(|| {
-                ProcessContext::new(
+            Box::new(|full| {
+                let mut ctx = ProcessContext::new(
                     &docker,
                     &toml,
                     &processing_options,
                     &processing_logger,
                     dry_run,
-                )?
-                .process()
-                .map_err(From::from)
+                )?;
+                if full || !incremental {
+                    applied.clear();
+                }
+                if incremental {
+                    ctx.process_incremental(&mut applied)
+                } else {
+                    ctx.process()
+                }
             })
         }
         LoadMode::Always => {
             trace!(root_logger, "Creating process closure according to load mode";
                    o!("load_mode" => "always"));
-            Box::new(|| {
+            Box::new(|full| {
                 let toml = load_config(args)?;
                 debug!(root_logger, "Reloaded configuration before processing";
                        o!("config" => format!("{:#?}", toml)));
-                ProcessContext::new(
+                let mut ctx = ProcessContext::new(
                     &docker,
                     &toml,
                     &processing_options,
                     &processing_logger,
                     dry_run,
-                )?
-                .process()
-                .map_err(From::from)
+                )?;
+                if full || !incremental {
+                    applied.clear();
+                }
+                if incremental {
+                    ctx.process_incremental(&mut applied)
+                } else {
+                    ctx.process()
+                }
             })
         }
     };
@@ -299,21 +691,36 @@ where
           "version" => crate_version!(),
           "started_at" => time::OffsetDateTime::now_utc().format(&Rfc3339).expect("failed to format time"));
 
+    let (s_confirm, r_confirm) = crossbeam_channel::bounded(0);
+    if confirm_timeout.is_some() {
+        trace!(root_logger, "Start control socket thread";
+               o!("control_socket" => &args.control_socket));
+        spawn_control_socket(&args.control_socket, s_confirm, root_logger)?;
+    }
+    let mut confirmation = Confirmation::new(confirm_timeout);
+
     // Initial processing
     debug!(root_logger, "Start first processing");
-    process()?;
-
-    if run_once || (!monitor_events && args.load_interval == 0) {
-        // Either run-once is specified or both events are not monitored and rules aren't processed
-        // regularly -- process once, then exit.
-        info!(root_logger,
-              "Run once specified (or load-interval is zero and events aren't monitored), exiting";
-              o!("version" => crate_version!(),
-                 "exited_at" => time::OffsetDateTime::now_utc().format(&Rfc3339).expect("failed to format time")));
-        return Ok(());
+    confirmation.process::<B>(&mut process, root_logger)?;
+
+    // Either run-once is specified or both events are not monitored and rules aren't processed
+    // regularly -- process once, then exit (after the rules were confirmed or reverted).
+    let process_once = run_once || (!monitor_events && args.load_interval == 0);
+    if process_once {
+        if !confirmation.is_pending() {
+            info!(root_logger,
+                  "Run once specified (or load-interval is zero and events aren't monitored), exiting";
+                  o!("version" => crate_version!(),
+                     "exited_at" => time::OffsetDateTime::now_utc().format(&Rfc3339).expect("failed to format time")));
+            return Ok(());
+        }
+        info!(
+            root_logger,
+            "Waiting for the rules to be confirmed before exiting"
+        );
     }
 
-    let event_trigger = if monitor_events {
+    let event_trigger = if monitor_events && !process_once {
         // Setup event monitoring
         trace!(root_logger, "Setup event monitoring channel";
                o!("monitor_events" => monitor_events));
@@ -328,7 +735,7 @@ where
 
         trace!(root_logger, "Start event monitoring thread";
                o!("docker_url" => &docker_url));
-        spawn_event_monitor(docker_url, s_event, root_logger);
+        spawn_event_monitor(docker_url, runtime, s_event, root_logger);
 
         // Note: we need both spawned threads for the entirety of the programs lifetime. As such we
         // do not bother cleaning them up, but rather let the OS handle the cleanup once we exit the
@@ -345,28 +752,67 @@ where
         r_dummy
     };
 
+    // Rules that weren't confirmed in time stay reverted until processing is requested through
+    // SIGHUP, since reprocessing them on an event would apply the unconfirmed rules again.
+    let mut reverted = false;
     loop {
         select! {
             recv(load_interval_chan) -> _ => {
-                info!(root_logger, "Load interval ticked, starting processing");
-                process()?;
+                if reverted {
+                    info!(root_logger, "Load interval ticked, skipping processing since the rules were reverted");
+                } else {
+                    info!(root_logger, "Load interval ticked, starting processing");
+                    process(false)?;
+                }
             },
             recv(event_trigger) -> _ => {
-                info!(root_logger, "Received Docker events, starting processing");
-                process()?;
+                if reverted {
+                    info!(root_logger, "Received Docker events, skipping processing since the rules were reverted");
+                } else {
+                    info!(root_logger, "Received Docker events, starting processing");
+                    process(false)?;
+                }
+            },
+            recv(confirmation.deadline) -> _ => {
+                confirmation.revert::<B>(root_logger)?;
+                reverted = true;
+                if process_once {
+                    break;
+                }
+                info!(root_logger, "Send SIGHUP to apply the rules again");
+            },
+            recv(r_confirm) -> s_reply => {
+                let confirmed = confirmation.confirm(root_logger);
+                if let Ok(s_reply) = s_reply {
+                    let _ = s_reply.send(confirmed);
+                }
+                if confirmed && process_once {
+                    break;
+                }
             },
             recv(r_signal) -> signal => {
                 match signal.expect("received an error instead of a signal") {
                     libc::SIGINT | libc::SIGTERM => {
                         info!(root_logger, "Received kill-signal, exiting";
                               o!("signal" => format!("{:?}", signal)));
+                        if confirmation.is_pending() {
+                            warn!(root_logger, "Exiting before the rules were confirmed, they stay applied");
+                        }
 
                         break;
                     }
                     libc::SIGHUP => {
-                        info!(root_logger, "Received HUP-signal, starting processing";
+                        info!(root_logger, "Received HUP-signal, starting processing of all rules";
                               o!("signal" => format!("{:?}", signal)));
-                        process()?;
+                        reverted = false;
+                        confirmation.process::<B>(&mut process, root_logger)?;
+                    }
+                    libc::SIGUSR1 => {
+                        info!(root_logger, "Received USR1-signal, confirming the rules";
+                              o!("signal" => format!("{:?}", signal)));
+                        if confirmation.confirm(root_logger) && process_once {
+                            break;
+                        }
                     }
                     _ => { bail!("got unexpected signal '{:?}'", signal); }
                 }
@@ -374,6 +820,10 @@ where
         }
     }
 
+    if confirm_timeout.is_some() {
+        let _ = fs::remove_file(&args.control_socket);
+    }
+
     info!(root_logger, "Application exiting";
           o!("version" => crate_version!(),
              "exited_at" => time::OffsetDateTime::now_utc().format(&Rfc3339).expect("failed to format time")));
@@ -381,9 +831,65 @@ where
     Ok(())
 }
 
+#[derive(Debug, Subcommand)]
+enum Command {
+    #[clap(about = "Print the JSON Schema of the configuration for the selected firewall-backend")]
+    Schema,
+    #[clap(
+        about = "Migrate deprecated fields of a configuration file or directory for the selected firewall-backend"
+    )]
+    MigrateConfig {
+        #[clap(
+            long = "in-place",
+            help = "Rewrite the configuration files instead of printing the migrated configuration"
+        )]
+        in_place: bool,
+        #[clap(
+            value_name = "PATH",
+            help = "Configuration file or directory to migrate"
+        )]
+        path: String,
+    },
+    #[clap(
+        about = "Write the containers and networks DFW sees to a JSON file, to be used by `dfw render`"
+    )]
+    Snapshot {
+        #[clap(
+            short = 'o',
+            long = "output",
+            value_name = "FILE",
+            help = "Write the snapshot to a file instead of stdout"
+        )]
+        output: Option<String>,
+    },
+    #[clap(
+        about = "Render the rules for a snapshot taken by `dfw snapshot`, without Docker or root privileges"
+    )]
+    Render {
+        #[clap(
+            long = "state",
+            value_name = "FILE",
+            help = "Snapshot of the containers and networks, as written by `dfw snapshot`"
+        )]
+        state: String,
+    },
+    #[clap(
+        about = "Compare the rules currently applied with the rules DFW would apply",
+        long_about = "Compare the rules currently applied with the rules DFW would apply. Rules DFW would remove are prefixed by `-`, rules DFW would add by `+`. Only the tables (nftables) or chains (iptables) owned by DFW are compared. Exits with 0 if there are no differences, 1 if there are, and 2 on errors."
+    )]
+    Diff,
+    #[clap(
+        about = "Confirm the rules applied by DFW running with --confirm-timeout",
+        long_about = "Confirm the rules applied by DFW running with --confirm-timeout, through the socket given by --control-socket. Alternatively, send SIGUSR1 to DFW."
+    )]
+    Confirm,
+}
+
 #[derive(Debug, Parser)]
-#[clap(author, version, about)]
+#[clap(author, version, about, subcommand_negates_reqs = true)]
 struct Args {
+    #[clap(subcommand)]
+    command: Option<Command>,
     #[clap(
         long = "log-level",
         value_name = "SEVERITY",
@@ -412,7 +918,7 @@ struct Args {
         required_unless_present = "config-file",
         long = "config-path",
         value_name = "PATH",
-        help = "Set a path with multiple TOML configuration files"
+        help = "Set a path with multiple configuration files (TOML, JSON or YAML)"
     )]
     config_path: Option<String>,
     #[clap(
@@ -422,6 +928,16 @@ struct Args {
         help = "Set the URL to the Docker instance (e.g. unix:///tmp/docker.sock)"
     )]
     docker_url: Option<String>,
+    #[clap(
+        arg_enum,
+        long = "runtime",
+        value_name = "RUNTIME",
+        default_value_t = Runtime::Docker,
+        ignore_case = true,
+        help = "Select the container runtime providing the Docker API",
+        long_help = "Select the container runtime providing the Docker API. With `podman`, the Docker-compatible API of Podman is used, connecting to /run/podman/podman.sock unless --docker-url is specified."
+    )]
+    runtime: Runtime,
     #[clap(
         short = 'i',
         long = "load-interval",
@@ -447,6 +963,16 @@ struct Args {
         help = "Time to wait after a event was received before processing the rules, in milliseconds"
     )]
     burst_timeout: u64,
+    #[clap(
+        arg_enum,
+        long = "apply-mode",
+        value_name = "MODE",
+        default_value_t = ApplyMode::Incremental,
+        ignore_case = true,
+        help = "Define if only the changes to the rules applied previously get applied, or all rules on every run",
+        long_help = "Define if only the changes to the rules applied previously get applied, or all rules on every run (flushing the tables or chains owned by DFW). In the incremental mode, all rules are still applied on startup, on SIGHUP and if the rules currently applied differ from the rules applied previously, e.g. because they were changed manually."
+    )]
+    apply_mode: ApplyMode,
     #[clap(
         parse(try_from_str = container_filter_try_from_str),
         long = "container-filter",
@@ -462,9 +988,40 @@ struct Args {
     #[clap(
         long = "dry-run",
         help = "Don't touch firewall-rules, just show what would be done",
-        long_help = "Don't touch firewall-rules, just show what would be done. Note that this requires Docker and the containers/networks referenced in the configuration to be available. If you want to check the config for validity, specify --check-config instead."
+        long_help = "Don't touch firewall-rules, just show what would be done. The rules are written to stdout, or to the file given by --output. Note that this requires Docker and the containers/networks referenced in the configuration to be available. If you want to check the config for validity, specify --check-config instead."
     )]
     dry_run: bool,
+    #[clap(
+        long = "output",
+        value_name = "FILE",
+        help = "Write the rules of a dry-run or of `dfw render` to a file instead of stdout"
+    )]
+    output: Option<String>,
+    #[clap(
+        arg_enum,
+        long = "output-format",
+        value_name = "FORMAT",
+        default_value_t = Format::Text,
+        ignore_case = true,
+        help = "Format of the rules written by a dry-run, `dfw render` or `dfw diff`",
+        long_help = "Format of the rules written by a dry-run, `dfw render` or `dfw diff`: either the nft script or iptables-restore input (IPv4 and IPv6), or a JSON array of objects holding the command and the lines of each ruleset. `dfw diff` lists the rules to remove and to add, or a JSON array of objects holding the command and the added and removed rules of each ruleset."
+    )]
+    output_format: Format,
+    #[clap(
+        parse(try_from_str = duration_try_from_str),
+        long = "confirm-timeout",
+        value_name = "DURATION",
+        help = "Revert the rules applied on startup or on SIGHUP unless they are confirmed in time",
+        long_help = "Revert the rules applied on startup or on SIGHUP to the rules applied before unless they are confirmed in time, through `dfw confirm` or SIGUSR1. The duration is given in seconds, or with a unit of ms, s, m or h (e.g. 60s). Rules that were reverted stay reverted until the next SIGHUP. Only the tables owned by DFW (nftables) or the filter and nat tables (iptables) are reverted, initialization rules outside of them and the rules DFW adds to custom tables (nftables) are kept."
+    )]
+    confirm_timeout: Option<Duration>,
+    #[clap(
+        long = "control-socket",
+        value_name = "PATH",
+        default_value = "/run/dfw.sock",
+        help = "Path of the socket `dfw confirm` connects to, if --confirm-timeout is specified"
+    )]
+    control_socket: String,
     #[clap(
         long = "check-config",
         help = "Verify if the provided configuration is valid, exit afterwards."
@@ -472,14 +1029,96 @@ struct Args {
     check_config: bool,
 }
 
+fn logger(args: &Args) -> Logger {
+    TerminalLoggerBuilder::new()
+        .format(sloggers::types::Format::Full)
+        .level(args.log_level)
+        .destination(Destination::Stderr)
+        .build()
+        .expect("Failed to setup logging")
+}
+
 fn main() {
     let args = Args::parse();
 
+    match args.command {
+        Some(Command::Schema) => {
+            let schema = match args.firewall_backend {
+                FirewallBackend::Nftables => config_schema::<DFW<dfw::nftables::Nftables>>(),
+                FirewallBackend::Iptables => config_schema::<DFW<dfw::iptables::Iptables>>(),
+            };
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&schema).expect("Failed to serialize schema")
+            );
+            return;
+        }
+        Some(Command::MigrateConfig { in_place, ref path }) => {
+            let backend = match args.firewall_backend {
+                FirewallBackend::Nftables => migrate::Backend::Nftables,
+                FirewallBackend::Iptables => migrate::Backend::Iptables,
+            };
+            if let Err(e) = migrate_config(path, in_place, backend) {
+                eprintln!("error: {}", e);
+                ::std::process::exit(1);
+            }
+            return;
+        }
+        Some(Command::Snapshot { ref output }) => {
+            if let Err(e) = snapshot(&args, output.as_deref()) {
+                eprintln!("error: {}", e);
+                ::std::process::exit(1);
+            }
+            return;
+        }
+        Some(Command::Render { ref state }) => {
+            let logger = logger(&args);
+            if let Err(e) = match args.firewall_backend {
+                FirewallBackend::Nftables => {
+                    render::<dfw::nftables::Nftables>(&args, state, &logger)
+                }
+                FirewallBackend::Iptables => {
+                    render::<dfw::iptables::Iptables>(&args, state, &logger)
+                }
+            } {
+                eprintln!("error: {}", e);
+                ::std::process::exit(1);
+            }
+            return;
+        }
+        Some(Command::Diff) => {
+            let logger = logger(&args);
+            match match args.firewall_backend {
+                FirewallBackend::Nftables => diff::<dfw::nftables::Nftables>(&args, &logger),
+                FirewallBackend::Iptables => diff::<dfw::iptables::Iptables>(&args, &logger),
+            } {
+                Ok(false) => return,
+                Ok(true) => ::std::process::exit(1),
+                Err(e) => {
+                    eprintln!("error: {}", e);
+                    ::std::process::exit(2);
+                }
+            }
+        }
+        Some(Command::Confirm) => {
+            if let Err(e) = confirm(&args.control_socket) {
+                eprintln!("error: {}", e);
+                ::std::process::exit(1);
+            }
+            return;
+        }
+        None => {}
+    }
+
     // Signals should be set up as early as possible, to set proper signal masks to all threads
     let (s_signal, r_signal) = crossbeam_channel::bounded(10);
-    let mut signals =
-        signal_hook::iterator::Signals::new([libc::SIGINT, libc::SIGTERM, libc::SIGHUP])
-            .expect("Failed to bind to process signals");
+    let mut signals = signal_hook::iterator::Signals::new([
+        libc::SIGINT,
+        libc::SIGTERM,
+        libc::SIGHUP,
+        libc::SIGUSR1,
+    ])
+    .expect("Failed to bind to process signals");
     thread::spawn(move || {
         for signal in signals.forever() {
             s_signal.send(signal).expect("Failed to send signal event");
@@ -487,12 +1126,7 @@ fn main() {
     });
 
     // Setup logging
-    let root_logger = TerminalLoggerBuilder::new()
-        .format(sloggers::types::Format::Full)
-        .level(args.log_level)
-        .destination(Destination::Stderr)
-        .build()
-        .expect("Failed to setup logging");
+    let root_logger = logger(&args);
 
     debug!(root_logger, "Application starting";
            o!("version" => crate_version!(),
@@ -505,5 +1139,6 @@ fn main() {
         error!(root_logger, "Encountered error";
                o!("error" => format!("{}", e),
                   "backtrace" => format!("{}", e.backtrace())));
+        ::std::process::exit(1);
     }
 }
@@ -14,7 +14,8 @@ struct StringOrStruct<T>(PhantomData<T>);
 
 impl<'de, T> de::Visitor<'de> for StringOrStruct<T>
 where
-    T: de::Deserialize<'de> + FromStr<Err = String>,
+    T: de::Deserialize<'de> + FromStr,
+    T::Err: fmt::Display,
 {
     type Value = T;
 
@@ -29,6 +30,13 @@ where
         FromStr::from_str(&value.to_string()).map_err(de::Error::custom)
     }
 
+    fn visit_u64<E>(self, value: u64) -> Result<T, E>
+    where
+        E: de::Error,
+    {
+        FromStr::from_str(&value.to_string()).map_err(de::Error::custom)
+    }
+
     fn visit_str<E>(self, value: &str) -> Result<T, E>
     where
         E: de::Error,
@@ -48,7 +56,8 @@ where
 //   https://github.com/serde-rs/serde/issues/901#issuecomment-297070279
 impl<'de, T> de::DeserializeSeed<'de> for StringOrStruct<T>
 where
-    T: de::Deserialize<'de> + FromStr<Err = String>,
+    T: de::Deserialize<'de> + FromStr,
+    T::Err: fmt::Display,
 {
     type Value = T;
 
@@ -60,20 +69,30 @@ where
     }
 }
 
-#[allow(dead_code)]
 pub fn string_or_struct<'de, T, D>(deserializer: D) -> Result<T, D::Error>
 where
-    T: de::Deserialize<'de> + FromStr<Err = String>,
+    T: de::Deserialize<'de> + FromStr,
+    T::Err: fmt::Display,
     D: de::Deserializer<'de>,
 {
     deserializer.deserialize_any(StringOrStruct(PhantomData))
 }
 
+pub fn option_string_or_struct<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: de::Deserialize<'de> + FromStr,
+    T::Err: fmt::Display,
+    D: de::Deserializer<'de>,
+{
+    string_or_struct(deserializer).map(Some)
+}
+
 struct SingleOrSeqStringOrStruct<T>(PhantomData<T>);
 
 impl<'de, T> de::Visitor<'de> for SingleOrSeqStringOrStruct<T>
 where
-    T: de::Deserialize<'de> + FromStr<Err = String>,
+    T: de::Deserialize<'de> + FromStr,
+    T::Err: fmt::Display,
 {
     type Value = Vec<T>;
 
@@ -93,6 +112,15 @@ where
             .map_err(de::Error::custom)
     }
 
+    fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        FromStr::from_str(&value.to_string())
+            .map(|e| vec![e])
+            .map_err(de::Error::custom)
+    }
+
     fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
     where
         E: de::Error,
@@ -124,12 +152,24 @@ where
 
 pub fn single_or_seq_string_or_struct<'de, T, D>(deserializer: D) -> Result<Vec<T>, D::Error>
 where
-    T: de::Deserialize<'de> + FromStr<Err = String>,
+    T: de::Deserialize<'de> + FromStr,
+    T::Err: fmt::Display,
     D: de::Deserializer<'de>,
 {
     deserializer.deserialize_any(SingleOrSeqStringOrStruct(PhantomData))
 }
 
+pub fn option_single_or_seq_string_or_struct<'de, T, D>(
+    deserializer: D,
+) -> Result<Option<Vec<T>>, D::Error>
+where
+    T: de::Deserialize<'de> + FromStr,
+    T::Err: fmt::Display,
+    D: de::Deserializer<'de>,
+{
+    single_or_seq_string_or_struct(deserializer).map(Some)
+}
+
 pub fn string_or_seq_string<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
 where
     D: de::Deserializer<'de>,
@@ -0,0 +1,262 @@
+// Copyright Pit Kleyersburg <pitkley@googlemail.com>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+//! This module holds the types to compare the rules currently applied with the rules DFW would
+//! apply.
+//!
+//! The firewall-backends read the rules of the tables and chains owned by DFW and normalize them,
+//! as well as the processed rules, to the same representation (e.g. stripping the prefix length
+//! `iptables-save` adds to single addresses, or canonicalizing the JSON listing of `nft`). Rules
+//! outside of the tables and chains owned by DFW, e.g. rules added through the initialization of
+//! the backend, are not compared.
+//!
+//! The same comparison, done per chain and retaining the order of the rules, is used to apply only
+//! the changes to the rules applied previously, see [`chain_delta`](fn.chain_delta.html).
+
+use crate::{errors::*, process::OutputFormat};
+use serde::Serialize;
+use std::{collections::HashMap, io::Write};
+
+/// Difference between the rules currently applied and the rules DFW would apply for one ruleset.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RulesetDiff {
+    /// Command the ruleset is applied with, see [`Ruleset`](../struct.Ruleset.html).
+    pub command: String,
+    /// Rules DFW would add.
+    pub added: Vec<String>,
+    /// Rules that are currently applied, but DFW would remove.
+    pub removed: Vec<String>,
+}
+
+impl RulesetDiff {
+    /// Compare the (normalized) rules currently applied with the (normalized) rules DFW would
+    /// apply.
+    ///
+    /// Every rule is only matched once, i.e. a rule that DFW would apply twice is reported as
+    /// added if it is currently applied only once. The rules retain their order.
+    pub fn new(command: &str, current: &[String], expected: &[String]) -> RulesetDiff {
+        RulesetDiff {
+            command: command.to_owned(),
+            added: subtract(expected, current),
+            removed: subtract(current, expected),
+        }
+    }
+
+    /// Check if the rules currently applied match the rules DFW would apply.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Return the rules of `rules` that are not part of `other`, matching every rule of `other` at
+/// most once.
+fn subtract(rules: &[String], other: &[String]) -> Vec<String> {
+    let mut remaining: HashMap<&str, usize> = HashMap::new();
+    for rule in other {
+        *remaining.entry(rule).or_default() += 1;
+    }
+
+    rules
+        .iter()
+        .filter(|rule| match remaining.get_mut(rule.as_str()) {
+            Some(count) if *count > 0 => {
+                *count -= 1;
+                false
+            }
+            _ => true,
+        })
+        .cloned()
+        .collect()
+}
+
+/// Upper bound for the number of rules compared by [`chain_delta`], beyond which the chain is
+/// rebuilt instead.
+const MAX_DELTA_CELLS: usize = 1 << 24;
+
+/// Changes transforming the rules of a chain into the rules DFW would apply, see [`chain_delta`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct ChainDelta {
+    /// Indexes of the current rules to remove, in ascending order.
+    pub(crate) removed: Vec<usize>,
+    /// Indexes of the expected rules to add, in ascending order, each with the index of the
+    /// current rule it has to be inserted before. Rules without a following current rule are
+    /// appended to the chain.
+    pub(crate) added: Vec<(usize, Option<usize>)>,
+}
+
+/// Compute the minimal changes transforming the (normalized) rules currently applied to a chain
+/// into the (normalized) rules DFW would apply, retaining the order of the rules.
+///
+/// Returns `None` if the chains are too large to be compared, in which case the chain should be
+/// rebuilt.
+pub(crate) fn chain_delta<T: PartialEq>(current: &[T], expected: &[T]) -> Option<ChainDelta> {
+    // Rules are usually only changed in a small part of the chain, which is why the common prefix
+    // and suffix are skipped before computing the longest common subsequence.
+    let prefix = current
+        .iter()
+        .zip(expected)
+        .take_while(|(current, expected)| current == expected)
+        .count();
+    let suffix = current[prefix..]
+        .iter()
+        .rev()
+        .zip(expected[prefix..].iter().rev())
+        .take_while(|(current, expected)| current == expected)
+        .count();
+    let current_middle = &current[prefix..current.len() - suffix];
+    let expected_middle = &expected[prefix..expected.len() - suffix];
+
+    let (rows, columns) = (current_middle.len() + 1, expected_middle.len() + 1);
+    if rows.saturating_mul(columns) > MAX_DELTA_CELLS {
+        return None;
+    }
+    // `lengths[i * columns + j]` is the length of the longest common subsequence of
+    // `current_middle[i..]` and `expected_middle[j..]`.
+    let mut lengths = vec![0u32; rows * columns];
+    for i in (0..current_middle.len()).rev() {
+        for j in (0..expected_middle.len()).rev() {
+            lengths[i * columns + j] = if current_middle[i] == expected_middle[j] {
+                lengths[(i + 1) * columns + j + 1] + 1
+            } else {
+                lengths[(i + 1) * columns + j].max(lengths[i * columns + j + 1])
+            };
+        }
+    }
+
+    let mut delta = ChainDelta::default();
+    let mut added = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < current_middle.len() || j < expected_middle.len() {
+        if i < current_middle.len()
+            && j < expected_middle.len()
+            && current_middle[i] == expected_middle[j]
+        {
+            // The rule is retained, it is the rule the preceding added rules are inserted before.
+            delta
+                .added
+                .extend(added.drain(..).map(|index| (index, Some(prefix + i))));
+            i += 1;
+            j += 1;
+        } else if j < expected_middle.len()
+            && (i == current_middle.len()
+                || lengths[i * columns + j + 1] >= lengths[(i + 1) * columns + j])
+        {
+            added.push(prefix + j);
+            j += 1;
+        } else {
+            delta.removed.push(prefix + i);
+            i += 1;
+        }
+    }
+    let next = if suffix > 0 {
+        Some(current.len() - suffix)
+    } else {
+        None
+    };
+    delta
+        .added
+        .extend(added.into_iter().map(|index| (index, next)));
+
+    Some(delta)
+}
+
+/// Write the differences in the given format.
+///
+/// The text format lists the rules DFW would remove prefixed by `-` and the rules DFW would add
+/// prefixed by `+`, grouped by ruleset. Rulesets without differences are omitted.
+pub fn write_diffs<W: Write>(
+    diffs: &[RulesetDiff],
+    format: OutputFormat,
+    mut writer: W,
+) -> Result<()> {
+    match format {
+        OutputFormat::Text => {
+            for (index, diff) in diffs.iter().filter(|diff| !diff.is_empty()).enumerate() {
+                if index > 0 {
+                    writeln!(writer)?;
+                }
+                writeln!(writer, "# {}", diff.command)?;
+                for rule in &diff.removed {
+                    writeln!(writer, "-{}", rule)?;
+                }
+                for rule in &diff.added {
+                    writeln!(writer, "+{}", rule)?;
+                }
+            }
+        }
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, diffs)?;
+            writeln!(writer)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rules(rules: &[&str]) -> Vec<String> {
+        rules.iter().map(|rule| (*rule).to_owned()).collect()
+    }
+
+    #[test]
+    fn ruleset_diff() {
+        let diff = RulesetDiff::new(
+            "nft",
+            &rules(&["a", "b", "b", "c"]),
+            &rules(&["b", "d", "a", "b", "b"]),
+        );
+        assert_eq!(rules(&["d", "b"]), diff.added);
+        assert_eq!(rules(&["c"]), diff.removed);
+        assert!(!diff.is_empty());
+
+        let diff = RulesetDiff::new("nft", &rules(&["a", "b"]), &rules(&["b", "a"]));
+        assert!(diff.is_empty());
+    }
+
+    #[test]
+    fn chain_delta_retains_order() {
+        let delta = chain_delta(&["a", "b", "c", "d"], &["a", "x", "c", "d", "y"]).unwrap();
+        assert_eq!(vec![1], delta.removed);
+        assert_eq!(vec![(1, Some(2)), (4, None)], delta.added);
+
+        let delta = chain_delta(&["a", "b"], &["x", "y", "a", "b"]).unwrap();
+        assert!(delta.removed.is_empty());
+        assert_eq!(vec![(0, Some(0)), (1, Some(0))], delta.added);
+
+        let delta = chain_delta(&["a", "b", "c"], &["c", "b", "a"]).unwrap();
+        assert_eq!(2, delta.removed.len());
+        assert_eq!(2, delta.added.len());
+
+        assert_eq!(
+            Some(ChainDelta::default()),
+            chain_delta(&["a", "b"], &["a", "b"])
+        );
+        assert_eq!(
+            vec![(0, None), (1, None)],
+            chain_delta(&[], &["a", "b"]).unwrap().added
+        );
+    }
+
+    #[test]
+    fn write_diffs_text() {
+        let diffs = vec![
+            RulesetDiff::new("iptables-restore", &rules(&["a"]), &rules(&["b"])),
+            RulesetDiff::new("ip6tables-restore", &rules(&["a"]), &rules(&["a"])),
+        ];
+        let mut output = Vec::new();
+        write_diffs(&diffs, OutputFormat::Text, &mut output).unwrap();
+        assert_eq!(
+            "# iptables-restore\n-a\n+b\n",
+            String::from_utf8(output).unwrap()
+        );
+    }
+}
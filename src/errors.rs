@@ -12,8 +12,11 @@
 //! [failure]: https://crates.io/crates/failure
 
 #![allow(missing_docs)]
+// The `failure` derive-macros generate non-local impl-blocks.
+#![allow(non_local_definitions)]
 
 use failure::{Error, Fail};
+use std::fmt;
 
 #[derive(Debug, Fail)]
 pub enum DFWError {
@@ -21,6 +24,66 @@ pub enum DFWError {
     NFTablesError { stdout: String, stderr: String },
     #[fail(display = "trait method unimplemented: {}", method)]
     TraitMethodUnimplemented { method: String },
+    #[fail(
+        display = "applying the rules failed at `{}`: {}\n{}",
+        step, message, rollback
+    )]
+    ApplyError {
+        step: String,
+        message: String,
+        rollback: Rollback,
+    },
 }
 
+/// Outcome of restoring the rules that were applied before applying the processed rules failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rollback {
+    /// The rules applied before have been restored.
+    Succeeded,
+    /// Restoring the rules applied before failed as well, the rules might be applied partially.
+    Failed(String),
+}
+
+impl fmt::Display for Rollback {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Rollback::Succeeded => write!(f, "the rules applied before have been restored"),
+            Rollback::Failed(message) => write!(
+                f,
+                "restoring the rules applied before failed, the rules might be applied \
+                 partially: {}",
+                message
+            ),
+        }
+    }
+}
+
+/// An error in the configuration, pointing at the location in the file it originates from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+    pub snippet: String,
+    pub suggestion: Option<String>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}\n{}",
+            self.file, self.line, self.column, self.message, self.snippet
+        )?;
+        if let Some(ref suggestion) = self.suggestion {
+            write!(f, "\nhelp: did you mean `{}`?", suggestion)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Fail for ConfigError {}
+
 pub type Result<E> = ::std::result::Result<E, Error>;
@@ -0,0 +1,344 @@
+// Copyright Pit Kleyersburg <pitkley@googlemail.com>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+//! Normalizing the rules of the DFW chains, to compare them with the processed rules.
+//!
+//! Both the output of `iptables-save` and the processed rules (in `iptables-restore` format) are
+//! reduced to the rules of the `DFWRS_*` chains and the rules jumping to them, normalized to
+//! `-t <table> -A <chain> <rule>` lines. `iptables-save` adds the prefix length of single addresses
+//! and the match-module of the protocol (`-p tcp -m tcp`), which are dropped.
+//!
+//! Since the `DFWRS_*` chains only hold rules of DFW, the position of a rule within its chain is
+//! the rule number `iptables` uses, which allows deleting and inserting single rules when only the
+//! changes to the rules applied previously are applied.
+
+use crate::diff::chain_delta;
+use std::collections::BTreeMap;
+
+/// Tables DFW applies its rules to.
+const TABLES: &[&str] = &["filter", "nat"];
+pub(super) const CHAIN_PREFIX: &str = "DFWRS_";
+
+/// Rule of a DFW chain, or a rule jumping to a DFW chain.
+#[derive(Debug, Clone)]
+pub(super) struct ChainRule {
+    table: String,
+    chain: String,
+    /// The rule as written by DFW or listed by `iptables-save`, without the chain.
+    rule: String,
+    /// The normalized rule, including the chain.
+    normalized: String,
+}
+
+/// Retain and normalize the rules of the DFW chains from `iptables-save` or `iptables-restore`
+/// formatted input.
+pub(super) fn normalize_rules(input: &str) -> Vec<String> {
+    lines(&parse_rules(input))
+}
+
+/// Format the normalized rules as `-t <table> -A <chain> <rule>` lines.
+pub(super) fn lines(rules: &[ChainRule]) -> Vec<String> {
+    rules
+        .iter()
+        .map(|rule| format!("-t {} -A {}", rule.table, rule.normalized))
+        .collect()
+}
+
+/// Retain the rules of the DFW chains from `iptables-save` or `iptables-restore` formatted input.
+pub(super) fn parse_rules(input: &str) -> Vec<ChainRule> {
+    let mut table = None;
+    let mut rules = Vec::new();
+
+    for line in input.lines().map(str::trim) {
+        if let Some(name) = line.strip_prefix('*') {
+            table = Some(name);
+            continue;
+        }
+
+        let (table, rule) = match (table, line.strip_prefix("-A ")) {
+            (Some(table), Some(rule)) => (table, rule),
+            _ => continue,
+        };
+        let is_dfw_rule = rule.starts_with(CHAIN_PREFIX)
+            || rule
+                .split_whitespace()
+                .skip_while(|&word| word != "-j")
+                .nth(1)
+                .is_some_and(|target| target.starts_with(CHAIN_PREFIX));
+        if is_dfw_rule {
+            let (chain, spec) = rule.split_once(' ').unwrap_or((rule, ""));
+            rules.push(ChainRule {
+                table: table.to_owned(),
+                chain: chain.to_owned(),
+                rule: spec.to_owned(),
+                normalized: normalize(rule),
+            });
+        }
+    }
+
+    rules
+}
+
+fn normalized_by_chain(rules: &[ChainRule]) -> BTreeMap<(&str, &str), Vec<&str>> {
+    let mut chains: BTreeMap<(&str, &str), Vec<&str>> = BTreeMap::new();
+    for rule in rules {
+        chains
+            .entry((&rule.table, &rule.chain))
+            .or_default()
+            .push(&rule.normalized);
+    }
+    chains
+}
+
+/// Check if both contain the same (normalized) rules, in the same order within every chain.
+pub(super) fn rules_match(rules: &[ChainRule], other: &[ChainRule]) -> bool {
+    normalized_by_chain(rules) == normalized_by_chain(other)
+}
+
+/// Build the `iptables-restore --noflush` input transforming the rules currently applied to the
+/// DFW chains into the expected rules, deleting and inserting rules by their rule number.
+///
+/// Returns `None` if the rules can't be transformed incrementally, e.g. because the rules jumping
+/// to the DFW chains changed or rules are added to a chain that currently holds no rules (and thus
+/// might not exist), in which case all rules have to be applied.
+pub(super) fn delta_script(current: &[ChainRule], expected: &[ChainRule]) -> Option<Vec<String>> {
+    #[allow(clippy::type_complexity)]
+    let mut chains: BTreeMap<(&str, &str), (Vec<&ChainRule>, Vec<&ChainRule>)> = BTreeMap::new();
+    for rule in current {
+        chains
+            .entry((&rule.table, &rule.chain))
+            .or_default()
+            .0
+            .push(rule);
+    }
+    for rule in expected {
+        chains
+            .entry((&rule.table, &rule.chain))
+            .or_default()
+            .1
+            .push(rule);
+    }
+
+    let mut tables: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    for ((table, chain), (current_rules, expected_rules)) in chains {
+        let delta = chain_delta(
+            &current_rules
+                .iter()
+                .map(|rule| &rule.normalized)
+                .collect::<Vec<_>>(),
+            &expected_rules
+                .iter()
+                .map(|rule| &rule.normalized)
+                .collect::<Vec<_>>(),
+        )?;
+        if delta.removed.is_empty() && delta.added.is_empty() {
+            continue;
+        }
+        if !chain.starts_with(CHAIN_PREFIX) || current_rules.is_empty() {
+            return None;
+        }
+
+        let commands = tables.entry(table).or_default();
+        // Rule numbers start at one, rules are deleted starting with the last rule to retain the
+        // numbers of the rules yet to be deleted.
+        for index in delta.removed.iter().rev() {
+            commands.push(format!("-D {} {}", chain, index + 1));
+        }
+        // Once the preceding rules are added, an added rule is located at its index within the
+        // expected rules.
+        for (index, before) in delta.added {
+            let rule = &expected_rules[index].rule;
+            commands.push(match before {
+                Some(_) => format!("-I {} {} {}", chain, index + 1, rule),
+                None => format!("-A {} {}", chain, rule),
+            });
+        }
+    }
+
+    Some(
+        tables
+            .into_iter()
+            .flat_map(|(table, commands)| {
+                std::iter::once(format!("*{}", table))
+                    .chain(commands)
+                    .chain(std::iter::once("COMMIT".to_owned()))
+            })
+            .collect(),
+    )
+}
+
+fn normalize(rule: &str) -> String {
+    let words: Vec<&str> = rule.split_whitespace().collect();
+    let mut normalized: Vec<String> = Vec::with_capacity(words.len());
+    let mut protocol = None;
+
+    let mut index = 0;
+    while index < words.len() {
+        let word = words[index];
+        match (word, words.get(index + 1)) {
+            ("-p", Some(&value)) => protocol = Some(value),
+            // `iptables-save` lists `-p tcp --dport 443` as `-p tcp -m tcp --dport 443`
+            ("-m", Some(&module)) if Some(module) == protocol => {
+                index += 2;
+                continue;
+            }
+            _ => {}
+        }
+
+        let host_prefix = if word.contains(':') { "/128" } else { "/32" };
+        let word = word.strip_suffix(host_prefix).unwrap_or(word);
+        if normalized.last().map(String::as_str) == Some("--state") {
+            let mut states: Vec<&str> = word.split(',').collect();
+            states.sort_unstable();
+            normalized.push(states.join(","));
+        } else {
+            normalized.push(word.to_owned());
+        }
+        index += 1;
+    }
+
+    normalized.join(" ")
+}
+
+/// Retain the tables DFW applies its rules to from the output of `iptables-save`, which restores
+/// the tables as they were saved when passed to `iptables-restore`.
+pub(super) fn snapshot_tables(input: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut retain = false;
+    for line in input.lines().map(str::trim) {
+        if let Some(table) = line.strip_prefix('*') {
+            retain = TABLES.contains(&table);
+        }
+        if retain && !line.is_empty() && !line.starts_with('#') {
+            lines.push(line.to_owned());
+        }
+        if line == "COMMIT" {
+            retain = false;
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn normalize_saved_rules() {
+        let saved = "\
+*filter
+:INPUT ACCEPT [0:0]
+:FORWARD DROP [0:0]
+:DFWRS_FORWARD - [0:0]
+-A FORWARD -j DFWRS_FORWARD
+-A FORWARD -j DOCKER-USER
+-A DFWRS_FORWARD -m state --state RELATED,ESTABLISHED -j ACCEPT
+-A DFWRS_FORWARD -s 172.20.0.2/32 -d 172.20.0.3/32 -i br-backend -o br-backend -j ACCEPT
+-A DFWRS_FORWARD -d 172.20.0.2/32 -i eni -o br-backend -p tcp -m tcp --dport 443 -j ACCEPT
+COMMIT
+*nat
+-A DOCKER -i docker0 -j RETURN
+-A DFWRS_PREROUTING -i eni -p tcp -m tcp --dport 443 -j DNAT --to-destination 172.20.0.2:443
+COMMIT
+";
+        let restore = "\
+*filter
+:DFWRS_FORWARD - [0:0]
+:FORWARD - [0:0]
+-F DFWRS_FORWARD
+-A DFWRS_FORWARD -m state --state ESTABLISHED,RELATED -j ACCEPT
+-A DFWRS_FORWARD -s 172.20.0.2 -d 172.20.0.3 -i br-backend -o br-backend -j ACCEPT
+-A DFWRS_FORWARD -d 172.20.0.2 -i eni -o br-backend -p tcp --dport 443 -j ACCEPT
+-A FORWARD -j DFWRS_FORWARD
+COMMIT
+*nat
+-A DFWRS_PREROUTING -i eni -p tcp --dport 443 -j DNAT --to-destination 172.20.0.2:443
+COMMIT
+";
+
+        let mut saved = normalize_rules(saved);
+        let mut restore = normalize_rules(restore);
+        assert_eq!(5, saved.len());
+        assert_eq!(
+            "-t nat -A DFWRS_PREROUTING -i eni -p tcp --dport 443 -j DNAT --to-destination 172.20.0.2:443",
+            saved[4]
+        );
+        saved.sort();
+        restore.sort();
+        assert_eq!(saved, restore);
+    }
+
+    #[test]
+    fn delta_script_uses_rule_numbers() {
+        let saved = "\
+*filter
+:DFWRS_FORWARD - [0:0]
+-A FORWARD -j DFWRS_FORWARD
+-A DFWRS_FORWARD -m state --state RELATED,ESTABLISHED -j ACCEPT
+-A DFWRS_FORWARD -s 172.20.0.2/32 -d 172.20.0.3/32 -j ACCEPT
+-A DFWRS_FORWARD -s 172.20.0.2/32 -d 172.20.0.4/32 -j ACCEPT
+-A DFWRS_FORWARD -j DROP
+COMMIT
+";
+        let restore = "\
+*filter
+-A DFWRS_FORWARD -m state --state ESTABLISHED,RELATED -j ACCEPT
+-A DFWRS_FORWARD -s 172.20.0.5 -d 172.20.0.3 -j ACCEPT
+-A DFWRS_FORWARD -j DROP
+-A DFWRS_FORWARD -i eni -j ACCEPT
+-A FORWARD -j DFWRS_FORWARD
+COMMIT
+";
+        let current = parse_rules(saved);
+        let expected = parse_rules(restore);
+        assert!(!rules_match(&current, &expected));
+        assert_eq!(
+            Some(vec![
+                "*filter".to_owned(),
+                "-D DFWRS_FORWARD 3".to_owned(),
+                "-D DFWRS_FORWARD 2".to_owned(),
+                "-I DFWRS_FORWARD 2 -s 172.20.0.5 -d 172.20.0.3 -j ACCEPT".to_owned(),
+                "-A DFWRS_FORWARD -i eni -j ACCEPT".to_owned(),
+                "COMMIT".to_owned(),
+            ]),
+            delta_script(&current, &expected),
+        );
+        assert_eq!(Some(Vec::new()), delta_script(&current, &current));
+
+        // Rules jumping to the DFW chains are not changed incrementally.
+        let expected = parse_rules(&restore.replace("-A FORWARD", "-A INPUT"));
+        assert_eq!(None, delta_script(&current, &expected));
+    }
+
+    #[test]
+    fn snapshot_retains_dfw_tables() {
+        let saved = "\
+# Generated by iptables-save v1.8.7 on Sun Jan  7 12:00:00 2024
+*mangle
+:PREROUTING ACCEPT [0:0]
+COMMIT
+*filter
+:INPUT ACCEPT [12:3456]
+:DFWRS_INPUT - [0:0]
+-A INPUT -j DFWRS_INPUT
+COMMIT
+# Completed on Sun Jan  7 12:00:00 2024
+";
+        assert_eq!(
+            vec![
+                "*filter",
+                ":INPUT ACCEPT [12:3456]",
+                ":DFWRS_INPUT - [0:0]",
+                "-A INPUT -j DFWRS_INPUT",
+                "COMMIT",
+            ],
+            snapshot_tables(saved),
+        );
+    }
+}
@@ -9,9 +9,11 @@
 
 //! This module implements the iptables backend for DFW.
 
-use crate::{errors::*, FirewallBackend, ProcessContext};
+use crate::{
+    diff::RulesetDiff, errors::*, process::AppliedRules, FirewallBackend, ProcessContext, Ruleset,
+};
 use failure::format_err;
-use slog::info;
+use slog::{debug, info, o, warn, Logger};
 use std::{
     collections::BTreeMap,
     io::{BufWriter, Write},
@@ -20,6 +22,7 @@ use std::{
 };
 use strum::EnumDiscriminants;
 
+mod diff;
 mod process;
 mod rule;
 pub mod types;
@@ -31,6 +34,8 @@ const DFW_PREROUTING_CHAIN: &str = "DFWRS_PREROUTING";
 
 const COMMAND_IPTABLES_RESTORE: &str = "iptables-restore";
 const COMMAND_IP6TABLES_RESTORE: &str = "ip6tables-restore";
+const COMMAND_IPTABLES_SAVE: &str = "iptables-save";
+const COMMAND_IP6TABLES_SAVE: &str = "ip6tables-save";
 
 type Table = String;
 type Chain = String;
@@ -47,32 +52,178 @@ impl FirewallBackend for Iptables {
     fn apply(rules: Vec<Self::Rule>, ctx: &ProcessContext<Self>) -> Result<()> {
         if ctx.dry_run {
             info!(ctx.logger, "Performing dry-run, will not update any rules");
+            ctx.dry_run_output.write(&Self::render(rules)?)?;
         } else {
+            // If applying the IPv6 rules fails, the IPv4 rules have been applied already. Both are
+            // restored to the snapshot in that case.
+            let snapshot = ctx.snapshot()?;
             info!(
                 ctx.logger,
                 "Applying IPv4 rules (using {})", COMMAND_IPTABLES_RESTORE
             );
-            Self::restore(IptablesRuleDiscriminants::V4, rules.clone())?;
+            Self::restore(
+                IptablesRuleDiscriminants::V4,
+                &Self::get_rules(rules.clone(), IptablesRuleDiscriminants::V4),
+                false,
+            )
+            .map_err(|error| ctx.rollback(COMMAND_IPTABLES_RESTORE, error, &snapshot))?;
             info!(
                 ctx.logger,
                 "Applying IPv6 rules (using {})", COMMAND_IP6TABLES_RESTORE
             );
-            Self::restore(IptablesRuleDiscriminants::V6, rules)?;
+            Self::restore(
+                IptablesRuleDiscriminants::V6,
+                &Self::get_rules(rules, IptablesRuleDiscriminants::V6),
+                false,
+            )
+            .map_err(|error| ctx.rollback(COMMAND_IP6TABLES_RESTORE, error, &snapshot))?;
+        }
+        Ok(())
+    }
+
+    fn apply_incremental(
+        rules: Vec<Self::Rule>,
+        applied: &mut AppliedRules,
+        ctx: &ProcessContext<Self>,
+    ) -> Result<()> {
+        let rulesets = Self::render(rules)?;
+        let snapshot = ctx.snapshot()?;
+        for (index, rule_discriminant) in
+            [IptablesRuleDiscriminants::V4, IptablesRuleDiscriminants::V6]
+                .into_iter()
+                .enumerate()
+        {
+            let command = &rulesets[index].command;
+            let lines = &rulesets[index].lines;
+            let script = match applied
+                .rulesets()
+                .and_then(|previous| previous.get(index))
+                .map(|previous| &previous.lines)
+            {
+                // Changes outside of the DFW chains, e.g. to the initialization rules, require
+                // all rules to be applied.
+                Some(previous) if other_lines(previous).eq(other_lines(lines)) => {
+                    debug!(ctx.logger, "Reading current rules";
+                           o!("rule_discriminant" => format!("{:?}", rule_discriminant)));
+                    let current = diff::parse_rules(&Self::save(rule_discriminant)?);
+                    if diff::rules_match(&current, &diff::parse_rules(&previous.join("\n"))) {
+                        diff::delta_script(&current, &diff::parse_rules(&lines.join("\n")))
+                    } else {
+                        warn!(ctx.logger, "Rules currently applied differ from the rules applied \
+                                           previously, applying all rules";
+                              o!("rule_discriminant" => format!("{:?}", rule_discriminant)));
+                        None
+                    }
+                }
+                _ => None,
+            };
+
+            match script {
+                Some(script) if script.is_empty() => {
+                    info!(ctx.logger, "Rules are unchanged";
+                          o!("rule_discriminant" => format!("{:?}", rule_discriminant)));
+                }
+                Some(script) => {
+                    info!(ctx.logger, "Applying changed rules (using {} --noflush)", command;
+                          o!("rule_discriminant" => format!("{:?}", rule_discriminant)));
+                    Self::restore(rule_discriminant, &script, true)
+                        .map_err(|error| ctx.rollback(command, error, &snapshot))?;
+                }
+                None => {
+                    info!(ctx.logger, "Applying all rules (using {})", command;
+                          o!("rule_discriminant" => format!("{:?}", rule_discriminant)));
+                    Self::restore(rule_discriminant, lines, false)
+                        .map_err(|error| ctx.rollback(command, error, &snapshot))?;
+                }
+            }
         }
+
+        applied.set(rulesets);
         Ok(())
     }
+
+    fn render(rules: Vec<Self::Rule>) -> Result<Vec<Ruleset>> {
+        Ok(vec![
+            Ruleset {
+                command: COMMAND_IPTABLES_RESTORE.to_owned(),
+                lines: Self::get_rules(rules.clone(), IptablesRuleDiscriminants::V4),
+            },
+            Ruleset {
+                command: COMMAND_IP6TABLES_RESTORE.to_owned(),
+                lines: Self::get_rules(rules, IptablesRuleDiscriminants::V6),
+            },
+        ])
+    }
+
+    fn diff(rules: Vec<Self::Rule>, ctx: &ProcessContext<Self>) -> Result<Vec<RulesetDiff>> {
+        [
+            (IptablesRuleDiscriminants::V4, COMMAND_IPTABLES_RESTORE),
+            (IptablesRuleDiscriminants::V6, COMMAND_IP6TABLES_RESTORE),
+        ]
+        .iter()
+        .map(|&(rule_discriminant, command)| {
+            debug!(ctx.logger, "Reading current rules";
+                   o!("rule_discriminant" => format!("{:?}", rule_discriminant)));
+            Ok(RulesetDiff::new(
+                command,
+                &diff::normalize_rules(&Self::save(rule_discriminant)?),
+                &diff::normalize_rules(
+                    &Self::get_rules(rules.clone(), rule_discriminant).join("\n"),
+                ),
+            ))
+        })
+        .collect()
+    }
+
+    fn snapshot(_logger: &Logger) -> Result<Vec<Ruleset>> {
+        Ok(vec![
+            Ruleset {
+                command: COMMAND_IPTABLES_RESTORE.to_owned(),
+                lines: diff::snapshot_tables(&Self::save(IptablesRuleDiscriminants::V4)?),
+            },
+            Ruleset {
+                command: COMMAND_IP6TABLES_RESTORE.to_owned(),
+                lines: diff::snapshot_tables(&Self::save(IptablesRuleDiscriminants::V6)?),
+            },
+        ])
+    }
+
+    fn restore_snapshot(snapshot: &[Ruleset], logger: &Logger) -> Result<()> {
+        // Both IP versions are restored, even if restoring one of them fails.
+        let mut errors = Vec::new();
+        for (ruleset, rule_discriminant) in snapshot
+            .iter()
+            .zip([IptablesRuleDiscriminants::V4, IptablesRuleDiscriminants::V6])
+        {
+            if ruleset.lines.is_empty() {
+                continue;
+            }
+            info!(logger, "Restoring rules (using {})", ruleset.command);
+            if let Err(error) = Self::restore(rule_discriminant, &ruleset.lines, false) {
+                errors.push(error.to_string());
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(format_err!("{}", errors.join("\n")))
+        }
+    }
 }
 
 impl Iptables {
     fn restore(
         rule_discriminant: IptablesRuleDiscriminants,
-        rules: Vec<IptablesRule>,
+        lines: &[String],
+        noflush: bool,
     ) -> Result<()> {
         let command = match rule_discriminant {
             IptablesRuleDiscriminants::V4 => COMMAND_IPTABLES_RESTORE,
             IptablesRuleDiscriminants::V6 => COMMAND_IP6TABLES_RESTORE,
         };
         let mut process = Command::new(command)
+            .args(if noflush { &["--noflush"][..] } else { &[] })
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
@@ -80,7 +231,11 @@ impl Iptables {
 
         // Get process stdin, write format as expected by iptables-restore
         match process.stdin.as_mut() {
-            Some(s) => Self::write_rules(rules, rule_discriminant, s)?,
+            Some(s) => {
+                for line in lines {
+                    writeln!(s, "{}", line)?;
+                }
+            }
             None => return Err(format_err!("cannot get stdin of {}", command)),
         }
 
@@ -97,6 +252,24 @@ impl Iptables {
         }
     }
 
+    /// Retrieve the rules currently applied, in `iptables-save` format.
+    fn save(rule_discriminant: IptablesRuleDiscriminants) -> Result<String> {
+        let command = match rule_discriminant {
+            IptablesRuleDiscriminants::V4 => COMMAND_IPTABLES_SAVE,
+            IptablesRuleDiscriminants::V6 => COMMAND_IP6TABLES_SAVE,
+        };
+        let output = Command::new(command).output()?;
+        if output.status.success() {
+            Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+        } else {
+            Err(format_err!(
+                "{} failed: '{}'",
+                command,
+                str::from_utf8(&output.stderr).unwrap_or("").trim(),
+            ))
+        }
+    }
+
     /// Retrieve the current text that would be passed to `iptables-restore` as a vector of lines.
     pub fn get_rules(
         rules: Vec<IptablesRule>,
@@ -179,6 +352,13 @@ impl Iptables {
     }
 }
 
+/// Lines of `iptables-restore` input that aren't rules of the DFW chains.
+fn other_lines(lines: &[String]) -> impl Iterator<Item = &String> {
+    lines
+        .iter()
+        .filter(|line| !line.starts_with(&format!("-A {}", diff::CHAIN_PREFIX)))
+}
+
 /// Rule representation for iptables firewall backend.
 #[derive(Debug, Clone, EnumDiscriminants)]
 pub enum IptablesRule {
@@ -13,7 +13,8 @@ use super::{
     DFW_INPUT_CHAIN, DFW_POSTROUTING_CHAIN, DFW_PREROUTING_CHAIN,
 };
 use crate::{errors::*, process::*, types::*, FirewallBackend};
-use failure::{format_err, ResultExt};
+use bollard::models::Network;
+use failure::{bail, format_err, ResultExt};
 use slog::{debug, info, o, trace};
 use time::format_description::well_known::Rfc3339;
 
@@ -69,6 +70,13 @@ impl Process<Iptables> for DFW<Iptables> {
                 &format!("-j {}", DFW_INPUT_CHAIN),
             ),
             set_policy(IptablesRuleDiscriminants::V4, "filter", "INPUT", "-"),
+            append_rule(
+                IptablesRuleDiscriminants::V6,
+                "filter",
+                "INPUT",
+                &format!("-j {}", DFW_INPUT_CHAIN),
+            ),
+            set_policy(IptablesRuleDiscriminants::V6, "filter", "INPUT", "-"),
             append_rule(
                 IptablesRuleDiscriminants::V4,
                 "filter",
@@ -100,6 +108,13 @@ impl Process<Iptables> for DFW<Iptables> {
                 &format!("-j {}", DFW_FORWARD_CHAIN),
             ),
             set_policy(IptablesRuleDiscriminants::V4, "filter", "FORWARD", "-"),
+            append_rule(
+                IptablesRuleDiscriminants::V6,
+                "filter",
+                "FORWARD",
+                &format!("-j {}", DFW_FORWARD_CHAIN),
+            ),
+            set_policy(IptablesRuleDiscriminants::V6, "filter", "FORWARD", "-"),
             append_rule(
                 IptablesRuleDiscriminants::V4,
                 "nat",
@@ -107,6 +122,13 @@ impl Process<Iptables> for DFW<Iptables> {
                 &format!("-j {}", DFW_PREROUTING_CHAIN),
             ),
             set_policy(IptablesRuleDiscriminants::V4, "nat", "PREROUTING", "-"),
+            append_rule(
+                IptablesRuleDiscriminants::V6,
+                "nat",
+                "PREROUTING",
+                &format!("-j {}", DFW_PREROUTING_CHAIN),
+            ),
+            set_policy(IptablesRuleDiscriminants::V6, "nat", "PREROUTING", "-"),
             append_rule(
                 IptablesRuleDiscriminants::V4,
                 "nat",
@@ -117,10 +139,16 @@ impl Process<Iptables> for DFW<Iptables> {
         ];
         for mut sub_rules in vec![
             self.backend_defaults.process(ctx)?,
-            self.container_to_container.process(ctx)?,
-            self.container_to_wider_world.process(ctx)?,
+            ctx.label_rules
+                .merge_container_to_container(&self.container_to_container)
+                .process(ctx)?,
+            ctx.label_rules
+                .merge_container_to_wider_world(&self.container_to_wider_world)
+                .process(ctx)?,
             self.container_to_host.process(ctx)?,
-            self.wider_world_to_container.process(ctx)?,
+            ctx.label_rules
+                .merge_wider_world_to_container(&self.wider_world_to_container)
+                .process(ctx)?,
             self.container_dnat.process(ctx)?,
             self.global_defaults.process(ctx)?,
         ]
@@ -254,25 +282,31 @@ impl Process<Iptables> for ContainerToContainer {
 
         if let Some(same_network_verdict) = self.same_network_verdict {
             for network in ctx.network_map.values() {
-                let network_id = network.id.as_ref().expect("Docker network ID missing");
-                let bridge_name = get_bridge_name(network_id)?;
-                trace!(ctx.logger, "Got bridge name";
+                // Containers on overlay networks are covered through `docker_gwbridge`.
+                if !has_host_interface(network) {
+                    continue;
+                }
+                let interface = ctx.get_network_interface(network)?;
+                trace!(ctx.logger, "Got network interface";
                        o!("network_name" => &network.name,
-                          "bridge_name" => &bridge_name));
-
-                let ipt_rule = Rule::new("filter", DFW_FORWARD_CHAIN)
-                    .in_interface(&bridge_name)
-                    .out_interface(&bridge_name)
-                    .jump(&same_network_verdict.to_string().to_uppercase())
-                    .build()?;
-
-                debug!(ctx.logger, "Add forward rule for same network verdict for bridge";
-                       o!("part" => "container_to_container",
-                          "bridge_name" => bridge_name,
-                          "same_network_verdict" => same_network_verdict,
-                          "rule" => &ipt_rule));
-
-                rules.push(append_built_rule(IptablesRuleDiscriminants::V4, &ipt_rule));
+                          "interface" => format!("{:?}", interface)));
+
+                let mut ipt_rule = Rule::new("filter", DFW_FORWARD_CHAIN);
+                ipt_rule
+                    .in_interface(interface.name())
+                    .out_interface(interface.name())
+                    .jump(&same_network_verdict.to_string().to_uppercase());
+
+                for ipt_rule in restrict_to_subnets(ipt_rule, network, &interface, true, true) {
+                    let ipt_rule = ipt_rule.build()?;
+                    debug!(ctx.logger, "Add forward rule for same network verdict for bridge";
+                           o!("part" => "container_to_container",
+                              "bridge_name" => interface.name(),
+                              "same_network_verdict" => same_network_verdict,
+                              "rule" => &ipt_rule));
+
+                    rules.push(append_built_rule(IptablesRuleDiscriminants::V4, &ipt_rule));
+                }
             }
         }
 
@@ -293,6 +327,18 @@ impl Process<Iptables> for ContainerToContainerRule {
         trace!(ctx.logger, "Processing rule";
                o!("part" => "container_to_container_rule",
                   "rule" => format!("{:?}", self)));
+        let src_selector = resolve_selector(
+            self.src_selector.as_ref(),
+            self.src_service.as_ref(),
+            self.src_swarm_service.as_ref(),
+        )?;
+        ensure_container_or_selector(self.src_container.as_ref(), src_selector.as_ref())?;
+        let dst_selector = resolve_selector(
+            self.dst_selector.as_ref(),
+            self.dst_service.as_ref(),
+            self.dst_swarm_service.as_ref(),
+        )?;
+        ensure_container_or_selector(self.dst_container.as_ref(), dst_selector.as_ref())?;
         let mut ipt_rule = Rule::new("filter", DFW_FORWARD_CHAIN);
 
         let network = match ctx.network_map.get(&self.network) {
@@ -304,74 +350,39 @@ impl Process<Iptables> for ContainerToContainerRule {
                   "network" => format!("{:?}", network)));
 
         let network_id = network.id.as_ref().expect("Docker network ID missing");
-        let bridge_name = get_bridge_name(network_id)?;
-        trace!(ctx.logger, "Got bridge name";
+        let interface = ctx.get_network_interface(network)?;
+        trace!(ctx.logger, "Got network interface";
                o!("network_name" => &network.name,
-                  "bridge_name" => &bridge_name));
+                  "interface" => format!("{:?}", interface)));
 
         ipt_rule
-            .in_interface(&bridge_name)
-            .out_interface(&bridge_name);
-
-        if let Some(ref src_container) = self.src_container {
-            let src_network = match get_network_for_container(
-                ctx.docker,
-                &ctx.container_map,
-                src_container,
-                network_id,
-            )? {
-                Some(src_network) => src_network,
-                None => return Ok(None),
-            };
-            trace!(ctx.logger, "Got source network";
-                   o!("network_name" => &network.name,
-                      "src_network" => format!("{:?}", src_network)));
-
-            let bridge_name = get_bridge_name(network_id)?;
-            trace!(ctx.logger, "Got bridge name";
-                   o!("network_name" => &network.name,
-                      "bridge_name" => &bridge_name));
-
-            ipt_rule
-                .in_interface(&bridge_name)
-                .out_interface(&bridge_name)
-                .source(
-                    src_network
-                        .ipv4_address
-                        .expect("IPv4 address for container missing")
-                        .split('/')
-                        .next()
-                        .ok_or_else(|| format_err!("IPv4 address is empty"))?,
-                );
-        }
-
-        if let Some(ref dst_container) = self.dst_container {
-            let dst_network = match get_network_for_container(
-                ctx.docker,
-                &ctx.container_map,
-                dst_container,
-                network_id,
-            )? {
-                Some(dst_network) => dst_network,
-                None => return Ok(None),
-            };
-            trace!(ctx.logger, "Got destination network";
-                   o!("network_name" => &network.name,
-                      "dst_network" => format!("{:?}", dst_network)));
-
-            let bridge_name = get_bridge_name(network_id)?;
-            trace!(ctx.logger, "Got bridge name";
-                   o!("network_name" => &network.name,
-                      "bridge_name" => &bridge_name));
-
-            ipt_rule.out_interface(&bridge_name).destination(
-                dst_network
-                    .ipv4_address
-                    .expect("IPv4 address for container missing")
-                    .split('/')
-                    .next()
-                    .ok_or_else(|| format_err!("IPv4 address is empty"))?,
-            );
+            .in_interface(interface.name())
+            .out_interface(interface.name());
+
+        let src_networks = get_network_containers(
+            ctx.container_runtime,
+            &ctx.container_map,
+            self.src_container.as_deref(),
+            src_selector.as_ref(),
+            network_id,
+        )?;
+        trace!(ctx.logger, "Got source networks";
+               o!("network_name" => &network.name,
+                  "src_networks" => format!("{:?}", src_networks)));
+        let dst_networks = get_network_containers(
+            ctx.container_runtime,
+            &ctx.container_map,
+            self.dst_container.as_deref(),
+            dst_selector.as_ref(),
+            network_id,
+        )?;
+        trace!(ctx.logger, "Got destination networks";
+               o!("network_name" => &network.name,
+                  "dst_networks" => format!("{:?}", dst_networks)));
+        if src_networks.as_ref().is_some_and(Vec::is_empty)
+            || dst_networks.as_ref().is_some_and(Vec::is_empty)
+        {
+            return Ok(None);
         }
 
         if let Some(ref filter) = self.matches {
@@ -381,15 +392,48 @@ impl Process<Iptables> for ContainerToContainerRule {
         // Set jump
         ipt_rule.jump(&self.verdict.to_string().to_uppercase());
 
-        let rule = ipt_rule.build()?;
-        debug!(ctx.logger, "Add forward rule";
-               o!("part" => "container_to_container",
-                  "rule" => &rule.rule));
+        let mut rules = Vec::new();
+        for &ip_version in single_ip_versions(self.typed_matches().ip_version()) {
+            let source_addresses =
+                get_rule_addresses(network, &interface, src_networks.as_deref(), ip_version);
+            let destination_addresses =
+                get_rule_addresses(network, &interface, dst_networks.as_deref(), ip_version);
+            // Skip the IP version if none of the containers have an address of it.
+            if source_addresses.as_ref().is_some_and(Vec::is_empty)
+                || destination_addresses.as_ref().is_some_and(Vec::is_empty)
+            {
+                continue;
+            }
 
-        Ok(Some(vec![append_built_rule(
-            IptablesRuleDiscriminants::V4,
-            &rule,
-        )]))
+            // Selectors can match multiple containers, generate a rule for every combination of
+            // them.
+            let ipt_rules = expand_rules(
+                vec![ipt_rule.clone()],
+                &source_addresses.unwrap_or_default(),
+                |ipt_rule, address| {
+                    ipt_rule.source(address);
+                },
+            );
+            let ipt_rules = expand_rules(
+                ipt_rules,
+                &destination_addresses.unwrap_or_default(),
+                |ipt_rule, address| {
+                    ipt_rule.destination(address);
+                },
+            );
+            for (rule_discriminant, ipt_rule) in
+                apply_typed_matches(ipt_rules, &self.typed_matches(), ip_version)?
+            {
+                let rule = ipt_rule.build()?;
+                debug!(ctx.logger, "Add forward rule";
+                       o!("part" => "container_to_container",
+                          "rule" => &rule.rule));
+
+                rules.push(append_built_rule(rule_discriminant, &rule));
+            }
+        }
+
+        Ok(Some(rules))
     }
 }
 
@@ -415,25 +459,32 @@ impl Process<Iptables> for ContainerToWiderWorld {
                           "external_network_interface" => external_network_interface,
                           "default_policy" => &self.default_policy));
                 for network in ctx.network_map.values() {
-                    let network_id = network.id.as_ref().expect("Docker network ID missing");
-                    let bridge_name = get_bridge_name(network_id)?;
-                    trace!(ctx.logger, "Got bridge name";
+                    // Containers on overlay networks are covered through `docker_gwbridge`.
+                    if !has_host_interface(network) {
+                        continue;
+                    }
+                    let interface = ctx.get_network_interface(network)?;
+                    trace!(ctx.logger, "Got network interface";
                            o!("network_name" => &network.name,
-                              "bridge_name" => &bridge_name));
+                              "interface" => format!("{:?}", interface)));
 
-                    let rule = Rule::new("filter", DFW_FORWARD_CHAIN)
-                        .in_interface(&bridge_name)
+                    let mut ipt_rule = Rule::new("filter", DFW_FORWARD_CHAIN);
+                    ipt_rule
+                        .in_interface(interface.name())
                         .out_interface(external_network_interface)
-                        .jump(&self.default_policy.to_string().to_uppercase())
-                        .build()?;
+                        .jump(&self.default_policy.to_string().to_uppercase());
 
-                    debug!(ctx.logger, "Add forward rule for default policy";
-                           o!("part" => "container_to_wider_world",
-                              "external_network_interface" => external_network_interface,
-                              "default_policy" => &self.default_policy,
-                              "rule" => &rule));
+                    for ipt_rule in restrict_to_subnets(ipt_rule, network, &interface, true, false)
+                    {
+                        let rule = ipt_rule.build()?;
+                        debug!(ctx.logger, "Add forward rule for default policy";
+                               o!("part" => "container_to_wider_world",
+                                  "external_network_interface" => external_network_interface,
+                                  "default_policy" => &self.default_policy,
+                                  "rule" => &rule));
 
-                    rules.push(append_built_rule(IptablesRuleDiscriminants::V4, &rule));
+                        rules.push(append_built_rule(IptablesRuleDiscriminants::V4, &rule));
+                    }
                 }
             }
         }
@@ -448,14 +499,30 @@ impl Process<Iptables> for ContainerToWiderWorldRule {
                o!("part" => "container_to_wider_world_rule",
                   "rule" => format!("{:?}", self)));
 
+        let src_selector = resolve_selector(
+            self.src_selector.as_ref(),
+            self.src_service.as_ref(),
+            self.src_swarm_service.as_ref(),
+        )?;
+        ensure_container_or_selector(self.src_container.as_ref(), src_selector.as_ref())?;
+        if self.network.is_none() && src_selector.is_some() {
+            bail!("the source selector requires the network to be specified");
+        }
         let mut ipt_rule = Rule::new("filter", DFW_FORWARD_CHAIN);
 
+        let mut source_addresses = Vec::new();
         if let Some(ref network) = self.network {
             if let Some(network) = ctx.network_map.get(network) {
+                let network = ctx.get_host_network(network)?;
                 let network_id = network.id.as_ref().expect("Docker network ID missing");
+                let interface = ctx.get_network_interface(network)?;
+                trace!(ctx.logger, "Got network interface";
+                       o!("network_name" => &network.name,
+                          "interface" => format!("{:?}", interface)));
+
                 if let Some(ref src_container) = self.src_container {
                     if let Some(src_network) = get_network_for_container(
-                        ctx.docker,
+                        ctx.container_runtime,
                         &ctx.container_map,
                         src_container,
                         network_id,
@@ -464,27 +531,27 @@ impl Process<Iptables> for ContainerToWiderWorldRule {
                                o!("network_name" => &network.name,
                                   "src_network" => format!("{:?}", src_network)));
 
-                        let bridge_name = get_bridge_name(network_id)?;
-                        trace!(ctx.logger, "Got bridge name";
-                               o!("network_name" => &network.name,
-                                  "bridge_name" => &bridge_name));
-
-                        ipt_rule.in_interface(&bridge_name).source(
-                            src_network
-                                .ipv4_address
-                                .expect("IPv4 address for container missing")
-                                .split('/')
-                                .next()
-                                .ok_or_else(|| format_err!("IPv4 address is empty"))?,
-                        );
+                        match get_container_ipv4_address_checked(network, &src_network)? {
+                            Some(src_address) => {
+                                ipt_rule.in_interface(interface.name()).source(&src_address);
+                            }
+                            // An IPv6-only container can't be the source of IPv4 traffic.
+                            None => return Ok(None),
+                        }
                     }
+                } else if let Some(ref src_selector) = src_selector {
+                    ipt_rule.in_interface(interface.name());
+
+                    source_addresses =
+                        match ctx.get_selector_source_addresses(network, src_selector)? {
+                            Some(source_addresses) => source_addresses,
+                            None => return Ok(None),
+                        };
                 } else {
-                    let bridge_name = get_bridge_name(network_id)?;
-                    trace!(ctx.logger, "Got bridge name";
-                           o!("network_name" => &network.name,
-                              "bridge_name" => &bridge_name));
-
-                    ipt_rule.in_interface(&bridge_name);
+                    ipt_rule.in_interface(interface.name());
+                    if interface.is_shared() {
+                        source_addresses = get_network_subnets(network, IpVersion::V4);
+                    }
                 }
             }
         }
@@ -516,15 +583,24 @@ impl Process<Iptables> for ContainerToWiderWorldRule {
             ipt_rule.out_interface(primary_external_network_interface);
         }
 
-        let rule = ipt_rule.build()?;
-        debug!(ctx.logger, "Add forward rule";
-               o!("part" => "container_to_wider_world",
-                  "rule" => &rule.rule));
+        let ipt_rules = expand_rules(vec![ipt_rule], &source_addresses, |ipt_rule, address| {
+            ipt_rule.source(address);
+        });
+        let mut rules = Vec::new();
+        for (rule_discriminant, ipt_rule) in apply_typed_matches(
+            ipt_rules,
+            &self.typed_matches(),
+            self.typed_matches().ip_version(),
+        )? {
+            let rule = ipt_rule.build()?;
+            debug!(ctx.logger, "Add forward rule";
+                   o!("part" => "container_to_wider_world",
+                      "rule" => &rule.rule));
 
-        Ok(Some(vec![append_built_rule(
-            IptablesRuleDiscriminants::V4,
-            &rule,
-        )]))
+            rules.push(append_built_rule(rule_discriminant, &rule));
+        }
+
+        Ok(Some(rules))
     }
 }
 
@@ -540,22 +616,28 @@ impl Process<Iptables> for ContainerToHost {
 
         // Default policy
         for network in ctx.network_map.values() {
-            let network_id = network.id.as_ref().expect("Docker network ID missing");
-            let bridge_name = get_bridge_name(network_id)?;
-            trace!(ctx.logger, "Got bridge name";
+            // Containers on overlay networks are covered through `docker_gwbridge`.
+            if !has_host_interface(network) {
+                continue;
+            }
+            let interface = ctx.get_network_interface(network)?;
+            trace!(ctx.logger, "Got network interface";
                    o!("network_name" => &network.name,
-                      "bridge_name" => &bridge_name));
-
-            let rule = Rule::new("filter", DFW_INPUT_CHAIN)
-                .in_interface(&bridge_name)
-                .jump(&self.default_policy.to_string().to_uppercase())
-                .build()?;
-
-            trace!(ctx.logger, "Add input rule for default policy";
-                   o!("part" => "container_to_host",
-                      "default_policy" => self.default_policy,
-                      "rule" => &rule));
-            rules.push(append_built_rule(IptablesRuleDiscriminants::V4, &rule));
+                      "interface" => format!("{:?}", interface)));
+
+            let mut ipt_rule = Rule::new("filter", DFW_INPUT_CHAIN);
+            ipt_rule
+                .in_interface(interface.name())
+                .jump(&self.default_policy.to_string().to_uppercase());
+
+            for ipt_rule in restrict_to_subnets(ipt_rule, network, &interface, true, false) {
+                let rule = ipt_rule.build()?;
+                trace!(ctx.logger, "Add input rule for default policy";
+                       o!("part" => "container_to_host",
+                          "default_policy" => self.default_policy,
+                          "rule" => &rule));
+                rules.push(append_built_rule(IptablesRuleDiscriminants::V4, &rule));
+            }
         }
 
         Ok(Some(rules))
@@ -567,6 +649,12 @@ impl Process<Iptables> for ContainerToHostRule {
         trace!(ctx.logger, "Process rule";
                o!("part" => "container_to_host_rule",
                   "rule" => format!("{:?}", self)));
+        let src_selector = resolve_selector(
+            self.src_selector.as_ref(),
+            self.src_service.as_ref(),
+            self.src_swarm_service.as_ref(),
+        )?;
+        ensure_container_or_selector(self.src_container.as_ref(), src_selector.as_ref())?;
         let mut ipt_rule = Rule::new("filter", DFW_INPUT_CHAIN);
 
         let network = match ctx.network_map.get(&self.network) {
@@ -577,33 +665,27 @@ impl Process<Iptables> for ContainerToHostRule {
                o!("network_name" => &network.name,
                   "network" => format!("{:?}", network)));
 
+        let network = ctx.get_host_network(network)?;
         let network_id = network.id.as_ref().expect("Docker network ID missing");
-        let bridge_name = get_bridge_name(network_id)?;
-        trace!(ctx.logger, "Got bridge name";
+        let interface = ctx.get_network_interface(network)?;
+        trace!(ctx.logger, "Got network interface";
                o!("network_name" => &network.name,
-                  "bridge_name" => &bridge_name));
-
-        ipt_rule.in_interface(&bridge_name);
-
-        if let Some(ref src_container) = self.src_container {
-            if let Some(src_network) = get_network_for_container(
-                ctx.docker,
-                &ctx.container_map,
-                src_container,
-                network_id,
-            )? {
-                trace!(ctx.logger, "Got source network";
-                       o!("network_name" => &network.name,
-                          "src_network" => format!("{:?}", src_network)));
-                ipt_rule.source(
-                    src_network
-                        .ipv4_address
-                        .expect("IPv4 address for container missing")
-                        .split('/')
-                        .next()
-                        .ok_or_else(|| format_err!("IPv4 address is empty"))?,
-                );
-            }
+                  "interface" => format!("{:?}", interface)));
+
+        ipt_rule.in_interface(interface.name());
+
+        let src_networks = get_network_containers(
+            ctx.container_runtime,
+            &ctx.container_map,
+            self.src_container.as_deref(),
+            src_selector.as_ref(),
+            network_id,
+        )?;
+        trace!(ctx.logger, "Got source networks";
+               o!("network_name" => &network.name,
+                  "src_networks" => format!("{:?}", src_networks)));
+        if src_networks.as_ref().is_some_and(Vec::is_empty) {
+            return Ok(None);
         }
 
         if let Some(ref filter) = self.matches {
@@ -612,24 +694,35 @@ impl Process<Iptables> for ContainerToHostRule {
 
         ipt_rule.jump(&self.verdict.to_string().to_uppercase());
 
-        // Try to build the rule without the out_interface defined to see if all of the other
-        // mandatory fields has been populated.
-        debug!(ctx.logger, "Build rule to verify contents";
-               o!("args" => format!("{:?}", ipt_rule)));
-        ipt_rule.build().context(format!(
-            "failed to build rule, maybe the container `{:?}` doesn't exist",
-            self.src_container
-        ))?;
+        let mut rules = Vec::new();
+        for &ip_version in single_ip_versions(self.typed_matches().ip_version()) {
+            let source_addresses =
+                get_rule_addresses(network, &interface, src_networks.as_deref(), ip_version);
+            // Skip the IP version if none of the containers have an address of it.
+            if source_addresses.as_ref().is_some_and(Vec::is_empty) {
+                continue;
+            }
 
-        let rule = ipt_rule.build()?;
-        debug!(ctx.logger, "Add input rule";
-               o!("part" => "container_to_host",
-                  "rule" => &rule));
+            let ipt_rules = expand_rules(
+                vec![ipt_rule.clone()],
+                &source_addresses.unwrap_or_default(),
+                |ipt_rule, address| {
+                    ipt_rule.source(address);
+                },
+            );
+            for (rule_discriminant, ipt_rule) in
+                apply_typed_matches(ipt_rules, &self.typed_matches(), ip_version)?
+            {
+                let rule = ipt_rule.build()?;
+                debug!(ctx.logger, "Add input rule";
+                       o!("part" => "container_to_host",
+                          "rule" => &rule));
 
-        Ok(Some(vec![append_built_rule(
-            IptablesRuleDiscriminants::V4,
-            &rule,
-        )]))
+                rules.push(append_built_rule(rule_discriminant, &rule));
+            }
+        }
+
+        Ok(Some(rules))
     }
 }
 
@@ -657,7 +750,19 @@ impl Process<Iptables> for WiderWorldToContainerRule {
                   "rule" => format!("{:?}", self)));
         let mut rules = Vec::new();
 
-        for expose_port in &self.expose_port {
+        let dst_selector = resolve_selector(
+            self.dst_selector.as_ref(),
+            self.dst_service.as_ref(),
+            self.dst_swarm_service.as_ref(),
+        )?;
+        if ctx
+            .network_map
+            .get(&self.network)
+            .is_some_and(is_ingress_network)
+        {
+            return routing_mesh_rules(self, ctx);
+        }
+        for expose_port in &split_families(&self.expose_port) {
             let mut ipt_forward_rule = Rule::new("filter", DFW_FORWARD_CHAIN);
             let mut ipt_dnat_rule = Rule::new("nat", DFW_PREROUTING_CHAIN);
             let mut ipt6_input_rule = Rule::new("filter", DFW_INPUT_CHAIN);
@@ -670,50 +775,71 @@ impl Process<Iptables> for WiderWorldToContainerRule {
                    o!("network_name" => &network.name,
                       "network" => format!("{:?}", network)));
 
+            let network = ctx.get_host_network(network)?;
             let network_id = network.id.as_ref().expect("Docker network ID missing");
-            let bridge_name = get_bridge_name(network_id)?;
-            trace!(ctx.logger, "Got bridge name";
+            let interface = ctx.get_network_interface(network)?;
+            trace!(ctx.logger, "Got network interface";
                    o!("network_name" => &network.name,
-                      "bridge_name" => &bridge_name));
+                      "interface" => format!("{:?}", interface)));
+            ensure_routed_network(network, &interface)?;
 
-            ipt_forward_rule.out_interface(&bridge_name);
+            ipt_forward_rule.out_interface(interface.name());
 
-            if let Some(dst_network) = get_network_for_container(
-                ctx.docker,
+            let mut ipv4_destination = true;
+            let mut ipv6_destination = None;
+            if let Some(dst_network) = get_network_for_single_container(
+                ctx.container_runtime,
                 &ctx.container_map,
-                &self.dst_container,
+                self.dst_container.as_deref(),
+                dst_selector.as_ref(),
                 network_id,
             )? {
                 trace!(ctx.logger, "Got destination network";
                        o!("network_name" => &network.name,
                           "dst_network" => format!("{:?}", dst_network)));
 
-                let container_ipv4_address = dst_network
-                    .ipv4_address
-                    .expect("IPv4 address for container missing");
-                ipt_forward_rule.destination(
-                    container_ipv4_address
-                        .split('/')
-                        .next()
-                        .ok_or_else(|| format_err!("IPv4 address is empty"))?,
-                );
-
-                let host_port = expose_port.host_port.to_string();
-                let container_port = match expose_port.container_port {
-                    Some(destination_port) => destination_port.to_string(),
-                    None => expose_port.host_port.to_string(),
-                };
+                let container_ipv4_address =
+                    get_container_ipv4_address_checked(network, &dst_network)?;
+                let container_ipv6_address = get_container_ipv6_address(network, &dst_network);
+
+                let host_ports = expose_port
+                    .host_port_range()
+                    .map_err(|error| format_err!("{}", error))?;
+                let container_ports = expose_port
+                    .container_port_range()
+                    .map_err(|error| format_err!("{}", error))?;
+                let host_port = format_port_range(host_ports);
+                let container_port = format_port_range(container_ports);
                 ipt_forward_rule.destination_port(&container_port);
                 ipt_dnat_rule.destination_port(&host_port);
-                ipt_dnat_rule.jump(&format!(
-                    "DNAT --to-destination {}:{}",
-                    container_ipv4_address
-                        .split('/')
-                        .next()
-                        .ok_or_else(|| format_err!("IPv4 address is empty"))?,
-                    container_port
-                ));
                 ipt6_input_rule.destination_port(&host_port);
+
+                if let Some(container_ipv4_address) = container_ipv4_address {
+                    ipt_forward_rule.destination(&container_ipv4_address);
+                    ipt_dnat_rule.jump(&format!(
+                        "DNAT --to-destination {}",
+                        format_dnat_destination(
+                            &container_ipv4_address,
+                            host_ports,
+                            container_ports
+                        )
+                    ));
+                } else {
+                    // The container is only reachable through IPv6.
+                    ipv4_destination = false;
+                }
+
+                if let Some(container_ipv6_address) = container_ipv6_address {
+                    trace!(ctx.logger, "Got IPv6 address of destination container";
+                           o!("network_name" => &network.name,
+                              "container_ipv6_address" => &container_ipv6_address));
+                    let dnat_destination = format_dnat_destination(
+                        &container_ipv6_address,
+                        host_ports,
+                        container_ports,
+                    );
+                    ipv6_destination = Some((container_ipv6_address, dnat_destination));
+                }
             } else {
                 // Network for container has to exist
                 continue;
@@ -732,9 +858,11 @@ impl Process<Iptables> for WiderWorldToContainerRule {
             debug!(ctx.logger, "Build rule to verify contents";
                    o!("args" => format!("{:?}", ipt_forward_rule)));
             ipt_forward_rule.build()?;
-            debug!(ctx.logger, "Build rule to verify contents";
-                   o!("args" => format!("{:?}", ipt_dnat_rule)));
-            ipt_dnat_rule.build()?;
+            if ipv4_destination {
+                debug!(ctx.logger, "Build rule to verify contents";
+                       o!("args" => format!("{:?}", ipt_dnat_rule)));
+                ipt_dnat_rule.build()?;
+            }
 
             if let Some(ref external_network_interface) = self.external_network_interface {
                 trace!(ctx.logger, "Rule has specific external network interface";
@@ -758,7 +886,10 @@ impl Process<Iptables> for WiderWorldToContainerRule {
 
             // If source CIDRs have been specified, create the FORWARD-rules as required to
             // restrict the traffic as intended.
-            if let Some(source_cidrs) = &self.source_cidr_v4 {
+            if !ipv4_destination {
+                trace!(ctx.logger, "Skip IPv4 rules, destination container has no IPv4 address";
+                       o!("part" => "wider_world_to_container"));
+            } else if let Some(source_cidrs) = &self.source_cidr_v4 {
                 debug!(ctx.logger, "Generate extended FORWARD rules, source CIDRs were specified";
                        o!("args" => format!("{:?}", ipt_dnat_rule),
                           "source_cidrs" => source_cidrs.join(", ")));
@@ -814,35 +945,49 @@ impl Process<Iptables> for WiderWorldToContainerRule {
                 ));
                 rules.push(append_built_rule(IptablesRuleDiscriminants::V4, &dnat_rule));
             }
+            // If the container has an IPv6 address on an IPv6-enabled network, the traffic is
+            // forwarded to it directly. Otherwise the traffic is only accepted on the host,
+            // relying on a separate NAT setup.
+            let ipt6_rules = match ipv6_destination {
+                Some((container_ipv6_address, dnat_destination)) => {
+                    let mut ipt6_forward_rule = ipt_forward_rule.clone();
+                    ipt6_forward_rule.destination(&container_ipv6_address);
+                    let mut ipt6_dnat_rule = ipt_dnat_rule.clone();
+                    ipt6_dnat_rule.jump(&format!("DNAT --to-destination {}", dnat_destination));
+                    vec![ipt6_forward_rule, ipt6_dnat_rule]
+                }
+                None => vec![ipt6_input_rule],
+            };
             if self.expose_via_ipv6 {
                 if let Some(source_cidrs) = &self.source_cidr_v6 {
-                    for additional_input_rule in source_cidrs
+                    for additional_ipv6_rule in ipt6_rules
                         .iter()
-                        .map(|source_cidr| {
-                            let mut input_rule = ipt6_input_rule.clone();
-                            input_rule.source(source_cidr);
-                            input_rule
+                        .flat_map(|ipt6_rule| {
+                            source_cidrs.iter().map(move |source_cidr| {
+                                let mut ipv6_rule = ipt6_rule.clone();
+                                ipv6_rule.source(source_cidr);
+                                ipv6_rule
+                            })
                         })
-                        .map(|input_rule| input_rule.build())
+                        .map(|ipv6_rule| ipv6_rule.build())
                         .collect::<Result<Vec<_>>>()?
                     {
-                        debug!(ctx.logger, "Add IPv6 INPUT rule";
+                        debug!(ctx.logger, "Add IPv6 rule";
                                o!("part" => "wider_world_to_container",
-                                   "rule" => &additional_input_rule.rule));
+                                   "rule" => &additional_ipv6_rule.rule));
                         rules.push(append_built_rule(
                             IptablesRuleDiscriminants::V6,
-                            &additional_input_rule,
+                            &additional_ipv6_rule,
                         ));
                     }
                 } else {
-                    let input_rule = ipt6_input_rule.build()?;
-                    debug!(ctx.logger, "Add IPv6 INPUT rule";
-                           o!("part" => "wider_world_to_container",
-                               "rule" => &input_rule.rule));
-                    rules.push(append_built_rule(
-                        IptablesRuleDiscriminants::V6,
-                        &input_rule,
-                    ));
+                    for ipt6_rule in ipt6_rules {
+                        let ipv6_rule = ipt6_rule.build()?;
+                        debug!(ctx.logger, "Add IPv6 rule";
+                               o!("part" => "wider_world_to_container",
+                                   "rule" => &ipv6_rule.rule));
+                        rules.push(append_built_rule(IptablesRuleDiscriminants::V6, &ipv6_rule));
+                    }
                 }
             }
         }
@@ -851,6 +996,85 @@ impl Process<Iptables> for WiderWorldToContainerRule {
     }
 }
 
+/// Generate the rules for ports published through the routing mesh of Docker Swarm.
+///
+/// Docker forwards the published ports to the ingress sandbox attached to `docker_gwbridge`
+/// itself, which is why the forwarded traffic only has to be accepted, restricted to the source
+/// CIDRs if specified. The routing mesh only supports IPv4.
+fn routing_mesh_rules(
+    rule: &WiderWorldToContainerRule,
+    ctx: &ProcessContext<Iptables>,
+) -> Result<Option<Vec<IptablesRule>>> {
+    let network = ctx
+        .network_map
+        .get(DOCKER_GWBRIDGE_NETWORK)
+        .ok_or_else(|| {
+            format_err!(
+                "the routing mesh requires the `{}` network to exist",
+                DOCKER_GWBRIDGE_NETWORK
+            )
+        })?;
+    let interface = ctx.get_network_interface(network)?;
+    trace!(ctx.logger, "Got network interface of routing mesh";
+           o!("network_name" => &network.name,
+              "interface" => format!("{:?}", interface)));
+
+    let external_network_interface = match rule
+        .external_network_interface
+        .as_ref()
+        .or(ctx.primary_external_network_interface.as_ref())
+    {
+        Some(external_network_interface) => external_network_interface,
+        None => return Ok(None),
+    };
+
+    let mut rules = Vec::new();
+    for expose_port in &split_families(&rule.expose_port) {
+        let host_ports = expose_port
+            .host_port_range()
+            .map_err(|error| format_err!("{}", error))?;
+        let container_ports = expose_port
+            .container_port_range()
+            .map_err(|error| format_err!("{}", error))?;
+        if host_ports != container_ports {
+            bail!(
+                "port {} is published through the routing mesh and can't be mapped to a \
+                 different container port",
+                format_port_range(host_ports)
+            );
+        }
+
+        // The routing mesh doesn't accept IPv6 traffic, nothing is accepted if only IPv6 source
+        // CIDRs were specified.
+        if rule.source_cidr_v4.is_none() && rule.source_cidr_v6.is_some() {
+            continue;
+        }
+        let mut ipt_rule = Rule::new("filter", DFW_FORWARD_CHAIN);
+        ipt_rule
+            .in_interface(external_network_interface)
+            .out_interface(interface.name())
+            .protocol(&expose_port.family)
+            .destination_port(&format_port_range(host_ports))
+            .jump("ACCEPT");
+
+        for ipt_rule in expand_rules(
+            vec![ipt_rule],
+            rule.source_cidr_v4.as_deref().unwrap_or_default(),
+            |ipt_rule, source_cidr| {
+                ipt_rule.source(source_cidr);
+            },
+        ) {
+            let ipt_rule = ipt_rule.build()?;
+            debug!(ctx.logger, "Add forward rule for routing mesh";
+                   o!("part" => "wider_world_to_container",
+                      "rule" => &ipt_rule));
+            rules.push(append_built_rule(IptablesRuleDiscriminants::V4, &ipt_rule));
+        }
+    }
+
+    Ok(Some(rules))
+}
+
 impl Process<Iptables> for ContainerDNAT {
     fn process(&self, ctx: &ProcessContext<Iptables>) -> Result<Option<Vec<IptablesRule>>> {
         debug!(ctx.logger, "Starting sub-processing";
@@ -872,9 +1096,24 @@ impl Process<Iptables> for ContainerDNATRule {
         trace!(ctx.logger, "Process rule";
                o!("part" => "container_dnat_rule",
                   "rule" => format!("{:?}", self)));
+        let src_selector = resolve_selector(
+            self.src_selector.as_ref(),
+            self.src_service.as_ref(),
+            self.src_swarm_service.as_ref(),
+        )?;
+        ensure_container_or_selector(self.src_container.as_ref(), src_selector.as_ref())?;
+        let dst_selector = resolve_selector(
+            self.dst_selector.as_ref(),
+            self.dst_service.as_ref(),
+            self.dst_swarm_service.as_ref(),
+        )?;
+        if self.src_network.is_none() && src_selector.is_some() {
+            bail!("the source selector requires the source network to be specified");
+        }
         let mut rules = Vec::new();
-        for expose_port in &self.expose_port {
+        for expose_port in &split_families(&self.expose_port) {
             let mut ipt_rule = Rule::new("nat", DFW_PREROUTING_CHAIN);
+            let mut source_addresses = Vec::new();
 
             if let Some(ref network) = self.src_network {
                 if let Some(network) = ctx.network_map.get(network) {
@@ -882,17 +1121,18 @@ impl Process<Iptables> for ContainerDNATRule {
                            o!("network_name" => &network.name,
                               "network" => format!("{:?}", network)));
 
+                    let network = ctx.get_host_network(network)?;
                     let network_id = network.id.as_ref().expect("Docker network ID missing");
-                    let bridge_name = get_bridge_name(network_id)?;
-                    trace!(ctx.logger, "Got bridge name";
+                    let interface = ctx.get_network_interface(network)?;
+                    trace!(ctx.logger, "Got network interface";
                            o!("network_name" => &network.name,
-                              "bridge_name" => &bridge_name));
+                              "interface" => format!("{:?}", interface)));
 
-                    ipt_rule.in_interface(&bridge_name);
+                    ipt_rule.in_interface(interface.name());
 
                     if let Some(ref src_container) = self.src_container {
                         if let Some(src_network) = get_network_for_container(
-                            ctx.docker,
+                            ctx.container_runtime,
                             &ctx.container_map,
                             src_container,
                             network_id,
@@ -901,20 +1141,22 @@ impl Process<Iptables> for ContainerDNATRule {
                                    o!("network_name" => &network.name,
                                       "src_network" => format!("{:?}", src_network)));
 
-                            let bridge_name = get_bridge_name(network_id)?;
-                            trace!(ctx.logger, "Got bridge name";
-                                   o!("network_name" => &network.name,
-                                      "bridge_name" => &bridge_name));
-
-                            ipt_rule.in_interface(&bridge_name).source(
-                                src_network
-                                    .ipv4_address
-                                    .expect("IPv4 address for container missing")
-                                    .split('/')
-                                    .next()
-                                    .ok_or_else(|| format_err!("IPv4 address is empty"))?,
-                            );
+                            match get_container_ipv4_address_checked(network, &src_network)? {
+                                Some(src_address) => {
+                                    ipt_rule.in_interface(interface.name()).source(&src_address);
+                                }
+                                // An IPv6-only container can't be the source of IPv4 traffic.
+                                None => continue,
+                            }
                         }
+                    } else if let Some(ref src_selector) = src_selector {
+                        source_addresses =
+                            match ctx.get_selector_source_addresses(network, src_selector)? {
+                                Some(source_addresses) => source_addresses,
+                                None => return Ok(None),
+                            };
+                    } else if interface.is_shared() {
+                        source_addresses = get_network_subnets(network, IpVersion::V4);
                     }
                 }
             }
@@ -923,11 +1165,13 @@ impl Process<Iptables> for ContainerDNATRule {
                 Some(network) => network,
                 None => continue,
             };
+            let network = ctx.get_host_network(network)?;
             let network_id = network.id.as_ref().expect("Docker network ID missing");
-            let dst_network = match get_network_for_container(
-                ctx.docker,
+            let dst_network = match get_network_for_single_container(
+                ctx.container_runtime,
                 &ctx.container_map,
-                &self.dst_container,
+                self.dst_container.as_deref(),
+                dst_selector.as_ref(),
                 network_id,
             )? {
                 Some(dst_network) => dst_network,
@@ -937,27 +1181,27 @@ impl Process<Iptables> for ContainerDNATRule {
                    o!("network_name" => &network.name,
                       "dst_network" => format!("{:?}", dst_network)));
 
-            let bridge_name = get_bridge_name(network_id)?;
-            trace!(ctx.logger, "Got bridge name";
+            let interface = ctx.get_network_interface(network)?;
+            trace!(ctx.logger, "Got network interface";
                    o!("network_name" => &network.name,
-                      "bridge_name" => &bridge_name));
-
-            ipt_rule.out_interface(&bridge_name);
-
-            let destination_port = match expose_port.container_port {
-                Some(destination_port) => destination_port.to_string(),
-                None => expose_port.host_port.to_string(),
+                      "interface" => format!("{:?}", interface)));
+            ensure_routed_network(network, &interface)?;
+
+            ipt_rule.out_interface(interface.name());
+
+            let destination_ports = expose_port
+                .container_port_range()
+                .map_err(|error| format_err!("{}", error))?;
+            ipt_rule.protocol(&expose_port.family);
+            ipt_rule.destination_port(&format_port_range(destination_ports));
+            let dst_address = match get_container_ipv4_address_checked(network, &dst_network)? {
+                Some(dst_address) => dst_address,
+                // Traffic can't be forwarded to an IPv6-only container through IPv4 DNAT.
+                None => continue,
             };
-            ipt_rule.destination_port(&destination_port);
             ipt_rule.jump(&format!(
-                "DNAT --to-destination {}:{}",
-                dst_network
-                    .ipv4_address
-                    .expect("IPv4 address for container missing")
-                    .split('/')
-                    .next()
-                    .ok_or_else(|| format_err!("IPv4 address is empty"))?,
-                destination_port
+                "DNAT --to-destination {}",
+                format_dnat_destination(&dst_address, destination_ports, destination_ports)
             ));
 
             // Try to build the rule without the out_interface defined to see if any of the
@@ -984,18 +1228,188 @@ impl Process<Iptables> for ContainerDNATRule {
                 }
             }
 
-            let rule = ipt_rule.build()?;
-            debug!(ctx.logger, "Add prerouting rule";
-                   o!("part" => "container_dnat",
-                      "rule" => &rule.rule));
+            for ipt_rule in expand_rules(vec![ipt_rule], &source_addresses, |ipt_rule, address| {
+                ipt_rule.source(address);
+            }) {
+                let rule = ipt_rule.build()?;
+                debug!(ctx.logger, "Add prerouting rule";
+                       o!("part" => "container_dnat",
+                          "rule" => &rule.rule));
 
-            rules.push(append_built_rule(IptablesRuleDiscriminants::V4, &rule));
+                rules.push(append_built_rule(IptablesRuleDiscriminants::V4, &rule));
+            }
         }
 
         Ok(Some(rules))
     }
 }
 
+/// Lower the typed matches of a rule, generating the rules for the given IP version.
+///
+/// Destination CIDRs are only applied for the IP version they belong to, rules for an IP version
+/// are omitted if only destination CIDRs of the other IP version are specified. Rules without
+/// destination CIDRs are generated for IPv4 if both IP versions are given. Rules matching ICMP or
+/// ICMPv6 are only generated for the IP version of the protocol.
+fn apply_typed_matches(
+    ipt_rules: Vec<Rule>,
+    matches: &TypedMatches,
+    ip_version: IpVersion,
+) -> Result<Vec<(IptablesRuleDiscriminants, Rule)>> {
+    matches.validate()?;
+
+    let requested_ip_version = ip_version;
+    let ip_version = match matches.restrict_ip_version(ip_version) {
+        Some(ip_version) => ip_version,
+        None => return Ok(Vec::new()),
+    };
+
+    let mut typed_rules = Vec::new();
+    for mut ipt_rule in ipt_rules {
+        if let Some(protocol) = matches.protocol {
+            ipt_rule.protocol(&protocol.to_string());
+        }
+        if let Some(src_port) = matches.src_port {
+            ipt_rule.source_port(&format_port_range((src_port.start, src_port.end)));
+        }
+        if let Some(dst_port) = matches.dst_port {
+            ipt_rule.destination_port(&format_port_range((dst_port.start, dst_port.end)));
+        }
+        if let Some(icmp_type) = matches.icmp_type {
+            ipt_rule.icmp_type(icmp_type);
+        }
+        if let Some(ct_state) = matches.ct_state {
+            ipt_rule.ct_state(
+                &ct_state
+                    .iter()
+                    .map(|state| state.to_string().to_uppercase())
+                    .collect::<Vec<_>>()
+                    .join(","),
+            );
+        }
+
+        if matches.dst_cidr_v4.is_none() && matches.dst_cidr_v6.is_none() {
+            if ip_version == IpVersion::V6
+                && requested_ip_version == IpVersion::Both
+                && ipt_rule.source.is_some()
+            {
+                bail!("ICMPv6 can't be matched for IPv4 container addresses");
+            }
+            let rule_discriminant = match ip_version {
+                IpVersion::V6 => IptablesRuleDiscriminants::V6,
+                IpVersion::V4 | IpVersion::Both => IptablesRuleDiscriminants::V4,
+            };
+            typed_rules.push((rule_discriminant, ipt_rule));
+            continue;
+        }
+        if let (Some(dst_cidr_v4), IpVersion::V4 | IpVersion::Both) =
+            (matches.dst_cidr_v4, ip_version)
+        {
+            if ipt_rule.destination.is_some() {
+                bail!("destination CIDRs can't be combined with a destination container");
+            }
+            let mut ipt_rule = ipt_rule.clone();
+            ipt_rule.destination(&dst_cidr_v4.join(","));
+            typed_rules.push((IptablesRuleDiscriminants::V4, ipt_rule));
+        }
+        if let (Some(dst_cidr_v6), IpVersion::V6 | IpVersion::Both) =
+            (matches.dst_cidr_v6, ip_version)
+        {
+            if ipt_rule.destination.is_some() {
+                bail!("destination CIDRs can't be combined with a destination container");
+            }
+            if ip_version == IpVersion::Both && ipt_rule.source.is_some() {
+                bail!("IPv6 destination CIDRs can't be combined with IPv4 container addresses");
+            }
+            let mut ipt_rule = ipt_rule.clone();
+            ipt_rule.destination(&dst_cidr_v6.join(","));
+            typed_rules.push((IptablesRuleDiscriminants::V6, ipt_rule));
+        }
+    }
+
+    Ok(typed_rules)
+}
+
+/// Restrict a rule to the IPv4 subnets of a network if the interface of the network is shared,
+/// generating a rule for every subnet.
+fn restrict_to_subnets(
+    ipt_rule: Rule,
+    network: &Network,
+    interface: &NetworkInterface,
+    source: bool,
+    destination: bool,
+) -> Vec<Rule> {
+    if !interface.is_shared() {
+        return vec![ipt_rule];
+    }
+
+    let subnets = get_network_subnets(network, IpVersion::V4);
+    if subnets.is_empty() {
+        return Vec::new();
+    }
+    let mut ipt_rules = vec![ipt_rule];
+    if source {
+        ipt_rules = expand_rules(ipt_rules, &subnets, |ipt_rule, subnet| {
+            ipt_rule.source(subnet);
+        });
+    }
+    if destination {
+        ipt_rules = expand_rules(ipt_rules, &subnets, |ipt_rule, subnet| {
+            ipt_rule.destination(subnet);
+        });
+    }
+    ipt_rules
+}
+
+/// Split port definitions applying to multiple families into one definition per family, since
+/// iptables can only match a single protocol per rule.
+fn split_families(expose_ports: &[ExposePort]) -> Vec<ExposePort> {
+    expose_ports
+        .iter()
+        .flat_map(|expose_port| {
+            expose_port.families().map(move |family| ExposePort {
+                family: family.to_owned(),
+                ..expose_port.clone()
+            })
+        })
+        .collect()
+}
+
+/// Format an inclusive range of ports for use in a port match.
+fn format_port_range((start, end): (u16, u16)) -> String {
+    if start == end {
+        start.to_string()
+    } else {
+        format!("{}:{}", start, end)
+    }
+}
+
+/// Format the destination of a DNAT target, mapping the range of host ports 1:1 to the range of
+/// container ports.
+fn format_dnat_destination(
+    address: &str,
+    host_ports: (u16, u16),
+    container_ports: (u16, u16),
+) -> String {
+    // IPv6 addresses have to be enclosed in brackets if they are followed by a port.
+    let bracketed_address = if address.contains(':') {
+        format!("[{}]", address)
+    } else {
+        address.to_owned()
+    };
+    if host_ports.0 == host_ports.1 {
+        format!("{}:{}", bracketed_address, container_ports.0)
+    } else if host_ports.0 == container_ports.0 {
+        // The destination port is retained if only the address is translated.
+        address.to_owned()
+    } else {
+        // Shift the port range, mapping the first host port to the first container port.
+        format!(
+            "{}:{}-{}/{}",
+            bracketed_address, container_ports.0, container_ports.1, host_ports.0
+        )
+    }
+}
+
 fn set_policy(
     rule_discriminant: IptablesRuleDiscriminants,
     table: &str,
@@ -27,6 +27,8 @@ pub(super) struct Rule {
     pub protocol: Option<String>,
     pub source_port: Option<String>,
     pub destination_port: Option<String>,
+    pub icmp_type: Option<String>,
+    pub ct_state: Option<String>,
 
     pub filter: Option<String>,
     pub jump: Option<String>,
@@ -67,42 +69,44 @@ impl Rule {
             protocol: None,
             source_port: None,
             destination_port: None,
+            icmp_type: None,
+            ct_state: None,
             filter: None,
             jump: None,
             comment: None,
         }
     }
 
-    pub fn source<S: ?Sized>(&mut self, value: &S) -> &mut Self
+    pub fn source<S>(&mut self, value: &S) -> &mut Self
     where
-        S: AsRef<str>,
+        S: AsRef<str> + ?Sized,
     {
         let new = self;
         new.source = Some(value.as_ref().into());
         new
     }
 
-    pub fn destination<S: ?Sized>(&mut self, value: &S) -> &mut Self
+    pub fn destination<S>(&mut self, value: &S) -> &mut Self
     where
-        S: AsRef<str>,
+        S: AsRef<str> + ?Sized,
     {
         let new = self;
         new.destination = Some(value.as_ref().into());
         new
     }
 
-    pub fn in_interface<S: ?Sized>(&mut self, value: &S) -> &mut Self
+    pub fn in_interface<S>(&mut self, value: &S) -> &mut Self
     where
-        S: AsRef<str>,
+        S: AsRef<str> + ?Sized,
     {
         let new = self;
         new.in_interface = Some(value.as_ref().into());
         new
     }
 
-    pub fn out_interface<S: ?Sized>(&mut self, value: &S) -> &mut Self
+    pub fn out_interface<S>(&mut self, value: &S) -> &mut Self
     where
-        S: AsRef<str>,
+        S: AsRef<str> + ?Sized,
     {
         let new = self;
         new.out_interface = Some(value.as_ref().into());
@@ -121,54 +125,72 @@ impl Rule {
         new
     }
 
-    pub fn protocol<S: ?Sized>(&mut self, value: &S) -> &mut Self
+    pub fn protocol<S>(&mut self, value: &S) -> &mut Self
     where
-        S: AsRef<str>,
+        S: AsRef<str> + ?Sized,
     {
         let new = self;
         new.protocol = Some(value.as_ref().into());
         new
     }
 
-    pub fn source_port<S: ?Sized>(&mut self, value: &S) -> &mut Self
+    pub fn source_port<S>(&mut self, value: &S) -> &mut Self
     where
-        S: AsRef<str>,
+        S: AsRef<str> + ?Sized,
     {
         let new = self;
         new.source_port = Some(value.as_ref().into());
         new
     }
 
-    pub fn destination_port<S: ?Sized>(&mut self, value: &S) -> &mut Self
+    pub fn destination_port<S>(&mut self, value: &S) -> &mut Self
     where
-        S: AsRef<str>,
+        S: AsRef<str> + ?Sized,
     {
         let new = self;
         new.destination_port = Some(value.as_ref().into());
         new
     }
 
-    pub fn filter<S: ?Sized>(&mut self, value: &S) -> &mut Self
+    pub fn icmp_type<S>(&mut self, value: &S) -> &mut Self
     where
-        S: AsRef<str>,
+        S: AsRef<str> + ?Sized,
+    {
+        let new = self;
+        new.icmp_type = Some(value.as_ref().into());
+        new
+    }
+
+    pub fn ct_state<S>(&mut self, value: &S) -> &mut Self
+    where
+        S: AsRef<str> + ?Sized,
+    {
+        let new = self;
+        new.ct_state = Some(value.as_ref().into());
+        new
+    }
+
+    pub fn filter<S>(&mut self, value: &S) -> &mut Self
+    where
+        S: AsRef<str> + ?Sized,
     {
         let new = self;
         new.filter = Some(value.as_ref().into());
         new
     }
 
-    pub fn jump<S: ?Sized>(&mut self, value: &S) -> &mut Self
+    pub fn jump<S>(&mut self, value: &S) -> &mut Self
     where
-        S: AsRef<str>,
+        S: AsRef<str> + ?Sized,
     {
         let new = self;
         new.jump = Some(value.as_ref().into());
         new
     }
 
-    pub fn comment<S: ?Sized>(&mut self, value: &S) -> &mut Self
+    pub fn comment<S>(&mut self, value: &S) -> &mut Self
     where
-        S: AsRef<str>,
+        S: AsRef<str> + ?Sized,
     {
         let new = self;
         new.comment = Some(value.as_ref().into());
@@ -221,6 +243,22 @@ impl Rule {
             args.push(destination_port.to_owned());
         }
 
+        if let Some(ref icmp_type) = self.icmp_type {
+            if self.protocol.as_deref() == Some("icmpv6") {
+                args.push("--icmpv6-type".to_owned());
+            } else {
+                args.push("--icmp-type".to_owned());
+            }
+            args.push(icmp_type.to_owned());
+        }
+
+        if let Some(ref ct_state) = self.ct_state {
+            args.push("-m".to_owned());
+            args.push("conntrack".to_owned());
+            args.push("--ctstate".to_owned());
+            args.push(ct_state.to_owned());
+        }
+
         if let Some(ref filter) = self.filter {
             args.push(filter.to_owned());
         }
@@ -28,12 +28,14 @@
 //! # "#).unwrap();
 //! ```
 
+use schemars::JsonSchema;
 use serde::Deserialize;
 use std::collections::HashMap;
 
 /// The defaults/configuration for the iptables backend.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
 #[serde(deny_unknown_fields)]
+#[schemars(rename = "IptablesDefaults")]
 pub struct Defaults {
     /// The optional initialization section.
     ///
@@ -81,8 +83,9 @@ pub struct Defaults {
 /// ]
 /// # "#).unwrap();
 /// ```
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
 #[serde(deny_unknown_fields)]
+#[schemars(rename = "IptablesInitialization")]
 pub struct Initialization {
     /// Initialization rules for iptables (IPv4). Expects a map where the key is a specific table
     /// and the value is a list of rules.
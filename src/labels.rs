@@ -0,0 +1,572 @@
+// Copyright Pit Kleyersburg <pitkley@googlemail.com>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+//! This module holds the conversion of container labels into rules.
+
+use crate::{errors::*, types::*};
+use bollard::models::ContainerSummary;
+use failure::{bail, format_err};
+use slog::{debug, o, warn, Logger};
+use std::collections::{BTreeMap, BTreeSet, HashMap as Map};
+
+pub(crate) const DEFAULT_LABEL_PREFIX: &str = "dfw";
+
+/// Rules derived from the labels of the containers, to be merged with the rules of the
+/// configuration.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct LabelRules {
+    pub(crate) container_to_container: Vec<ContainerToContainerRule>,
+    pub(crate) container_to_wider_world: Vec<ContainerToWiderWorldRule>,
+    pub(crate) wider_world_to_container: Vec<WiderWorldToContainerRule>,
+}
+
+impl LabelRules {
+    /// Collect the rules declared through the labels of the given containers.
+    ///
+    /// Containers with invalid labels are skipped (with a warning), such that a single
+    /// misconfigured container cannot prevent the firewall from being applied.
+    pub(crate) fn from_containers(
+        containers: &[ContainerSummary],
+        container_map: &Map<String, ContainerSummary>,
+        prefix: &str,
+        logger: &Logger,
+    ) -> LabelRules {
+        let mut label_rules = LabelRules::default();
+        for container in containers {
+            match LabelRules::for_container(container, container_map, prefix) {
+                Ok(mut container_label_rules) => {
+                    label_rules
+                        .container_to_container
+                        .append(&mut container_label_rules.container_to_container);
+                    label_rules
+                        .container_to_wider_world
+                        .append(&mut container_label_rules.container_to_wider_world);
+                    label_rules
+                        .wider_world_to_container
+                        .append(&mut container_label_rules.wider_world_to_container);
+                }
+                Err(error) => {
+                    warn!(logger, "Ignoring invalid rule labels of container";
+                          o!("container" => get_container_name(container),
+                             "error" => format!("{}", error)));
+                }
+            }
+        }
+        debug!(logger, "Got rules from container labels";
+               o!("label_rules" => format!("{:?}", label_rules)));
+
+        label_rules
+    }
+
+    fn for_container(
+        container: &ContainerSummary,
+        container_map: &Map<String, ContainerSummary>,
+        prefix: &str,
+    ) -> Result<LabelRules> {
+        let mut label_rules = LabelRules::default();
+        let labels = match container.labels {
+            Some(ref labels) => labels,
+            None => return Ok(label_rules),
+        };
+        let label = |name: &str| {
+            labels
+                .get(&format!("{}.{}", prefix, name))
+                .map(|value| value.trim())
+        };
+
+        let expose = label("expose");
+        let egress = label("egress");
+        let allow_from = label("allow-from");
+        if expose.is_none() && egress.is_none() && allow_from.is_none() {
+            return Ok(label_rules);
+        }
+
+        let container_name = get_container_name(container)
+            .ok_or_else(|| format_err!("container has no name"))?
+            .to_owned();
+        let networks = match label("network") {
+            Some(network) => vec![network.to_owned()],
+            None => get_container_networks(container),
+        };
+        if networks.is_empty() {
+            bail!("container is not attached to any network");
+        }
+
+        if let Some(expose) = expose {
+            let network = match networks.as_slice() {
+                [network] => network,
+                _ => bail!(
+                    "container is attached to multiple networks, specify the network to expose \
+                     it through using the `{}.network` label",
+                    prefix
+                ),
+            };
+            let expose_port = split_list(expose)
+                .map(|port| {
+                    port.parse::<ExposePort>().map_err(|error| {
+                        format_err!("invalid `{}.expose` label: {}", prefix, error)
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            label_rules
+                .wider_world_to_container
+                .push(WiderWorldToContainerRule {
+                    network: network.clone(),
+                    dst_container: Some(container_name.clone()),
+                    dst_selector: None,
+                    dst_service: None,
+                    dst_swarm_service: None,
+                    expose_port,
+                    external_network_interface: None,
+                    expose_via_ipv6: true,
+                    source_cidr_v4: None,
+                    source_cidr_v6: None,
+                });
+        }
+
+        if let Some(egress) = egress {
+            let verdict = egress.parse::<RuleVerdict>().map_err(|_| {
+                format_err!(
+                    "invalid `{}.egress` label: unknown verdict `{}`",
+                    prefix,
+                    egress
+                )
+            })?;
+            for network in &networks {
+                label_rules
+                    .container_to_wider_world
+                    .push(ContainerToWiderWorldRule {
+                        network: Some(network.clone()),
+                        src_container: Some(container_name.clone()),
+                        src_selector: None,
+                        src_service: None,
+                        src_swarm_service: None,
+                        protocol: None,
+                        src_port: None,
+                        dst_port: None,
+                        dst_cidr_v4: None,
+                        dst_cidr_v6: None,
+                        icmp_type: None,
+                        ct_state: None,
+                        matches: None,
+                        verdict,
+                        external_network_interface: None,
+                    });
+            }
+        }
+
+        if let Some(allow_from) = allow_from {
+            for src_container in split_list(allow_from) {
+                // Only generate rules for networks both containers are attached to, the source
+                // container might not be running yet or might not share a network at all.
+                let src_networks = container_map
+                    .get(src_container)
+                    .map(get_container_networks)
+                    .unwrap_or_default();
+                for network in networks
+                    .iter()
+                    .filter(|network| src_networks.contains(network))
+                {
+                    label_rules
+                        .container_to_container
+                        .push(ContainerToContainerRule {
+                            network: network.clone(),
+                            src_container: Some(src_container.to_owned()),
+                            src_selector: None,
+                            src_service: None,
+                            src_swarm_service: None,
+                            dst_container: Some(container_name.clone()),
+                            dst_selector: None,
+                            dst_service: None,
+                            dst_swarm_service: None,
+                            protocol: None,
+                            src_port: None,
+                            dst_port: None,
+                            dst_cidr_v4: None,
+                            dst_cidr_v6: None,
+                            icmp_type: None,
+                            ct_state: None,
+                            ip_version: None,
+                            matches: None,
+                            verdict: RuleVerdict::Accept,
+                        });
+                }
+            }
+        }
+
+        Ok(label_rules)
+    }
+
+    /// Merge the label rules into the container-to-container section.
+    ///
+    /// The section defines the default policy, label rules are thus ignored if the section is not
+    /// configured.
+    pub(crate) fn merge_container_to_container(
+        &self,
+        section: &Option<ContainerToContainer>,
+    ) -> Option<ContainerToContainer> {
+        section.as_ref().map(|section| ContainerToContainer {
+            rules: merge_rules(&section.rules, &self.container_to_container),
+            ..section.clone()
+        })
+    }
+
+    /// Merge the label rules into the container-to-wider-world section.
+    ///
+    /// The section defines the default policy, label rules are thus ignored if the section is not
+    /// configured.
+    pub(crate) fn merge_container_to_wider_world(
+        &self,
+        section: &Option<ContainerToWiderWorld>,
+    ) -> Option<ContainerToWiderWorld> {
+        section.as_ref().map(|section| ContainerToWiderWorld {
+            rules: merge_rules(&section.rules, &self.container_to_wider_world),
+            ..section.clone()
+        })
+    }
+
+    /// Merge the label rules into the wider-world-to-container section.
+    pub(crate) fn merge_wider_world_to_container(
+        &self,
+        section: &Option<WiderWorldToContainer>,
+    ) -> Option<WiderWorldToContainer> {
+        match section {
+            Some(section) => Some(WiderWorldToContainer {
+                rules: merge_rules(&section.rules, &self.wider_world_to_container),
+            }),
+            None if !self.wider_world_to_container.is_empty() => Some(WiderWorldToContainer {
+                rules: Some(self.wider_world_to_container.clone()),
+            }),
+            None => None,
+        }
+    }
+}
+
+/// Generate the rules isolating Compose projects from each other.
+///
+/// For every network shared by containers of multiple Compose projects, traffic between the
+/// containers of different projects is dropped.
+pub(crate) fn compose_isolation_rules(
+    containers: &[ContainerSummary],
+) -> Vec<ContainerToContainerRule> {
+    let mut network_projects: BTreeMap<String, BTreeSet<&String>> = BTreeMap::new();
+    for container in containers {
+        let project = match container
+            .labels
+            .as_ref()
+            .and_then(|labels| labels.get(COMPOSE_PROJECT_LABEL))
+        {
+            Some(project) => project,
+            None => continue,
+        };
+        for network in get_container_networks(container) {
+            network_projects.entry(network).or_default().insert(project);
+        }
+    }
+
+    let project_selector = |project: &str| {
+        ContainerSelector(maplit::btreemap! {
+            COMPOSE_PROJECT_LABEL.to_owned() => project.to_owned(),
+        })
+    };
+    let mut rules = Vec::new();
+    for (network, projects) in network_projects {
+        for src_project in &projects {
+            for dst_project in projects.iter().filter(|project| *project != src_project) {
+                rules.push(ContainerToContainerRule {
+                    network: network.clone(),
+                    src_container: None,
+                    src_selector: Some(project_selector(src_project)),
+                    src_service: None,
+                    src_swarm_service: None,
+                    dst_container: None,
+                    dst_selector: Some(project_selector(dst_project)),
+                    dst_service: None,
+                    dst_swarm_service: None,
+                    protocol: None,
+                    src_port: None,
+                    dst_port: None,
+                    dst_cidr_v4: None,
+                    dst_cidr_v6: None,
+                    icmp_type: None,
+                    ct_state: None,
+                    ip_version: None,
+                    matches: None,
+                    verdict: RuleVerdict::Drop,
+                });
+            }
+        }
+    }
+
+    rules
+}
+
+fn merge_rules<T: Clone>(rules: &Option<Vec<T>>, label_rules: &[T]) -> Option<Vec<T>> {
+    if label_rules.is_empty() {
+        return rules.clone();
+    }
+
+    let mut merged_rules = rules.clone().unwrap_or_default();
+    merged_rules.extend_from_slice(label_rules);
+    Some(merged_rules)
+}
+
+fn split_list(value: &str) -> impl Iterator<Item = &str> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+}
+
+fn get_container_name(container: &ContainerSummary) -> Option<&str> {
+    container
+        .names
+        .as_ref()
+        .and_then(|names| names.first())
+        .map(|name| name.trim_start_matches('/'))
+}
+
+fn get_container_networks(container: &ContainerSummary) -> Vec<String> {
+    let mut networks = container
+        .network_settings
+        .as_ref()
+        .and_then(|network_settings| network_settings.networks.as_ref())
+        .map(|networks| networks.keys().cloned().collect::<Vec<_>>())
+        .unwrap_or_default();
+    networks.sort();
+    networks
+}
+
+#[cfg(test)]
+mod test {
+    use super::{compose_isolation_rules, LabelRules};
+    use crate::{process::get_container_map, types::*};
+    use bollard::models::{ContainerSummary, ContainerSummaryNetworkSettings, EndpointSettings};
+    use slog::{o, Discard, Logger};
+
+    fn container(name: &str, networks: &[&str], labels: &[(&str, &str)]) -> ContainerSummary {
+        ContainerSummary {
+            id: Some(format!("{}-id", name)),
+            names: Some(vec![format!("/{}", name)]),
+            labels: Some(
+                labels
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect(),
+            ),
+            network_settings: Some(ContainerSummaryNetworkSettings {
+                networks: Some(
+                    networks
+                        .iter()
+                        .map(|network| (network.to_string(), EndpointSettings::default()))
+                        .collect(),
+                ),
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn label_rules(containers: &[ContainerSummary], prefix: &str) -> LabelRules {
+        LabelRules::from_containers(
+            containers,
+            &get_container_map(containers),
+            prefix,
+            &Logger::root(Discard, o!()),
+        )
+    }
+
+    #[test]
+    fn labels_to_rules() {
+        let containers = vec![
+            container(
+                "web",
+                &["frontend", "backend"],
+                &[
+                    ("dfw.network", "frontend"),
+                    ("dfw.expose", "80/tcp, 443:8443/tcp"),
+                    ("dfw.egress", "accept"),
+                    ("dfw.allow-from", "backend"),
+                ],
+            ),
+            container("backend", &["frontend"], &[]),
+        ];
+        let actual = label_rules(&containers, "dfw");
+
+        assert_eq!(
+            actual.wider_world_to_container,
+            vec![WiderWorldToContainerRule {
+                network: "frontend".to_owned(),
+                dst_container: Some("web".to_owned()),
+                dst_selector: None,
+                dst_service: None,
+                dst_swarm_service: None,
+                expose_port: vec![
+                    ExposePort {
+                        host_port: 80,
+                        host_port_end: None,
+                        container_port: None,
+                        family: "tcp".to_owned(),
+                    },
+                    ExposePort {
+                        host_port: 443,
+                        host_port_end: None,
+                        container_port: Some(8443),
+                        family: "tcp".to_owned(),
+                    },
+                ],
+                external_network_interface: None,
+                expose_via_ipv6: true,
+                source_cidr_v4: None,
+                source_cidr_v6: None,
+            }]
+        );
+        assert_eq!(
+            actual.container_to_wider_world,
+            vec![ContainerToWiderWorldRule {
+                network: Some("frontend".to_owned()),
+                src_container: Some("web".to_owned()),
+                src_selector: None,
+                src_service: None,
+                src_swarm_service: None,
+                protocol: None,
+                src_port: None,
+                dst_port: None,
+                dst_cidr_v4: None,
+                dst_cidr_v6: None,
+                icmp_type: None,
+                ct_state: None,
+                matches: None,
+                verdict: RuleVerdict::Accept,
+                external_network_interface: None,
+            }]
+        );
+        assert_eq!(
+            actual.container_to_container,
+            vec![ContainerToContainerRule {
+                network: "frontend".to_owned(),
+                src_container: Some("backend".to_owned()),
+                src_selector: None,
+                src_service: None,
+                src_swarm_service: None,
+                dst_container: Some("web".to_owned()),
+                dst_selector: None,
+                dst_service: None,
+                dst_swarm_service: None,
+                protocol: None,
+                src_port: None,
+                dst_port: None,
+                dst_cidr_v4: None,
+                dst_cidr_v6: None,
+                icmp_type: None,
+                ct_state: None,
+                ip_version: None,
+                matches: None,
+                verdict: RuleVerdict::Accept,
+            }]
+        );
+    }
+
+    #[test]
+    fn labels_with_custom_prefix() {
+        let containers = vec![container(
+            "web",
+            &["frontend"],
+            &[("dfw.egress", "accept"), ("custom.egress", "drop")],
+        )];
+        let actual = label_rules(&containers, "custom");
+
+        assert_eq!(actual.container_to_wider_world.len(), 1);
+        assert_eq!(
+            actual.container_to_wider_world[0].verdict,
+            RuleVerdict::Drop
+        );
+    }
+
+    #[test]
+    fn invalid_labels_are_skipped() {
+        let containers = vec![
+            // Multiple networks without the network label are ambiguous for exposing.
+            container("ambiguous", &["a", "b"], &[("dfw.expose", "80")]),
+            container("invalid", &["a"], &[("dfw.egress", "maybe")]),
+            container("valid", &["a"], &[("dfw.egress", "reject")]),
+        ];
+        let actual = label_rules(&containers, "dfw");
+
+        assert!(actual.wider_world_to_container.is_empty());
+        assert_eq!(actual.container_to_wider_world.len(), 1);
+        assert_eq!(
+            actual.container_to_wider_world[0].src_container,
+            Some("valid".to_owned())
+        );
+    }
+
+    #[test]
+    fn merge_requires_section_with_default_policy() {
+        let label_rules = label_rules(
+            &[container("web", &["a"], &[("dfw.egress", "accept")])],
+            "dfw",
+        );
+
+        assert_eq!(label_rules.merge_container_to_wider_world(&None), None);
+        let merged = label_rules
+            .merge_container_to_wider_world(&Some(ContainerToWiderWorld {
+                default_policy: RuleVerdict::Drop,
+                rules: None,
+            }))
+            .unwrap();
+        assert_eq!(merged.default_policy, RuleVerdict::Drop);
+        assert_eq!(merged.rules.unwrap().len(), 1);
+    }
+
+    #[test]
+    fn compose_projects_sharing_a_network_are_isolated() {
+        let containers = vec![
+            container(
+                "a_web_1",
+                &["a_default", "proxy"],
+                &[(COMPOSE_PROJECT_LABEL, "a")],
+            ),
+            container(
+                "b_web_1",
+                &["b_default", "proxy"],
+                &[(COMPOSE_PROJECT_LABEL, "b")],
+            ),
+            container("proxy", &["proxy"], &[]),
+        ];
+        let actual = compose_isolation_rules(&containers)
+            .into_iter()
+            .map(|rule| {
+                (
+                    rule.network,
+                    rule.src_selector.unwrap().0[COMPOSE_PROJECT_LABEL].clone(),
+                    rule.dst_selector.unwrap().0[COMPOSE_PROJECT_LABEL].clone(),
+                    rule.verdict,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            actual,
+            vec![
+                (
+                    "proxy".to_owned(),
+                    "a".to_owned(),
+                    "b".to_owned(),
+                    RuleVerdict::Drop
+                ),
+                (
+                    "proxy".to_owned(),
+                    "b".to_owned(),
+                    "a".to_owned(),
+                    RuleVerdict::Drop
+                ),
+            ]
+        );
+    }
+}
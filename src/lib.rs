@@ -33,16 +33,24 @@
 
 // declare modules
 mod de;
+pub mod diff;
 pub mod errors;
 pub mod iptables;
+mod labels;
+pub mod migrate;
 pub mod nftables;
 pub mod process;
+pub mod runtime;
+mod schema;
 pub mod types;
 pub mod util;
+pub mod validate;
 
-use errors::Result;
-use process::{Process, ProcessContext};
-use serde::de::DeserializeOwned;
+use diff::RulesetDiff;
+use errors::{DFWError, Result};
+use process::{AppliedRules, Process, ProcessContext};
+use serde::{de::DeserializeOwned, Serialize};
+use slog::Logger;
 use std::fmt::Debug;
 use types::DFW;
 
@@ -60,4 +68,62 @@ where
 
     /// Apply the processed rules.
     fn apply(rules: Vec<Self::Rule>, ctx: &ProcessContext<Self>) -> Result<()>;
+
+    /// Apply only the changes between the processed rules and the rules applied previously.
+    ///
+    /// Backends that don't support this apply all rules, see
+    /// [`ProcessContext::process_incremental`](process/struct.ProcessContext.html#method.process_incremental).
+    fn apply_incremental(
+        rules: Vec<Self::Rule>,
+        applied: &mut AppliedRules,
+        ctx: &ProcessContext<Self>,
+    ) -> Result<()> {
+        applied.clear();
+        Self::apply(rules, ctx)
+    }
+
+    /// Render the processed rules in the format they are applied in, without applying them.
+    fn render(_rules: Vec<Self::Rule>) -> Result<Vec<Ruleset>> {
+        Err(DFWError::TraitMethodUnimplemented {
+            method: "FirewallBackend::render".to_owned(),
+        }
+        .into())
+    }
+
+    /// Compare the processed rules with the rules currently applied, without applying them.
+    fn diff(_rules: Vec<Self::Rule>, _ctx: &ProcessContext<Self>) -> Result<Vec<RulesetDiff>> {
+        Err(DFWError::TraitMethodUnimplemented {
+            method: "FirewallBackend::diff".to_owned(),
+        }
+        .into())
+    }
+
+    /// Save the rules currently applied to the tables or chains owned by DFW, in the format they
+    /// can be restored in.
+    fn snapshot(_logger: &Logger) -> Result<Vec<Ruleset>> {
+        Err(DFWError::TraitMethodUnimplemented {
+            method: "FirewallBackend::snapshot".to_owned(),
+        }
+        .into())
+    }
+
+    /// Restore the rules saved by [`snapshot`](#method.snapshot).
+    ///
+    /// This doesn't require a [`ProcessContext`](process/struct.ProcessContext.html), which allows
+    /// restoring the rules even if the container runtime isn't reachable.
+    fn restore_snapshot(_snapshot: &[Ruleset], _logger: &Logger) -> Result<()> {
+        Err(DFWError::TraitMethodUnimplemented {
+            method: "FirewallBackend::restore_snapshot".to_owned(),
+        }
+        .into())
+    }
+}
+
+/// Processed rules as they are passed to the command applying them, e.g. an nft script.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Ruleset {
+    /// Command the ruleset is applied with, e.g. `nft` or `ip6tables-restore`.
+    pub command: String,
+    /// Lines of the ruleset.
+    pub lines: Vec<String>,
 }
@@ -0,0 +1,458 @@
+// Copyright Pit Kleyersburg <pitkley@googlemail.com>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+//! Migration of configurations using deprecated fields.
+//!
+//! The migration operates on the TOML document itself, which means that comments and formatting
+//! of the configuration are retained. JSON and YAML configurations are migrated through their
+//! TOML representation and are rewritten as a whole, which doesn't retain comments, formatting or
+//! the order of keys.
+
+use crate::{
+    errors::*,
+    util::{without_nulls, ConfigFormat},
+};
+use failure::{bail, format_err, ResultExt};
+use std::path::Path;
+use toml_edit::{DocumentMut, Item, Key, Table};
+
+/// The major version of the configuration schema the migration produces.
+pub const CURRENT_VERSION: u32 = 1;
+
+/// Firewall-backend the configuration is migrated for.
+///
+/// Some deprecated fields are only honored by specific firewall-backends, which is why the
+/// migration has to know the backend the configuration is used with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// The nftables backend.
+    Nftables,
+    /// The iptables backend.
+    Iptables,
+}
+
+/// Result of migrating a configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigratedConfig {
+    /// The migrated configuration.
+    pub contents: String,
+    /// The changes that were applied.
+    pub changes: Vec<String>,
+    /// Problems that require manual intervention.
+    pub notes: Vec<String>,
+}
+
+impl MigratedConfig {
+    /// Check if the migration changed the configuration.
+    pub fn is_changed(&self) -> bool {
+        !self.changes.is_empty()
+    }
+}
+
+/// A single migration step.
+///
+/// Every step is tagged with the major version of the configuration schema it migrates to. Steps
+/// are applied in order, up to and including the target version. Once a new major version is
+/// introduced, its first step is responsible for setting the top-level `version` key.
+struct Step {
+    version: u32,
+    apply: fn(&mut DocumentMut, Backend, &mut MigratedConfig) -> Result<()>,
+}
+
+const STEPS: &[Step] = &[
+    Step {
+        version: 1,
+        apply: rename_defaults,
+    },
+    Step {
+        version: 1,
+        apply: move_initialization,
+    },
+    Step {
+        version: 1,
+        apply: move_custom_tables,
+    },
+];
+
+/// Migrate a TOML configuration to the [current version](constant.CURRENT_VERSION.html).
+pub fn migrate(contents: &str, backend: Backend) -> Result<MigratedConfig> {
+    migrate_to(contents, backend, CURRENT_VERSION)
+}
+
+/// Migrate a TOML configuration to the given major version of the configuration schema.
+///
+/// Configurations without a top-level `version` key are of version 1.
+pub fn migrate_to(contents: &str, backend: Backend, version: u32) -> Result<MigratedConfig> {
+    if version > CURRENT_VERSION {
+        bail!(
+            "can't migrate to version {}, the latest supported version is {}",
+            version,
+            CURRENT_VERSION
+        );
+    }
+    let mut document = contents
+        .parse::<DocumentMut>()
+        .context("failed to parse the configuration")?;
+    let document_version = match document.get("version") {
+        None => 1,
+        Some(item) => item
+            .as_integer()
+            .and_then(|version| u32::try_from(version).ok())
+            .ok_or_else(|| format_err!("`version` has to be a positive integer"))?,
+    };
+    if document_version > version {
+        bail!(
+            "the configuration is of version {}, it can't be migrated to version {}",
+            document_version,
+            version
+        );
+    }
+
+    let mut migrated = MigratedConfig {
+        contents: String::new(),
+        changes: Vec::new(),
+        notes: Vec::new(),
+    };
+    for step in STEPS.iter().filter(|step| step.version <= version) {
+        (step.apply)(&mut document, backend, &mut migrated)?;
+    }
+    migrated.contents = if migrated.is_changed() {
+        document.to_string()
+    } else {
+        contents.to_owned()
+    };
+
+    Ok(migrated)
+}
+
+/// Migrate a configuration file, the format being chosen by the file extension as described in
+/// [`load_file`](../util/fn.load_file.html).
+pub fn migrate_file(file: &Path, contents: &str, backend: Backend) -> Result<MigratedConfig> {
+    let format = ConfigFormat::from_path(file);
+    let value: serde_json::Value = match format {
+        ConfigFormat::Toml => return migrate(contents, backend),
+        ConfigFormat::Json => {
+            serde_json::from_str(contents).context("failed to parse the configuration")?
+        }
+        ConfigFormat::Yaml => {
+            serde_yaml::from_str(contents).context("failed to parse the configuration")?
+        }
+    };
+    let toml = toml::to_string(&toml::Value::try_from(without_nulls(value))?)
+        .context("failed to convert the configuration to TOML")?;
+
+    let mut migrated = migrate(&toml, backend)?;
+    migrated.contents = if migrated.is_changed() {
+        let value: serde_json::Value = toml::from_str(&migrated.contents)?;
+        match format {
+            ConfigFormat::Json => serde_json::to_string_pretty(&value)? + "\n",
+            _ => serde_yaml::to_string(&value)?,
+        }
+    } else {
+        contents.to_owned()
+    };
+
+    Ok(migrated)
+}
+
+/// `defaults` was renamed to `global_defaults` in v1.2.0.
+fn rename_defaults(
+    document: &mut DocumentMut,
+    _: Backend,
+    migrated: &mut MigratedConfig,
+) -> Result<()> {
+    if !document.contains_key("defaults") {
+        return Ok(());
+    }
+    if document.contains_key("global_defaults") {
+        migrated.notes.push(
+            "both `defaults` and `global_defaults` are specified, merge them manually".to_owned(),
+        );
+        return Ok(());
+    }
+
+    if let Some((key, item)) = document.remove_entry("defaults") {
+        document.insert_formatted(&renamed(&key, "global_defaults"), item);
+        migrated
+            .changes
+            .push("renamed `defaults` to `global_defaults`".to_owned());
+    }
+
+    Ok(())
+}
+
+/// `initialization` was moved to `backend_defaults.initialization` in v1.2.0.
+fn move_initialization(
+    document: &mut DocumentMut,
+    backend: Backend,
+    migrated: &mut MigratedConfig,
+) -> Result<()> {
+    if !document.contains_key("initialization") {
+        return Ok(());
+    }
+    if backend != Backend::Nftables {
+        migrated.notes.push(
+            "`initialization` is ignored by the iptables backend, move the rules to \
+             `backend_defaults.initialization.v4`/`v6` or remove it"
+                .to_owned(),
+        );
+        return Ok(());
+    }
+
+    if backend_defaults(document)?.contains_key("initialization") {
+        migrated.notes.push(
+            "both `initialization` and `backend_defaults.initialization` are specified, the \
+             former is ignored and has to be merged manually"
+                .to_owned(),
+        );
+        return Ok(());
+    }
+
+    if let Some((key, item)) = document.remove_entry("initialization") {
+        backend_defaults(document)?.insert_formatted(&key, item);
+        migrated
+            .changes
+            .push("moved `initialization` to `backend_defaults.initialization`".to_owned());
+    }
+
+    Ok(())
+}
+
+/// `global_defaults.custom_tables` was moved to `backend_defaults.custom_tables` in v1.2.0.
+fn move_custom_tables(
+    document: &mut DocumentMut,
+    backend: Backend,
+    migrated: &mut MigratedConfig,
+) -> Result<()> {
+    let has_custom_tables = document
+        .get("global_defaults")
+        .and_then(Item::as_table_like)
+        .is_some_and(|global_defaults| global_defaults.contains_key("custom_tables"));
+    if !has_custom_tables {
+        return Ok(());
+    }
+    if backend != Backend::Nftables {
+        migrated.notes.push(
+            "`global_defaults.custom_tables` is ignored by the iptables backend, remove it"
+                .to_owned(),
+        );
+        return Ok(());
+    }
+    if backend_defaults(document)?.contains_key("custom_tables") {
+        migrated.notes.push(
+            "both `global_defaults.custom_tables` and `backend_defaults.custom_tables` are \
+             specified, the former is ignored and has to be merged manually"
+                .to_owned(),
+        );
+        return Ok(());
+    }
+
+    let entry = match document.get_mut("global_defaults") {
+        Some(Item::Table(global_defaults)) => global_defaults.remove_entry("custom_tables"),
+        Some(global_defaults) => global_defaults
+            .as_table_like_mut()
+            .and_then(|global_defaults| global_defaults.remove("custom_tables"))
+            .map(|item| (Key::new("custom_tables"), item)),
+        None => None,
+    };
+    if let Some((key, item)) = entry {
+        backend_defaults(document)?.insert_formatted(&key, item);
+        migrated.changes.push(
+            "moved `global_defaults.custom_tables` to `backend_defaults.custom_tables`".to_owned(),
+        );
+    }
+
+    Ok(())
+}
+
+/// Retrieve the `backend_defaults` table, creating it if necessary.
+///
+/// Fails if `backend_defaults` is specified, but isn't a table.
+fn backend_defaults(document: &mut DocumentMut) -> Result<&mut Table> {
+    let item = document.entry("backend_defaults").or_insert_with(|| {
+        let mut table = Table::new();
+        table.set_implicit(true);
+        Item::Table(table)
+    });
+    if let Item::Value(toml_edit::Value::InlineTable(_)) = item {
+        if let Item::Value(toml_edit::Value::InlineTable(inline_table)) = std::mem::take(item) {
+            *item = Item::Table(inline_table.into_table());
+        }
+    }
+
+    item.as_table_mut()
+        .ok_or_else(|| format_err!("`backend_defaults` has to be a table"))
+}
+
+fn renamed(key: &Key, name: &str) -> Key {
+    Key::new(name).with_leaf_decor(key.leaf_decor().clone())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn migrate_deprecated_fields() {
+        let config = r#"# Global settings.
+[defaults]
+external_network_interfaces = "eth0"
+# Tables DFW should hook into.
+custom_tables = { name = "filter", chains = ["input", "forward"] }
+
+# Rules applied before DFW.
+[initialization]
+rules = [
+    "add rule inet filter input tcp dport 22 accept",
+]
+
+[container_to_container]
+default_policy = "drop"
+"#;
+        let expected = r#"# Global settings.
+[global_defaults]
+external_network_interfaces = "eth0"
+
+[backend_defaults]
+# Tables DFW should hook into.
+custom_tables = { name = "filter", chains = ["input", "forward"] }
+
+# Rules applied before DFW.
+[backend_defaults.initialization]
+rules = [
+    "add rule inet filter input tcp dport 22 accept",
+]
+
+[container_to_container]
+default_policy = "drop"
+"#;
+
+        let migrated = migrate(config, Backend::Nftables).unwrap();
+
+        assert_eq!(expected, migrated.contents);
+        assert_eq!(3, migrated.changes.len());
+        assert!(migrated.notes.is_empty());
+    }
+
+    #[test]
+    fn migrate_unchanged() {
+        let config = r#"[global_defaults]
+external_network_interfaces = "eth0" # The uplink.
+"#;
+
+        let migrated = migrate(config, Backend::Nftables).unwrap();
+
+        assert_eq!(config, migrated.contents);
+        assert!(!migrated.is_changed());
+    }
+
+    #[test]
+    fn migrate_conflicting_fields() {
+        let config = r#"[initialization]
+rules = ["add table inet custom"]
+
+[backend_defaults.initialization]
+rules = ["add table inet other"]
+"#;
+
+        let migrated = migrate(config, Backend::Nftables).unwrap();
+
+        assert_eq!(config, migrated.contents);
+        assert_eq!(1, migrated.notes.len());
+    }
+
+    #[test]
+    fn migrate_iptables() {
+        let config = r#"[initialization]
+rules = ["add table inet custom"]
+"#;
+
+        let migrated = migrate(config, Backend::Iptables).unwrap();
+
+        assert_eq!(config, migrated.contents);
+        assert_eq!(1, migrated.notes.len());
+    }
+
+    #[test]
+    fn migrate_invalid_backend_defaults() {
+        for backend_defaults in &["5", "[]", "\"nftables\""] {
+            let config = format!(
+                "backend_defaults = {}\n\n[initialization]\nrules = []\n",
+                backend_defaults
+            );
+            assert!(migrate(&config, Backend::Nftables).is_err());
+
+            let config = format!(
+                "backend_defaults = {}\n\n[global_defaults]\ncustom_tables = []\n",
+                backend_defaults
+            );
+            assert!(migrate(&config, Backend::Nftables).is_err());
+        }
+    }
+
+    #[test]
+    fn migrate_to_unknown_version() {
+        assert!(migrate_to("", Backend::Nftables, CURRENT_VERSION + 1).is_err());
+    }
+
+    #[test]
+    fn migrate_newer_configuration() {
+        let config = format!("version = {}\n", CURRENT_VERSION + 1);
+        assert!(migrate(&config, Backend::Nftables).is_err());
+        assert!(migrate("version = \"1\"\n", Backend::Nftables).is_err());
+
+        let config = format!("version = {}\n", CURRENT_VERSION);
+        assert!(!migrate(&config, Backend::Nftables).unwrap().is_changed());
+    }
+
+    #[test]
+    fn migrate_json() {
+        let config = r#"{
+  "defaults": { "external_network_interfaces": "eth0" },
+  "initialization": { "rules": ["add table inet custom"] }
+}
+"#;
+        let expected = r#"{
+  "backend_defaults": {
+    "initialization": {
+      "rules": [
+        "add table inet custom"
+      ]
+    }
+  },
+  "global_defaults": {
+    "external_network_interfaces": "eth0"
+  }
+}
+"#;
+
+        let migrated = migrate_file(Path::new("dfw.json"), config, Backend::Nftables).unwrap();
+
+        assert_eq!(expected, migrated.contents);
+        assert_eq!(2, migrated.changes.len());
+    }
+
+    #[test]
+    fn migrate_yaml() {
+        let config = "defaults:\n  external_network_interfaces: eth0\n";
+
+        let migrated = migrate_file(Path::new("dfw.yml"), config, Backend::Nftables).unwrap();
+
+        assert_eq!(
+            "global_defaults:\n  external_network_interfaces: eth0\n",
+            migrated.contents
+        );
+
+        let unchanged = "# The uplink.\nglobal_defaults:\n  external_network_interfaces: eth0\n";
+        let migrated = migrate_file(Path::new("dfw.yaml"), unchanged, Backend::Nftables).unwrap();
+
+        assert_eq!(unchanged, migrated.contents);
+        assert!(!migrated.is_changed());
+    }
+}
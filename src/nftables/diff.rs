@@ -0,0 +1,1041 @@
+// Copyright Pit Kleyersburg <pitkley@googlemail.com>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+//! Reading the rules of the DFW tables through `nft -j`, to compare them with the processed rules.
+//!
+//! Both the rules listed by `nft -j list table` and the processed rules are typed libnftables JSON
+//! objects. Before comparing them, their statements are canonicalized, since nft lists some of
+//! them differently from how DFW writes them: addresses of a single host are listed without the
+//! prefix, the elements of anonymous sets may be reordered, and sets of flags like the connection
+//! tracking state are listed as plain lists.
+//!
+//! The handles of the listed rules allow deleting rules and inserting rules at their position when
+//! only the changes to the rules applied previously are applied.
+//!
+//! Named sets and maps are compared by their elements, which allows updating only the elements
+//! if the addresses of containers change.
+
+use super::{
+    json::{self, Chain, Command, Document, ListedObject, Listing, Object, Rule, Table},
+    rule::{Expression, ExpressionObject, Nat, Operator, Statement, StatementObject, Statements},
+    Family,
+};
+use crate::{diff::chain_delta, errors::*};
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+const TABLE: &str = "dfw";
+const FAMILIES: &[Family] = &[Family::Inet, Family::Ip, Family::Ip6];
+
+/// Rule of a chain of the DFW tables.
+#[derive(Debug, Clone)]
+pub(super) struct ChainRule {
+    /// Chain the rule belongs to, including the family and table, e.g. `inet dfw forward`.
+    chain: String,
+    /// The rule as processed by DFW or listed by `nft`, including the handle of listed rules.
+    rule: Rule,
+    /// The rule with canonical statements and without the handle, see `canonical_statements`.
+    canonical: Rule,
+}
+
+/// Named set or map of the DFW tables.
+#[derive(Debug, Clone, Default)]
+pub(super) struct Set {
+    map: bool,
+    /// Definition of the set as processed by DFW, or the name of the set as listed by `nft`.
+    definition: Option<json::Set>,
+    /// Elements of the set, by the JSON representation of the canonical element.
+    elements: BTreeMap<String, Expression>,
+}
+
+/// Chains, chain policies, rules and sets of the DFW tables.
+#[derive(Debug, Clone, Default)]
+pub(super) struct Chains {
+    /// Chains, by their name including the family and table.
+    chains: BTreeMap<String, Chain>,
+    /// Rules of the chains, in the order they are applied.
+    rules: Vec<ChainRule>,
+    /// Named sets and maps, including the family and table, e.g. `inet dfw dfw_web_5a0f13c2_v4`.
+    sets: BTreeMap<String, Set>,
+}
+
+impl Set {
+    fn kind(&self) -> &'static str {
+        if self.map {
+            "map"
+        } else {
+            "set"
+        }
+    }
+
+    fn extend<'a>(&mut self, elements: impl IntoIterator<Item = &'a Expression>) {
+        for element in elements {
+            self.elements
+                .insert(json_key(&canonical(element)), element.clone());
+        }
+    }
+
+    /// Command for the set or map itself, e.g. to create or delete it.
+    fn command(&self, command: fn(ListedObject) -> Command, set: json::Set) -> Command {
+        command(if self.map {
+            Object::Map(set).into()
+        } else {
+            Object::Set(set).into()
+        })
+    }
+}
+
+impl Chains {
+    /// Canonical sets, set elements, rules and chain policies, as compared by `dfw diff`.
+    pub(super) fn lines(&self) -> Vec<String> {
+        self.sets
+            .iter()
+            .flat_map(|(name, set)| {
+                std::iter::once(format!("add {} {}", set.kind(), name)).chain(
+                    set.elements.values().map(move |element| {
+                        format!("add element {} {{ {} }}", name, canonical(element))
+                    }),
+                )
+            })
+            .chain(
+                self.chains
+                    .iter()
+                    .filter_map(|(name, chain)| match effective_policy(chain) {
+                        "accept" => None,
+                        policy => Some(format!("add chain {} {{ policy {} ; }}", name, policy)),
+                    }),
+            )
+            .chain(
+                self.rules.iter().map(|rule| {
+                    Command::Add(Object::Rule(rule.canonical.clone()).into()).to_string()
+                }),
+            )
+            .collect()
+    }
+
+    /// Check if both contain the same chains, policies and (canonical) rules in the same order,
+    /// and if the sets of `other` hold the same elements.
+    ///
+    /// Sets only contained in `self` are ignored, they are no longer referenced by any rule and
+    /// are removed when applying the changes.
+    pub(super) fn matches(&self, other: &Chains) -> bool {
+        self.chains.len() == other.chains.len()
+            && self.chains.iter().all(|(name, chain)| {
+                other.chains.get(name).is_some_and(|other_chain| {
+                    effective_policy(chain) == effective_policy(other_chain)
+                })
+            })
+            && self.canonical_by_chain() == other.canonical_by_chain()
+            && other.sets.iter().all(|(name, other_set)| {
+                self.sets.get(name).is_some_and(|set| {
+                    set.map == other_set.map && set.elements.keys().eq(other_set.elements.keys())
+                })
+            })
+    }
+
+    fn rules_by_chain(&self) -> BTreeMap<&str, Vec<&ChainRule>> {
+        let mut rules: BTreeMap<&str, Vec<&ChainRule>> = self
+            .chains
+            .keys()
+            .map(|chain| (chain.as_str(), Vec::new()))
+            .collect();
+        for rule in &self.rules {
+            rules.entry(&rule.chain).or_default().push(rule);
+        }
+        rules
+    }
+
+    fn canonical_by_chain(&self) -> BTreeMap<&str, Vec<&Rule>> {
+        self.rules_by_chain()
+            .into_iter()
+            .map(|(chain, rules)| (chain, rules.iter().map(|rule| &rule.canonical).collect()))
+            .collect()
+    }
+
+    fn add_rule(&mut self, rule: &Rule) {
+        let mut canonical = rule.clone();
+        canonical.handle = None;
+        canonical.expr = canonical_statements(&rule.expr);
+        self.rules.push(ChainRule {
+            chain: name(rule.family, &rule.chain),
+            rule: rule.clone(),
+            canonical,
+        });
+    }
+}
+
+/// Chains without a policy, i.e. regular chains, behave like chains with the `accept` policy.
+fn effective_policy(chain: &Chain) -> &str {
+    chain.policy.as_deref().unwrap_or("accept")
+}
+
+/// Name of a chain or set of the DFW tables, including the family and table.
+fn name(family: Family, name: &str) -> String {
+    format!("{} {} {}", family, TABLE, name)
+}
+
+/// Read the chains and rules currently applied to the DFW tables, including the rule handles.
+///
+/// Tables that don't exist (yet) are treated as empty.
+pub(super) fn current_chains() -> Result<Chains> {
+    let mut listing = Listing {
+        nftables: Vec::new(),
+    };
+    for family in FAMILIES {
+        if let Some(table) = list_table_json(*family)? {
+            listing.nftables.extend(table.nftables);
+        }
+    }
+
+    Ok(chains_from_listing(&listing))
+}
+
+/// Save the DFW tables currently applied as a libnftables JSON document, which replaces the DFW
+/// tables with the saved ones when applied through `nft -j -f`.
+///
+/// Tables that don't exist (yet) are deleted when restoring them.
+pub(super) fn snapshot() -> Result<String> {
+    let mut tables = Vec::new();
+    for family in FAMILIES {
+        tables.push((*family, list_table_json(*family)?));
+    }
+
+    Ok(serde_json::to_string(&restore_commands(tables))?)
+}
+
+fn restore_commands(tables: Vec<(Family, Option<Listing>)>) -> Document<Command> {
+    let mut commands = Vec::new();
+    for (family, listing) in tables {
+        // Adding the table first ensures that deleting it succeeds if it doesn't exist.
+        let table = ListedObject::Object(Object::Table(Table::new(family, TABLE)));
+        commands.push(Command::Add(table.clone()));
+        commands.push(Command::Delete(table));
+        if let Some(listing) = listing {
+            commands.extend(
+                listing
+                    .nftables
+                    .into_iter()
+                    .filter(|object| !object.is_metainfo())
+                    .map(|object| Command::Add(object.without_handle())),
+            );
+        }
+    }
+    Document { nftables: commands }
+}
+
+/// List a DFW table through `nft -j`, returning `None` if it doesn't exist.
+fn list_table_json(family: Family) -> Result<Option<Listing>> {
+    json::list(&["list", "table", &family.to_string(), TABLE])
+}
+
+/// Build the chains from the JSON listing of the DFW tables.
+fn chains_from_listing(listing: &Listing) -> Chains {
+    let mut chains = Chains::default();
+
+    for object in listing.objects() {
+        match object {
+            Object::Chain(chain) if chain.table == TABLE => {
+                chains
+                    .chains
+                    .insert(name(chain.family, &chain.name), chain.clone());
+            }
+            Object::Rule(rule) if rule.table == TABLE => chains.add_rule(rule),
+            Object::Set(set) | Object::Map(set) if set.table == TABLE => {
+                let mut listed = Set {
+                    map: matches!(object, Object::Map(_)),
+                    definition: Some(set.elements(Vec::new())),
+                    ..Default::default()
+                };
+                listed.extend(&set.elem);
+                chains.sets.insert(name(set.family, &set.name), listed);
+            }
+            _ => {}
+        }
+    }
+
+    chains
+}
+
+/// The table of the object of a command, if it is a table, chain, rule, set or elements.
+fn command_table(command: &Command) -> Option<&str> {
+    match command.object() {
+        ListedObject::Object(Object::Table(table)) => Some(&table.name),
+        ListedObject::Object(Object::Chain(chain)) => Some(&chain.table),
+        ListedObject::Object(Object::Rule(rule)) => Some(&rule.table),
+        ListedObject::Object(Object::Set(set) | Object::Map(set) | Object::Element(set)) => {
+            Some(&set.table)
+        }
+        ListedObject::Other(_) => None,
+    }
+}
+
+/// Check if a processed command is a command for the DFW tables, as opposed to e.g. rules hooking
+/// into custom tables.
+pub(super) fn is_dfw_table_command(command: &Command) -> bool {
+    command_table(command) == Some(TABLE)
+}
+
+/// Retain the chains, chain policies, rules and sets of the DFW tables of the processed commands.
+pub(super) fn expected_chains(commands: &[Command]) -> Chains {
+    let mut chains = Chains::default();
+
+    for command in commands
+        .iter()
+        .filter(|command| is_dfw_table_command(command))
+    {
+        let object = match command.object() {
+            ListedObject::Object(object) => object,
+            ListedObject::Other(_) => continue,
+        };
+        match (command, object) {
+            (Command::Add(_) | Command::Insert(_), Object::Rule(rule)) => chains.add_rule(rule),
+            (Command::Add(_), Object::Chain(chain)) => {
+                let entry = chains
+                    .chains
+                    .entry(name(chain.family, &chain.name))
+                    .or_insert_with(|| chain.clone());
+                if chain.policy.is_some() {
+                    entry.policy = chain.policy.clone();
+                }
+            }
+            (Command::Add(_), Object::Set(set) | Object::Map(set)) => {
+                let entry = chains.sets.entry(name(set.family, &set.name)).or_default();
+                entry.map = matches!(object, Object::Map(_));
+                entry.definition = Some(set.clone());
+            }
+            (Command::Flush(_), Object::Set(set) | Object::Map(set)) => {
+                if let Some(entry) = chains.sets.get_mut(&name(set.family, &set.name)) {
+                    entry.elements.clear();
+                }
+            }
+            (Command::Add(_), Object::Element(set)) => {
+                chains
+                    .sets
+                    .entry(name(set.family, &set.name))
+                    .or_default()
+                    .extend(&set.elem);
+            }
+            _ => {}
+        }
+    }
+
+    chains
+}
+
+/// Build the commands transforming the chains currently applied into the expected chains,
+/// deleting rules by their handle and inserting rules before the handle of the following rule.
+///
+/// Sets are created before and removed after the rules referencing them, only the elements that
+/// changed are deleted from or added to the sets that exist already.
+///
+/// Returns `None` if the chains can't be transformed incrementally, e.g. because a chain has to be
+/// created or removed, in which case all rules have to be applied.
+pub(super) fn delta_commands(current: &Chains, expected: &Chains) -> Option<Vec<Command>> {
+    if current.chains.keys().ne(expected.chains.keys()) {
+        return None;
+    }
+
+    let mut commands = Vec::new();
+    for (name, expected_set) in &expected.sets {
+        let definition = expected_set.definition.as_ref()?;
+        let added: Vec<Expression> = match current.sets.get(name) {
+            Some(current_set) => {
+                if current_set.map != expected_set.map {
+                    return None;
+                }
+                let removed: Vec<Expression> = current_set
+                    .elements
+                    .iter()
+                    .filter(|(key, _)| !expected_set.elements.contains_key(*key))
+                    .map(|(_, element)| match element {
+                        // Map elements are deleted by their key.
+                        Expression::List(pair) if pair.len() == 2 => pair[0].clone(),
+                        element => element.clone(),
+                    })
+                    .collect();
+                if !removed.is_empty() {
+                    commands.push(Command::Delete(
+                        Object::Element(definition.elements(removed)).into(),
+                    ));
+                }
+                expected_set
+                    .elements
+                    .iter()
+                    .filter(|(key, _)| !current_set.elements.contains_key(*key))
+                    .map(|(_, element)| element.clone())
+                    .collect()
+            }
+            None => {
+                commands.push(expected_set.command(Command::Add, definition.clone()));
+                expected_set.elements.values().cloned().collect()
+            }
+        };
+        if !added.is_empty() {
+            commands.push(Command::Add(
+                Object::Element(definition.elements(added)).into(),
+            ));
+        }
+    }
+
+    for (name, expected_chain) in &expected.chains {
+        let policy = effective_policy(expected_chain);
+        if policy != effective_policy(&current.chains[name]) {
+            let mut chain = expected_chain.clone();
+            chain.policy = Some(policy.to_owned());
+            commands.push(Command::Add(Object::Chain(chain).into()));
+        }
+    }
+
+    let current_rules = current.rules_by_chain();
+    for (chain, expected_rules) in expected.rules_by_chain() {
+        let current_rules = current_rules.get(chain)?;
+        let delta = chain_delta(
+            &current_rules
+                .iter()
+                .map(|rule| &rule.canonical)
+                .collect::<Vec<_>>(),
+            &expected_rules
+                .iter()
+                .map(|rule| &rule.canonical)
+                .collect::<Vec<_>>(),
+        )?;
+
+        for index in delta.removed {
+            let current_rule = &current_rules[index].rule;
+            let mut rule = Rule::new(
+                current_rule.family,
+                TABLE,
+                &current_rule.chain,
+                Statements::default(),
+            );
+            rule.handle = Some(current_rule.handle?);
+            commands.push(Command::Delete(Object::Rule(rule).into()));
+        }
+        for (index, before) in delta.added {
+            let mut rule = expected_rules[index].rule.clone();
+            commands.push(match before {
+                Some(before) => {
+                    rule.handle = Some(current_rules[before].rule.handle?);
+                    Command::Insert(Object::Rule(rule).into())
+                }
+                None => {
+                    rule.handle = None;
+                    Command::Add(Object::Rule(rule).into())
+                }
+            });
+        }
+    }
+
+    for (name, current_set) in &current.sets {
+        if !expected.sets.contains_key(name) {
+            let definition = current_set.definition.as_ref()?;
+            commands.push(current_set.command(Command::Delete, definition.elements(Vec::new())));
+        }
+    }
+
+    Some(commands)
+}
+
+/// Canonicalize the statements of a rule, such that the statements DFW writes and the statements
+/// nft lists for them are equal.
+fn canonical_statements(statements: &Statements) -> Statements {
+    Statements(
+        statements
+            .0
+            .iter()
+            .map(|statement| match statement {
+                Statement::Object(StatementObject::Match { op, left, right }) => {
+                    Statement::Object(StatementObject::Match {
+                        // The implicit operator is listed for sets of flags, which are matched
+                        // like a set of the flags.
+                        op: match op {
+                            Operator::In => Operator::Eq,
+                            op => *op,
+                        },
+                        left: canonical(left),
+                        right: match right {
+                            Expression::List(flags) => canonical_set(flags),
+                            right => canonical(right),
+                        },
+                    })
+                }
+                Statement::Object(StatementObject::Mangle { key, value }) => {
+                    Statement::Object(StatementObject::Mangle {
+                        key: canonical(key),
+                        value: canonical(value),
+                    })
+                }
+                // The family of the address is only listed within the `inet` family.
+                Statement::Object(StatementObject::Dnat(nat)) => {
+                    Statement::Object(StatementObject::Dnat(Nat {
+                        addr: nat.addr.as_ref().map(canonical),
+                        port: nat.port.as_ref().map(canonical),
+                        family: None,
+                    }))
+                }
+                statement => statement.clone(),
+            })
+            .collect(),
+    )
+}
+
+/// Canonicalize an expression: addresses are written in their shortest form, prefixes covering a
+/// single address are written as the address and anonymous sets are sorted.
+fn canonical(expression: &Expression) -> Expression {
+    match expression {
+        Expression::String(value) => match value.parse::<IpAddr>() {
+            Ok(address) => address.to_string().into(),
+            Err(_) => expression.clone(),
+        },
+        Expression::List(values) => Expression::List(values.iter().map(canonical).collect()),
+        Expression::Object(object) => match &**object {
+            ExpressionObject::Prefix { addr, len } => canonical_prefix(addr, *len),
+            ExpressionObject::Range([start, end]) => {
+                Expression::range(canonical(start), canonical(end))
+            }
+            ExpressionObject::Set(values) => canonical_set(values),
+            ExpressionObject::Map { key, data } => Expression::map(canonical(key), canonical(data)),
+            ExpressionObject::And([left, right]) => {
+                Expression::Object(Box::new(ExpressionObject::And([
+                    canonical(left),
+                    canonical(right),
+                ])))
+            }
+            _ => expression.clone(),
+        },
+        _ => expression.clone(),
+    }
+}
+
+/// Sort and deduplicate the elements of an anonymous set, a set of a single element matches like
+/// the element itself.
+fn canonical_set(values: &[Expression]) -> Expression {
+    let elements: BTreeMap<String, Expression> = values
+        .iter()
+        .map(|value| {
+            let value = canonical(value);
+            (json_key(&value), value)
+        })
+        .collect();
+    Expression::set_or_value(elements.into_values())
+}
+
+/// Mask the address of a prefix to the network address, as nft lists it.
+fn canonical_prefix(addr: &str, len: u8) -> Expression {
+    let address = match addr.parse::<IpAddr>() {
+        Ok(IpAddr::V4(address)) if len <= 32 => IpAddr::V4(Ipv4Addr::from(
+            u32::from(address) & u32::MAX.checked_shl(32 - u32::from(len)).unwrap_or(0),
+        )),
+        Ok(IpAddr::V6(address)) if len <= 128 => IpAddr::V6(Ipv6Addr::from(
+            u128::from(address) & u128::MAX.checked_shl(128 - u32::from(len)).unwrap_or(0),
+        )),
+        _ => return Expression::address(&format!("{}/{}", addr, len)),
+    };
+    match (address, len) {
+        (IpAddr::V4(_), 32) | (IpAddr::V6(_), 128) => address.to_string().into(),
+        _ => Expression::address(&format!("{}/{}", address, len)),
+    }
+}
+
+/// Key identifying an expression, e.g. an element of a set.
+fn json_key(expression: &Expression) -> String {
+    serde_json::to_string(expression).unwrap_or_else(|_| expression.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        nftables::{rule::RuleBuilder, split_rules, Nftables},
+        process::{ContainerFilter, Process, ProcessContext, ProcessingOptions},
+        runtime::InMemoryRuntime,
+        types::DFW,
+    };
+    use serde_json::{json, Value};
+    use slog::{o, Discard, Logger};
+
+    fn listing(objects: Value) -> Listing {
+        serde_json::from_value(json!({ "nftables": objects })).unwrap()
+    }
+
+    fn commands(commands: Value) -> Vec<Command> {
+        serde_json::from_value::<Document<Command>>(json!({ "nftables": commands }))
+            .unwrap()
+            .nftables
+    }
+
+    fn statements(statements: Value) -> Statements {
+        serde_json::from_value(statements).unwrap()
+    }
+
+    fn matches(left: Value, right: Value) -> Value {
+        json!({"match": {"op": "==", "left": left, "right": right}})
+    }
+
+    fn payload(protocol: &str, field: &str) -> Value {
+        json!({"payload": {"protocol": protocol, "field": field}})
+    }
+
+    fn meta(key: &str) -> Value {
+        json!({"meta": {"key": key}})
+    }
+
+    fn mark() -> Value {
+        json!({"mangle": {"key": {"meta": {"key": "mark"}}, "value": 223}})
+    }
+
+    fn base_chain(family: &str, name: &str, hook: &str, policy: &str) -> Value {
+        json!({"chain": {"family": family, "table": "dfw", "name": name, "handle": 1, "type": "filter", "hook": hook, "prio": -5, "policy": policy}})
+    }
+
+    fn rule(family: &str, chain: &str, handle: u64, expr: Value) -> Value {
+        json!({"rule": {"family": family, "table": "dfw", "chain": chain, "handle": handle, "expr": expr}})
+    }
+
+    fn strings(commands: Vec<Command>) -> Vec<String> {
+        commands.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn canonical_listed_statements() {
+        // Statements as listed by nft
+        let listed = statements(json!([
+            matches(meta("iifname"), json!("br-backend")),
+            {"match": {"op": "in", "left": {"ct": {"key": "state"}}, "right": ["established", "related"]}},
+            matches(payload("ip6", "saddr"), json!({"prefix": {"addr": "fd00:db8:0:0::2", "len": 128}})),
+            matches(payload("ip", "daddr"), json!({"prefix": {"addr": "10.0.0.0", "len": 8}})),
+            mark(),
+            {"dnat": {"family": "ip", "addr": "172.20.0.2", "port": 443}},
+        ]));
+        // Statements as written by DFW
+        let written = Statements(vec![
+            Statement::matches(Expression::meta("iifname"), "br-backend"),
+            Statement::ct_state(Expression::set(["related", "established"])),
+            Statement::matches(Expression::payload("ip6", "saddr"), "fd00:db8::2"),
+            Statement::matches(
+                Expression::payload("ip", "daddr"),
+                Expression::address("10.1.2.3/8"),
+            ),
+            Statement::mark(),
+            Statement::Object(StatementObject::Dnat(Nat {
+                addr: Some("172.20.0.2".into()),
+                port: Some(443u16.into()),
+                family: None,
+            })),
+        ]);
+        assert_ne!(listed, written);
+        assert_eq!(
+            canonical_statements(&listed),
+            canonical_statements(&written)
+        );
+        assert_eq!(
+            "meta iifname br-backend ct state { \"established\", \"related\" } ip6 saddr fd00:db8::2 ip daddr 10.0.0.0/8 meta mark set 0xdf dnat 172.20.0.2:443"
+                .replace('"', ""),
+            canonical_statements(&written).to_string(),
+        );
+
+        // The statements DFW builds for a rule are retained.
+        let mut nft_rule = RuleBuilder::default();
+        nft_rule
+            .in_interface("eni")
+            .source_address("172.20.0.2/32")
+            .destination_port(443u16)
+            .verdict(crate::types::RuleVerdict::Accept);
+        let built = nft_rule.build().unwrap();
+        assert_eq!(
+            statements(json!([
+                matches(payload("tcp", "dport"), json!(443)),
+                matches(payload("ip", "saddr"), json!("172.20.0.2")),
+                matches(meta("iifname"), json!("eni")),
+                mark(),
+                {"accept": null},
+            ])),
+            canonical_statements(&built),
+        );
+    }
+
+    #[test]
+    fn chains_from_json_listing() {
+        let chains = chains_from_listing(&listing(json!([
+            {"metainfo": {"version": "1.0.6", "release_name": "Lester Gooch #5", "json_schema_version": 1}},
+            {"table": {"family": "inet", "name": "dfw", "handle": 7}},
+            {"set": {"family": "inet", "name": "dfw_web_e79ae99f_v4", "table": "dfw", "type": "ipv4_addr", "handle": 3, "flags": ["interval"], "elem": ["172.20.0.2", {"prefix": {"addr": "172.21.0.0", "len": 16}}, {"range": ["172.22.0.1", "172.22.0.9"]}]}},
+            base_chain("inet", "forward", "forward", "drop"),
+            rule("inet", "forward", 5, json!([
+                matches(payload("ip", "saddr"), json!("@dfw_web_e79ae99f_v4")),
+                matches(payload("ip", "daddr"), json!("172.20.0.3")),
+                {"accept": null},
+            ])),
+            rule("inet", "forward", 6, json!([matches(meta("iifname"), json!("eni")), {"accept": null}])),
+            {"table": {"family": "ip", "name": "dfw", "handle": 8}},
+            {"map": {"family": "ip", "name": "dfw_web_dnat_ec5802f4_v4", "table": "dfw", "type": "inet_service", "handle": 2, "map": "ipv4_addr", "elem": [[443, "172.20.0.2"], [{"range": [8000, 8080]}, "172.20.0.3"]]}},
+            {"chain": {"family": "ip", "table": "dfw", "name": "prerouting", "handle": 1, "type": "nat", "hook": "prerouting", "prio": -105, "policy": "accept"}},
+            rule("ip", "prerouting", 5, json!([
+                matches(meta("iifname"), json!("eni")),
+                mark(),
+                {"dnat": {"addr": {"map": {"key": {"payload": {"protocol": "tcp", "field": "dport"}}, "data": "@dfw_web_dnat_ec5802f4_v4"}}}},
+            ])),
+            {"table": {"family": "inet", "name": "filter", "handle": 9}},
+            {"chain": {"family": "inet", "table": "filter", "name": "forward", "handle": 1, "type": "filter", "hook": "forward", "prio": 0, "policy": "accept"}},
+        ])));
+        assert_eq!(
+            vec![
+                "add set inet dfw dfw_web_e79ae99f_v4",
+                "add element inet dfw dfw_web_e79ae99f_v4 { 172.20.0.2 }",
+                "add element inet dfw dfw_web_e79ae99f_v4 { 172.21.0.0/16 }",
+                "add element inet dfw dfw_web_e79ae99f_v4 { 172.22.0.1-172.22.0.9 }",
+                "add map ip dfw dfw_web_dnat_ec5802f4_v4",
+                "add element ip dfw dfw_web_dnat_ec5802f4_v4 { 443 : 172.20.0.2 }",
+                "add element ip dfw dfw_web_dnat_ec5802f4_v4 { 8000-8080 : 172.20.0.3 }",
+                "add chain inet dfw forward { policy drop ; }",
+                "add rule inet dfw forward ip saddr @dfw_web_e79ae99f_v4 ip daddr 172.20.0.3 accept",
+                "add rule inet dfw forward meta iifname eni accept",
+                "add rule ip dfw prerouting meta iifname eni meta mark set 0xdf dnat tcp dport map @dfw_web_dnat_ec5802f4_v4",
+            ],
+            chains.lines(),
+        );
+        assert_eq!(
+            vec![Some(5), Some(6), Some(5)],
+            chains
+                .rules
+                .iter()
+                .map(|rule| rule.rule.handle)
+                .collect::<Vec<_>>(),
+        );
+    }
+
+    #[test]
+    fn expected_rules_only_dfw_tables() {
+        let commands = commands(json!([
+            {"add": {"table": {"family": "inet", "name": "dfw"}}},
+            {"add": {"chain": {"family": "inet", "table": "dfw", "name": "input", "type": "filter", "hook": "input", "prio": -5}}},
+            {"add": {"rule": {"family": "inet", "table": "dfw", "chain": "input", "expr": [{"match": {"op": "in", "left": {"ct": {"key": "state"}}, "right": "invalid"}}, {"drop": null}]}}},
+            {"add": {"chain": {"family": "inet", "table": "dfw", "name": "forward", "type": "filter", "hook": "forward", "prio": -5}}},
+            {"add": {"chain": {"family": "inet", "table": "dfw", "name": "forward", "type": "filter", "hook": "forward", "prio": -5, "policy": "drop"}}},
+            {"insert": {"rule": {"family": "inet", "table": "filter", "chain": "forward", "comment": "DFW-MARKER:defaults;filter;forward;meta-mark", "expr": [{"accept": null}]}}},
+            {"add": {"rule": {"family": "inet", "table": "dfw", "chain": "forward", "expr": [matches(meta("iifname"), json!("eni")), {"accept": null}]}}},
+        ]));
+        assert_eq!(
+            vec![
+                "add chain inet dfw forward { policy drop ; }",
+                "add rule inet dfw input ct state invalid drop",
+                "add rule inet dfw forward meta iifname eni accept",
+            ],
+            expected_chains(&commands).lines(),
+        );
+        assert_eq!(
+            vec![true, true, true, true, true, false, true],
+            commands
+                .iter()
+                .map(is_dfw_table_command)
+                .collect::<Vec<_>>(),
+        );
+    }
+
+    #[test]
+    fn delta_commands_use_handles() {
+        let current = chains_from_listing(&listing(json!([
+            base_chain("inet", "forward", "forward", "accept"),
+            rule(
+                "inet",
+                "forward",
+                4,
+                json!([{"match": {"op": "in", "left": {"ct": {"key": "state"}}, "right": "invalid"}}, {"drop": null}])
+            ),
+            rule(
+                "inet",
+                "forward",
+                5,
+                json!([
+                    matches(payload("ip", "saddr"), json!("172.20.0.2")),
+                    matches(payload("ip", "daddr"), json!("172.20.0.3")),
+                    {"accept": null},
+                ])
+            ),
+            rule(
+                "inet",
+                "forward",
+                6,
+                json!([matches(meta("iifname"), json!("eni")), {"accept": null}])
+            ),
+        ])));
+        let processed = |source: &str, interfaces: &[&str]| {
+            let mut processed = vec![
+                json!({"add": {"table": {"family": "inet", "name": "dfw"}}}),
+                json!({"flush": {"table": {"family": "inet", "name": "dfw"}}}),
+                json!({"add": {"chain": {"family": "inet", "table": "dfw", "name": "forward", "type": "filter", "hook": "forward", "prio": -5}}}),
+                json!({"add": {"rule": {"family": "inet", "table": "dfw", "chain": "forward", "expr": [{"match": {"op": "in", "left": {"ct": {"key": "state"}}, "right": "invalid"}}, {"drop": null}]}}}),
+                json!({"add": {"rule": {"family": "inet", "table": "dfw", "chain": "forward", "expr": [
+                    matches(payload("ip", "saddr"), json!(source)),
+                    matches(payload("ip", "daddr"), json!("172.20.0.3")),
+                    {"accept": null},
+                ]}}}),
+            ];
+            for interface in interfaces {
+                processed.push(json!({"add": {"rule": {"family": "inet", "table": "dfw", "chain": "forward", "expr": [
+                    matches(meta("iifname"), json!(interface)),
+                    {"accept": null},
+                ]}}}));
+            }
+            commands(Value::Array(processed))
+        };
+
+        // The rules applied previously match the rules currently applied.
+        assert!(current.matches(&expected_chains(&processed("172.20.0.2", &["eni"]))));
+
+        let mut rules = processed("172.20.0.4", &["eni", "eni2"]);
+        rules.insert(
+            3,
+            commands(json!([{"add": {"chain": {"family": "inet", "table": "dfw", "name": "forward", "type": "filter", "hook": "forward", "prio": -5, "policy": "drop"}}}]))
+                .remove(0),
+        );
+        let expected = expected_chains(&rules);
+        assert!(!current.matches(&expected));
+        assert_eq!(
+            Some(vec![
+                "add chain inet dfw forward { type filter hook forward priority -5 ; policy drop ; }"
+                    .to_owned(),
+                "delete rule inet dfw forward handle 5".to_owned(),
+                "insert rule inet dfw forward position 6 ip saddr 172.20.0.4 ip daddr 172.20.0.3 accept"
+                    .to_owned(),
+                "add rule inet dfw forward meta iifname eni2 accept".to_owned(),
+            ]),
+            delta_commands(&current, &expected).map(strings),
+        );
+        assert_eq!(
+            json!({"insert": {"rule": {"family": "inet", "table": "dfw", "chain": "forward", "handle": 6, "expr": [
+                matches(payload("ip", "saddr"), json!("172.20.0.4")),
+                matches(payload("ip", "daddr"), json!("172.20.0.3")),
+                {"accept": null},
+            ]}}}),
+            serde_json::to_value(&delta_commands(&current, &expected).unwrap()[2]).unwrap(),
+        );
+
+        // Chains that don't exist yet can't be created incrementally.
+        assert_eq!(None, delta_commands(&Chains::default(), &expected));
+    }
+
+    #[test]
+    fn delta_commands_update_set_elements() {
+        let current = chains_from_listing(&listing(json!([
+            {"set": {"family": "inet", "name": "dfw_web_e79ae99f_v4", "table": "dfw", "type": "ipv4_addr", "handle": 3, "elem": ["172.20.0.2", "172.20.0.4"]}},
+            {"set": {"family": "inet", "name": "dfw_db_3584ecfb_v4", "table": "dfw", "type": "ipv4_addr", "handle": 4}},
+            base_chain("inet", "forward", "forward", "accept"),
+            rule("inet", "forward", 5, json!([
+                matches(payload("ip", "saddr"), json!("@dfw_web_e79ae99f_v4")),
+                matches(payload("ip", "daddr"), json!("@dfw_db_3584ecfb_v4")),
+                {"accept": null},
+            ])),
+            {"map": {"family": "ip", "name": "dfw_web_dnat_ec5802f4_v4", "table": "dfw", "type": "inet_service", "handle": 2, "map": "ipv4_addr", "elem": [[443, "172.20.0.2"]]}},
+        ])));
+        let rules = commands(json!([
+            {"add": {"table": {"family": "inet", "name": "dfw"}}},
+            {"flush": {"table": {"family": "inet", "name": "dfw"}}},
+            {"add": {"chain": {"family": "inet", "table": "dfw", "name": "forward", "type": "filter", "hook": "forward", "prio": -5}}},
+            {"add": {"set": {"family": "inet", "table": "dfw", "name": "dfw_web_e79ae99f_v4", "type": "ipv4_addr"}}},
+            {"flush": {"set": {"family": "inet", "table": "dfw", "name": "dfw_web_e79ae99f_v4"}}},
+            {"add": {"element": {"family": "inet", "table": "dfw", "name": "dfw_web_e79ae99f_v4", "elem": ["172.20.0.2", "172.20.0.3"]}}},
+            {"add": {"set": {"family": "inet", "table": "dfw", "name": "dfw_app_1f1a2b3c_v4", "type": "ipv4_addr"}}},
+            {"flush": {"set": {"family": "inet", "table": "dfw", "name": "dfw_app_1f1a2b3c_v4"}}},
+            {"add": {"element": {"family": "inet", "table": "dfw", "name": "dfw_app_1f1a2b3c_v4", "elem": ["172.20.0.5"]}}},
+            {"add": {"rule": {"family": "inet", "table": "dfw", "chain": "forward", "expr": [
+                matches(payload("ip", "saddr"), json!("@dfw_web_e79ae99f_v4")),
+                matches(payload("ip", "daddr"), json!("@dfw_app_1f1a2b3c_v4")),
+                {"accept": null},
+            ]}}},
+            {"add": {"table": {"family": "ip", "name": "dfw"}}},
+            {"flush": {"table": {"family": "ip", "name": "dfw"}}},
+            {"add": {"map": {"family": "ip", "table": "dfw", "name": "dfw_web_dnat_ec5802f4_v4", "type": "inet_service", "map": "ipv4_addr"}}},
+            {"flush": {"map": {"family": "ip", "table": "dfw", "name": "dfw_web_dnat_ec5802f4_v4"}}},
+            {"add": {"element": {"family": "ip", "table": "dfw", "name": "dfw_web_dnat_ec5802f4_v4", "elem": [[443, "172.20.0.3"]]}}},
+        ]));
+        let expected = expected_chains(&rules);
+        assert!(!current.matches(&expected));
+        assert_eq!(
+            Some(vec![
+                "add set inet dfw dfw_app_1f1a2b3c_v4 { type ipv4_addr ; }".to_owned(),
+                "add element inet dfw dfw_app_1f1a2b3c_v4 { 172.20.0.5 }".to_owned(),
+                "delete element inet dfw dfw_web_e79ae99f_v4 { 172.20.0.4 }".to_owned(),
+                "add element inet dfw dfw_web_e79ae99f_v4 { 172.20.0.3 }".to_owned(),
+                "delete element ip dfw dfw_web_dnat_ec5802f4_v4 { 443 }".to_owned(),
+                "add element ip dfw dfw_web_dnat_ec5802f4_v4 { 443 : 172.20.0.3 }".to_owned(),
+                "delete rule inet dfw forward handle 5".to_owned(),
+                "add rule inet dfw forward ip saddr @dfw_web_e79ae99f_v4 ip daddr @dfw_app_1f1a2b3c_v4 accept"
+                    .to_owned(),
+                "delete set inet dfw dfw_db_3584ecfb_v4".to_owned(),
+            ]),
+            delta_commands(&current, &expected).map(strings),
+        );
+
+        // Sets that are no longer referenced don't prevent applying the changes incrementally.
+        let mut previous = rules[..4].to_vec();
+        previous.extend(commands(json!([
+            {"add": {"element": {"family": "inet", "table": "dfw", "name": "dfw_web_e79ae99f_v4", "elem": ["172.20.0.4", "172.20.0.2"]}}},
+            {"add": {"rule": {"family": "inet", "table": "dfw", "chain": "forward", "expr": [
+                matches(payload("ip", "saddr"), json!("@dfw_web_e79ae99f_v4")),
+                matches(payload("ip", "daddr"), json!("@dfw_db_3584ecfb_v4")),
+                {"accept": null},
+            ]}}},
+        ])));
+        assert!(current.matches(&expected_chains(&previous)));
+    }
+
+    /// Process the rules for the running containers, like `apply_incremental` does.
+    fn processed_commands(containers: &[(&str, &str, &str)]) -> Vec<Command> {
+        let runtime = InMemoryRuntime::from_json(
+            json!({
+                "containers": containers
+                    .iter()
+                    .map(|(id, name, _)| json!({
+                        "Id": id,
+                        "Names": [format!("/{}", name)],
+                        "Labels": {"app": name},
+                        "State": "running",
+                    }))
+                    .collect::<Vec<_>>(),
+                "networks": [{
+                    "Name": "backend",
+                    "Id": "b3a0f1c2d3e4f5a6b7c8d9e0f1a2b3c4d5e6f7a8b9c0d1e2f3a4b5c6d7e8f9a0",
+                    "Driver": "bridge",
+                    "IPAM": {"Config": [{"Subnet": "172.20.0.0/16", "Gateway": "172.20.0.1"}]},
+                    "Options": {"com.docker.network.bridge.name": "br-backend"},
+                    "Containers": containers
+                        .iter()
+                        .map(|(id, name, address)| (id.to_string(), json!({
+                            "Name": name,
+                            "IPv4Address": address,
+                            "IPv6Address": "",
+                        })))
+                        .collect::<serde_json::Map<_, _>>(),
+                }],
+            })
+            .to_string()
+            .as_bytes(),
+        )
+        .unwrap();
+        let dfw: DFW<Nftables> = toml::from_str(
+            r#"
+            [global_defaults]
+            external_network_interfaces = "eni"
+
+            [container_to_container]
+            default_policy = "drop"
+
+            [[container_to_container.rules]]
+            network = "backend"
+            src_container = "web"
+            dst_selector = { "app" = "db" }
+            verdict = "accept"
+
+            [wider_world_to_container]
+            [[wider_world_to_container.rules]]
+            network = "backend"
+            dst_container = "web"
+            expose_port = "8000-8001:8080-8081"
+            "#,
+        )
+        .unwrap();
+        let processing_options = ProcessingOptions {
+            container_filter: ContainerFilter::Running,
+            ..Default::default()
+        };
+        let logger = Logger::root(Discard, o!());
+        let ctx = ProcessContext::new(&runtime, &dfw, &processing_options, &logger, true).unwrap();
+        let rules = Process::<Nftables>::process(&dfw, &ctx).unwrap().unwrap();
+        split_rules(rules).1
+    }
+
+    /// Chains as listed by nft after applying the commands, i.e. including the rule handles.
+    fn applied(commands: &[Command]) -> Chains {
+        let expected = expected_chains(commands);
+        let mut objects: Vec<Value> = expected
+            .chains
+            .values()
+            .map(|chain| json!({ "chain": chain }))
+            .collect();
+        objects.extend(expected.rules.iter().enumerate().map(|(handle, rule)| {
+            let mut rule = rule.rule.clone();
+            rule.handle = Some(handle as u64 + 10);
+            json!({ "rule": rule })
+        }));
+        chains_from_listing(&listing(Value::Array(objects)))
+    }
+
+    #[test]
+    fn delta_commands_container_start_stop() {
+        let web = (
+            "5c1e2b7a9d3f4e6a8b0c1d2e3f4a5b6c7d8e9f0a1b2c3d4e5f6a7b8c9d0e1f2a",
+            "web",
+            "172.20.0.2/16",
+        );
+        let db = (
+            "e4d3c2b1a0f9e8d7c6b5a4f3e2d1c0b9a8f7e6d5c4b3a2f1e0d9c8b7a6f5e4d3",
+            "db",
+            "172.20.0.3/16",
+        );
+        let running = processed_commands(&[web, db]);
+        let stopped = processed_commands(&[web]);
+
+        // The port-map DNAT rule of the web container is matched as listed, the changes can be
+        // applied incrementally.
+        let current = applied(&running);
+        assert!(current.matches(&expected_chains(&running)));
+        assert!(current
+            .lines()
+            .iter()
+            .any(|line| line
+                .contains("dnat 172.20.0.2 : tcp dport map { 8000 : 8080, 8001 : 8081 }")));
+
+        // Stopping the db container only deletes the rule allowing web to reach it.
+        assert_eq!(
+            Some(vec!["delete rule inet dfw forward handle 16".to_owned()]),
+            delta_commands(&current, &expected_chains(&stopped)).map(strings),
+        );
+
+        // Starting it again only adds the rule, before the rule accepting the exposed ports.
+        let current = applied(&stopped);
+        assert!(current.matches(&expected_chains(&stopped)));
+        assert_eq!(
+            Some(vec![
+                "insert rule inet dfw forward position 16 ip saddr 172.20.0.2 ip daddr 172.20.0.3 \
+                 meta iifname br-backend meta oifname br-backend meta mark set 0xdf accept"
+                    .to_owned()
+            ]),
+            delta_commands(&current, &expected_chains(&running)).map(strings),
+        );
+    }
+
+    #[test]
+    fn restore_commands_replace_tables() {
+        let listing = listing(json!([
+            {"metainfo": {"version": "1.0.6", "release_name": "Lester Gooch #5", "json_schema_version": 1}},
+            {"table": {"family": "inet", "name": "dfw", "handle": 7}},
+            {"chain": {"family": "inet", "table": "dfw", "name": "input", "handle": 1, "type": "filter", "hook": "input", "prio": -5, "policy": "accept"}},
+            {"rule": {"family": "inet", "table": "dfw", "chain": "input", "handle": 4, "expr": [{"match": {"op": "in", "left": {"ct": {"key": "state"}}, "right": "invalid"}}, {"drop": null}]}}
+        ]));
+        assert_eq!(
+            json!({"nftables": [
+                {"add": {"table": {"family": "inet", "name": "dfw"}}},
+                {"delete": {"table": {"family": "inet", "name": "dfw"}}},
+                {"add": {"table": {"family": "inet", "name": "dfw"}}},
+                {"add": {"chain": {"family": "inet", "table": "dfw", "name": "input", "type": "filter", "hook": "input", "prio": -5, "policy": "accept"}}},
+                {"add": {"rule": {"family": "inet", "table": "dfw", "chain": "input", "expr": [{"match": {"op": "in", "left": {"ct": {"key": "state"}}, "right": "invalid"}}, {"drop": null}]}}},
+                {"add": {"table": {"family": "ip", "name": "dfw"}}},
+                {"delete": {"table": {"family": "ip", "name": "dfw"}}},
+            ]}),
+            serde_json::to_value(restore_commands(vec![
+                (Family::Inet, Some(listing)),
+                (Family::Ip, None)
+            ]))
+            .unwrap(),
+        );
+    }
+}
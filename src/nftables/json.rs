@@ -0,0 +1,548 @@
+// Copyright Pit Kleyersburg <pitkley@googlemail.com>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+//! Typed model of the libnftables JSON schema (see `libnftables-json(5)`), as listed by
+//! `nft -j list` and applied by `nft -j -f`.
+//!
+//! The model covers the objects DFW generates and inspects: tables, chains, rules, sets and maps.
+//! The statements of rules are modelled in the `rule` module. Fields DFW doesn't inspect, e.g. the
+//! device of a netdev chain, are retained as they were listed, such that listed objects can be
+//! applied again unchanged.
+//!
+//! Commands are displayed in the nft syntax, which is how the processed rules are rendered.
+
+use super::{rule::Expression, rule::Statements, Family, Hook, Nftables, Type};
+use crate::errors::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fmt;
+
+/// A libnftables JSON document, holding either listed objects or commands.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(super) struct Document<T> {
+    pub(super) nftables: Vec<T>,
+}
+
+/// The objects listed by `nft -j list`.
+pub(super) type Listing = Document<ListedObject>;
+
+/// Command applying an object, e.g. `{ "add": { "table": { ... } } }`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(super) enum Command {
+    Add(ListedObject),
+    /// Insert a rule at the beginning of the chain, or before the rule with the handle.
+    Insert(ListedObject),
+    Flush(ListedObject),
+    Delete(ListedObject),
+}
+
+/// Object as listed by `nft -j list`.
+///
+/// Objects DFW doesn't inspect, e.g. the `metainfo` object or flowtables, are retained as they
+/// were listed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub(super) enum ListedObject {
+    Object(Object),
+    Other(Map<String, Value>),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(super) enum Object {
+    Table(Table),
+    Chain(Chain),
+    Rule(Rule),
+    Set(Set),
+    Map(Set),
+    /// Elements of a named set or map, only used in commands.
+    Element(Set),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(super) struct Table {
+    pub(super) family: Family,
+    pub(super) name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) handle: Option<u64>,
+    #[serde(flatten)]
+    pub(super) other: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(super) struct Chain {
+    pub(super) family: Family,
+    pub(super) table: String,
+    pub(super) name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) handle: Option<u64>,
+    /// Type of the chain, only listed for base chains.
+    #[serde(default, rename = "type", skip_serializing_if = "Option::is_none")]
+    pub(super) chain_type: Option<Type>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) hook: Option<Hook>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) prio: Option<i64>,
+    /// Policy of the chain, only listed for base chains.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) policy: Option<String>,
+    #[serde(flatten)]
+    pub(super) other: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(super) struct Rule {
+    pub(super) family: Family,
+    pub(super) table: String,
+    pub(super) chain: String,
+    /// Handle of the rule, or of the rule it is added after or inserted before.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) handle: Option<u64>,
+    #[serde(default, skip_serializing_if = "Statements::is_empty")]
+    pub(super) expr: Statements,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) comment: Option<String>,
+    #[serde(flatten)]
+    pub(super) other: Map<String, Value>,
+}
+
+/// Named set or map.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(super) struct Set {
+    pub(super) family: Family,
+    pub(super) table: String,
+    pub(super) name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) handle: Option<u64>,
+    /// Type of the elements, or of the keys of the map.
+    #[serde(default, rename = "type", skip_serializing_if = "Option::is_none")]
+    pub(super) set_type: Option<String>,
+    /// Type of the values of the map.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) map: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(super) flags: Vec<String>,
+    /// Elements of the set, or key-value pairs (`[key, value]`) of the map.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(super) elem: Vec<Expression>,
+    #[serde(flatten)]
+    pub(super) other: Map<String, Value>,
+}
+
+impl Table {
+    pub(super) fn new(family: Family, name: &str) -> Table {
+        Table {
+            family,
+            name: name.to_owned(),
+            handle: None,
+            other: Map::new(),
+        }
+    }
+}
+
+impl Chain {
+    pub(super) fn new(family: Family, table: &str, name: &str) -> Chain {
+        Chain {
+            family,
+            table: table.to_owned(),
+            name: name.to_owned(),
+            handle: None,
+            chain_type: None,
+            hook: None,
+            prio: None,
+            policy: None,
+            other: Map::new(),
+        }
+    }
+}
+
+impl Rule {
+    pub(super) fn new(family: Family, table: &str, chain: &str, expr: Statements) -> Rule {
+        Rule {
+            family,
+            table: table.to_owned(),
+            chain: chain.to_owned(),
+            handle: None,
+            expr,
+            comment: None,
+            other: Map::new(),
+        }
+    }
+}
+
+impl Set {
+    pub(super) fn new(family: Family, table: &str, name: &str) -> Set {
+        Set {
+            family,
+            table: table.to_owned(),
+            name: name.to_owned(),
+            handle: None,
+            set_type: None,
+            map: None,
+            flags: Vec::new(),
+            elem: Vec::new(),
+            other: Map::new(),
+        }
+    }
+
+    /// The elements of the set, identified by the family, table and name of the set.
+    pub(super) fn elements(&self, elem: Vec<Expression>) -> Set {
+        Set {
+            elem,
+            ..Set::new(self.family, &self.table, &self.name)
+        }
+    }
+}
+
+impl From<Object> for ListedObject {
+    fn from(object: Object) -> ListedObject {
+        ListedObject::Object(object)
+    }
+}
+
+impl Command {
+    pub(super) fn object(&self) -> &ListedObject {
+        match self {
+            Command::Add(object)
+            | Command::Insert(object)
+            | Command::Flush(object)
+            | Command::Delete(object) => object,
+        }
+    }
+
+    pub(super) fn object_mut(&mut self) -> &mut ListedObject {
+        match self {
+            Command::Add(object)
+            | Command::Insert(object)
+            | Command::Flush(object)
+            | Command::Delete(object) => object,
+        }
+    }
+
+    fn verb(&self) -> &'static str {
+        match self {
+            Command::Add(_) => "add",
+            Command::Insert(_) => "insert",
+            Command::Flush(_) => "flush",
+            Command::Delete(_) => "delete",
+        }
+    }
+}
+
+/// The command in the nft syntax, e.g. `add rule inet dfw input ct state invalid drop`.
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let verb = self.verb();
+        let object = match self.object() {
+            ListedObject::Object(object) => object,
+            ListedObject::Other(other) => {
+                return write!(f, "{} {}", verb, Value::from(other.clone()))
+            }
+        };
+        match object {
+            Object::Table(table) => write!(f, "{} table {} {}", verb, table.family, table.name),
+            Object::Chain(chain) => {
+                write!(
+                    f,
+                    "{} chain {} {} {}",
+                    verb, chain.family, chain.table, chain.name
+                )?;
+                let mut definition = Vec::new();
+                if let (Some(chain_type), Some(hook), Some(prio)) =
+                    (chain.chain_type, chain.hook, chain.prio)
+                {
+                    definition.push(format!(
+                        "type {} hook {} priority {} ;",
+                        chain_type, hook, prio
+                    ));
+                }
+                if let Some(policy) = &chain.policy {
+                    definition.push(format!("policy {} ;", policy));
+                }
+                if !definition.is_empty() {
+                    write!(f, " {{ {} }}", definition.join(" "))?;
+                }
+                Ok(())
+            }
+            Object::Rule(rule) => {
+                write!(
+                    f,
+                    "{} rule {} {} {}",
+                    verb, rule.family, rule.table, rule.chain
+                )?;
+                match (self, rule.handle) {
+                    (Command::Delete(_), Some(handle)) => return write!(f, " handle {}", handle),
+                    (_, Some(handle)) => write!(f, " position {}", handle)?,
+                    (_, None) => {}
+                }
+                if !rule.expr.is_empty() {
+                    write!(f, " {}", rule.expr)?;
+                }
+                if let Some(comment) = &rule.comment {
+                    write!(f, r#" comment "{}""#, comment)?;
+                }
+                Ok(())
+            }
+            Object::Set(set) | Object::Map(set) => {
+                let kind = match object {
+                    Object::Map(_) => "map",
+                    _ => "set",
+                };
+                write!(
+                    f,
+                    "{} {} {} {} {}",
+                    verb, kind, set.family, set.table, set.name
+                )?;
+                if let Some(set_type) = &set.set_type {
+                    write!(f, " {{ type {}", set_type)?;
+                    if let Some(map) = &set.map {
+                        write!(f, " : {}", map)?;
+                    }
+                    f.write_str(" ;")?;
+                    if !set.flags.is_empty() {
+                        write!(f, " flags {} ;", set.flags.join(", "))?;
+                    }
+                    f.write_str(" }")?;
+                }
+                Ok(())
+            }
+            Object::Element(set) => write!(
+                f,
+                "{} element {} {} {} {{ {} }}",
+                verb,
+                set.family,
+                set.table,
+                set.name,
+                set.elem
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
+
+impl ListedObject {
+    /// Check if this is the `metainfo` object, describing the version of nft.
+    pub(super) fn is_metainfo(&self) -> bool {
+        matches!(self, ListedObject::Other(other) if other.contains_key("metainfo"))
+    }
+
+    /// Remove the handle, which is assigned by nftables when the object is added.
+    ///
+    /// Rules are otherwise added after the rule the handle refers to.
+    pub(super) fn without_handle(mut self) -> ListedObject {
+        match &mut self {
+            ListedObject::Object(Object::Table(Table { handle, .. }))
+            | ListedObject::Object(Object::Chain(Chain { handle, .. }))
+            | ListedObject::Object(Object::Rule(Rule { handle, .. }))
+            | ListedObject::Object(Object::Set(Set { handle, .. }))
+            | ListedObject::Object(Object::Map(Set { handle, .. }))
+            | ListedObject::Object(Object::Element(Set { handle, .. })) => *handle = None,
+            ListedObject::Other(other) => {
+                for object in other.values_mut() {
+                    if let Some(object) = object.as_object_mut() {
+                        object.remove("handle");
+                    }
+                }
+            }
+        }
+        self
+    }
+}
+
+impl Listing {
+    /// The objects DFW inspects, in the order they were listed.
+    pub(super) fn objects(&self) -> impl Iterator<Item = &Object> {
+        self.nftables.iter().filter_map(|object| match object {
+            ListedObject::Object(object) => Some(object),
+            ListedObject::Other(_) => None,
+        })
+    }
+
+    /// Check if the chain holds a rule with exactly the given comment, e.g. a marker rule.
+    pub(super) fn has_rule_comment(
+        &self,
+        family: Family,
+        table: &str,
+        chain: &str,
+        comment: &str,
+    ) -> bool {
+        self.objects().any(|object| match object {
+            Object::Rule(rule) => {
+                rule.family == family
+                    && rule.table == table
+                    && rule.chain == chain
+                    && rule.comment.as_deref() == Some(comment)
+            }
+            _ => false,
+        })
+    }
+}
+
+/// List objects through `nft -j`, e.g. `list(&["list", "ruleset"])`, returning `None` if the
+/// listed table or chain doesn't exist.
+pub(super) fn list(args: &[&str]) -> Result<Option<Listing>> {
+    let mut json_args = vec!["-j"];
+    json_args.extend_from_slice(args);
+    match Nftables::list(&json_args)? {
+        Some(output) => Ok(Some(serde_json::from_str(&output)?)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    const LISTING: &str = r#"{"nftables": [
+        {"metainfo": {"version": "1.0.6", "release_name": "Lester Gooch #5", "json_schema_version": 1}},
+        {"table": {"family": "inet", "name": "filter", "handle": 3}},
+        {"chain": {"family": "inet", "table": "filter", "name": "forward", "handle": 2, "type": "filter", "hook": "forward", "prio": 0, "policy": "accept"}},
+        {"chain": {"family": "inet", "table": "filter", "name": "custom", "handle": 4}},
+        {"map": {"family": "inet", "name": "ports", "table": "filter", "type": "inet_service", "handle": 5, "map": "ipv4_addr", "elem": [[443, "172.20.0.2"]]}},
+        {"rule": {"family": "inet", "table": "filter", "chain": "forward", "handle": 6, "comment": "DFW-MARKER:defaults;filter;forward;meta-mark", "expr": [{"accept": null}]}},
+        {"rule": {"family": "inet", "table": "filter", "chain": "custom", "handle": 7, "comment": "DFW-MARKER:defaults;filter;forward;ct-state-invalid-drop", "expr": [{"drop": null}]}}
+    ]}"#;
+
+    #[test]
+    fn parse_listing() {
+        let listing: Listing = serde_json::from_str(LISTING).unwrap();
+        assert_eq!(7, listing.nftables.len());
+        assert!(listing.nftables[0].is_metainfo());
+        assert_eq!(6, listing.objects().count());
+
+        let objects: Vec<&Object> = listing.objects().collect();
+        match objects.get(1) {
+            Some(Object::Chain(chain)) => {
+                assert_eq!(Family::Inet, chain.family);
+                assert_eq!(Some(2), chain.handle);
+                assert_eq!(Some("accept"), chain.policy.as_deref());
+                assert_eq!(Some(Type::Filter), chain.chain_type);
+                assert_eq!(Some(Hook::Forward), chain.hook);
+                assert_eq!(Some(0), chain.prio);
+                assert!(chain.other.is_empty());
+            }
+            object => panic!("expected chain, got {:?}", object),
+        }
+        match objects.get(3) {
+            Some(Object::Map(map)) => {
+                assert_eq!(Some("inet_service"), map.set_type.as_deref());
+                assert_eq!(Some("ipv4_addr"), map.map.as_deref());
+                assert_eq!(
+                    vec![Expression::List(vec![
+                        Expression::Number(443),
+                        Expression::from("172.20.0.2")
+                    ])],
+                    map.elem
+                );
+            }
+            object => panic!("expected map, got {:?}", object),
+        }
+    }
+
+    #[test]
+    fn rule_comments_match_exactly() {
+        let listing: Listing = serde_json::from_str(LISTING).unwrap();
+        assert!(listing.has_rule_comment(
+            Family::Inet,
+            "filter",
+            "forward",
+            "DFW-MARKER:defaults;filter;forward;meta-mark"
+        ));
+        // The marker of a rule in a different chain doesn't count.
+        assert!(!listing.has_rule_comment(
+            Family::Inet,
+            "filter",
+            "forward",
+            "DFW-MARKER:defaults;filter;forward;ct-state-invalid-drop"
+        ));
+        assert!(!listing.has_rule_comment(
+            Family::Inet,
+            "filter",
+            "forward",
+            "DFW-MARKER:defaults;filter;forward"
+        ));
+        assert!(!listing.has_rule_comment(
+            Family::Ip,
+            "filter",
+            "forward",
+            "DFW-MARKER:defaults;filter;forward;meta-mark"
+        ));
+    }
+
+    #[test]
+    fn listed_objects_without_handle() {
+        let listing: Listing = serde_json::from_str(LISTING).unwrap();
+        let commands = Document {
+            nftables: listing
+                .nftables
+                .into_iter()
+                .skip(1)
+                .take(2)
+                .map(|object| Command::Add(object.without_handle()))
+                .chain(std::iter::once(Command::Delete(ListedObject::Object(
+                    Object::Table(Table::new(Family::Ip6, "dfw")),
+                ))))
+                .collect(),
+        };
+        assert_eq!(
+            json!({"nftables": [
+                {"add": {"table": {"family": "inet", "name": "filter"}}},
+                {"add": {"chain": {"family": "inet", "table": "filter", "name": "forward", "type": "filter", "hook": "forward", "prio": 0, "policy": "accept"}}},
+                {"delete": {"table": {"family": "ip6", "name": "dfw"}}},
+            ]}),
+            serde_json::to_value(&commands).unwrap(),
+        );
+    }
+
+    #[test]
+    fn display_commands() {
+        let listing: Listing = serde_json::from_str(LISTING).unwrap();
+        let commands: Vec<String> = listing
+            .nftables
+            .into_iter()
+            .skip(1)
+            .map(|object| Command::Add(object.without_handle()).to_string())
+            .collect();
+        assert_eq!(
+            vec![
+                "add table inet filter",
+                "add chain inet filter forward { type filter hook forward priority 0 ; policy accept ; }",
+                "add chain inet filter custom",
+                "add map inet filter ports { type inet_service : ipv4_addr ; }",
+                r#"add rule inet filter forward accept comment "DFW-MARKER:defaults;filter;forward;meta-mark""#,
+                r#"add rule inet filter custom drop comment "DFW-MARKER:defaults;filter;forward;ct-state-invalid-drop""#,
+            ],
+            commands
+        );
+
+        let mut rule = Rule::new(Family::Inet, "dfw", "input", Statements::default());
+        rule.handle = Some(4);
+        assert_eq!(
+            "delete rule inet dfw input handle 4",
+            Command::Delete(Object::Rule(rule).into()).to_string()
+        );
+        let ports = Set::new(Family::Inet, "filter", "ports");
+        assert_eq!(
+            "add element inet filter ports { 443 : 172.20.0.2 }",
+            Command::Add(
+                Object::Element(ports.elements(vec![Expression::List(vec![
+                    Expression::Number(443),
+                    Expression::from("172.20.0.2")
+                ])]))
+                .into()
+            )
+            .to_string()
+        );
+    }
+}
@@ -9,14 +9,23 @@
 
 //! This module implements the nftables backend for DFW.
 
-use crate::{errors::*, FirewallBackend, ProcessContext};
-use slog::{debug, info, o, trace};
+use crate::{
+    diff::RulesetDiff, errors::*, process::AppliedRules, FirewallBackend, ProcessContext, Ruleset,
+};
+use failure::{bail, format_err};
+use json::{Command, Document, ListedObject, Object};
+use rule::Statements;
+use serde::{Deserialize, Serialize};
+use slog::{debug, info, o, trace, warn, Logger};
 use std::{
+    collections::HashMap,
+    fmt,
     io::{prelude::*, BufWriter},
-    process::Command,
 };
 use strum::Display;
 
+mod diff;
+mod json;
 mod process;
 mod rule;
 pub mod types;
@@ -31,52 +40,356 @@ const NF_PRIORITY_INET_FILTER_ANY_DFW: i16 = NF_IP_PRI_FILTER - 5;
 const NF_PRIORITY_IP_NAT_POSTROUTING_DFW: i16 = NF_IP_PRI_NAT_SRC - 5;
 const NF_PRIORITY_IP6_NAT_POSTROUTING_DFW: i16 = NF_IP_PRI_NAT_SRC - 5;
 
-const DFW_MARK: &str = "0xdf";
+const DFW_MARK: u64 = 0xdf;
+
+const COMMAND_NFT: &str = "nft";
+
+/// Table the `matches` of rules are compiled in, see `Nftables::compile_matches`.
+const MATCHES_TABLE: &str = "dfw_matches";
 
 /// Marker struct to implement nftables as a firewall backend.
+///
+/// The rules are applied as a libnftables JSON document through `nft -j -f`, and rendered as the
+/// equivalent nft script. The initialization rules of the configuration are nft commands, they are
+/// applied as an nft script before the document.
 #[derive(Debug)]
 pub struct Nftables;
 impl FirewallBackend for Nftables {
-    type Rule = String;
+    type Rule = NftablesRule;
     type Defaults = types::Defaults;
 
     fn apply(rules: Vec<Self::Rule>, ctx: &ProcessContext<Nftables>) -> Result<()> {
         if ctx.dry_run {
             info!(ctx.logger, "Performing dry-run, will not update any rules");
+            ctx.dry_run_output.write(&Self::render(rules)?)?;
         } else {
-            // To atomically update the ruleset, we need to write a file and pass that to `nft -f`.
-            let rule_file = tempfile::Builder::new().tempfile()?;
-            let rule_file_path = rule_file.as_ref().as_os_str().to_os_string();
-            debug!(ctx.logger, "Writing rules to temporary file";
-                   o!("file_path" => rule_file_path.to_string_lossy().into_owned()));
-            let mut writer = BufWriter::new(rule_file);
-
-            for rule in rules {
-                writeln!(writer, "{}", rule)?;
+            let (scripts, commands) = split_rules(rules);
+            let commands = Self::compile_matches(commands, &ctx.logger)?;
+            let snapshot = ctx.snapshot()?;
+            info!(ctx.logger, "Applying rules (using nft)");
+            Self::run(&scripts, &commands, &ctx.logger)
+                .map_err(|error| ctx.rollback(COMMAND_NFT, error, &snapshot))?;
+        }
+
+        Ok(())
+    }
+
+    fn apply_incremental(
+        rules: Vec<Self::Rule>,
+        applied: &mut AppliedRules,
+        ctx: &ProcessContext<Nftables>,
+    ) -> Result<()> {
+        let (scripts, commands) = split_rules(rules);
+        let commands = Self::compile_matches(commands, &ctx.logger)?;
+        let snapshot = ctx.snapshot()?;
+        let delta = match applied.rulesets() {
+            Some(previous) => {
+                debug!(ctx.logger, "Reading current rules (using nft)");
+                let current = diff::current_chains()?;
+                let mut previous_commands = Vec::new();
+                for document in previous.iter().flat_map(|ruleset| &ruleset.lines) {
+                    let document: Document<Command> = serde_json::from_str(document)?;
+                    previous_commands.extend(document.nftables);
+                }
+                if current.matches(&diff::expected_chains(&previous_commands)) {
+                    diff::delta_commands(&current, &diff::expected_chains(&commands))
+                } else {
+                    warn!(
+                        ctx.logger,
+                        "Rules currently applied differ from the rules applied \
+                                       previously, applying all rules"
+                    );
+                    None
+                }
             }
-            writer.flush()?;
-            trace!(ctx.logger, "Finished writing rules to temporary file");
+            None => None,
+        };
 
-            info!(ctx.logger, "Applying rules (using nft)");
-            let output = Command::new("nft").arg("-f").arg(rule_file_path).output()?;
-            if !output.status.success() {
-                return Err(DFWError::NFTablesError {
-                    stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
-                    stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        match delta {
+            Some(delta) => {
+                if delta.is_empty() {
+                    info!(ctx.logger, "Rules are unchanged");
+                } else {
+                    info!(ctx.logger, "Applying changed rules (using nft)";
+                          o!("changes" => delta.len()));
                 }
-                .into());
-            } else {
-                return Ok(());
+                // Commands outside of the DFW tables, e.g. rules hooking into custom tables, are
+                // applied on every run, as they are when applying all rules.
+                let mut commands_with_others: Vec<Command> = commands
+                    .iter()
+                    .filter(|command| !diff::is_dfw_table_command(command))
+                    .cloned()
+                    .collect();
+                commands_with_others.extend(delta);
+                Self::run(&scripts, &commands_with_others, &ctx.logger)
+                    .map_err(|error| ctx.rollback(COMMAND_NFT, error, &snapshot))?;
+            }
+            None => {
+                info!(ctx.logger, "Applying all rules (using nft)");
+                Self::run(&scripts, &commands, &ctx.logger)
+                    .map_err(|error| ctx.rollback(COMMAND_NFT, error, &snapshot))?;
             }
         }
 
+        applied.set(vec![Ruleset {
+            command: COMMAND_NFT.to_owned(),
+            lines: vec![serde_json::to_string(&Document { nftables: commands })?],
+        }]);
+        Ok(())
+    }
+
+    fn render(rules: Vec<Self::Rule>) -> Result<Vec<Ruleset>> {
+        Ok(vec![Ruleset {
+            command: COMMAND_NFT.to_owned(),
+            lines: rules.iter().map(ToString::to_string).collect(),
+        }])
+    }
+
+    fn diff(rules: Vec<Self::Rule>, ctx: &ProcessContext<Nftables>) -> Result<Vec<RulesetDiff>> {
+        let (_, commands) = split_rules(rules);
+        let commands = Self::compile_matches(commands, &ctx.logger)?;
+        debug!(ctx.logger, "Reading current rules (using nft)");
+        Ok(vec![RulesetDiff::new(
+            COMMAND_NFT,
+            &diff::current_chains()?.lines(),
+            &diff::expected_chains(&commands).lines(),
+        )])
+    }
+
+    /// The snapshot holds a single line, the libnftables JSON document restoring the DFW tables.
+    fn snapshot(_logger: &Logger) -> Result<Vec<Ruleset>> {
+        Ok(vec![Ruleset {
+            command: COMMAND_NFT.to_owned(),
+            lines: vec![diff::snapshot()?],
+        }])
+    }
+
+    fn restore_snapshot(snapshot: &[Ruleset], logger: &Logger) -> Result<()> {
+        for document in snapshot.iter().flat_map(|ruleset| &ruleset.lines) {
+            info!(logger, "Restoring rules (using nft)");
+            Self::run_json(document, logger)?;
+        }
         Ok(())
     }
 }
 
+/// Processed nftables rule, i.e. a command like adding a rule to a chain.
+///
+/// Rules are displayed in the nft syntax, e.g. `add rule inet dfw input ct state invalid drop`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NftablesRule(RuleKind);
+
+#[derive(Debug, Clone, PartialEq)]
+enum RuleKind {
+    /// Command applied through the libnftables JSON document.
+    Command(Command),
+    /// nft command of the configuration, e.g. an initialization rule, applied as an nft script.
+    Script(String),
+}
+
+impl From<Command> for NftablesRule {
+    fn from(command: Command) -> NftablesRule {
+        NftablesRule(RuleKind::Command(command))
+    }
+}
+
+impl NftablesRule {
+    fn script(command: String) -> NftablesRule {
+        NftablesRule(RuleKind::Script(command))
+    }
+}
+
+impl fmt::Display for NftablesRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            RuleKind::Command(command) => command.fmt(f),
+            RuleKind::Script(command) => f.write_str(command),
+        }
+    }
+}
+
+/// Split the rules into the nft scripts and the commands of the libnftables JSON document.
+fn split_rules(rules: Vec<NftablesRule>) -> (Vec<String>, Vec<Command>) {
+    let mut scripts = Vec::new();
+    let mut commands = Vec::new();
+    for rule in rules {
+        match rule.0 {
+            RuleKind::Command(command) => commands.push(command),
+            RuleKind::Script(command) => scripts.push(command),
+        }
+    }
+    (scripts, commands)
+}
+
+impl Nftables {
+    /// Apply the nft scripts, followed by the commands in a single transaction.
+    fn run(scripts: &[String], commands: &[Command], logger: &Logger) -> Result<()> {
+        if !scripts.is_empty() {
+            Self::run_file(scripts, false, logger)?;
+        }
+        if !commands.is_empty() {
+            Self::run_json(
+                &serde_json::to_string(&Document {
+                    nftables: commands.to_vec(),
+                })?,
+                logger,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Apply the libnftables JSON document in a single transaction.
+    fn run_json(document: &str, logger: &Logger) -> Result<()> {
+        Self::run_file(&[document.to_owned()], true, logger)
+    }
+
+    fn run_file(commands: &[String], json: bool, logger: &Logger) -> Result<()> {
+        // To atomically update the ruleset, we need to write a file and pass that to `nft -f`.
+        let rule_file = tempfile::Builder::new().tempfile()?;
+        let rule_file_path = rule_file.as_ref().as_os_str().to_os_string();
+        debug!(logger, "Writing rules to temporary file";
+               o!("file_path" => rule_file_path.to_string_lossy().into_owned()));
+        let mut writer = BufWriter::new(rule_file);
+
+        for command in commands {
+            writeln!(writer, "{}", command)?;
+        }
+        writer.flush()?;
+        trace!(logger, "Finished writing rules to temporary file");
+
+        let mut command = std::process::Command::new(COMMAND_NFT);
+        if json {
+            command.arg("-j");
+        }
+        let output = command.arg("-f").arg(rule_file_path).output()?;
+        if !output.status.success() {
+            return Err(DFWError::NFTablesError {
+                stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+                stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            }
+            .into());
+        }
+
+        Ok(())
+    }
+
+    /// Compile the nft expressions of the configuration (`matches`) to the statements they are
+    /// represented by in the libnftables JSON schema.
+    ///
+    /// nft only parses expressions as part of a command. The expressions are thus added as rules
+    /// to a table without base chains, which doesn't affect any traffic, and read back through
+    /// `nft -j` before the table is removed again.
+    fn compile_matches(mut commands: Vec<Command>, logger: &Logger) -> Result<Vec<Command>> {
+        let mut matches: Vec<(Family, String)> = Vec::new();
+        for command in &commands {
+            if let ListedObject::Object(Object::Rule(rule)) = command.object() {
+                for rule_matches in rule.expr.matches() {
+                    let key = (rule.family, rule_matches.to_owned());
+                    if !matches.contains(&key) {
+                        matches.push(key);
+                    }
+                }
+            }
+        }
+        if matches.is_empty() {
+            return Ok(commands);
+        }
+
+        let mut families: Vec<Family> = matches.iter().map(|(family, _)| *family).collect();
+        families.sort_by_key(ToString::to_string);
+        families.dedup();
+        let mut script = Vec::new();
+        for family in &families {
+            // Adding the table first ensures that deleting it succeeds if it doesn't exist.
+            script.push(format!("add table {} {}", family, MATCHES_TABLE));
+            script.push(format!("delete table {} {}", family, MATCHES_TABLE));
+            script.push(format!("add table {} {}", family, MATCHES_TABLE));
+            script.push(format!("add chain {} {} matches", family, MATCHES_TABLE));
+        }
+        for (family, rule_matches) in &matches {
+            script.push(format!(
+                "add rule {} {} matches {}",
+                family, MATCHES_TABLE, rule_matches
+            ));
+        }
+        debug!(logger, "Compiling matches (using nft)";
+               o!("matches" => matches.len()));
+        Self::run_file(&script, false, logger)?;
+
+        let compiled = Self::list_matches(&families, &matches);
+        let removed = Self::run_file(
+            &families
+                .iter()
+                .map(|family| format!("delete table {} {}", family, MATCHES_TABLE))
+                .collect::<Vec<_>>(),
+            false,
+            logger,
+        );
+        let compiled = compiled?;
+        removed?;
+
+        for command in &mut commands {
+            if let ListedObject::Object(Object::Rule(rule)) = command.object_mut() {
+                rule.expr.replace_matches(rule.family, &compiled)?;
+            }
+        }
+        Ok(commands)
+    }
+
+    /// Read the statements the matches were compiled to, see `compile_matches`.
+    fn list_matches(
+        families: &[Family],
+        matches: &[(Family, String)],
+    ) -> Result<HashMap<(Family, String), Statements>> {
+        let mut compiled = HashMap::new();
+        for family in families {
+            let listing = json::list(&["list", "table", &family.to_string(), MATCHES_TABLE])?
+                .ok_or_else(|| format_err!("table `{} {}` is missing", family, MATCHES_TABLE))?;
+            let rules: Vec<Statements> = listing
+                .objects()
+                .filter_map(|object| match object {
+                    Object::Rule(rule) => Some(rule.expr.clone()),
+                    _ => None,
+                })
+                .collect();
+            let family_matches: Vec<(Family, String)> = matches
+                .iter()
+                .filter(|(rule_family, _)| rule_family == family)
+                .cloned()
+                .collect();
+            if family_matches.len() != rules.len() {
+                bail!("the matches weren't compiled to one rule each");
+            }
+            compiled.extend(family_matches.into_iter().zip(rules));
+        }
+        Ok(compiled)
+    }
+
+    /// Run an `nft` list command, e.g. `list(&["list", "ruleset"])`, returning `None` if the
+    /// listed table or chain doesn't exist.
+    fn list(args: &[&str]) -> Result<Option<String>> {
+        let output = std::process::Command::new(COMMAND_NFT)
+            .args(args)
+            .output()?;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if output.status.success() {
+            Ok(Some(String::from_utf8_lossy(&output.stdout).into_owned()))
+        } else if stderr.contains("No such file or directory") {
+            Ok(None)
+        } else {
+            Err(DFWError::NFTablesError {
+                stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+                stderr: stderr.into_owned(),
+            }
+            .into())
+        }
+    }
+}
+
 /// Representation of nftables table-families.
-#[derive(Debug, Clone, Copy, Display)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Display, Serialize, Deserialize)]
 #[strum(serialize_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
 pub enum Family {
     /// IPv4 table family
     Ip,
@@ -98,8 +411,9 @@ pub enum Family {
 ///
 /// Parts of the documentation have been taken from
 /// <https://wiki.nftables.org/wiki-nftables/index.php/Configuring_chains>.
-#[derive(Debug, Clone, Copy, Display)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, Serialize, Deserialize)]
 #[strum(serialize_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
 pub enum Type {
     /// Is used to filter packets.
     ///
@@ -142,8 +456,9 @@ pub enum Type {
 ///
 /// Parts of the documentation have been taken from
 /// <https://wiki.nftables.org/wiki-nftables/index.php/Configuring_chains>.
-#[derive(Debug, Clone, Copy, Display)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, Serialize, Deserialize)]
 #[strum(serialize_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
 pub enum Hook {
     /// Ingress allows traffic-filtering before pre-routing, after the packet traversed the NIC.
     ///
@@ -8,89 +8,98 @@
 // except according to those terms.
 
 use super::{
-    rule::RuleBuilder, Family, Hook, Nftables, Type, DFW_MARK, NF_PRIORITY_INET_FILTER_ANY_DFW,
+    json::{self, Chain, Command, Object, Table},
+    rule::{Expression, Nat, RuleBuilder, Statement, Statements},
+    Family, Hook, Nftables, NftablesRule, Type, NF_PRIORITY_INET_FILTER_ANY_DFW,
     NF_PRIORITY_IP6_NAT_POSTROUTING_DFW, NF_PRIORITY_IP6_NAT_PREROUTING_DFW,
     NF_PRIORITY_IP_NAT_POSTROUTING_DFW, NF_PRIORITY_IP_NAT_PREROUTING_DFW,
 };
 use crate::{errors::*, process::*, types::*, FirewallBackend};
-use failure::{format_err, ResultExt};
+use bollard::models::{Network, NetworkContainer};
+use failure::{bail, format_err, ResultExt};
 use slog::{debug, info, o, trace, warn};
-use std::process::Command;
 use time::format_description::well_known::Rfc3339;
 
+/// Maximum number of ports of a range that is shifted to a different range of container ports.
+const MAX_SHIFTED_PORTS: u32 = 256;
+
+/// Base chains of the DFW tables, with their type, hook and priority.
+const BASE_CHAINS: &[(Family, &str, Type, Hook, i16)] = &[
+    (
+        Family::Inet,
+        "input",
+        Type::Filter,
+        Hook::Input,
+        NF_PRIORITY_INET_FILTER_ANY_DFW,
+    ),
+    (
+        Family::Inet,
+        "forward",
+        Type::Filter,
+        Hook::Forward,
+        NF_PRIORITY_INET_FILTER_ANY_DFW,
+    ),
+    (
+        Family::Ip,
+        "prerouting",
+        Type::Nat,
+        Hook::Prerouting,
+        NF_PRIORITY_IP_NAT_PREROUTING_DFW,
+    ),
+    (
+        Family::Ip,
+        "postrouting",
+        Type::Nat,
+        Hook::Postrouting,
+        NF_PRIORITY_IP_NAT_POSTROUTING_DFW,
+    ),
+    (
+        Family::Ip6,
+        "prerouting",
+        Type::Nat,
+        Hook::Prerouting,
+        NF_PRIORITY_IP6_NAT_PREROUTING_DFW,
+    ),
+    (
+        Family::Ip6,
+        "postrouting",
+        Type::Nat,
+        Hook::Postrouting,
+        NF_PRIORITY_IP6_NAT_POSTROUTING_DFW,
+    ),
+];
+
 impl Process<Nftables> for DFW<Nftables> {
-    fn process(&self, ctx: &ProcessContext<Nftables>) -> Result<Option<Vec<String>>> {
+    fn process(&self, ctx: &ProcessContext<Nftables>) -> Result<Option<Vec<NftablesRule>>> {
         info!(ctx.logger, "Starting processing";
               o!("started_processing_at" => time::OffsetDateTime::now_utc().format(&Rfc3339).expect("failed to format time")));
         let mut rules = vec![
             add_table(Family::Inet, "dfw"),
             flush_table(Family::Inet, "dfw"),
-            add_base_chain(
-                Family::Inet,
-                "dfw",
-                "input",
-                Type::Filter,
-                Hook::Input,
-                NF_PRIORITY_INET_FILTER_ANY_DFW,
-            ),
-            add_rule(Family::Inet, "dfw", "input", "ct state invalid drop"),
+            add_base_chain(Family::Inet, "input"),
+            add_rule(Family::Inet, "dfw", "input", ct_state_invalid_drop()),
             add_rule(
                 Family::Inet,
                 "dfw",
                 "input",
-                "ct state { related, established } accept",
+                ct_state_related_established_accept(),
             ),
-            add_base_chain(
-                Family::Inet,
-                "dfw",
-                "forward",
-                Type::Filter,
-                Hook::Forward,
-                NF_PRIORITY_INET_FILTER_ANY_DFW,
-            ),
-            add_rule(Family::Inet, "dfw", "forward", "ct state invalid drop"),
+            add_base_chain(Family::Inet, "forward"),
+            add_rule(Family::Inet, "dfw", "forward", ct_state_invalid_drop()),
             add_rule(
                 Family::Inet,
                 "dfw",
                 "forward",
-                "ct state { related, established } accept",
+                ct_state_related_established_accept(),
             ),
             add_table(Family::Ip, "dfw"),
             flush_table(Family::Ip, "dfw"),
-            add_base_chain(
-                Family::Ip,
-                "dfw",
-                "prerouting",
-                Type::Nat,
-                Hook::Prerouting,
-                NF_PRIORITY_IP_NAT_PREROUTING_DFW,
-            ),
-            add_base_chain(
-                Family::Ip,
-                "dfw",
-                "postrouting",
-                Type::Nat,
-                Hook::Postrouting,
-                NF_PRIORITY_IP_NAT_POSTROUTING_DFW,
-            ),
+            add_base_chain(Family::Ip, "prerouting"),
+            add_base_chain(Family::Ip, "postrouting"),
             add_table(Family::Ip6, "dfw"),
             flush_table(Family::Ip6, "dfw"),
-            add_base_chain(
-                Family::Ip6,
-                "dfw",
-                "prerouting",
-                Type::Nat,
-                Hook::Prerouting,
-                NF_PRIORITY_IP6_NAT_PREROUTING_DFW,
-            ),
-            add_base_chain(
-                Family::Ip6,
-                "dfw",
-                "postrouting",
-                Type::Nat,
-                Hook::Postrouting,
-                NF_PRIORITY_IP6_NAT_POSTROUTING_DFW,
-            ),
+            add_base_chain(Family::Ip6, "prerouting"),
+            add_base_chain(Family::Ip6, "postrouting"),
         ];
         for mut sub_rules in vec![
             self.backend_defaults
@@ -106,10 +115,16 @@ impl Process<Nftables> for DFW<Nftables> {
                 })
                 .process(ctx)?,
             self.global_defaults.process(ctx)?,
-            self.container_to_container.process(ctx)?,
-            self.container_to_wider_world.process(ctx)?,
+            ctx.label_rules
+                .merge_container_to_container(&self.container_to_container)
+                .process(ctx)?,
+            ctx.label_rules
+                .merge_container_to_wider_world(&self.container_to_wider_world)
+                .process(ctx)?,
             self.container_to_host.process(ctx)?,
-            self.wider_world_to_container.process(ctx)?,
+            ctx.label_rules
+                .merge_wider_world_to_container(&self.wider_world_to_container)
+                .process(ctx)?,
             self.container_dnat.process(ctx)?,
         ]
         .into_iter()
@@ -126,7 +141,7 @@ impl Process<Nftables> for DFW<Nftables> {
 }
 
 impl Process<Nftables> for <Nftables as FirewallBackend>::Defaults {
-    fn process(&self, ctx: &ProcessContext<Nftables>) -> Result<Option<Vec<String>>> {
+    fn process(&self, ctx: &ProcessContext<Nftables>) -> Result<Option<Vec<NftablesRule>>> {
         // NOTE: v1.2.0 deprecated the `nftables::types::Defaults::rules` field in favour of the
         // field present in the `Initialization` sub-type. To retain backwards-compatibility we
         // allow the old field to be present and we will use it if the new field was not specified.
@@ -157,7 +172,10 @@ impl Process<Nftables> for <Nftables as FirewallBackend>::Defaults {
                         true
                     })
             })
-            .unwrap_or_default();
+            .unwrap_or_default()
+            .into_iter()
+            .map(NftablesRule::script)
+            .collect::<Vec<_>>();
 
         // NOTE: v1.2.0 deprecated the `GlobalDefaults::custom_tables` field in favour of the field
         // present in the backend-specific defaults-type. To retain backwards-compatibility we
@@ -184,12 +202,17 @@ impl Process<Nftables> for <Nftables as FirewallBackend>::Defaults {
 
         // Hook into other chains if requested
         if let Some(custom_tables) = custom_tables {
-            // Retrieve current ruleset to avoid duplication of already existing rules.
-            let current_ruleset = Command::new("nft")
-                .args(["list", "ruleset"])
-                .output()
-                .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
-                .ok();
+            // Retrieve current ruleset to avoid duplication of already existing rules. A dry run
+            // must not depend on `nft` being available, it renders every rule instead.
+            let current_ruleset = match json::list(&["list", "ruleset"]) {
+                Ok(current_ruleset) => current_ruleset,
+                Err(error) if ctx.dry_run => {
+                    warn!(ctx.logger, "Failed to list the current ruleset during dry run";
+                          o!("error" => error.to_string()));
+                    None
+                }
+                Err(error) => return Err(error),
+            };
 
             for custom_table in custom_tables {
                 for chain in &custom_table.chains {
@@ -197,39 +220,45 @@ impl Process<Nftables> for <Nftables as FirewallBackend>::Defaults {
 
                     // This is a small helper macro to de-duplicate the "add if it doesn't already
                     // exist" logic. It essentially generates a marker which uniquely identifies a
-                    // rule, checks if that marker is already in the current ruleset, and if not,
-                    // adds it. This enables us to not duplicate rules that are outside of our table
-                    // where flushing is not an option.
+                    // rule, checks if a rule of the chain is already commented with that marker,
+                    // and if not, adds it. This enables us to not duplicate rules that are outside
+                    // of our table where flushing is not an option.
                     macro_rules! m {
                         ($mark:expr, $rule:expr) => {
                             let marker =
                                 generate_marker(&["defaults", &custom_table.name, chain, $mark]);
-                            if !current_ruleset
-                                .as_ref()
-                                .map(|current_ruleset| current_ruleset.contains(&marker))
-                                .unwrap_or(false)
-                            {
+                            if !current_ruleset.as_ref().is_some_and(|current_ruleset| {
+                                current_ruleset.has_rule_comment(
+                                    Family::Inet,
+                                    &custom_table.name,
+                                    chain,
+                                    &marker,
+                                )
+                            }) {
                                 additional_rules.push(insert_rule(
                                     Family::Inet,
                                     &custom_table.name,
                                     chain,
-                                    &format!("{} comment \"{}\"", $rule, marker),
-                                    None,
+                                    $rule,
+                                    &marker,
                                 ));
                             }
                         };
                     }
                     // Handle `ct state invalid drop` rule
-                    m!("ct-state-invalid-drop", "ct state invalid drop");
+                    m!("ct-state-invalid-drop", ct_state_invalid_drop());
                     // Handle `ct state { related, established } accept` rule
                     m!(
                         "ct-state-relatedestablished-accept",
-                        "ct state { related, established } accept"
+                        ct_state_related_established_accept()
                     );
                     // Handle `meta mark ... accept` rule
                     m!(
                         "meta-mark",
-                        &format!("meta mark and {} == {} accept", DFW_MARK, DFW_MARK)
+                        Statements(vec![
+                            Statement::marked(),
+                            Statement::verdict(RuleVerdict::Accept)
+                        ])
                     );
 
                     // The rules above are not added at the end, but inserted at the top. We thus
@@ -246,7 +275,7 @@ impl Process<Nftables> for <Nftables as FirewallBackend>::Defaults {
 }
 
 impl Process<Nftables> for GlobalDefaults {
-    fn process(&self, ctx: &ProcessContext<Nftables>) -> Result<Option<Vec<String>>> {
+    fn process(&self, ctx: &ProcessContext<Nftables>) -> Result<Option<Vec<NftablesRule>>> {
         let mut rules = Vec::new();
 
         // Enforce policy for default Docker-bridge (usually docker0) to access host-resources
@@ -257,32 +286,21 @@ impl Process<Nftables> for GlobalDefaults {
                 .ok_or_else(|| format_err!("couldn't get network options"))?
                 .get("com.docker.network.bridge.name")
             {
+                let verdict = policy_verdict(self.default_docker_bridge_to_host_policy);
                 // Set policy for input-chain
-                rules.push(add_rule(
-                    Family::Inet,
-                    "dfw",
-                    "input",
-                    &format!(
-                        "meta iifname {} meta mark set {} {}",
-                        bridge_name, DFW_MARK, self.default_docker_bridge_to_host_policy,
-                    ),
-                ));
+                let mut nft_rule = RuleBuilder::default();
+                nft_rule.in_interface(bridge_name).verdict(verdict);
+                rules.push(add_rule(Family::Inet, "dfw", "input", nft_rule.build()?));
 
                 // Set policy for forward-chain, divided by the external network interfaces.
                 if let Some(ref external_network_interfaces) = self.external_network_interfaces {
                     for external_network_interface in external_network_interfaces {
-                        rules.push(add_rule(
-                            Family::Inet,
-                            "dfw",
-                            "forward",
-                            &format!(
-                                "meta iifname {} oifname {} meta mark set {} {}",
-                                bridge_name,
-                                external_network_interface,
-                                DFW_MARK,
-                                self.default_docker_bridge_to_host_policy,
-                            ),
-                        ));
+                        let mut nft_rule = RuleBuilder::default();
+                        nft_rule
+                            .in_interface(bridge_name)
+                            .out_interface(external_network_interface)
+                            .verdict(verdict);
+                        rules.push(add_rule(Family::Inet, "dfw", "forward", nft_rule.build()?));
                     }
                 }
             }
@@ -292,24 +310,12 @@ impl Process<Nftables> for GlobalDefaults {
         if let Some(ref external_network_interfaces) = self.external_network_interfaces {
             for external_network_interface in external_network_interfaces {
                 // Configure postrouting
-                rules.push(add_rule(
-                    Family::Ip,
-                    "dfw",
-                    "postrouting",
-                    &format!(
-                        "meta oifname {} meta mark set {} masquerade",
-                        external_network_interface, DFW_MARK,
-                    ),
-                ));
-                rules.push(add_rule(
-                    Family::Ip6,
-                    "dfw",
-                    "postrouting",
-                    &format!(
-                        "meta oifname {} meta mark set {} masquerade",
-                        external_network_interface, DFW_MARK,
-                    ),
-                ));
+                let mut rule = RuleBuilder::default()
+                    .out_interface(external_network_interface)
+                    .build()?;
+                rule.push(Statement::masquerade());
+                rules.push(add_rule(Family::Ip, "dfw", "postrouting", rule.clone()));
+                rules.push(add_rule(Family::Ip6, "dfw", "postrouting", rule));
             }
         }
         Ok(Some(rules))
@@ -317,11 +323,10 @@ impl Process<Nftables> for GlobalDefaults {
 }
 
 impl Process<Nftables> for ContainerToContainer {
-    fn process(&self, ctx: &ProcessContext<Nftables>) -> Result<Option<Vec<String>>> {
+    fn process(&self, ctx: &ProcessContext<Nftables>) -> Result<Option<Vec<NftablesRule>>> {
         // Enforce default policy for container-to-container communication.
         let mut rules = vec![set_chain_policy(
             Family::Inet,
-            "dfw",
             "forward",
             self.default_policy,
         )];
@@ -332,25 +337,31 @@ impl Process<Nftables> for ContainerToContainer {
 
         if let Some(same_network_verdict) = self.same_network_verdict {
             for network in ctx.network_map.values() {
-                let network_id = network.id.as_ref().expect("Docker network ID missing");
-                let bridge_name = get_bridge_name(network_id)?;
-                trace!(ctx.logger, "Got bridge name";
+                // Containers on overlay networks are covered through `docker_gwbridge`.
+                if !has_host_interface(network) {
+                    continue;
+                }
+                let interface = ctx.get_network_interface(network)?;
+                trace!(ctx.logger, "Got network interface";
                        o!("network_name" => &network.name,
-                          "bridge_name" => &bridge_name));
-
-                let rule = RuleBuilder::default()
-                    .in_interface(&bridge_name)
-                    .out_interface(&bridge_name)
-                    .verdict(same_network_verdict)
-                    .build()?;
-
-                debug!(ctx.logger, "Add forward rule for same network verdict for bridge";
-                       o!("part" => "container_to_container",
-                          "bridge_name" => bridge_name,
-                          "same_network_verdict" => same_network_verdict,
-                          "rule" => &rule));
-
-                rules.push(add_rule(Family::Inet, "dfw", "forward", &rule));
+                          "interface" => format!("{:?}", interface)));
+
+                let mut nft_rule = RuleBuilder::default();
+                nft_rule
+                    .in_interface(interface.name())
+                    .out_interface(interface.name())
+                    .verdict(same_network_verdict);
+
+                for nft_rule in restrict_to_subnets(nft_rule, network, &interface, true, true) {
+                    let rule = nft_rule.build()?;
+                    debug!(ctx.logger, "Add forward rule for same network verdict for bridge";
+                           o!("part" => "container_to_container",
+                              "bridge_name" => interface.name(),
+                              "same_network_verdict" => same_network_verdict,
+                              "rule" => rule.to_string()));
+
+                    rules.push(add_rule(Family::Inet, "dfw", "forward", rule));
+                }
             }
         }
 
@@ -359,7 +370,20 @@ impl Process<Nftables> for ContainerToContainer {
 }
 
 impl Process<Nftables> for ContainerToContainerRule {
-    fn process(&self, ctx: &ProcessContext<Nftables>) -> Result<Option<Vec<String>>> {
+    fn process(&self, ctx: &ProcessContext<Nftables>) -> Result<Option<Vec<NftablesRule>>> {
+        let src_selector = resolve_selector(
+            self.src_selector.as_ref(),
+            self.src_service.as_ref(),
+            self.src_swarm_service.as_ref(),
+        )?;
+        ensure_container_or_selector(self.src_container.as_ref(), src_selector.as_ref())?;
+        let dst_selector = resolve_selector(
+            self.dst_selector.as_ref(),
+            self.dst_service.as_ref(),
+            self.dst_swarm_service.as_ref(),
+        )?;
+        ensure_container_or_selector(self.dst_container.as_ref(), dst_selector.as_ref())?;
+
         let mut rules = Vec::new();
         let mut nft_rule = RuleBuilder::default();
         let network = match ctx.network_map.get(&self.network) {
@@ -370,76 +394,52 @@ impl Process<Nftables> for ContainerToContainerRule {
                     o!("network_name" => &self.network,
                         "network" => format!("{:?}", network)));
         let network_id = network.id.as_ref().expect("Docker network ID missing");
-        let bridge_name = get_bridge_name(network_id)?;
-        trace!(ctx.logger, "Got bridge name";
+        let interface = ctx.get_network_interface(network)?;
+        trace!(ctx.logger, "Got network interface";
                     o!("network_name" => &network.name,
-                        "bridge_name" => &bridge_name));
+                        "interface" => format!("{:?}", interface)));
 
         nft_rule
-            .in_interface(&bridge_name)
-            .out_interface(&bridge_name);
-
-        if let Some(ref src_container) = self.src_container {
-            trace!(ctx.logger, "Getting network for container");
-            let src_network = match get_network_for_container(
-                ctx.docker,
-                &ctx.container_map,
-                src_container,
-                network_id,
-            )? {
-                Some(src_network) => src_network,
-                None => return Ok(None),
-            };
-            trace!(ctx.logger, "Got source network";
-                        o!("network_name" => &network.name,
-                            "src_network" => format!("{:?}", src_network)));
-
-            let bridge_name = get_bridge_name(network_id)?;
-            trace!(ctx.logger, "Got bridge name";
-                        o!("network_name" => &network.name,
-                            "bridge_name" => &bridge_name));
-
-            nft_rule
-                .in_interface(&bridge_name)
-                .out_interface(&bridge_name)
-                .source_address(
-                    src_network
-                        .ipv4_address
-                        .expect("IPv4 address for container missing")
-                        .split('/')
-                        .next()
-                        .ok_or_else(|| format_err!("IPv4 address is empty"))?,
-                );
-        }
-
-        if let Some(ref dst_container) = self.dst_container {
-            let dst_network = match get_network_for_container(
-                ctx.docker,
-                &ctx.container_map,
-                dst_container,
-                network_id,
-            )? {
-                Some(dst_network) => dst_network,
-                None => return Ok(None),
-            };
-            trace!(ctx.logger, "Got destination network";
-                        o!("network_name" => &network.name,
-                            "dst_network" => format!("{:?}", dst_network)));
-
-            let network_id = network.id.as_ref().expect("Docker network ID missing");
-            let bridge_name = get_bridge_name(network_id)?;
-            trace!(ctx.logger, "Got bridge name";
-                        o!("network_name" => &network.name,
-                            "bridge_name" => &bridge_name));
-
-            nft_rule.out_interface(&bridge_name).destination_address(
-                dst_network
-                    .ipv4_address
-                    .expect("IPv4 address for container missing")
-                    .split('/')
-                    .next()
-                    .ok_or_else(|| format_err!("IPv4 address is empty"))?,
-            );
+            .in_interface(interface.name())
+            .out_interface(interface.name());
+
+        let src_networks = get_network_containers(
+            ctx.container_runtime,
+            &ctx.container_map,
+            self.src_container.as_deref(),
+            src_selector.as_ref(),
+            network_id,
+        )?;
+        trace!(ctx.logger, "Got source networks";
+                    o!("network_name" => &network.name,
+                        "src_networks" => format!("{:?}", src_networks)));
+        let dst_networks = get_network_containers(
+            ctx.container_runtime,
+            &ctx.container_map,
+            self.dst_container.as_deref(),
+            dst_selector.as_ref(),
+            network_id,
+        )?;
+        trace!(ctx.logger, "Got destination networks";
+                    o!("network_name" => &network.name,
+                        "dst_networks" => format!("{:?}", dst_networks)));
+        let src_sets = AddressSets::new(
+            ctx,
+            &self.network,
+            self.src_container.as_deref(),
+            src_selector.as_ref(),
+        );
+        let dst_sets = AddressSets::new(
+            ctx,
+            &self.network,
+            self.dst_container.as_deref(),
+            dst_selector.as_ref(),
+        );
+        // Rules matching address sets are retained, the sets are filled once the containers start.
+        if (src_sets.is_none() && src_networks.as_ref().is_some_and(Vec::is_empty))
+            || (dst_sets.is_none() && dst_networks.as_ref().is_some_and(Vec::is_empty))
+        {
+            return Ok(None);
         }
 
         if let Some(matches) = &self.matches {
@@ -447,15 +447,64 @@ impl Process<Nftables> for ContainerToContainerRule {
         }
         nft_rule.verdict(self.verdict);
 
-        let rule = nft_rule.build()?;
-        rules.push(add_rule(Family::Inet, "dfw", "forward", &rule));
+        for &ip_version in rule_ip_versions(
+            self.typed_matches().ip_version(),
+            src_networks.is_some() || dst_networks.is_some() || interface.is_shared(),
+        ) {
+            let source_addresses = get_rule_addresses_or_set(
+                &mut rules,
+                Family::Inet,
+                src_sets.as_ref(),
+                network,
+                &interface,
+                src_networks.as_deref(),
+                ip_version,
+            );
+            let destination_addresses = get_rule_addresses_or_set(
+                &mut rules,
+                Family::Inet,
+                dst_sets.as_ref(),
+                network,
+                &interface,
+                dst_networks.as_deref(),
+                ip_version,
+            );
+            // Skip the IP version if none of the containers have an address of it.
+            if source_addresses.as_ref().is_some_and(Vec::is_empty)
+                || destination_addresses.as_ref().is_some_and(Vec::is_empty)
+            {
+                continue;
+            }
+
+            let mut nft_rule = nft_rule.clone();
+            if source_addresses.is_none() && destination_addresses.is_none() {
+                set_nfproto(&mut nft_rule, ip_version);
+            }
+
+            // Selectors can match multiple containers, generate a rule for every combination of
+            // them.
+            let nft_rules = expand_rules(
+                vec![nft_rule],
+                &source_addresses.unwrap_or_default(),
+                |nft_rule, address| set_source_address(nft_rule, ip_version, address),
+            );
+            let nft_rules = expand_rules(
+                nft_rules,
+                &destination_addresses.unwrap_or_default(),
+                |nft_rule, address| set_destination_address(nft_rule, ip_version, address),
+            );
+            for nft_rule in apply_typed_matches(nft_rules, &self.typed_matches(), ip_version)? {
+                let rule = nft_rule.build()?;
+                rules.push(add_rule(Family::Inet, "dfw", "forward", rule));
+            }
+        }
 
         Ok(Some(rules))
     }
 }
 
 impl Process<Nftables> for ContainerToWiderWorld {
-    fn process(&self, ctx: &ProcessContext<Nftables>) -> Result<Option<Vec<String>>> {
+    fn process(&self, ctx: &ProcessContext<Nftables>) -> Result<Option<Vec<NftablesRule>>> {
         let mut rules = Vec::new();
 
         if let Some(mut ctww_rules) = self.rules.process(ctx)? {
@@ -474,25 +523,32 @@ impl Process<Nftables> for ContainerToWiderWorld {
                           "external_network_interface" => external_network_interface,
                           "default_policy" => &self.default_policy));
                 for network in ctx.network_map.values() {
-                    let network_id = network.id.as_ref().expect("Docker network ID missing");
-                    let bridge_name = get_bridge_name(network_id)?;
-                    trace!(ctx.logger, "Got bridge name";
+                    // Containers on overlay networks are covered through `docker_gwbridge`.
+                    if !has_host_interface(network) {
+                        continue;
+                    }
+                    let interface = ctx.get_network_interface(network)?;
+                    trace!(ctx.logger, "Got network interface";
                            o!("network_name" => &network.name,
-                              "bridge_name" => &bridge_name));
+                              "interface" => format!("{:?}", interface)));
 
-                    let rule = RuleBuilder::default()
-                        .in_interface(&bridge_name)
+                    let mut nft_rule = RuleBuilder::default();
+                    nft_rule
+                        .in_interface(interface.name())
                         .out_interface(external_network_interface)
-                        .verdict(self.default_policy)
-                        .build()?;
-
-                    debug!(ctx.logger, "Add forward rule for default policy";
-                           o!("part" => "container_to_wider_world",
-                              "external_network_interface" => external_network_interface,
-                              "default_policy" => &self.default_policy,
-                              "rule" => &rule));
-
-                    rules.push(add_rule(Family::Inet, "dfw", "forward", &rule));
+                        .verdict(self.default_policy);
+
+                    for nft_rule in restrict_to_subnets(nft_rule, network, &interface, true, false)
+                    {
+                        let rule = nft_rule.build()?;
+                        debug!(ctx.logger, "Add forward rule for default policy";
+                               o!("part" => "container_to_wider_world",
+                                  "external_network_interface" => external_network_interface,
+                                  "default_policy" => &self.default_policy,
+                                  "rule" => rule.to_string()));
+
+                        rules.push(add_rule(Family::Inet, "dfw", "forward", rule));
+                    }
                 }
             }
         }
@@ -502,26 +558,62 @@ impl Process<Nftables> for ContainerToWiderWorld {
 }
 
 impl Process<Nftables> for ContainerToWiderWorldRule {
-    fn process(&self, ctx: &ProcessContext<Nftables>) -> Result<Option<Vec<String>>> {
+    fn process(&self, ctx: &ProcessContext<Nftables>) -> Result<Option<Vec<NftablesRule>>> {
         let mut rules = Vec::new();
         debug!(ctx.logger, "Process rule";
                    o!("part" => "container_to_wider_world",
                       "rule" => format!("{:?}", self)));
+        let src_selector = resolve_selector(
+            self.src_selector.as_ref(),
+            self.src_service.as_ref(),
+            self.src_swarm_service.as_ref(),
+        )?;
+        ensure_container_or_selector(self.src_container.as_ref(), src_selector.as_ref())?;
+        if self.network.is_none() && src_selector.is_some() {
+            bail!("the source selector requires the network to be specified");
+        }
         let mut nft_rule = RuleBuilder::default();
 
-        if let Some(ref network) = self.network {
-            if let Some(network) = ctx.network_map.get(network) {
+        let mut source_addresses = Vec::new();
+        if let Some(ref network_name) = self.network {
+            if let Some(network) = ctx.network_map.get(network_name) {
+                let network = ctx.get_host_network(network)?;
                 let network_id = network.id.as_ref().expect("Docker network ID missing");
-                let bridge_name = get_bridge_name(network_id)?;
-                trace!(ctx.logger, "Got bridge name";
+                let interface = ctx.get_network_interface(network)?;
+                trace!(ctx.logger, "Got network interface";
                            o!("network_name" => &network.name,
-                              "bridge_name" => &bridge_name));
+                              "interface" => format!("{:?}", interface)));
 
-                nft_rule.in_interface(&bridge_name);
+                nft_rule.in_interface(interface.name());
 
-                if let Some(ref src_container) = self.src_container {
+                if let Some(src_sets) = AddressSets::new(
+                    ctx,
+                    network_name,
+                    self.src_container.as_deref(),
+                    src_selector.as_ref(),
+                ) {
+                    let src_networks = get_network_containers(
+                        ctx.container_runtime,
+                        &ctx.container_map,
+                        self.src_container.as_deref(),
+                        src_selector.as_ref(),
+                        network_id,
+                    )?
+                    .unwrap_or_default();
+                    trace!(ctx.logger, "Got source networks for address set";
+                               o!("network_name" => &network.name,
+                                  "src_networks" => format!("{:?}", src_networks)));
+                    source_addresses = address_set(
+                        &mut rules,
+                        Family::Inet,
+                        &src_sets,
+                        network,
+                        &src_networks,
+                        IpVersion::V4,
+                    );
+                } else if let Some(ref src_container) = self.src_container {
                     if let Some(src_network) = get_network_for_container(
-                        ctx.docker,
+                        ctx.container_runtime,
                         &ctx.container_map,
                         src_container,
                         network_id,
@@ -530,27 +622,24 @@ impl Process<Nftables> for ContainerToWiderWorldRule {
                                    o!("network_name" => &network.name,
                                       "src_network" => format!("{:?}", src_network)));
 
-                        let bridge_name = get_bridge_name(network_id)?;
-                        trace!(ctx.logger, "Got bridge name";
-                                   o!("network_name" => &network.name,
-                                      "bridge_name" => &bridge_name));
-
-                        nft_rule.in_interface(&bridge_name).source_address(
-                            src_network
-                                .ipv4_address
-                                .expect("IPv4 address for container missing")
-                                .split('/')
-                                .next()
-                                .ok_or_else(|| format_err!("IPv4 address is empty"))?,
-                        );
+                        match get_container_ipv4_address_checked(network, &src_network)? {
+                            Some(src_address) => {
+                                nft_rule
+                                    .in_interface(interface.name())
+                                    .source_address(&src_address);
+                            }
+                            // An IPv6-only container can't be the source of IPv4 traffic.
+                            None => return Ok(None),
+                        }
                     }
-                } else {
-                    let bridge_name = get_bridge_name(network_id)?;
-                    trace!(ctx.logger, "Got bridge name";
-                               o!("network_name" => &network.name,
-                                  "bridge_name" => &bridge_name));
-
-                    nft_rule.in_interface(&bridge_name);
+                } else if let Some(ref src_selector) = src_selector {
+                    source_addresses =
+                        match ctx.get_selector_source_addresses(network, src_selector)? {
+                            Some(source_addresses) => source_addresses,
+                            None => return Ok(None),
+                        };
+                } else if interface.is_shared() {
+                    source_addresses = get_network_subnets(network, IpVersion::V4);
                 }
             }
         }
@@ -582,19 +671,28 @@ impl Process<Nftables> for ContainerToWiderWorldRule {
             nft_rule.out_interface(primary_external_network_interface);
         }
 
-        let rule = nft_rule.build()?;
-        debug!(ctx.logger, "Add forward rule";
-                   o!("part" => "container_to_wider_world",
-                      "rule" => &rule));
+        let nft_rules = expand_rules(vec![nft_rule], &source_addresses, |nft_rule, address| {
+            nft_rule.source_address(address);
+        });
+        for nft_rule in apply_typed_matches(
+            nft_rules,
+            &self.typed_matches(),
+            self.typed_matches().ip_version(),
+        )? {
+            let rule = nft_rule.build()?;
+            debug!(ctx.logger, "Add forward rule";
+                       o!("part" => "container_to_wider_world",
+                          "rule" => rule.to_string()));
 
-        // Apply the rule
-        rules.push(add_rule(Family::Inet, "dfw", "forward", &rule));
+            // Apply the rule
+            rules.push(add_rule(Family::Inet, "dfw", "forward", rule));
+        }
         Ok(Some(rules))
     }
 }
 
 impl Process<Nftables> for ContainerToHost {
-    fn process(&self, ctx: &ProcessContext<Nftables>) -> Result<Option<Vec<String>>> {
+    fn process(&self, ctx: &ProcessContext<Nftables>) -> Result<Option<Vec<NftablesRule>>> {
         let mut rules = Vec::new();
 
         if let Some(mut cth_rules) = self.rules.process(ctx)? {
@@ -603,22 +701,28 @@ impl Process<Nftables> for ContainerToHost {
 
         // Default policy
         for network in ctx.network_map.values() {
-            let network_id = network.id.as_ref().expect("Docker network ID missing");
-            let bridge_name = get_bridge_name(network_id)?;
-            trace!(ctx.logger, "Got bridge name";
+            // Containers on overlay networks are covered through `docker_gwbridge`.
+            if !has_host_interface(network) {
+                continue;
+            }
+            let interface = ctx.get_network_interface(network)?;
+            trace!(ctx.logger, "Got network interface";
                    o!("network_name" => &network.name,
-                      "bridge_name" => &bridge_name));
-
-            let rule = RuleBuilder::default()
-                .in_interface(&bridge_name)
-                .verdict(self.default_policy)
-                .build()?;
+                      "interface" => format!("{:?}", interface)));
 
-            trace!(ctx.logger, "Add input rule for default policy";
-                   o!("part" => "container_to_host",
-                      "default_policy" => self.default_policy,
-                      "rule" => &rule));
-            rules.push(add_rule(Family::Inet, "dfw", "input", &rule));
+            let mut nft_rule = RuleBuilder::default();
+            nft_rule
+                .in_interface(interface.name())
+                .verdict(self.default_policy);
+
+            for nft_rule in restrict_to_subnets(nft_rule, network, &interface, true, false) {
+                let rule = nft_rule.build()?;
+                trace!(ctx.logger, "Add input rule for default policy";
+                       o!("part" => "container_to_host",
+                          "default_policy" => self.default_policy,
+                          "rule" => rule.to_string()));
+                rules.push(add_rule(Family::Inet, "dfw", "input", rule));
+            }
         }
 
         Ok(Some(rules))
@@ -626,11 +730,17 @@ impl Process<Nftables> for ContainerToHost {
 }
 
 impl Process<Nftables> for ContainerToHostRule {
-    fn process(&self, ctx: &ProcessContext<Nftables>) -> Result<Option<Vec<String>>> {
+    fn process(&self, ctx: &ProcessContext<Nftables>) -> Result<Option<Vec<NftablesRule>>> {
         let mut rules = Vec::new();
         debug!(ctx.logger, "Process rule";
                    o!("part" => "container_to_host",
                       "rule" => format!("{:?}", self)));
+        let src_selector = resolve_selector(
+            self.src_selector.as_ref(),
+            self.src_service.as_ref(),
+            self.src_swarm_service.as_ref(),
+        )?;
+        ensure_container_or_selector(self.src_container.as_ref(), src_selector.as_ref())?;
         let mut nft_rule = RuleBuilder::default();
 
         let network = match ctx.network_map.get(&self.network) {
@@ -641,33 +751,33 @@ impl Process<Nftables> for ContainerToHostRule {
                    o!("network_name" => &network.name,
                       "network" => format!("{:?}", network)));
 
+        let network = ctx.get_host_network(network)?;
         let network_id = network.id.as_ref().expect("Docker network ID missing");
-        let bridge_name = get_bridge_name(network_id)?;
-        trace!(ctx.logger, "Got bridge name";
+        let interface = ctx.get_network_interface(network)?;
+        trace!(ctx.logger, "Got network interface";
                    o!("network_name" => &network.name,
-                      "bridge_name" => &bridge_name));
-
-        nft_rule.in_interface(&bridge_name);
-
-        if let Some(ref src_container) = self.src_container {
-            if let Some(src_network) = get_network_for_container(
-                ctx.docker,
-                &ctx.container_map,
-                src_container,
-                network_id,
-            )? {
-                trace!(ctx.logger, "Got source network";
-                           o!("network_name" => &network.name,
-                              "src_network" => format!("{:?}", src_network)));
-                nft_rule.source_address(
-                    src_network
-                        .ipv4_address
-                        .expect("IPv4 address for container missing")
-                        .split('/')
-                        .next()
-                        .ok_or_else(|| format_err!("IPv4 address is empty"))?,
-                );
-            }
+                      "interface" => format!("{:?}", interface)));
+
+        nft_rule.in_interface(interface.name());
+
+        let src_networks = get_network_containers(
+            ctx.container_runtime,
+            &ctx.container_map,
+            self.src_container.as_deref(),
+            src_selector.as_ref(),
+            network_id,
+        )?;
+        trace!(ctx.logger, "Got source networks";
+                   o!("network_name" => &network.name,
+                      "src_networks" => format!("{:?}", src_networks)));
+        let src_sets = AddressSets::new(
+            ctx,
+            &self.network,
+            self.src_container.as_deref(),
+            src_selector.as_ref(),
+        );
+        if src_sets.is_none() && src_networks.as_ref().is_some_and(Vec::is_empty) {
+            return Ok(None);
         }
 
         if let Some(ref matches) = self.matches {
@@ -676,29 +786,51 @@ impl Process<Nftables> for ContainerToHostRule {
 
         nft_rule.verdict(self.verdict);
 
-        // Try to build the rule without the out_interface defined to see if any of the other
-        // mandatory fields has been populated.
-        debug!(ctx.logger, "Build rule to verify contents";
-                   o!("args" => format!("{:?}", nft_rule)));
-        nft_rule.build().context(format!(
-            "failed to build rule, maybe the container `{:?}` doesn't exist",
-            self.src_container
-        ))?;
+        for &ip_version in rule_ip_versions(
+            self.typed_matches().ip_version(),
+            src_networks.is_some() || interface.is_shared(),
+        ) {
+            let source_addresses = get_rule_addresses_or_set(
+                &mut rules,
+                Family::Inet,
+                src_sets.as_ref(),
+                network,
+                &interface,
+                src_networks.as_deref(),
+                ip_version,
+            );
+            // Skip the IP version if none of the containers have an address of it.
+            if source_addresses.as_ref().is_some_and(Vec::is_empty) {
+                continue;
+            }
 
-        let rule = nft_rule.build()?;
-        debug!(ctx.logger, "Add input rule";
-                   o!("part" => "container_to_host",
-                      "rule" => &rule));
+            let mut nft_rule = nft_rule.clone();
+            if source_addresses.is_none() {
+                set_nfproto(&mut nft_rule, ip_version);
+            }
 
-        // Apply the rule
-        rules.push(add_rule(Family::Inet, "dfw", "input", &rule));
+            let nft_rules = expand_rules(
+                vec![nft_rule],
+                &source_addresses.unwrap_or_default(),
+                |nft_rule, address| set_source_address(nft_rule, ip_version, address),
+            );
+            for nft_rule in apply_typed_matches(nft_rules, &self.typed_matches(), ip_version)? {
+                let rule = nft_rule.build()?;
+                debug!(ctx.logger, "Add input rule";
+                           o!("part" => "container_to_host",
+                              "rule" => rule.to_string()));
+
+                // Apply the rule
+                rules.push(add_rule(Family::Inet, "dfw", "input", rule));
+            }
+        }
 
         Ok(Some(rules))
     }
 }
 
 impl Process<Nftables> for WiderWorldToContainer {
-    fn process(&self, ctx: &ProcessContext<Nftables>) -> Result<Option<Vec<String>>> {
+    fn process(&self, ctx: &ProcessContext<Nftables>) -> Result<Option<Vec<NftablesRule>>> {
         if self.rules.is_some() {
             debug!(ctx.logger, "Process rules";
                    o!("part" => "wider_world_to_container"));
@@ -715,7 +847,7 @@ impl WiderWorldToContainerRule {
     fn apply_source_cidrs_v4(
         &self,
         ctx: &ProcessContext<Nftables>,
-        rules: &mut Vec<String>,
+        rules: &mut Vec<NftablesRule>,
         source_cidrs: &[String],
         nft_forward_rule: RuleBuilder,
         nft_dnat_rule: RuleBuilder,
@@ -735,12 +867,12 @@ impl WiderWorldToContainerRule {
         {
             debug!(ctx.logger, "Add FORWARD rule";
                    o!("part" => "wider_world_to_container",
-                      "rule" => &additional_forward_rule));
+                      "rule" => additional_forward_rule.to_string()));
             rules.push(add_rule(
                 Family::Inet,
                 "dfw",
                 "forward",
-                &additional_forward_rule,
+                additional_forward_rule,
             ));
         }
         for additional_dnat_rule in source_cidrs
@@ -755,12 +887,12 @@ impl WiderWorldToContainerRule {
         {
             debug!(ctx.logger, "Add DNAT rule";
                    o!("part" => "wider_world_to_container",
-                      "rule" => &additional_dnat_rule));
+                      "rule" => additional_dnat_rule.to_string()));
             rules.push(add_rule(
                 Family::Ip,
                 "dfw",
                 "prerouting",
-                &additional_dnat_rule,
+                additional_dnat_rule,
             ));
         }
 
@@ -770,43 +902,53 @@ impl WiderWorldToContainerRule {
     fn apply_source_cidrs_v6(
         &self,
         ctx: &ProcessContext<Nftables>,
-        rules: &mut Vec<String>,
+        rules: &mut Vec<NftablesRule>,
         source_cidrs: &[String],
-        nft_mark_rule: RuleBuilder,
+        nft_ipv6_rules: &[(Family, &str, RuleBuilder)],
     ) -> Result<()> {
-        debug!(ctx.logger, "Generate extended prerouting rules, source CIDRs (IPv6) were specified";
-               o!("args" => format!("{:?}", nft_mark_rule),
+        debug!(ctx.logger, "Generate extended IPv6 rules, source CIDRs (IPv6) were specified";
+               o!("args" => format!("{:?}", nft_ipv6_rules),
                   "source_cidrs" => source_cidrs.join(", ")));
-        for additional_mark_rule in source_cidrs
-            .iter()
-            .map(|source_cidr| {
-                let mut mark_rule = nft_mark_rule.clone();
-                mark_rule.source_address_v6(source_cidr);
-                mark_rule
-            })
-            .map(|dnat_rule| dnat_rule.build())
-            .collect::<Result<Vec<_>>>()?
-        {
-            debug!(ctx.logger, "Add mark rule";
-                            o!("part" => "wider_world_to_container",
-                               "rule" => &additional_mark_rule));
-            rules.push(add_rule(
-                Family::Ip6,
-                "dfw",
-                "prerouting",
-                &additional_mark_rule,
-            ));
+        for (family, chain, nft_ipv6_rule) in nft_ipv6_rules {
+            for additional_ipv6_rule in source_cidrs
+                .iter()
+                .map(|source_cidr| {
+                    let mut ipv6_rule = nft_ipv6_rule.clone();
+                    ipv6_rule.source_address_v6(source_cidr);
+                    ipv6_rule
+                })
+                .map(|ipv6_rule| ipv6_rule.build())
+                .collect::<Result<Vec<_>>>()?
+            {
+                debug!(ctx.logger, "Add IPv6 rule";
+                       o!("part" => "wider_world_to_container",
+                          "chain" => chain,
+                          "rule" => additional_ipv6_rule.to_string()));
+                rules.push(add_rule(*family, "dfw", chain, additional_ipv6_rule));
+            }
         }
         Ok(())
     }
 }
 
 impl Process<Nftables> for WiderWorldToContainerRule {
-    fn process(&self, ctx: &ProcessContext<Nftables>) -> Result<Option<Vec<String>>> {
+    fn process(&self, ctx: &ProcessContext<Nftables>) -> Result<Option<Vec<NftablesRule>>> {
         let mut rules = Vec::new();
         debug!(ctx.logger, "Process rule";
                    o!("part" => "wider_world_to_container",
                       "rule" => format!("{:?}", self)));
+        let dst_selector = resolve_selector(
+            self.dst_selector.as_ref(),
+            self.dst_service.as_ref(),
+            self.dst_swarm_service.as_ref(),
+        )?;
+        if ctx
+            .network_map
+            .get(&self.network)
+            .is_some_and(is_ingress_network)
+        {
+            return routing_mesh_rules(self, ctx);
+        }
         for expose_port in &self.expose_port {
             let mut nft_forward_rule = RuleBuilder::default();
             let mut nft_dnat_rule = RuleBuilder::default();
@@ -820,64 +962,128 @@ impl Process<Nftables> for WiderWorldToContainerRule {
                    o!("network_name" => &network.name,
                       "network" => format!("{:?}", network)));
 
+            let network = ctx.get_host_network(network)?;
             let network_id = network.id.as_ref().expect("Docker network ID missing");
-            let bridge_name = get_bridge_name(network_id)?;
-            trace!(ctx.logger, "Got bridge name";
+            let interface = ctx.get_network_interface(network)?;
+            trace!(ctx.logger, "Got network interface";
                    o!("network_name" => &network.name,
-                      "bridge_name" => &bridge_name));
-
-            nft_forward_rule.out_interface(&bridge_name);
-
-            if let Some(dst_network) = get_network_for_container(
-                ctx.docker,
+                      "interface" => format!("{:?}", interface)));
+            ensure_routed_network(network, &interface)?;
+
+            nft_forward_rule.out_interface(interface.name());
+
+            let host_ports = expose_port
+                .host_port_range()
+                .map_err(|error| format_err!("{}", error))?;
+            let container_ports = expose_port
+                .container_port_range()
+                .map_err(|error| format_err!("{}", error))?;
+            let host_port = port_range(host_ports);
+            let container_port = port_range(container_ports);
+
+            let mut ipv4_destination = true;
+            let mut ipv6_destination = None;
+            let dst_network = get_network_for_single_container(
+                ctx.container_runtime,
                 &ctx.container_map,
-                &self.dst_container,
+                self.dst_container.as_deref(),
+                dst_selector.as_ref(),
                 network_id,
-            )? {
+            )?;
+            if let Some(dst_sets) = AddressSets::new(
+                ctx,
+                &self.network,
+                self.dst_container.as_deref(),
+                dst_selector.as_ref(),
+            ) {
+                trace!(ctx.logger, "Got destination network for address set";
+                       o!("network_name" => &network.name,
+                          "dst_network" => format!("{:?}", dst_network)));
+
+                // The forward rules match the address of the container through a set, the DNAT
+                // rules translate the host ports to it through a map.
+                let dst_networks = dst_network.into_iter().collect::<Vec<_>>();
+                for &ip_version in single_ip_versions(IpVersion::Both) {
+                    // There is no set for IPv6 if IPv6 isn't enabled for the network.
+                    let destination_address = match address_set(
+                        &mut rules,
+                        Family::Inet,
+                        &dst_sets,
+                        network,
+                        &dst_networks,
+                        ip_version,
+                    )
+                    .pop()
+                    {
+                        Some(destination_address) => destination_address,
+                        None => continue,
+                    };
+                    let family = match ip_version {
+                        IpVersion::V6 => Family::Ip6,
+                        _ => Family::Ip,
+                    };
+                    let dnat_destination = dnat_map(
+                        &mut rules,
+                        family,
+                        &dst_sets,
+                        network,
+                        &dst_networks,
+                        ip_version,
+                        host_ports,
+                        container_ports,
+                        &expose_port.family,
+                    )?;
+                    match ip_version {
+                        IpVersion::V6 => {
+                            ipv6_destination = Some((destination_address, dnat_destination));
+                        }
+                        _ => {
+                            nft_forward_rule.destination_address(&destination_address);
+                            nft_dnat_rule.dnat(dnat_destination);
+                        }
+                    }
+                }
+                nft_forward_rule.destination_port(container_port.clone());
+                nft_dnat_rule.destination_port(host_port.clone());
+                nft_mark_rule.destination_port(host_port.clone());
+            } else if let Some(dst_network) = dst_network {
                 trace!(ctx.logger, "Got destination network";
                        o!("network_name" => &network.name,
                           "dst_network" => format!("{:?}", dst_network)));
 
-                let container_ipv4_address = dst_network
-                    .ipv4_address
-                    .expect("IPv4 address for container missing");
-                nft_forward_rule.destination_address(
-                    container_ipv4_address
-                        .split('/')
-                        .next()
-                        .ok_or_else(|| format_err!("IPv4 address is empty"))?,
-                );
-
-                let host_port = expose_port.host_port.to_string();
-                let container_port = match expose_port.container_port {
-                    Some(destination_port) => destination_port.to_string(),
-                    None => expose_port.host_port.to_string(),
-                };
-                nft_forward_rule.destination_port(&container_port);
-                nft_dnat_rule.destination_port(&host_port);
-                nft_dnat_rule.dnat(&format!(
-                    "{}:{}",
-                    container_ipv4_address
-                        .split('/')
-                        .next()
-                        .ok_or_else(|| format_err!("IPv4 address is empty"))?,
-                    container_port
-                ));
-                nft_mark_rule.destination_port(&host_port);
-            // INFO: correct IPv6 handling would include actually using IPv6-addresses.
-            // While the code below is correct, the postrouting does not work since nftables cannot
-            // route IPv6 packets to IPv4 destinations.
-            //
-            // if !dst_network.ipv6_address.is_empty() {
-            //     nft_mark_rule.dnat(&format!(
-            //         "{}:{}",
-            //         dst_network.ipv6_address
-            //         .expect("IPv6 address for container missing")
-            //         .split('/')
-            //         .next()
-            //         .ok_or_else(|| format_err!("Invalid IPv6 address"))?,
-            //     destination_port));
-            // }
+                let container_ipv4_address =
+                    get_container_ipv4_address_checked(network, &dst_network)?;
+                let container_ipv6_address = get_container_ipv6_address(network, &dst_network);
+
+                nft_forward_rule.destination_port(container_port.clone());
+                nft_dnat_rule.destination_port(host_port.clone());
+                nft_mark_rule.destination_port(host_port.clone());
+
+                if let Some(container_ipv4_address) = container_ipv4_address {
+                    nft_forward_rule.destination_address(&container_ipv4_address);
+                    nft_dnat_rule.dnat(dnat_destination(
+                        &container_ipv4_address,
+                        host_ports,
+                        container_ports,
+                        &expose_port.family,
+                    )?);
+                } else {
+                    // The container is only reachable through IPv6.
+                    ipv4_destination = false;
+                }
+
+                if let Some(container_ipv6_address) = container_ipv6_address {
+                    trace!(ctx.logger, "Got IPv6 address of destination container";
+                           o!("network_name" => &network.name,
+                              "container_ipv6_address" => &container_ipv6_address));
+                    let dnat_destination = dnat_destination(
+                        &container_ipv6_address,
+                        host_ports,
+                        container_ports,
+                        &expose_port.family,
+                    )?;
+                    ipv6_destination = Some((container_ipv6_address, dnat_destination));
+                }
             } else {
                 // Network for container has to exist
                 return Ok(None);
@@ -923,48 +1129,68 @@ impl Process<Nftables> for WiderWorldToContainerRule {
                 return Ok(None);
             }
 
+            // If the container has an IPv6 address on an IPv6-enabled network, the traffic is
+            // forwarded to it directly. Otherwise the IPv6 packets are only marked, relying on a
+            // separate NAT setup.
+            let nft_ipv6_rules = match ipv6_destination {
+                Some((container_ipv6_address, dnat_destination)) => {
+                    let mut nft_forward6_rule = nft_forward_rule.clone();
+                    nft_forward6_rule.destination_address = None;
+                    nft_forward6_rule.destination_address_v6(&container_ipv6_address);
+                    let mut nft_dnat6_rule = nft_dnat_rule.clone();
+                    nft_dnat6_rule.dnat(dnat_destination);
+                    vec![
+                        (Family::Inet, "forward", nft_forward6_rule),
+                        (Family::Ip6, "prerouting", nft_dnat6_rule),
+                    ]
+                }
+                None => vec![(Family::Ip6, "prerouting", nft_mark_rule)],
+            };
+
             // If source CIDRs have been specified, create the FORWARD-rules as required to
             // restrict the traffic as intended.
-            if let Some(source_cidrs_v4) = &self.source_cidr_v4 {
-                self.apply_source_cidrs_v4(
-                    ctx,
-                    &mut rules,
-                    source_cidrs_v4,
-                    nft_forward_rule.clone(),
-                    nft_dnat_rule.clone(),
-                )?;
-            }
-            if self.expose_via_ipv6 {
-                if let Some(source_cidrs_v6) = &self.source_cidr_v6 {
-                    self.apply_source_cidrs_v6(
+            if ipv4_destination {
+                if let Some(source_cidrs_v4) = &self.source_cidr_v4 {
+                    self.apply_source_cidrs_v4(
                         ctx,
                         &mut rules,
-                        source_cidrs_v6,
-                        nft_mark_rule.clone(),
+                        source_cidrs_v4,
+                        nft_forward_rule.clone(),
+                        nft_dnat_rule.clone(),
                     )?;
                 }
             }
+            if self.expose_via_ipv6 {
+                if let Some(source_cidrs_v6) = &self.source_cidr_v6 {
+                    self.apply_source_cidrs_v6(ctx, &mut rules, source_cidrs_v6, &nft_ipv6_rules)?;
+                }
+            }
 
             // If no source CIDRs were specified, we create the default rules that allow all
             // connections from any IP.
             if self.source_cidr_v4.is_none() && self.source_cidr_v6.is_none() {
-                let forward_rule = nft_forward_rule.build()?;
-                debug!(ctx.logger, "Add forward rule";
-                       o!("part" => "wider_world_to_container",
-                          "rule" => &forward_rule));
-                let dnat_rule = nft_dnat_rule.build()?;
-                debug!(ctx.logger, "Add DNAT rule";
-                       o!("part" => "wider_world_to_container",
-                          "rule" => &dnat_rule));
-                let mark_rule = nft_mark_rule.build()?;
-                debug!(ctx.logger, "Add mark rule";
-                       o!("part" => "wider_world_to_container",
-                          "rule" => &mark_rule));
-                // Apply the rule
-                rules.push(add_rule(Family::Inet, "dfw", "forward", &forward_rule));
-                rules.push(add_rule(Family::Ip, "dfw", "prerouting", &dnat_rule));
+                if ipv4_destination {
+                    let forward_rule = nft_forward_rule.build()?;
+                    debug!(ctx.logger, "Add forward rule";
+                           o!("part" => "wider_world_to_container",
+                              "rule" => forward_rule.to_string()));
+                    let dnat_rule = nft_dnat_rule.build()?;
+                    debug!(ctx.logger, "Add DNAT rule";
+                           o!("part" => "wider_world_to_container",
+                              "rule" => dnat_rule.to_string()));
+                    // Apply the rule
+                    rules.push(add_rule(Family::Inet, "dfw", "forward", forward_rule));
+                    rules.push(add_rule(Family::Ip, "dfw", "prerouting", dnat_rule));
+                }
                 if self.expose_via_ipv6 {
-                    rules.push(add_rule(Family::Ip6, "dfw", "prerouting", &mark_rule));
+                    for (family, chain, nft_ipv6_rule) in &nft_ipv6_rules {
+                        let ipv6_rule = nft_ipv6_rule.build()?;
+                        debug!(ctx.logger, "Add IPv6 rule";
+                               o!("part" => "wider_world_to_container",
+                                  "chain" => chain,
+                                  "rule" => ipv6_rule.to_string()));
+                        rules.push(add_rule(*family, "dfw", chain, ipv6_rule));
+                    }
                 }
             }
         }
@@ -973,8 +1199,88 @@ impl Process<Nftables> for WiderWorldToContainerRule {
     }
 }
 
+/// Generate the rules for ports published through the routing mesh of Docker Swarm.
+///
+/// Docker forwards the published ports to the ingress sandbox attached to `docker_gwbridge`
+/// itself, which is why the forwarded traffic only has to be accepted, restricted to the source
+/// CIDRs if specified. The routing mesh only supports IPv4.
+fn routing_mesh_rules(
+    rule: &WiderWorldToContainerRule,
+    ctx: &ProcessContext<Nftables>,
+) -> Result<Option<Vec<NftablesRule>>> {
+    let network = ctx
+        .network_map
+        .get(DOCKER_GWBRIDGE_NETWORK)
+        .ok_or_else(|| {
+            format_err!(
+                "the routing mesh requires the `{}` network to exist",
+                DOCKER_GWBRIDGE_NETWORK
+            )
+        })?;
+    let interface = ctx.get_network_interface(network)?;
+    trace!(ctx.logger, "Got network interface of routing mesh";
+           o!("network_name" => &network.name,
+              "interface" => format!("{:?}", interface)));
+
+    let external_network_interface = match rule
+        .external_network_interface
+        .as_ref()
+        .or(ctx.primary_external_network_interface.as_ref())
+    {
+        Some(external_network_interface) => external_network_interface,
+        None => return Ok(None),
+    };
+
+    let mut rules = Vec::new();
+    for expose_port in &rule.expose_port {
+        let host_ports = expose_port
+            .host_port_range()
+            .map_err(|error| format_err!("{}", error))?;
+        let container_ports = expose_port
+            .container_port_range()
+            .map_err(|error| format_err!("{}", error))?;
+        if host_ports != container_ports {
+            bail!(
+                "port {} is published through the routing mesh and can't be mapped to a \
+                 different container port",
+                port_range(host_ports)
+            );
+        }
+
+        let mut nft_rule = RuleBuilder::default();
+        nft_rule
+            .in_interface(external_network_interface)
+            .out_interface(interface.name())
+            .protocol(&expose_port.family)
+            .destination_port(port_range(host_ports))
+            .verdict(RuleVerdict::Accept);
+
+        // The routing mesh doesn't accept IPv6 traffic, nothing is accepted if only IPv6 source
+        // CIDRs were specified.
+        if rule.source_cidr_v4.is_none() && rule.source_cidr_v6.is_some() {
+            continue;
+        }
+        let nft_rules = expand_rules(
+            vec![nft_rule],
+            rule.source_cidr_v4.as_deref().unwrap_or_default(),
+            |nft_rule, source_cidr| {
+                nft_rule.source_address(source_cidr);
+            },
+        );
+        for nft_rule in nft_rules {
+            let nft_rule = nft_rule.build()?;
+            debug!(ctx.logger, "Add forward rule for routing mesh";
+                   o!("part" => "wider_world_to_container",
+                      "rule" => nft_rule.to_string()));
+            rules.push(add_rule(Family::Inet, "dfw", "forward", nft_rule));
+        }
+    }
+
+    Ok(Some(rules))
+}
+
 impl Process<Nftables> for ContainerDNAT {
-    fn process(&self, ctx: &ProcessContext<Nftables>) -> Result<Option<Vec<String>>> {
+    fn process(&self, ctx: &ProcessContext<Nftables>) -> Result<Option<Vec<NftablesRule>>> {
         if self.rules.is_some() {
             debug!(ctx.logger, "Process rules";
                 o!("part" => "container_dnat"));
@@ -988,31 +1294,72 @@ impl Process<Nftables> for ContainerDNAT {
 }
 
 impl Process<Nftables> for ContainerDNATRule {
-    fn process(&self, ctx: &ProcessContext<Nftables>) -> Result<Option<Vec<String>>> {
+    fn process(&self, ctx: &ProcessContext<Nftables>) -> Result<Option<Vec<NftablesRule>>> {
         debug!(ctx.logger, "Process rule";
                    o!("part" => "container_dnat",
                       "rule" => format!("{:?}", self)));
+        let src_selector = resolve_selector(
+            self.src_selector.as_ref(),
+            self.src_service.as_ref(),
+            self.src_swarm_service.as_ref(),
+        )?;
+        ensure_container_or_selector(self.src_container.as_ref(), src_selector.as_ref())?;
+        let dst_selector = resolve_selector(
+            self.dst_selector.as_ref(),
+            self.dst_service.as_ref(),
+            self.dst_swarm_service.as_ref(),
+        )?;
+        if self.src_network.is_none() && src_selector.is_some() {
+            bail!("the source selector requires the source network to be specified");
+        }
         let mut rules = Vec::new();
         for expose_port in &self.expose_port {
             let mut nft_rule = RuleBuilder::default();
+            let mut source_addresses = Vec::new();
 
-            if let Some(ref network) = self.src_network {
-                if let Some(network) = ctx.network_map.get(network) {
+            if let Some(ref network_name) = self.src_network {
+                if let Some(network) = ctx.network_map.get(network_name) {
                     trace!(ctx.logger, "Got network";
                                o!("network_name" => &network.name,
                                   "network" => format!("{:?}", network)));
 
+                    let network = ctx.get_host_network(network)?;
                     let network_id = network.id.as_ref().expect("Docker network ID missing");
-                    let bridge_name = get_bridge_name(network_id)?;
-                    trace!(ctx.logger, "Got bridge name";
+                    let interface = ctx.get_network_interface(network)?;
+                    trace!(ctx.logger, "Got network interface";
                                o!("network_name" => &network.name,
-                                  "bridge_name" => &bridge_name));
+                                  "interface" => format!("{:?}", interface)));
 
-                    nft_rule.in_interface(&bridge_name);
+                    nft_rule.in_interface(interface.name());
 
-                    if let Some(ref src_container) = self.src_container {
+                    if let Some(src_sets) = AddressSets::new(
+                        ctx,
+                        network_name,
+                        self.src_container.as_deref(),
+                        src_selector.as_ref(),
+                    ) {
+                        let src_networks = get_network_containers(
+                            ctx.container_runtime,
+                            &ctx.container_map,
+                            self.src_container.as_deref(),
+                            src_selector.as_ref(),
+                            network_id,
+                        )?
+                        .unwrap_or_default();
+                        trace!(ctx.logger, "Got source networks for address set";
+                                   o!("network_name" => &network.name,
+                                      "src_networks" => format!("{:?}", src_networks)));
+                        source_addresses = address_set(
+                            &mut rules,
+                            Family::Ip,
+                            &src_sets,
+                            network,
+                            &src_networks,
+                            IpVersion::V4,
+                        );
+                    } else if let Some(ref src_container) = self.src_container {
                         if let Some(src_network) = get_network_for_container(
-                            ctx.docker,
+                            ctx.container_runtime,
                             &ctx.container_map,
                             src_container,
                             network_id,
@@ -1021,20 +1368,24 @@ impl Process<Nftables> for ContainerDNATRule {
                                        o!("network_name" => &network.name,
                                           "src_network" => format!("{:?}", src_network)));
 
-                            let bridge_name = get_bridge_name(network_id)?;
-                            trace!(ctx.logger, "Got bridge name";
-                                       o!("network_name" => &network.name,
-                                          "bridge_name" => &bridge_name));
-
-                            nft_rule.in_interface(&bridge_name).source_address(
-                                src_network
-                                    .ipv4_address
-                                    .expect("IPv4 address for container missing")
-                                    .split('/')
-                                    .next()
-                                    .ok_or_else(|| format_err!("IPv4 address is empty"))?,
-                            );
+                            match get_container_ipv4_address_checked(network, &src_network)? {
+                                Some(src_address) => {
+                                    nft_rule
+                                        .in_interface(interface.name())
+                                        .source_address(&src_address);
+                                }
+                                // An IPv6-only container can't be the source of IPv4 traffic.
+                                None => continue,
+                            }
                         }
+                    } else if let Some(ref src_selector) = src_selector {
+                        source_addresses =
+                            match ctx.get_selector_source_addresses(network, src_selector)? {
+                                Some(source_addresses) => source_addresses,
+                                None => return Ok(None),
+                            };
+                    } else if interface.is_shared() {
+                        source_addresses = get_network_subnets(network, IpVersion::V4);
                     }
                 }
             }
@@ -1043,112 +1394,602 @@ impl Process<Nftables> for ContainerDNATRule {
                 Some(network) => network,
                 None => return Ok(None),
             };
+            let network = ctx.get_host_network(network)?;
             let network_id = network.id.as_ref().expect("Docker network ID missing");
-            let dst_network = match get_network_for_container(
-                ctx.docker,
+            let dst_network = get_network_for_single_container(
+                ctx.container_runtime,
                 &ctx.container_map,
-                &self.dst_container,
+                self.dst_container.as_deref(),
+                dst_selector.as_ref(),
                 network_id,
-            )? {
-                Some(dst_network) => dst_network,
-                None => return Ok(None),
-            };
+            )?;
+            let dst_sets = AddressSets::new(
+                ctx,
+                &self.dst_network,
+                self.dst_container.as_deref(),
+                dst_selector.as_ref(),
+            );
+            if dst_network.is_none() && dst_sets.is_none() {
+                return Ok(None);
+            }
             trace!(ctx.logger, "Got destination network";
                        o!("network_name" => &network.name,
                           "dst_network" => format!("{:?}", dst_network)));
 
-            let bridge_name = get_bridge_name(network_id)?;
-            trace!(ctx.logger, "Got bridge name";
+            let interface = ctx.get_network_interface(network)?;
+            trace!(ctx.logger, "Got network interface";
                        o!("network_name" => &network.name,
-                          "bridge_name" => &bridge_name));
+                          "interface" => format!("{:?}", interface)));
+            ensure_routed_network(network, &interface)?;
+
+            nft_rule.out_interface(interface.name());
+
+            let destination_ports = expose_port
+                .container_port_range()
+                .map_err(|error| format_err!("{}", error))?;
+            nft_rule.protocol(&expose_port.family);
+            nft_rule.destination_port(port_range(destination_ports));
+            if let Some(dst_sets) = &dst_sets {
+                nft_rule.dnat(dnat_map(
+                    &mut rules,
+                    Family::Ip,
+                    dst_sets,
+                    network,
+                    &dst_network.into_iter().collect::<Vec<_>>(),
+                    IpVersion::V4,
+                    destination_ports,
+                    destination_ports,
+                    &expose_port.family,
+                )?);
+            } else if let Some(dst_network) = dst_network {
+                let dst_address = match get_container_ipv4_address_checked(network, &dst_network)? {
+                    Some(dst_address) => dst_address,
+                    // Traffic can't be forwarded to an IPv6-only container through IPv4 DNAT.
+                    None => continue,
+                };
+                nft_rule.dnat(dnat_destination(
+                    &dst_address,
+                    destination_ports,
+                    destination_ports,
+                    &expose_port.family,
+                )?);
+            }
 
-            nft_rule.out_interface(&bridge_name);
+            for nft_rule in expand_rules(vec![nft_rule], &source_addresses, |nft_rule, address| {
+                nft_rule.source_address(address);
+            }) {
+                let rule = nft_rule.build()?;
+                debug!(ctx.logger, "Add prerouting rule";
+                           o!("part" => "container_dnat",
+                              "rule" => rule.to_string()));
 
-            let destination_port = match expose_port.container_port {
-                Some(destination_port) => destination_port.to_string(),
-                None => expose_port.host_port.to_string(),
-            };
-            nft_rule.destination_port(&destination_port);
-            nft_rule.dnat(&format!(
-                "{}:{}",
-                dst_network
-                    .ipv4_address
-                    .expect("IPv4 address for container missing")
-                    .split('/')
-                    .next()
-                    .ok_or_else(|| format_err!("IPv4 address is empty"))?,
-                destination_port
+                // Apply the rule
+                rules.push(add_rule(Family::Ip, "dfw", "prerouting", rule));
+            }
+        }
+
+        Ok(Some(rules))
+    }
+}
+
+/// Lower the typed matches of a rule, generating the rules for the given IP version.
+///
+/// Destination CIDRs are only applied for the IP version they belong to, rules for an IP version
+/// are omitted if only destination CIDRs of the other IP version are specified. Rules matching
+/// ICMP or ICMPv6 are only generated for the IP version of the protocol.
+fn apply_typed_matches(
+    nft_rules: Vec<RuleBuilder>,
+    matches: &TypedMatches,
+    ip_version: IpVersion,
+) -> Result<Vec<RuleBuilder>> {
+    matches.validate()?;
+
+    let ip_version = match matches.restrict_ip_version(ip_version) {
+        Some(ip_version) => ip_version,
+        None => return Ok(Vec::new()),
+    };
+
+    let mut typed_rules = Vec::new();
+    for mut nft_rule in nft_rules {
+        if let Some(protocol) = matches.protocol {
+            nft_rule.protocol(protocol.to_string());
+        }
+        if let Some(src_port) = matches.src_port {
+            nft_rule.source_port(port_range((src_port.start, src_port.end)));
+        }
+        if let Some(dst_port) = matches.dst_port {
+            nft_rule.destination_port(port_range((dst_port.start, dst_port.end)));
+        }
+        if let Some(icmp_type) = matches.icmp_type {
+            nft_rule.icmp_type(icmp_type);
+        }
+        if let Some(ct_state) = matches.ct_state {
+            nft_rule.ct_state(Expression::set_or_value(
+                ct_state.iter().map(ToString::to_string),
             ));
+        }
 
-            let rule = nft_rule.build()?;
-            debug!(ctx.logger, "Add prerouting rule";
-                       o!("part" => "container_dnat",
-                          "rule" => &rule));
+        if matches.dst_cidr_v4.is_none() && matches.dst_cidr_v6.is_none() {
+            if ip_version == IpVersion::V6
+                && (nft_rule.source_address.is_some() || nft_rule.destination_address.is_some())
+            {
+                bail!("ICMPv6 can't be matched for IPv4 container addresses");
+            }
+            typed_rules.push(nft_rule);
+            continue;
+        }
+        if let (Some(dst_cidr_v4), IpVersion::V4 | IpVersion::Both) =
+            (matches.dst_cidr_v4, ip_version)
+        {
+            if nft_rule.destination_address.is_some() {
+                bail!("destination CIDRs can't be combined with a destination container");
+            }
+            let mut nft_rule = nft_rule.clone();
+            nft_rule.destination_address = Some(Expression::set_or_value(
+                dst_cidr_v4.iter().map(|cidr| Expression::address(cidr)),
+            ));
+            typed_rules.push(nft_rule);
+        }
+        if let (Some(dst_cidr_v6), IpVersion::V6 | IpVersion::Both) =
+            (matches.dst_cidr_v6, ip_version)
+        {
+            if nft_rule.destination_address_v6.is_some() {
+                bail!("destination CIDRs can't be combined with a destination container");
+            }
+            if nft_rule.source_address.is_some() || nft_rule.destination_address.is_some() {
+                bail!("IPv6 destination CIDRs can't be combined with IPv4 container addresses");
+            }
+            let mut nft_rule = nft_rule.clone();
+            nft_rule.destination_address_v6 = Some(Expression::set_or_value(
+                dst_cidr_v6.iter().map(|cidr| Expression::address(cidr)),
+            ));
+            typed_rules.push(nft_rule);
+        }
+    }
 
-            // Apply the rule
-            rules.push(add_rule(Family::Ip, "dfw", "prerouting", &rule));
+    Ok(typed_rules)
+}
+
+/// Determine the IP versions a rule has to be generated for.
+///
+/// Rules that aren't restricted to specific containers are family-agnostic in the `inet` table,
+/// they only have to be generated once.
+fn rule_ip_versions(ip_version: IpVersion, has_containers: bool) -> &'static [IpVersion] {
+    if has_containers {
+        single_ip_versions(ip_version)
+    } else {
+        match ip_version {
+            IpVersion::V4 => &[IpVersion::V4],
+            IpVersion::V6 => &[IpVersion::V6],
+            IpVersion::Both => &[IpVersion::Both],
         }
+    }
+}
 
-        Ok(Some(rules))
+/// Restrict a rule that isn't matching any addresses to a single IP version.
+fn set_nfproto(nft_rule: &mut RuleBuilder, ip_version: IpVersion) {
+    match ip_version {
+        IpVersion::V4 => {
+            nft_rule.nfproto("ipv4");
+        }
+        IpVersion::V6 => {
+            nft_rule.nfproto("ipv6");
+        }
+        IpVersion::Both => {}
+    }
+}
+
+/// Restrict a rule to the subnets of a network if the interface of the network is shared,
+/// generating a rule for every subnet.
+fn restrict_to_subnets(
+    nft_rule: RuleBuilder,
+    network: &Network,
+    interface: &NetworkInterface,
+    source: bool,
+    destination: bool,
+) -> Vec<RuleBuilder> {
+    if !interface.is_shared() {
+        return vec![nft_rule];
+    }
+
+    let mut nft_rules = Vec::new();
+    for &ip_version in single_ip_versions(IpVersion::Both) {
+        let subnets = get_network_subnets(network, ip_version);
+        if subnets.is_empty() {
+            continue;
+        }
+        let mut ip_version_rules = vec![nft_rule.clone()];
+        if source {
+            ip_version_rules = expand_rules(ip_version_rules, &subnets, |nft_rule, subnet| {
+                set_source_address(nft_rule, ip_version, subnet)
+            });
+        }
+        if destination {
+            ip_version_rules = expand_rules(ip_version_rules, &subnets, |nft_rule, subnet| {
+                set_destination_address(nft_rule, ip_version, subnet)
+            });
+        }
+        nft_rules.append(&mut ip_version_rules);
+    }
+    nft_rules
+}
+
+fn set_source_address(nft_rule: &mut RuleBuilder, ip_version: IpVersion, address: &str) {
+    match ip_version {
+        IpVersion::V6 => nft_rule.source_address_v6(address),
+        _ => nft_rule.source_address(address),
+    };
+}
+
+fn set_destination_address(nft_rule: &mut RuleBuilder, ip_version: IpVersion, address: &str) {
+    match ip_version {
+        IpVersion::V6 => nft_rule.destination_address_v6(address),
+        _ => nft_rule.destination_address(address),
+    };
+}
+
+/// Inclusive range of ports for use in a port match, or a single port.
+fn port_range((start, end): (u16, u16)) -> Expression {
+    if start == end {
+        start.into()
+    } else {
+        Expression::range(start, end)
+    }
+}
+
+/// Destination of a DNAT statement, mapping the range of host ports 1:1 to the range of container
+/// ports.
+fn dnat_destination(
+    address: &str,
+    host_ports: (u16, u16),
+    container_ports: (u16, u16),
+    family: &str,
+) -> Result<Nat> {
+    Ok(Nat {
+        addr: Some(address.into()),
+        port: if host_ports.0 == host_ports.1 {
+            Some(container_ports.0.into())
+        } else if host_ports.0 == container_ports.0 {
+            // The destination port is retained if only the address is translated.
+            None
+        } else {
+            Some(port_map(host_ports, container_ports, family)?)
+        },
+        family: None,
+    })
+}
+
+/// Map translating the range of host ports 1:1 to the range of container ports.
+///
+/// nft can't shift a range of ports by an offset, the map thus holds an element per port. To
+/// keep the ruleset from growing with the size of the range, the range is limited to
+/// `MAX_SHIFTED_PORTS` ports.
+fn port_map(
+    host_ports: (u16, u16),
+    container_ports: (u16, u16),
+    family: &str,
+) -> Result<Expression> {
+    let ports = u32::from(host_ports.1 - host_ports.0) + 1;
+    if ports > MAX_SHIFTED_PORTS {
+        bail!(
+            "host ports {} can't be mapped to container ports {}: nftables requires a map \
+             element per port when shifting a port range, which is limited to {} ports",
+            port_range(host_ports),
+            port_range(container_ports),
+            MAX_SHIFTED_PORTS
+        );
+    }
+
+    Ok(Expression::map(
+        Expression::payload(transport_protocol(family), "dport"),
+        Expression::set(
+            (host_ports.0..=host_ports.1)
+                .zip(container_ports.0..=container_ports.1)
+                .map(|(host_port, container_port)| Expression::pair(host_port, container_port)),
+        ),
+    ))
+}
+
+/// Ports of multiple protocols are matched through the transport header, which is valid for all
+/// of them.
+fn transport_protocol(family: &str) -> &str {
+    if family.contains('+') {
+        "th"
+    } else {
+        family
     }
 }
 
+/// Check if the addresses of the containers selected by rules are matched through named sets.
+fn use_address_sets(ctx: &ProcessContext<Nftables>) -> bool {
+    ctx.dfw
+        .backend_defaults
+        .as_ref()
+        .is_some_and(|defaults| defaults.address_sets)
+}
+
+/// Names of the sets and maps holding the addresses of the containers a rule selects on a network,
+/// either by name or by selector.
+struct AddressSets {
+    /// Readable part of the names, the container name or the values of the selector.
+    label: String,
+    /// The network and the container name or selector, which the names are unique for.
+    key: String,
+}
+
+impl AddressSets {
+    /// Create the names for the containers a rule selects, if address sets are enabled and the
+    /// rule selects containers.
+    fn new(
+        ctx: &ProcessContext<Nftables>,
+        network: &str,
+        container_name: Option<&str>,
+        selector: Option<&ContainerSelector>,
+    ) -> Option<AddressSets> {
+        if !use_address_sets(ctx) {
+            return None;
+        }
+        let (label, key) = match (container_name, selector) {
+            (Some(container_name), _) => (
+                container_name.to_owned(),
+                format!("container:{}", container_name),
+            ),
+            (None, Some(selector)) => (
+                selector.0.values().cloned().collect::<Vec<_>>().join("_"),
+                format!("selector:{:?}", selector.0),
+            ),
+            (None, None) => return None,
+        };
+
+        Some(AddressSets {
+            label,
+            key: format!("{}\0{}", network, key),
+        })
+    }
+
+    /// Name of the set holding the addresses of an IP version, e.g. `dfw_web_5a0f13c2_v4`.
+    fn set(&self, ip_version: IpVersion) -> String {
+        self.name("", &self.key, ip_version)
+    }
+
+    /// Name of the map translating the host ports of an exposed port to the addresses of an IP
+    /// version, e.g. `dfw_web_dnat_9be1f04a_v4`.
+    fn dnat_map(&self, host_ports: (u16, u16), family: &str, ip_version: IpVersion) -> String {
+        self.name(
+            "_dnat",
+            &format!("{}\0{}:{}", self.key, family, port_range(host_ports)),
+            ip_version,
+        )
+    }
+
+    fn name(&self, kind: &str, key: &str, ip_version: IpVersion) -> String {
+        // nft only accepts alphanumeric characters and a few special characters in identifiers,
+        // the hash of the key keeps the names unique nonetheless.
+        let label = self
+            .label
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .take(24)
+            .collect::<String>();
+        let suffix = match ip_version {
+            IpVersion::V4 => "v4",
+            IpVersion::V6 => "v6",
+            IpVersion::Both => unreachable!("sets hold addresses of a single IP version"),
+        };
+        format!("dfw_{}{}_{:08x}_{}", label, kind, fnv1a(key), suffix)
+    }
+}
+
+/// Hash a value using 32-bit FNV-1a, which is stable across versions of DFW and Rust.
+fn fnv1a(value: &str) -> u32 {
+    value.bytes().fold(0x811c_9dc5, |hash, byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+    })
+}
+
+/// Retrieve the addresses a rule on a network is restricted to, like `get_rule_addresses`.
+///
+/// If the rule selects containers and address sets are enabled, the addresses are added to a named
+/// set instead, which is returned as the only address to match.
+fn get_rule_addresses_or_set(
+    rules: &mut Vec<NftablesRule>,
+    family: Family,
+    sets: Option<&AddressSets>,
+    network: &Network,
+    interface: &NetworkInterface,
+    network_containers: Option<&[NetworkContainer]>,
+    ip_version: IpVersion,
+) -> Option<Vec<String>> {
+    match (sets, network_containers) {
+        (Some(sets), Some(network_containers)) => Some(address_set(
+            rules,
+            family,
+            sets,
+            network,
+            network_containers,
+            ip_version,
+        )),
+        _ => get_rule_addresses(network, interface, network_containers, ip_version),
+    }
+}
+
+/// Define the set holding the addresses of an IP version of the containers, returning the
+/// reference to the set.
+///
+/// No set is defined for IPv6 if IPv6 isn't enabled for the network, since it would always be
+/// empty.
+fn address_set(
+    rules: &mut Vec<NftablesRule>,
+    family: Family,
+    sets: &AddressSets,
+    network: &Network,
+    network_containers: &[NetworkContainer],
+    ip_version: IpVersion,
+) -> Vec<String> {
+    if ip_version == IpVersion::V6 && network.enable_ipv6 != Some(true) {
+        return Vec::new();
+    }
+
+    let name = sets.set(ip_version);
+    let mut set = json::Set::new(family, "dfw", &name);
+    set.set_type = Some(address_type(ip_version).to_owned());
+    rules.append(&mut define_set(
+        Object::Set,
+        set,
+        get_addresses(network, network_containers, ip_version)
+            .iter()
+            .map(|address| Expression::address(address))
+            .collect(),
+    ));
+    vec![format!("@{}", name)]
+}
+
+/// Define the map translating the host ports to the address of an IP version of the container,
+/// returning the destination of the DNAT statement using the map.
+#[allow(clippy::too_many_arguments)]
+fn dnat_map(
+    rules: &mut Vec<NftablesRule>,
+    family: Family,
+    sets: &AddressSets,
+    network: &Network,
+    network_containers: &[NetworkContainer],
+    ip_version: IpVersion,
+    host_ports: (u16, u16),
+    container_ports: (u16, u16),
+    protocol_family: &str,
+) -> Result<Nat> {
+    let name = sets.dnat_map(host_ports, protocol_family, ip_version);
+    let mut map = json::Set::new(family, "dfw", &name);
+    map.set_type = Some("inet_service".to_owned());
+    map.map = Some(address_type(ip_version).to_owned());
+    if host_ports.0 != host_ports.1 {
+        map.flags.push("interval".to_owned());
+    }
+    rules.append(&mut define_set(
+        Object::Map,
+        map,
+        get_addresses(network, network_containers, ip_version)
+            .iter()
+            .map(|address| Expression::pair(port_range(host_ports), Expression::address(address)))
+            .collect(),
+    ));
+
+    Ok(Nat {
+        addr: Some(Expression::map(
+            Expression::payload(transport_protocol(protocol_family), "dport"),
+            format!("@{}", name).into(),
+        )),
+        port: if host_ports.0 == container_ports.0 {
+            // The destination port is retained if only the address is translated.
+            None
+        } else if host_ports.0 == host_ports.1 {
+            Some(container_ports.0.into())
+        } else {
+            Some(port_map(host_ports, container_ports, protocol_family)?)
+        },
+        family: None,
+    })
+}
+
+fn address_type(ip_version: IpVersion) -> &'static str {
+    match ip_version {
+        IpVersion::V6 => "ipv6_addr",
+        _ => "ipv4_addr",
+    }
+}
+
+/// Convert the policy of a chain to the verdict of a rule enforcing it.
+fn policy_verdict(policy: ChainPolicy) -> RuleVerdict {
+    match policy {
+        ChainPolicy::Accept => RuleVerdict::Accept,
+        ChainPolicy::Drop => RuleVerdict::Drop,
+    }
+}
+
+/// Statements of the `ct state invalid drop` rule.
+fn ct_state_invalid_drop() -> Statements {
+    Statements(vec![
+        Statement::ct_state("invalid"),
+        Statement::verdict(RuleVerdict::Drop),
+    ])
+}
+
+/// Statements of the `ct state { related, established } accept` rule.
+fn ct_state_related_established_accept() -> Statements {
+    Statements(vec![
+        Statement::ct_state(Expression::set(["related", "established"])),
+        Statement::verdict(RuleVerdict::Accept),
+    ])
+}
+
 /// Construct nft command for adding a table.
-fn add_table(family: Family, table: &str) -> String {
-    format!("add table {} {}", family, table)
+fn add_table(family: Family, table: &str) -> NftablesRule {
+    Command::Add(Object::Table(Table::new(family, table)).into()).into()
 }
 
 /// Construct nft command for flushing a table.
-fn flush_table(family: Family, table: &str) -> String {
-    format!("flush table {} {}", family, table)
+fn flush_table(family: Family, table: &str) -> NftablesRule {
+    Command::Flush(Object::Table(Table::new(family, table)).into()).into()
 }
 
-/// Construct nft command for adding a base chain.
-fn add_base_chain(
-    family: Family,
-    table: &str,
-    chain: &str,
-    r#type: Type,
-    hook: Hook,
-    priority: i16,
-) -> String {
-    format!(
-        "add chain {} {} {} {{ type {} hook {} priority {} ; }}",
-        family, table, chain, r#type, hook, priority
-    )
+/// Definition of a base chain of the DFW tables, see `BASE_CHAINS`.
+fn base_chain(family: Family, chain: &str) -> Chain {
+    let &(_, _, chain_type, hook, priority) = BASE_CHAINS
+        .iter()
+        .find(|(base_family, base_chain, ..)| *base_family == family && *base_chain == chain)
+        .unwrap_or_else(|| panic!("`{} dfw {}` isn't a base chain", family, chain));
+    let mut base_chain = Chain::new(family, "dfw", chain);
+    base_chain.chain_type = Some(chain_type);
+    base_chain.hook = Some(hook);
+    base_chain.prio = Some(priority.into());
+    base_chain
+}
+
+/// Construct nft command for adding a base chain of the DFW tables.
+fn add_base_chain(family: Family, chain: &str) -> NftablesRule {
+    Command::Add(Object::Chain(base_chain(family, chain)).into()).into()
 }
 
-/// Construct nft command for setting the policy for a chain.
-fn set_chain_policy(family: Family, table: &str, chain: &str, policy: ChainPolicy) -> String {
-    format!(
-        "add chain {} {} {} {{ policy {} ; }}",
-        family, table, chain, policy
-    )
+/// Construct nft commands for (re)defining a named set or map, replacing its elements.
+fn define_set(
+    object: fn(json::Set) -> Object,
+    set: json::Set,
+    elements: Vec<Expression>,
+) -> Vec<NftablesRule> {
+    let mut commands = vec![
+        Command::Add(object(set.clone()).into()).into(),
+        Command::Flush(object(set.elements(Vec::new())).into()).into(),
+    ];
+    if !elements.is_empty() {
+        commands.push(Command::Add(Object::Element(set.elements(elements)).into()).into());
+    }
+    commands
+}
+
+/// Construct nft command for setting the policy for a base chain of the DFW tables.
+///
+/// nftables only accepts the policy together with the definition of the base chain.
+fn set_chain_policy(family: Family, chain: &str, policy: ChainPolicy) -> NftablesRule {
+    let mut chain = base_chain(family, chain);
+    chain.policy = Some(policy.to_string());
+    Command::Add(Object::Chain(chain).into()).into()
 }
 
 /// Construct nft command for adding a rule to a chain.
-fn add_rule(family: Family, table: &str, chain: &str, rule: &str) -> String {
-    format!("add rule {} {} {} {}", family, table, chain, rule)
+fn add_rule(family: Family, table: &str, chain: &str, rule: Statements) -> NftablesRule {
+    Command::Add(Object::Rule(json::Rule::new(family, table, chain, rule)).into()).into()
 }
 
-/// Construct nft command for inserting a rule into a chain.
+/// Construct nft command for inserting a rule at the beginning of a chain, identified by the
+/// comment.
 fn insert_rule(
     family: Family,
     table: &str,
     chain: &str,
-    rule: &str,
-    position: Option<u32>,
-) -> String {
-    format!(
-        "insert rule {} {} {} {}{}",
-        family,
-        table,
-        chain,
-        if let Some(position) = position {
-            format!("position {} ", position)
-        } else {
-            "".to_owned()
-        },
-        rule
-    )
+    rule: Statements,
+    comment: &str,
+) -> NftablesRule {
+    let mut rule = json::Rule::new(family, table, chain, rule);
+    rule.comment = Some(comment.to_owned());
+    Command::Insert(Object::Rule(rule).into()).into()
 }
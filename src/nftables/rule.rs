@@ -7,9 +7,454 @@
 // option. This file may not be copied, modified or distributed
 // except according to those terms.
 
-use crate::{errors::*, nftables::DFW_MARK, types::RuleVerdict};
+//! Typed model of the statements of a rule, serialized to and parsed from the libnftables JSON
+//! schema (see `libnftables-json(5)`).
+//!
+//! The model covers the statements and expressions DFW generates. Statements and expressions
+//! listed by `nft -j` that aren't covered, e.g. because they were compiled from the `matches` of a
+//! rule, are retained as they were listed. Statements are displayed in the nft syntax, which is
+//! how the processed rules are rendered.
+
+use super::{Family, DFW_MARK};
+use crate::{errors::*, types::RuleVerdict};
 use derive_builder::Builder;
 use failure::bail;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{collections::HashMap, fmt};
+
+/// Expression of a statement, e.g. the left- or right-hand side of a match.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum Expression {
+    /// Value like an address or interface name, or a reference to a named set (`@name`).
+    String(String),
+    Number(u64),
+    /// Key-value pair of a map element, or a list of flags as listed by newer versions of nft.
+    List(Vec<Expression>),
+    Object(Box<ExpressionObject>),
+    /// Expression DFW doesn't generate, retained as it was listed.
+    Other(Value),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase", deny_unknown_fields)]
+pub(crate) enum ExpressionObject {
+    /// Header field of a protocol, e.g. `ip saddr` or `th dport`.
+    Payload {
+        protocol: String,
+        field: String,
+    },
+    Meta {
+        key: String,
+    },
+    Ct {
+        key: String,
+    },
+    Prefix {
+        addr: String,
+        len: u8,
+    },
+    Range([Expression; 2]),
+    /// Anonymous set, e.g. `{ 80, 443 }`.
+    Set(Vec<Expression>),
+    /// Lookup of the key in a map, e.g. `tcp dport map @name`.
+    Map {
+        key: Expression,
+        data: Expression,
+    },
+    /// Binary AND, e.g. `meta mark and 0xdf`.
+    #[serde(rename = "&")]
+    And([Expression; 2]),
+}
+
+/// Statement of a rule.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum Statement {
+    Object(StatementObject),
+    /// nft expressions of the configuration (`matches`), which have to be compiled to their JSON
+    /// representation before the rule can be serialized.
+    #[serde(skip)]
+    Matches(String),
+    /// Statement DFW doesn't generate, retained as it was listed.
+    Other(Value),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase", deny_unknown_fields)]
+pub(crate) enum StatementObject {
+    Match {
+        op: Operator,
+        left: Expression,
+        right: Expression,
+    },
+    Mangle {
+        key: Expression,
+        value: Expression,
+    },
+    Accept(()),
+    Drop(()),
+    Reject(()),
+    Masquerade(()),
+    Dnat(Nat),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum Operator {
+    #[serde(rename = "==")]
+    Eq,
+    #[serde(rename = "!=")]
+    Ne,
+    /// Implicit operator, which nft uses to match flags like the connection tracking state.
+    #[serde(rename = "in")]
+    In,
+}
+
+/// Destination of a DNAT statement.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Nat {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) addr: Option<Expression>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) port: Option<Expression>,
+    /// Family of the address, only listed within the `inet` family.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) family: Option<String>,
+}
+
+/// Statements of a rule, e.g. `ip saddr 172.20.0.2 meta mark set 0xdf accept`.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub(crate) struct Statements(pub(crate) Vec<Statement>);
+
+impl Expression {
+    pub(crate) fn payload(protocol: &str, field: &str) -> Expression {
+        Expression::Object(Box::new(ExpressionObject::Payload {
+            protocol: protocol.to_owned(),
+            field: field.to_owned(),
+        }))
+    }
+
+    pub(crate) fn meta(key: &str) -> Expression {
+        Expression::Object(Box::new(ExpressionObject::Meta {
+            key: key.to_owned(),
+        }))
+    }
+
+    pub(crate) fn ct(key: &str) -> Expression {
+        Expression::Object(Box::new(ExpressionObject::Ct {
+            key: key.to_owned(),
+        }))
+    }
+
+    /// Parse an address, which is either a single address, a prefix (`10.0.0.0/8`) or a
+    /// reference to a named set (`@name`).
+    pub(crate) fn address(address: &str) -> Expression {
+        match address
+            .split_once('/')
+            .and_then(|(addr, len)| Some((addr, len.parse().ok()?)))
+        {
+            Some((addr, len)) => Expression::Object(Box::new(ExpressionObject::Prefix {
+                addr: addr.to_owned(),
+                len,
+            })),
+            None => Expression::from(address),
+        }
+    }
+
+    pub(crate) fn range(start: impl Into<Expression>, end: impl Into<Expression>) -> Expression {
+        Expression::Object(Box::new(ExpressionObject::Range([
+            start.into(),
+            end.into(),
+        ])))
+    }
+
+    pub(crate) fn set<T: Into<Expression>>(values: impl IntoIterator<Item = T>) -> Expression {
+        Expression::Object(Box::new(ExpressionObject::Set(
+            values.into_iter().map(Into::into).collect(),
+        )))
+    }
+
+    /// Anonymous set of the values, or the value itself if there is only one.
+    pub(crate) fn set_or_value<T: Into<Expression>>(
+        values: impl IntoIterator<Item = T>,
+    ) -> Expression {
+        let mut values: Vec<Expression> = values.into_iter().map(Into::into).collect();
+        if values.len() == 1 {
+            values.remove(0)
+        } else {
+            Expression::set(values)
+        }
+    }
+
+    pub(crate) fn map(key: Expression, data: Expression) -> Expression {
+        Expression::Object(Box::new(ExpressionObject::Map { key, data }))
+    }
+
+    pub(crate) fn pair(key: impl Into<Expression>, value: impl Into<Expression>) -> Expression {
+        Expression::List(vec![key.into(), value.into()])
+    }
+
+    /// Check if this is the packet mark, or a binary operation on it.
+    fn is_mark(&self) -> bool {
+        match self {
+            Expression::Object(object) => match &**object {
+                ExpressionObject::Meta { key } => key == "mark",
+                ExpressionObject::And([left, _]) => left.is_mark(),
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+}
+
+impl From<&str> for Expression {
+    fn from(value: &str) -> Expression {
+        Expression::String(value.to_owned())
+    }
+}
+
+impl From<String> for Expression {
+    fn from(value: String) -> Expression {
+        Expression::String(value)
+    }
+}
+
+impl From<u16> for Expression {
+    fn from(value: u16) -> Expression {
+        Expression::Number(value.into())
+    }
+}
+
+impl From<u64> for Expression {
+    fn from(value: u64) -> Expression {
+        Expression::Number(value)
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expression::String(value) => f.write_str(value),
+            Expression::Number(value) => write!(f, "{}", value),
+            Expression::List(values) => f.write_str(&join(values, " : ")),
+            Expression::Object(object) => match &**object {
+                ExpressionObject::Payload { protocol, field } => {
+                    write!(f, "{} {}", protocol, field)
+                }
+                ExpressionObject::Meta { key } => write!(f, "meta {}", key),
+                ExpressionObject::Ct { key } => write!(f, "ct {}", key),
+                ExpressionObject::Prefix { addr, len } => write!(f, "{}/{}", addr, len),
+                ExpressionObject::Range([start, end]) => write!(f, "{}-{}", start, end),
+                ExpressionObject::Set(values) => write!(f, "{{ {} }}", join(values, ", ")),
+                ExpressionObject::Map { key, data } => write!(f, "{} map {}", key, data),
+                ExpressionObject::And([left, right]) => write!(f, "{} and {}", left, right),
+            },
+            Expression::Other(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// Display numbers in hexadecimal, like the packet mark is written.
+struct Hex<'a>(&'a Expression);
+
+impl fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Expression::Number(value) => write!(f, "{:#x}", value),
+            Expression::Object(object) => match &**object {
+                ExpressionObject::And([left, right]) => {
+                    write!(f, "{} and {}", Hex(left), Hex(right))
+                }
+                _ => self.0.fmt(f),
+            },
+            expression => expression.fmt(f),
+        }
+    }
+}
+
+impl Statement {
+    /// Match the expression against the value, e.g. `ip saddr 172.20.0.2`.
+    pub(crate) fn matches(left: Expression, right: impl Into<Expression>) -> Statement {
+        Statement::Object(StatementObject::Match {
+            op: Operator::Eq,
+            left,
+            right: right.into(),
+        })
+    }
+
+    /// Match the connection tracking state, e.g. `ct state { related, established }`.
+    pub(crate) fn ct_state(states: impl Into<Expression>) -> Statement {
+        Statement::Object(StatementObject::Match {
+            op: Operator::In,
+            left: Expression::ct("state"),
+            right: states.into(),
+        })
+    }
+
+    /// Match packets marked by DFW, i.e. `meta mark and 0xdf == 0xdf`.
+    pub(crate) fn marked() -> Statement {
+        Statement::Object(StatementObject::Match {
+            op: Operator::Eq,
+            left: Expression::Object(Box::new(ExpressionObject::And([
+                Expression::meta("mark"),
+                DFW_MARK.into(),
+            ]))),
+            right: DFW_MARK.into(),
+        })
+    }
+
+    /// Mark the packet as handled by DFW, i.e. `meta mark set 0xdf`.
+    pub(crate) fn mark() -> Statement {
+        Statement::Object(StatementObject::Mangle {
+            key: Expression::meta("mark"),
+            value: DFW_MARK.into(),
+        })
+    }
+
+    pub(crate) fn verdict(verdict: RuleVerdict) -> Statement {
+        Statement::Object(match verdict {
+            RuleVerdict::Accept => StatementObject::Accept(()),
+            RuleVerdict::Drop => StatementObject::Drop(()),
+            RuleVerdict::Reject => StatementObject::Reject(()),
+        })
+    }
+
+    pub(crate) fn masquerade() -> Statement {
+        Statement::Object(StatementObject::Masquerade(()))
+    }
+}
+
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Statement::Object(object) => object.fmt(f),
+            Statement::Matches(matches) => f.write_str(matches),
+            Statement::Other(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+impl fmt::Display for StatementObject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StatementObject::Match { op, left, right } => {
+                let binary = matches!(
+                    left,
+                    Expression::Object(object) if matches!(**object, ExpressionObject::And(_))
+                );
+                let (hex_left, hex_right) = (Hex(left), Hex(right));
+                let (left, right): (&dyn fmt::Display, &dyn fmt::Display) = if left.is_mark() {
+                    (&hex_left, &hex_right)
+                } else {
+                    (left, right)
+                };
+                match op {
+                    // nft requires the operator following a binary operation.
+                    Operator::Eq if binary => write!(f, "{} == {}", left, right),
+                    Operator::Eq | Operator::In => write!(f, "{} {}", left, right),
+                    Operator::Ne => write!(f, "{} != {}", left, right),
+                }
+            }
+            StatementObject::Mangle { key, value } if key.is_mark() => {
+                write!(f, "{} set {}", key, Hex(value))
+            }
+            StatementObject::Mangle { key, value } => write!(f, "{} set {}", key, value),
+            StatementObject::Accept(()) => f.write_str("accept"),
+            StatementObject::Drop(()) => f.write_str("drop"),
+            StatementObject::Reject(()) => f.write_str("reject"),
+            StatementObject::Masquerade(()) => f.write_str("masquerade"),
+            StatementObject::Dnat(nat) => nat.fmt(f),
+        }
+    }
+}
+
+impl fmt::Display for Nat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("dnat")?;
+        if let Some(family) = &self.family {
+            write!(f, " {} to", family)?;
+        }
+        match (&self.addr, &self.port) {
+            // IPv6 addresses have to be enclosed in brackets if they are followed by a port.
+            (Some(Expression::String(addr)), Some(port)) if addr.contains(':') => {
+                write!(f, " [{}]", addr)?;
+                self.fmt_port(port, f)
+            }
+            (Some(addr), Some(port)) => {
+                write!(f, " {}", addr)?;
+                self.fmt_port(port, f)
+            }
+            (Some(addr), None) => write!(f, " {}", addr),
+            (None, Some(port)) => write!(f, " :{}", port),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+impl Nat {
+    fn fmt_port(&self, port: &Expression, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.addr, port) {
+            (Some(Expression::String(_)), Expression::Number(port)) => write!(f, ":{}", port),
+            _ => write!(f, " : {}", port),
+        }
+    }
+}
+
+impl Statements {
+    pub(crate) fn push(&mut self, statement: Statement) {
+        self.0.push(statement);
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The nft expressions of the configuration (`matches`) the statements contain.
+    pub(crate) fn matches(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().filter_map(|statement| match statement {
+            Statement::Matches(matches) => Some(matches.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Replace the nft expressions of the configuration (`matches`) with the statements they were
+    /// compiled to, by the family of the rule and the expressions.
+    pub(crate) fn replace_matches(
+        &mut self,
+        family: Family,
+        compiled: &HashMap<(Family, String), Statements>,
+    ) -> Result<()> {
+        let mut statements = Vec::with_capacity(self.0.len());
+        for statement in &self.0 {
+            match statement {
+                Statement::Matches(matches) => match compiled.get(&(family, matches.to_owned())) {
+                    Some(compiled) => statements.extend(compiled.0.iter().cloned()),
+                    None => bail!("the matches `{}` of a rule weren't compiled", matches),
+                },
+                statement => statements.push(statement.clone()),
+            }
+        }
+        self.0 = statements;
+        Ok(())
+    }
+}
+
+impl fmt::Display for Statements {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&join(&self.0, " "))
+    }
+}
+
+fn join<T: fmt::Display>(values: &[T], separator: &str) -> String {
+    values
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(separator)
+}
 
 #[derive(Debug, Clone, Builder)]
 #[builder(derive(Debug), pattern = "mutable", build_fn(skip))]
@@ -19,117 +464,169 @@ pub(crate) struct Rule {
     pub in_interface: String,
     #[builder(setter(into))]
     pub out_interface: String,
+    #[builder(setter(custom), field(public))]
+    pub source_address: Expression,
+    #[builder(setter(custom), field(public))]
+    pub destination_address: Expression,
+    #[builder(setter(custom))]
+    pub source_address_v6: Expression,
+    #[builder(setter(custom), field(public))]
+    pub destination_address_v6: Expression,
     #[builder(setter(into))]
-    pub source_address: String,
+    pub nfproto: String,
     #[builder(setter(into))]
-    pub destination_address: String,
-    #[builder(setter(into))]
-    pub source_address_v6: String,
+    pub protocol: String,
     #[builder(setter(into))]
-    pub destination_address_v6: String,
+    pub source_port: Expression,
     #[builder(setter(into))]
-    pub protocol: String,
+    pub destination_port: Expression,
     #[builder(setter(into))]
-    pub source_port: String,
+    pub icmp_type: Expression,
     #[builder(setter(into))]
-    pub destination_port: String,
+    pub ct_state: Expression,
     #[builder(setter(into))]
     pub matches: String,
     #[builder(setter(into))]
-    pub comment: String,
-    #[builder(setter(into))]
     pub verdict: RuleVerdict,
     #[builder(setter(into))]
-    pub dnat: String,
+    pub dnat: Nat,
 }
 
 impl RuleBuilder {
-    pub(crate) fn build(&self) -> Result<String> {
-        let mut args: Vec<String> = Vec::new();
+    /// Match the IPv4 source address, an address, a prefix or a named set (`@name`).
+    pub(crate) fn source_address(&mut self, address: &str) -> &mut Self {
+        self.source_address = Some(Expression::address(address));
+        self
+    }
+
+    /// Match the IPv4 destination address, an address, a prefix or a named set (`@name`).
+    pub(crate) fn destination_address(&mut self, address: &str) -> &mut Self {
+        self.destination_address = Some(Expression::address(address));
+        self
+    }
+
+    /// Match the IPv6 source address, an address, a prefix or a named set (`@name`).
+    pub(crate) fn source_address_v6(&mut self, address: &str) -> &mut Self {
+        self.source_address_v6 = Some(Expression::address(address));
+        self
+    }
+
+    /// Match the IPv6 destination address, an address, a prefix or a named set (`@name`).
+    pub(crate) fn destination_address_v6(&mut self, address: &str) -> &mut Self {
+        self.destination_address_v6 = Some(Expression::address(address));
+        self
+    }
+
+    pub(crate) fn build(&self) -> Result<Statements> {
+        let mut statements = Statements::default();
+
+        // Handle layer 3 protocol matches
+        if let Some(nfproto) = &self.nfproto {
+            statements.push(Statement::matches(
+                Expression::meta("nfproto"),
+                nfproto.as_str(),
+            ));
+        }
 
         // Handle protocol matches
         if self.source_port.is_some() || self.destination_port.is_some() {
-            args.push(self.protocol.clone().unwrap_or_else(|| "tcp".to_owned()));
+            let protocol = self.protocol.as_deref().unwrap_or("tcp");
+            let protocols = protocol.split('+').collect::<Vec<_>>();
+            let header = if protocols.len() > 1 {
+                // Ports of multiple protocols are matched through the transport header, which is
+                // valid for all of them.
+                statements.push(Statement::matches(
+                    Expression::meta("l4proto"),
+                    Expression::set(protocols),
+                ));
+                "th"
+            } else {
+                protocol
+            };
             if let Some(source_port) = &self.source_port {
-                args.push("sport".to_owned());
-                args.push(source_port.to_owned());
+                statements.push(Statement::matches(
+                    Expression::payload(header, "sport"),
+                    source_port.clone(),
+                ));
             }
             if let Some(destination_port) = &self.destination_port {
-                args.push("dport".to_owned());
-                args.push(destination_port.to_owned());
+                statements.push(Statement::matches(
+                    Expression::payload(header, "dport"),
+                    destination_port.clone(),
+                ));
             }
+        } else if let Some(icmp_type) = &self.icmp_type {
+            statements.push(Statement::matches(
+                Expression::payload(self.protocol.as_deref().unwrap_or("icmp"), "type"),
+                icmp_type.clone(),
+            ));
+        } else if let Some(protocol) = &self.protocol {
+            statements.push(Statement::matches(
+                Expression::meta("l4proto"),
+                Expression::set_or_value(protocol.split('+')),
+            ));
         }
 
-        // Handle `ip` matches
-        if let Some(source_address) = &self.source_address {
-            args.push("ip".to_owned());
-            args.push("saddr".to_owned());
-            args.push(source_address.to_owned());
-        }
-        if let Some(destination_address) = &self.destination_address {
-            args.push("ip".to_owned());
-            args.push("daddr".to_owned());
-            args.push(destination_address.to_owned());
+        // Handle `ip` and `ip6` matches
+        for (protocol, field, address) in [
+            ("ip", "saddr", &self.source_address),
+            ("ip", "daddr", &self.destination_address),
+            ("ip6", "saddr", &self.source_address_v6),
+            ("ip6", "daddr", &self.destination_address_v6),
+        ] {
+            if let Some(address) = address {
+                statements.push(Statement::matches(
+                    Expression::payload(protocol, field),
+                    address.clone(),
+                ));
+            }
         }
 
-        // Handle `ip6` matches
-        if let Some(source_address) = &self.source_address_v6 {
-            args.push("ip6".to_owned());
-            args.push("saddr".to_owned());
-            args.push(source_address.to_owned());
+        // Handle interface-matches
+        if let Some(in_interface) = &self.in_interface {
+            statements.push(Statement::matches(
+                Expression::meta("iifname"),
+                in_interface.as_str(),
+            ));
         }
-        if let Some(destination_address) = &self.destination_address_v6 {
-            args.push("ip6".to_owned());
-            args.push("daddr".to_owned());
-            args.push(destination_address.to_owned());
+        if let Some(out_interface) = &self.out_interface {
+            statements.push(Statement::matches(
+                Expression::meta("oifname"),
+                out_interface.as_str(),
+            ));
         }
 
-        // Handle interface-matches
-        if self.in_interface.is_some() || self.out_interface.is_some() {
-            args.push("meta".to_owned());
-            if let Some(in_interface) = &self.in_interface {
-                args.push("iifname".to_owned());
-                args.push(in_interface.to_owned());
-            }
-            if let Some(out_interface) = &self.out_interface {
-                args.push("oifname".to_owned());
-                args.push(out_interface.to_owned());
-            }
+        // Handle connection tracking matches
+        if let Some(ct_state) = &self.ct_state {
+            statements.push(Statement::ct_state(ct_state.clone()));
         }
 
         // Bail if none of the above was initialized
-        if args.is_empty() {
+        if statements.is_empty() {
             bail!("one of `{source,destination}_{port,address{,_v6}}`, `{in,out}_interface` must be initialized");
         }
 
         // Unconditionally set mark
-        args.push("meta".to_owned());
-        args.push("mark".to_owned());
-        args.push("set".to_owned());
-        args.push(DFW_MARK.to_owned());
+        statements.push(Statement::mark());
 
         if let Some(matches) = &self.matches {
-            args.push(matches.to_owned());
+            statements.push(Statement::Matches(matches.to_owned()));
         }
 
-        if let Some(verdict) = &self.verdict {
-            args.push(verdict.to_string());
+        if let Some(verdict) = self.verdict {
+            statements.push(Statement::verdict(verdict));
         } else if let Some(dnat) = &self.dnat {
-            args.push("dnat".to_owned());
-            args.push(dnat.to_owned());
-        }
-
-        if let Some(comment) = &self.comment {
-            args.push(format!(r#"comment "{}""#, comment));
+            statements.push(Statement::Object(StatementObject::Dnat(dnat.clone())));
         }
 
-        Ok(args.join(" "))
+        Ok(statements)
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use serde_json::json;
 
     #[test]
     fn builder_should_fail() {
@@ -140,7 +637,168 @@ mod test {
     #[test]
     fn builder_should_succeed() {
         let mut rule = RuleBuilder::default();
-        rule.source_port("1");
+        rule.source_port(1u16);
         assert!(rule.build().is_ok());
     }
+
+    #[test]
+    fn builder_multiple_protocols() {
+        let mut rule = RuleBuilder::default();
+        rule.protocol("tcp+udp").destination_port(53u16);
+        assert_eq!(
+            rule.build().unwrap().to_string(),
+            "meta l4proto { tcp, udp } th dport 53 meta mark set 0xdf"
+        );
+    }
+
+    #[test]
+    fn builder_typed_matches() {
+        let mut rule = RuleBuilder::default();
+        rule.protocol("icmp")
+            .icmp_type("echo-request")
+            .ct_state(Expression::set(["new", "established"]))
+            .verdict(RuleVerdict::Accept);
+        assert_eq!(
+            rule.build().unwrap().to_string(),
+            "icmp type echo-request ct state { new, established } meta mark set 0xdf accept"
+        );
+
+        let mut rule = RuleBuilder::default();
+        rule.protocol("udp").in_interface("eth0");
+        assert_eq!(
+            rule.build().unwrap().to_string(),
+            "meta l4proto udp meta iifname eth0 meta mark set 0xdf"
+        );
+    }
+
+    #[test]
+    fn serialize_statements() {
+        let mut rule = RuleBuilder::default();
+        rule.in_interface("eni")
+            .protocol("tcp")
+            .destination_port(Expression::range(8000u16, 8001u16))
+            .source_address("10.0.0.0/8")
+            .ct_state("new")
+            .dnat(Nat {
+                addr: Some("172.20.0.2".into()),
+                port: Some(Expression::map(
+                    Expression::payload("tcp", "dport"),
+                    Expression::set([
+                        Expression::pair(8000u16, 80u16),
+                        Expression::pair(8001u16, 81u16),
+                    ]),
+                )),
+                family: None,
+            });
+        let statements = rule.build().unwrap();
+        assert_eq!(
+            "tcp dport 8000-8001 ip saddr 10.0.0.0/8 meta iifname eni ct state new \
+             meta mark set 0xdf dnat 172.20.0.2 : tcp dport map { 8000 : 80, 8001 : 81 }",
+            statements.to_string(),
+        );
+        assert_eq!(
+            json!([
+                {"match": {"op": "==", "left": {"payload": {"protocol": "tcp", "field": "dport"}}, "right": {"range": [8000, 8001]}}},
+                {"match": {"op": "==", "left": {"payload": {"protocol": "ip", "field": "saddr"}}, "right": {"prefix": {"addr": "10.0.0.0", "len": 8}}}},
+                {"match": {"op": "==", "left": {"meta": {"key": "iifname"}}, "right": "eni"}},
+                {"match": {"op": "in", "left": {"ct": {"key": "state"}}, "right": "new"}},
+                {"mangle": {"key": {"meta": {"key": "mark"}}, "value": 223}},
+                {"dnat": {"addr": "172.20.0.2", "port": {"map": {"key": {"payload": {"protocol": "tcp", "field": "dport"}}, "data": {"set": [[8000, 80], [8001, 81]]}}}}},
+            ]),
+            serde_json::to_value(&statements).unwrap(),
+        );
+        assert_eq!(
+            statements,
+            serde_json::from_value(serde_json::to_value(&statements).unwrap()).unwrap(),
+        );
+    }
+
+    #[test]
+    fn display_marked_and_nat() {
+        let statements = Statements(vec![
+            Statement::marked(),
+            Statement::verdict(RuleVerdict::Accept),
+        ]);
+        assert_eq!("meta mark and 0xdf == 0xdf accept", statements.to_string());
+
+        let nat = |addr: Expression, port: Option<Expression>| {
+            Statement::Object(StatementObject::Dnat(Nat {
+                addr: Some(addr),
+                port,
+                family: None,
+            }))
+            .to_string()
+        };
+        assert_eq!(
+            "dnat 172.20.0.2:443",
+            nat("172.20.0.2".into(), Some(443u16.into()))
+        );
+        assert_eq!(
+            "dnat [fd00::2]:443",
+            nat("fd00::2".into(), Some(443u16.into()))
+        );
+        assert_eq!(
+            "dnat tcp dport map @web : 80",
+            nat(
+                Expression::map(Expression::payload("tcp", "dport"), "@web".into()),
+                Some(80u16.into())
+            )
+        );
+    }
+
+    #[test]
+    fn parse_unknown_statements() {
+        let statements: Statements = serde_json::from_value(json!([
+            {"match": {"op": "in", "left": {"ct": {"key": "state"}}, "right": ["established", "related"]}},
+            {"match": {"op": "==", "left": {"ct": {"key": "state", "dir": "original"}}, "right": "new"}},
+            {"match": {"op": "<", "left": {"meta": {"key": "length"}}, "right": 64}},
+            {"counter": {"packets": 0, "bytes": 0}},
+            {"accept": null},
+        ]))
+        .unwrap();
+        assert_eq!(
+            Statement::ct_state(Expression::List(vec![
+                "established".into(),
+                "related".into()
+            ])),
+            statements.0[0]
+        );
+        assert!(matches!(
+            statements.0[1],
+            Statement::Object(StatementObject::Match {
+                left: Expression::Other(_),
+                ..
+            })
+        ));
+        assert!(matches!(statements.0[2], Statement::Other(_)));
+        assert!(matches!(statements.0[3], Statement::Other(_)));
+        assert_eq!(Statement::verdict(RuleVerdict::Accept), statements.0[4]);
+    }
+
+    #[test]
+    fn matches_have_to_be_compiled() {
+        let mut rule = RuleBuilder::default();
+        rule.in_interface("eni").matches("tcp flags syn");
+        let mut statements = rule.build().unwrap();
+        assert_eq!(
+            vec!["tcp flags syn"],
+            statements.matches().collect::<Vec<_>>()
+        );
+        assert!(serde_json::to_value(&statements).is_err());
+
+        let compiled = Statements(vec![Statement::Other(json!({"match": {}}))]);
+        assert!(statements
+            .replace_matches(Family::Ip, &HashMap::new())
+            .is_err());
+        statements
+            .replace_matches(
+                Family::Inet,
+                &[((Family::Inet, "tcp flags syn".to_owned()), compiled.clone())]
+                    .into_iter()
+                    .collect(),
+            )
+            .unwrap();
+        assert_eq!(0, statements.matches().count());
+        assert_eq!(Some(&compiled.0[0]), statements.0.last());
+    }
 }
@@ -27,11 +27,13 @@
 //! ```
 
 use crate::de::*;
+use schemars::JsonSchema;
 use serde::Deserialize;
 
 /// The defaults/configuration for the nftables backend.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq, Default)]
 #[serde(deny_unknown_fields)]
+#[schemars(rename = "NftablesDefaults")]
 pub struct Defaults {
     /// Specify the names of custom nft-tables that should be partially managed.
     ///
@@ -63,6 +65,7 @@ pub struct Defaults {
     /// # "#).unwrap();
     /// ```
     #[serde(default, deserialize_with = "option_struct_or_seq_struct")]
+    #[schemars(schema_with = "crate::schema::struct_or_seq_struct::<Table>")]
     pub custom_tables: Option<Vec<Table>>,
 
     /// The optional initialization section.
@@ -81,13 +84,39 @@ pub struct Defaults {
     /// # "#).unwrap();
     /// ```
     pub initialization: Option<Initialization>,
+
+    /// Match the addresses of the containers selected by rules through named sets.
+    ///
+    /// # Explanation
+    ///
+    /// By default the addresses of the containers a rule selects are part of the generated rules,
+    /// every container resulting in a rule of its own. If address sets are enabled, the rules
+    /// match against a named set per network and container or selector instead (e.g.
+    /// `ip saddr @dfw_web_5a0f13c2_v4`), and ports exposed to containers are translated through a
+    /// named map from the host port to the container address.
+    ///
+    /// Rules are then generated even if none of the selected containers are running, starting or
+    /// stopping containers only changes the elements of the sets and maps.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use dfw::nftables::types::*;
+    /// # use toml;
+    /// # toml::from_str::<Defaults>(r#"
+    /// address_sets = true
+    /// # "#).unwrap();
+    /// ```
+    #[serde(default)]
+    pub address_sets: bool,
 }
 
 /// Reference to an nftables table, specifically to the input- and forward-chains within it.
 ///
 /// This is used by DFW when managing other tables is required.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash, Default)]
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq, Hash, Default)]
 #[serde(deny_unknown_fields)]
+#[schemars(rename = "NftablesTable")]
 pub struct Table {
     /// Name of the custom table.
     pub name: String,
@@ -97,8 +126,9 @@ pub struct Table {
 }
 
 /// The initialization section allows you to execute any commands against nftables.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq, Default)]
 #[serde(deny_unknown_fields)]
+#[schemars(rename = "NftablesInitialization")]
 pub struct Initialization {
     /// Initialization rules for nftables
     ///
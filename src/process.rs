@@ -9,16 +9,24 @@
 
 //! This module holds the types related to configuration processing and rule creation.
 
-use crate::{errors::*, types::*, util::FutureExt, FirewallBackend};
-use bollard::{
-    container::ListContainersOptions,
-    models::{ContainerSummary, Network, NetworkContainer},
-    Docker,
+use crate::{
+    diff::RulesetDiff,
+    errors::*,
+    labels::{compose_isolation_rules, LabelRules, DEFAULT_LABEL_PREFIX},
+    runtime::ContainerRuntime,
+    types::*,
+    FirewallBackend, Ruleset,
 };
-use failure::{bail, format_err};
+use bollard::models::{ContainerSummary, Network, NetworkContainer};
+use failure::{bail, format_err, ResultExt};
 use maplit::hashmap;
-use slog::{debug, o, trace, Logger};
-use std::collections::HashMap as Map;
+use slog::{debug, o, trace, warn, Logger};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap as Map},
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+};
 
 /// This trait allows a type to define its own processing rules. It is expected to return a list
 /// of rules that can be applied with nft.
@@ -108,14 +116,17 @@ where
     B: FirewallBackend,
     DFW<B>: Process<B>,
 {
-    pub(crate) docker: &'a Docker,
+    pub(crate) container_runtime: &'a dyn ContainerRuntime,
     pub(crate) dfw: &'a DFW<B>,
     pub(crate) container_map: Map<String, ContainerSummary>,
-    pub(crate) network_map: Map<String, Network>,
+    pub(crate) network_map: BTreeMap<String, Network>,
+    pub(crate) label_rules: LabelRules,
     pub(crate) external_network_interfaces: Option<Vec<String>>,
     pub(crate) primary_external_network_interface: Option<String>,
+    pub(crate) runtime: Runtime,
     pub(crate) logger: Logger,
     pub(crate) dry_run: bool,
+    pub(crate) dry_run_output: RulesetOutput,
 }
 
 impl<'a, B> ProcessContext<'a, B>
@@ -124,8 +135,13 @@ where
     DFW<B>: Process<B>,
 {
     /// Create a new instance of `ProcessDFW` for rule processing.
+    ///
+    /// The containers and networks are retrieved from the given container runtime, e.g. a
+    /// [`bollard::Docker`] client, see [`ContainerRuntime`](../runtime/trait.ContainerRuntime.html).
+    ///
+    /// [`bollard::Docker`]: https://docs.rs/bollard/*/bollard/struct.Docker.html
     pub fn new(
-        docker: &'a Docker,
+        container_runtime: &'a dyn ContainerRuntime,
         dfw: &'a DFW<B>,
         processing_options: &'a ProcessingOptions,
         logger: &'a Logger,
@@ -133,14 +149,7 @@ where
     ) -> Result<ProcessContext<'a, B>> {
         let logger = logger.new(o!());
 
-        let list_containers_options = match processing_options.container_filter {
-            ContainerFilter::All => None,
-            ContainerFilter::Running => Some(ListContainersOptions {
-                filters: hashmap! { "status" => vec!["running"]},
-                ..Default::default()
-            }),
-        };
-        let containers = docker.list_containers(list_containers_options).sync()?;
+        let containers = container_runtime.list_containers(&processing_options.container_filter)?;
         debug!(logger, "Got list of containers";
                o!("containers" => format!("{:#?}", containers)));
 
@@ -148,7 +157,7 @@ where
         trace!(logger, "Got map of containers";
                o!("container_map" => format!("{:#?}", container_map)));
 
-        let networks = docker.list_networks::<String>(None).sync()?;
+        let networks = container_runtime.list_networks()?;
         debug!(logger, "Got list of networks";
                o!("networks" => format!("{:#?}", networks)));
 
@@ -157,6 +166,49 @@ where
         trace!(logger, "Got map of networks";
                o!("container_map" => format!("{:#?}", container_map)));
 
+        let label_prefix = dfw
+            .global_defaults
+            .container_label_prefix
+            .as_deref()
+            .unwrap_or(DEFAULT_LABEL_PREFIX);
+        let mut label_rules = if dfw.global_defaults.container_label_rules {
+            LabelRules::from_containers(&containers, &container_map, label_prefix, &logger)
+        } else {
+            LabelRules::default()
+        };
+        if !label_rules.container_to_container.is_empty() && dfw.container_to_container.is_none() {
+            warn!(logger, "Ignoring container-to-container rules from container labels, \
+                           the section is not configured";
+                  o!("label_prefix" => label_prefix));
+        }
+        if dfw
+            .compose
+            .as_ref()
+            .map(|compose| compose.isolate_projects)
+            .unwrap_or(false)
+        {
+            let mut isolation_rules = compose_isolation_rules(&containers);
+            debug!(logger, "Got rules isolating compose projects";
+                   o!("isolation_rules" => format!("{:?}", isolation_rules)));
+            if !isolation_rules.is_empty() && dfw.container_to_container.is_none() {
+                warn!(
+                    logger,
+                    "Ignoring rules isolating Compose projects, \
+                               the container-to-container section is not configured"
+                );
+            }
+            label_rules
+                .container_to_container
+                .append(&mut isolation_rules);
+        }
+        if !label_rules.container_to_wider_world.is_empty()
+            && dfw.container_to_wider_world.is_none()
+        {
+            warn!(logger, "Ignoring container-to-wider-world rules from container labels, \
+                           the section is not configured";
+                  o!("label_prefix" => label_prefix));
+        }
+
         let external_network_interfaces = dfw
             .global_defaults
             .external_network_interfaces
@@ -168,17 +220,82 @@ where
             .map(|s| s.to_owned());
 
         Ok(ProcessContext {
-            docker,
+            container_runtime,
             dfw,
             container_map,
             network_map,
+            label_rules,
             external_network_interfaces,
             primary_external_network_interface,
+            runtime: processing_options.runtime,
             logger,
             dry_run,
+            dry_run_output: processing_options.dry_run_output.clone(),
         })
     }
 
+    /// Resolve the network carrying the traffic between the host and the containers of a network.
+    ///
+    /// Containers on overlay networks reach the host through the `docker_gwbridge` network, which
+    /// is why rules for them are generated for this network instead.
+    pub(crate) fn get_host_network<'n>(&'n self, network: &'n Network) -> Result<&'n Network> {
+        if !is_overlay_network(network) {
+            return Ok(network);
+        }
+        let network_name = network.name.as_deref().unwrap_or_default();
+        if is_ingress_network(network) {
+            bail!(
+                "the ingress network `{}` can only be used by wider-world-to-container rules",
+                network_name
+            );
+        }
+        self.network_map
+            .get(DOCKER_GWBRIDGE_NETWORK)
+            .ok_or_else(|| {
+                format_err!(
+                    "overlay network `{}` requires the `{}` network to exist",
+                    network_name,
+                    DOCKER_GWBRIDGE_NETWORK
+                )
+            })
+    }
+
+    /// Resolve the host interface of a network, taking the runtime of the context into account.
+    pub(crate) fn get_network_interface(&self, network: &Network) -> Result<NetworkInterface> {
+        get_network_interface(network, self.runtime)
+    }
+
+    /// Resolve the IPv4 addresses of the containers a source selector matches on a network.
+    ///
+    /// Containers without an IPv4 address on the network are skipped. Returns `None` if none of
+    /// the matching containers are attached to the network with an IPv4 address, in which case no
+    /// rule must be generated.
+    pub(crate) fn get_selector_source_addresses(
+        &self,
+        network: &Network,
+        selector: &ContainerSelector,
+    ) -> Result<Option<Vec<String>>> {
+        let network_id = network.id.as_ref().expect("Docker network ID missing");
+        let src_networks = get_network_containers_for_selector(
+            self.container_runtime,
+            &self.container_map,
+            selector,
+            network_id,
+        )?;
+        trace!(self.logger, "Got source networks for selector";
+               o!("network_name" => &network.name,
+                  "src_selector" => format!("{:?}", selector),
+                  "src_networks" => format!("{:?}", src_networks)));
+        let source_addresses = src_networks
+            .iter()
+            .filter_map(get_container_ipv4_address)
+            .collect::<Vec<_>>();
+        if source_addresses.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(source_addresses))
+    }
+
     /// Start the processing using the configuration given at creation.
     pub fn process(&mut self) -> Result<()> {
         let rules = Process::<B>::process(self.dfw, self)?;
@@ -188,6 +305,105 @@ where
 
         Ok(())
     }
+
+    /// Start the processing using the configuration given at creation, only applying the changes
+    /// to the rules applied by the previous run.
+    ///
+    /// All rules are applied if no rules were applied previously, or if the rules currently applied
+    /// differ from the rules applied previously, e.g. because they were changed manually. The
+    /// applied rules are recorded in `applied` for the next run.
+    pub fn process_incremental(&mut self, applied: &mut AppliedRules) -> Result<()> {
+        let rules = Process::<B>::process(self.dfw, self)?;
+        if let Some(rules) = rules {
+            if self.dry_run {
+                B::apply(rules, self)?;
+            } else if let Err(e) = B::apply_incremental(rules, applied, self) {
+                // The state of the rules is unknown, the next run has to apply all rules.
+                applied.clear();
+                return Err(e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Process the configuration given at creation and render the resulting rules, without
+    /// applying them.
+    pub fn render(&self) -> Result<Vec<Ruleset>> {
+        B::render(Process::<B>::process(self.dfw, self)?.unwrap_or_default())
+    }
+
+    /// Process the configuration given at creation and compare the resulting rules with the rules
+    /// currently applied, without applying them.
+    pub fn diff(&self) -> Result<Vec<RulesetDiff>> {
+        B::diff(
+            Process::<B>::process(self.dfw, self)?.unwrap_or_default(),
+            self,
+        )
+    }
+
+    /// Save the rules currently applied before applying the processed rules, see
+    /// [`FirewallBackend::snapshot`](../trait.FirewallBackend.html#method.snapshot).
+    pub(crate) fn snapshot(&self) -> Result<Vec<Ruleset>> {
+        debug!(self.logger, "Saving rules currently applied");
+        Ok(B::snapshot(&self.logger).context("failed to save the rules currently applied")?)
+    }
+
+    /// Restore the snapshot after applying the processed rules failed at the given step, returning
+    /// the error describing the failure and the outcome of the rollback.
+    pub(crate) fn rollback(
+        &self,
+        step: &str,
+        error: failure::Error,
+        snapshot: &[Ruleset],
+    ) -> failure::Error {
+        warn!(self.logger, "Applying the rules failed, restoring the rules applied before";
+              o!("step" => step,
+                 "error" => error.to_string()));
+        let rollback = match B::restore_snapshot(snapshot, &self.logger) {
+            Ok(()) => Rollback::Succeeded,
+            Err(rollback_error) => Rollback::Failed(rollback_error.to_string()),
+        };
+
+        DFWError::ApplyError {
+            step: step.to_owned(),
+            message: error.to_string(),
+            rollback,
+        }
+        .into()
+    }
+}
+
+/// Rules applied by the previous processing run, see
+/// [`ProcessContext::process_incremental`](struct.ProcessContext.html#method.process_incremental).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AppliedRules {
+    rulesets: Option<Vec<Ruleset>>,
+}
+
+impl AppliedRules {
+    /// Create an instance without any applied rules, resulting in all rules being applied by the
+    /// next run.
+    pub fn new() -> AppliedRules {
+        AppliedRules::default()
+    }
+
+    /// Rulesets applied by the previous run, as recorded by the firewall-backend. The nftables
+    /// backend records the libnftables JSON document it applied, the iptables backend the rulesets
+    /// returned by [`FirewallBackend::render`](../trait.FirewallBackend.html#method.render).
+    pub fn rulesets(&self) -> Option<&[Ruleset]> {
+        self.rulesets.as_deref()
+    }
+
+    /// Record the rulesets applied by a run.
+    pub fn set(&mut self, rulesets: Vec<Ruleset>) {
+        self.rulesets = Some(rulesets);
+    }
+
+    /// Forget the applied rules, resulting in all rules being applied by the next run.
+    pub fn clear(&mut self) {
+        self.rulesets = None;
+    }
 }
 
 /// Option to filter the containers to be processed
@@ -199,39 +415,336 @@ pub enum ContainerFilter {
     Running,
 }
 
+/// Container runtime providing the Docker API DFW talks to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Runtime {
+    /// Docker.
+    #[default]
+    Docker,
+    /// Podman, through its Docker-compatible API.
+    ///
+    /// Podman lists its default network as `bridge` (its actual name being `podman`), the bridges
+    /// of its networks are named `podman<n>` (netavark) or `cni-podman<n>` (CNI).
+    Podman,
+}
+
+impl Runtime {
+    /// Socket the API of the runtime is served on by default.
+    pub fn default_socket(&self) -> &'static str {
+        match self {
+            Runtime::Docker => "/var/run/docker.sock",
+            Runtime::Podman => "/run/podman/podman.sock",
+        }
+    }
+
+    /// Container events the runtime emits when the containers, and thus the rules, change.
+    pub fn container_events(&self) -> &'static [&'static str] {
+        match self {
+            Runtime::Docker => &["create", "destroy", "start", "restart", "die", "stop"],
+            // Depending on its version, Podman either forwards its own event names or translates
+            // some of them to the ones Docker uses.
+            Runtime::Podman => &[
+                "create", "remove", "destroy", "start", "restart", "died", "die", "stop",
+            ],
+        }
+    }
+
+    /// Filters to apply when subscribing to the events of the runtime.
+    pub fn event_filters(&self) -> Map<&'static str, Vec<&'static str>> {
+        match self {
+            Runtime::Docker => hashmap! { "type" => vec!["container"] },
+            // Podman emits a lot of container events irrelevant to DFW (e.g. `init`, `cleanup`,
+            // `exec_died`, `health_status`), which are filtered out on the server-side.
+            Runtime::Podman => hashmap! {
+                "type" => vec!["container"],
+                "event" => self.container_events().to_vec(),
+            },
+        }
+    }
+
+    /// Bridge of the default network of the runtime.
+    fn default_bridge_name(&self) -> &'static str {
+        match self {
+            Runtime::Docker => "docker0",
+            Runtime::Podman => "podman0",
+        }
+    }
+
+    /// Check if the network is the default network of the runtime.
+    fn is_default_network(&self, network: &Network) -> bool {
+        match self {
+            Runtime::Docker => network.name.as_deref() == Some("bridge"),
+            Runtime::Podman => matches!(network.name.as_deref(), Some("bridge" | "podman")),
+        }
+    }
+}
+
+/// Format rendered rulesets are written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// The rulesets as they are passed to the commands applying them, each preceded by a comment
+    /// naming the command.
+    #[default]
+    Text,
+    /// A JSON array of the rulesets, see [`Ruleset`](../struct.Ruleset.html).
+    Json,
+}
+
+/// Destination and format rendered rulesets are written to.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct RulesetOutput {
+    /// File to write the rulesets to, replacing its contents. The rulesets are written to stdout
+    /// if no file is given.
+    pub file: Option<PathBuf>,
+    /// Format to write the rulesets in, see [`OutputFormat`](enum.OutputFormat.html).
+    pub format: OutputFormat,
+}
+
+impl RulesetOutput {
+    /// Write the rulesets to the destination.
+    pub fn write(&self, rulesets: &[Ruleset]) -> Result<()> {
+        match self.file {
+            Some(ref file) => {
+                let mut writer = BufWriter::new(File::create(file).with_context(|_| {
+                    format!("failed to create output file {}", file.display())
+                })?);
+                write_rulesets(rulesets, self.format, &mut writer)?;
+                writer.flush()?;
+            }
+            None => write_rulesets(rulesets, self.format, io::stdout().lock())?,
+        }
+
+        Ok(())
+    }
+}
+
+/// Write the rulesets in the given format.
+pub fn write_rulesets<W: Write>(
+    rulesets: &[Ruleset],
+    format: OutputFormat,
+    mut writer: W,
+) -> Result<()> {
+    match format {
+        OutputFormat::Text => {
+            for (index, ruleset) in rulesets.iter().enumerate() {
+                if index > 0 {
+                    writeln!(writer)?;
+                }
+                writeln!(writer, "# {}", ruleset.command)?;
+                for line in &ruleset.lines {
+                    writeln!(writer, "{}", line)?;
+                }
+            }
+        }
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, rulesets)?;
+            writeln!(writer)?;
+        }
+    }
+
+    Ok(())
+}
+
 /// Options to configure the processing procedure.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ProcessingOptions {
     /// Option to filter the containers to be processed, see
     /// [`ContainerFilter`](enum.ContainerFilter.html).
     pub container_filter: ContainerFilter,
+    /// Runtime providing the containers and networks, see [`Runtime`](enum.Runtime.html).
+    pub runtime: Runtime,
+    /// Destination the rules are written to instead of being applied when performing a dry-run,
+    /// see [`RulesetOutput`](struct.RulesetOutput.html).
+    pub dry_run_output: RulesetOutput,
 }
 
 impl Default for ProcessingOptions {
     fn default() -> Self {
         ProcessingOptions {
             container_filter: ContainerFilter::All,
+            runtime: Runtime::Docker,
+            dry_run_output: RulesetOutput::default(),
         }
     }
 }
 
-pub(crate) fn get_bridge_name(network_id: &str) -> Result<String> {
+/// Docker network option holding the name of the bridge of a network.
+const BRIDGE_NAME_OPTION: &str = "com.docker.network.bridge.name";
+
+/// Resolve the name of the bridge interface of a network.
+///
+/// This is the bridge name configured through the `com.docker.network.bridge.name` option (e.g.
+/// `docker0` for the default `bridge` network), falling back to the bridge of the default network
+/// of the runtime and to the name Docker generates from the network ID.
+///
+/// Podman doesn't derive the bridge name from the network ID, its networks thus have to carry
+/// the option unless they are the default network.
+pub(crate) fn get_bridge_name(network: &Network, runtime: Runtime) -> Result<String> {
+    if let Some(bridge_name) = network
+        .options
+        .as_ref()
+        .and_then(|options| options.get(BRIDGE_NAME_OPTION))
+        .filter(|bridge_name| !bridge_name.is_empty())
+    {
+        return Ok(bridge_name.to_owned());
+    }
+    if runtime.is_default_network(network) {
+        return Ok(runtime.default_bridge_name().to_owned());
+    }
+    if runtime == Runtime::Podman {
+        bail!(
+            "the bridge of Podman network `{}` is unknown, the network has no `{}` option",
+            network.name.as_deref().unwrap_or_default(),
+            BRIDGE_NAME_OPTION
+        );
+    }
+
+    let network_id = network.id.as_deref().unwrap_or_default();
     if network_id.len() < 12 {
         bail!("network has to be longer than 12 characters");
     }
     Ok(format!("br-{}", &network_id[..12]))
 }
 
+/// Docker network option holding the parent interface of a macvlan or ipvlan network.
+const PARENT_OPTION: &str = "parent";
+
+/// Host interface the traffic of the containers of a network passes through.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum NetworkInterface {
+    /// Bridge of a `bridge` network, carrying only the traffic of the network.
+    Bridge(String),
+    /// Parent interface of a `macvlan` or `ipvlan` network.
+    ///
+    /// The interface is shared with other traffic of the host, which is why rules have to match on
+    /// the addresses of the containers in addition to the interface.
+    Parent(String),
+}
+
+impl NetworkInterface {
+    /// Name of the interface.
+    pub(crate) fn name(&self) -> &str {
+        match self {
+            NetworkInterface::Bridge(name) | NetworkInterface::Parent(name) => name,
+        }
+    }
+
+    /// Check if the interface is shared with traffic not belonging to the network.
+    pub(crate) fn is_shared(&self) -> bool {
+        matches!(self, NetworkInterface::Parent(_))
+    }
+}
+
+/// Name of the bridge network Docker Swarm attaches the containers of overlay networks to,
+/// carrying their traffic from and to the host.
+pub const DOCKER_GWBRIDGE_NETWORK: &str = "docker_gwbridge";
+
+/// Check if a network is an overlay network.
+///
+/// The traffic between the containers of an overlay network is encapsulated and never passes
+/// through an interface of the host.
+pub(crate) fn is_overlay_network(network: &Network) -> bool {
+    network.driver.as_deref() == Some("overlay")
+}
+
+/// Check if the traffic of the containers of a network passes through an interface of the host.
+///
+/// This isn't the case for overlay networks and the `host` and `none` networks.
+pub(crate) fn has_host_interface(network: &Network) -> bool {
+    !matches!(network.driver.as_deref(), Some("overlay" | "host" | "null"))
+}
+
+/// Check if a network is the ingress network of the Docker Swarm routing mesh.
+pub(crate) fn is_ingress_network(network: &Network) -> bool {
+    is_overlay_network(network) && network.ingress == Some(true)
+}
+
+/// Resolve the host interface of a network based on its driver.
+///
+/// Networks using the `macvlan` or `ipvlan` driver have to have a parent interface and at least
+/// one subnet, since rules for them are restricted to the addresses of the network. Overlay
+/// networks, as well as the `host` and `none` networks, have no host interface.
+pub(crate) fn get_network_interface(
+    network: &Network,
+    runtime: Runtime,
+) -> Result<NetworkInterface> {
+    match network.driver.as_deref() {
+        Some(driver @ ("overlay" | "host" | "null")) => bail!(
+            "{} network `{}` has no host interface, traffic between its containers can't be \
+             filtered",
+            driver,
+            network.name.as_deref().unwrap_or_default()
+        ),
+        Some(driver @ ("macvlan" | "ipvlan")) => {
+            let network_name = network.name.as_deref().unwrap_or_default();
+            let parent = network
+                .options
+                .as_ref()
+                .and_then(|options| options.get(PARENT_OPTION))
+                .filter(|parent| !parent.is_empty())
+                .ok_or_else(|| {
+                    format_err!(
+                        "{} network `{}` has no parent interface, rules for it can't be generated",
+                        driver,
+                        network_name
+                    )
+                })?;
+            if get_network_subnets(network, IpVersion::Both).is_empty() {
+                bail!(
+                    "{} network `{}` has no subnet, rules for it can't be restricted to its \
+                     containers",
+                    driver,
+                    network_name
+                );
+            }
+            Ok(NetworkInterface::Parent(parent.to_owned()))
+        }
+        _ => get_bridge_name(network, runtime).map(NetworkInterface::Bridge),
+    }
+}
+
+/// Ensure that traffic towards the containers of a network passes through the host.
+///
+/// Containers on `macvlan` and `ipvlan` networks are reachable directly through the parent
+/// interface, rules forwarding traffic to them would never match.
+pub(crate) fn ensure_routed_network(network: &Network, interface: &NetworkInterface) -> Result<()> {
+    if interface.is_shared() {
+        bail!(
+            "traffic to containers on {} network `{}` doesn't pass through the host and can't be \
+             forwarded",
+            network.driver.as_deref().unwrap_or_default(),
+            network.name.as_deref().unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
+/// Retrieve the subnets of a network, restricted to the given IP version.
+pub(crate) fn get_network_subnets(network: &Network, ip_version: IpVersion) -> Vec<String> {
+    network
+        .ipam
+        .as_ref()
+        .and_then(|ipam| ipam.config.as_ref())
+        .into_iter()
+        .flatten()
+        .filter_map(|config| config.subnet.clone())
+        .filter(|subnet| match ip_version {
+            IpVersion::V4 => !subnet.contains(':'),
+            IpVersion::V6 => subnet.contains(':'),
+            IpVersion::Both => true,
+        })
+        .collect()
+}
+
 pub(crate) fn get_network_for_container(
-    docker: &Docker,
+    container_runtime: &dyn ContainerRuntime,
     container_map: &Map<String, ContainerSummary>,
     container_name: &str,
     network_id: &str,
 ) -> Result<Option<NetworkContainer>> {
     if let Some(container) = container_map.get(container_name) {
-        Ok(docker
-            .inspect_network::<String>(network_id, None)
-            .sync()?
+        Ok(container_runtime
+            .inspect_network(network_id)?
             .containers
             .and_then(|containers| {
                 container
@@ -244,6 +757,375 @@ pub(crate) fn get_network_for_container(
     }
 }
 
+pub(crate) fn get_network_containers_for_selector(
+    container_runtime: &dyn ContainerRuntime,
+    container_map: &Map<String, ContainerSummary>,
+    selector: &ContainerSelector,
+    network_id: &str,
+) -> Result<Vec<NetworkContainer>> {
+    // The container map holds an entry for every name of a container, we thus deduplicate the
+    // matching containers by their IDs.
+    let container_ids = container_map
+        .values()
+        .filter(|container| selector.matches(container.labels.as_ref()))
+        .filter_map(|container| container.id.as_ref())
+        .collect::<BTreeSet<_>>();
+    if container_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let network_containers = container_runtime
+        .inspect_network(network_id)?
+        .containers
+        .unwrap_or_default();
+    let mut matching_network_containers = container_ids
+        .into_iter()
+        .filter_map(|container_id| network_containers.get(container_id).cloned())
+        .collect::<Vec<_>>();
+    // Sort the containers to ensure that the generated rules are stable.
+    matching_network_containers.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(matching_network_containers)
+}
+
+/// Resolve the single container a rule targets, either by name or by selector, on a network.
+///
+/// Returns `None` if the container is not attached to the network, and fails if the selector
+/// matches more than one container.
+pub(crate) fn get_network_for_single_container(
+    container_runtime: &dyn ContainerRuntime,
+    container_map: &Map<String, ContainerSummary>,
+    container_name: Option<&str>,
+    selector: Option<&ContainerSelector>,
+    network_id: &str,
+) -> Result<Option<NetworkContainer>> {
+    match (container_name, selector) {
+        (Some(container_name), None) => {
+            get_network_for_container(container_runtime, container_map, container_name, network_id)
+        }
+        (None, Some(selector)) => {
+            let mut network_containers = get_network_containers_for_selector(
+                container_runtime,
+                container_map,
+                selector,
+                network_id,
+            )?;
+            if network_containers.len() > 1 {
+                bail!(
+                    "selector {:?} matches {} containers, but traffic can only be forwarded to a \
+                     single container",
+                    selector.0,
+                    network_containers.len()
+                );
+            }
+            Ok(network_containers.pop())
+        }
+        (Some(_), Some(_)) => bail!("a container can't be specified both by name and by selector"),
+        (None, None) => bail!("either a container name or a selector has to be specified"),
+    }
+}
+
+/// Retrieve the IPv6 address (without the prefix length) of a container on a network.
+///
+/// Returns `None` if IPv6 isn't enabled for the network or the container has no IPv6 address on
+/// it.
+pub(crate) fn get_container_ipv6_address(
+    network: &Network,
+    network_container: &NetworkContainer,
+) -> Option<String> {
+    if network.enable_ipv6 != Some(true) {
+        return None;
+    }
+    network_container
+        .ipv6_address
+        .as_deref()
+        .and_then(|ipv6_address| ipv6_address.split('/').next())
+        .filter(|ipv6_address| !ipv6_address.is_empty())
+        .map(ToOwned::to_owned)
+}
+
+/// Resolve the containers on a network a rule is restricted to, either by name or by selector.
+///
+/// Returns `None` if the rule isn't restricted to specific containers, and an empty list if none
+/// of the containers are attached to the network.
+pub(crate) fn get_network_containers(
+    container_runtime: &dyn ContainerRuntime,
+    container_map: &Map<String, ContainerSummary>,
+    container_name: Option<&str>,
+    selector: Option<&ContainerSelector>,
+    network_id: &str,
+) -> Result<Option<Vec<NetworkContainer>>> {
+    if let Some(container_name) = container_name {
+        Ok(Some(
+            get_network_for_container(
+                container_runtime,
+                container_map,
+                container_name,
+                network_id,
+            )?
+            .into_iter()
+            .collect(),
+        ))
+    } else if let Some(selector) = selector {
+        get_network_containers_for_selector(container_runtime, container_map, selector, network_id)
+            .map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
+/// Split an IP version into the single IP versions rules have to be generated for.
+pub(crate) fn single_ip_versions(ip_version: IpVersion) -> &'static [IpVersion] {
+    match ip_version {
+        IpVersion::V4 => &[IpVersion::V4],
+        IpVersion::V6 => &[IpVersion::V6],
+        IpVersion::Both => &[IpVersion::V4, IpVersion::V6],
+    }
+}
+
+/// Retrieve the addresses of a single IP version of containers on a network.
+///
+/// Containers without an address of the IP version are skipped.
+pub(crate) fn get_addresses(
+    network: &Network,
+    network_containers: &[NetworkContainer],
+    ip_version: IpVersion,
+) -> Vec<String> {
+    network_containers
+        .iter()
+        .filter_map(|network_container| match ip_version {
+            IpVersion::V4 => get_container_ipv4_address(network_container),
+            IpVersion::V6 => get_container_ipv6_address(network, network_container),
+            IpVersion::Both => unreachable!("addresses are retrieved for a single IP version"),
+        })
+        .collect()
+}
+
+/// Retrieve the addresses of a single IP version a rule on a network is restricted to.
+///
+/// These are the addresses of the containers the rule targets. Rules not targeting specific
+/// containers are restricted to the subnets of the network if its interface is shared, and aren't
+/// restricted at all otherwise.
+pub(crate) fn get_rule_addresses(
+    network: &Network,
+    interface: &NetworkInterface,
+    network_containers: Option<&[NetworkContainer]>,
+    ip_version: IpVersion,
+) -> Option<Vec<String>> {
+    match network_containers {
+        Some(network_containers) => Some(get_addresses(network, network_containers, ip_version)),
+        None if interface.is_shared() => Some(get_network_subnets(network, ip_version)),
+        None => None,
+    }
+}
+
+/// Ensure that a rule doesn't reference a container both by name and by selector.
+pub(crate) fn ensure_container_or_selector(
+    container_name: Option<&String>,
+    selector: Option<&ContainerSelector>,
+) -> Result<()> {
+    if let (Some(container_name), Some(selector)) = (container_name, selector) {
+        bail!(
+            "container `{}` can't be specified together with selector {:?}",
+            container_name,
+            selector.0
+        );
+    }
+    Ok(())
+}
+
+/// Backend-neutral matches of a rule, to be lowered by the firewall-backends.
+pub(crate) struct TypedMatches<'a> {
+    pub(crate) protocol: Option<Protocol>,
+    pub(crate) src_port: Option<PortRange>,
+    pub(crate) dst_port: Option<PortRange>,
+    pub(crate) dst_cidr_v4: Option<&'a [String]>,
+    pub(crate) dst_cidr_v6: Option<&'a [String]>,
+    pub(crate) icmp_type: Option<&'a str>,
+    pub(crate) ct_state: Option<&'a [ConnectionState]>,
+    /// IP version explicitly specified for the rule.
+    pub(crate) ip_version: Option<IpVersion>,
+    /// Whether the rule has a match-string, which can't be checked for the IP version it is
+    /// written for.
+    pub(crate) has_matches: bool,
+}
+
+impl TypedMatches<'_> {
+    /// Ensure that the matches are consistent, independent of the firewall-backend.
+    pub(crate) fn validate(&self) -> Result<()> {
+        if let Some(icmp_type) = self.icmp_type {
+            if !matches!(self.protocol, Some(Protocol::Icmp) | Some(Protocol::Icmpv6)) {
+                bail!(
+                    "ICMP type `{}` requires the protocol to be `icmp` or `icmpv6`",
+                    icmp_type
+                );
+            }
+        }
+        if self.src_port.is_some() || self.dst_port.is_some() {
+            if let Some(protocol @ (Protocol::Icmp | Protocol::Icmpv6)) = self.protocol {
+                bail!("ports can't be matched for protocol `{}`", protocol);
+            }
+        }
+        if let (Some(protocol), Some(ip_version)) = (self.protocol, self.ip_version) {
+            if protocol
+                .ip_version()
+                .is_some_and(|protocol_ip_version| protocol_ip_version != ip_version)
+            {
+                bail!(
+                    "protocol `{}` can't be matched for IP version `{}`",
+                    protocol,
+                    ip_version
+                );
+            }
+        }
+        for port_range in self.src_port.iter().chain(self.dst_port.iter()) {
+            if port_range.end < port_range.start {
+                bail!(
+                    "port range {}-{} is invalid, the end has to be greater than the start",
+                    port_range.start,
+                    port_range.end
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Determine the IP versions to generate the rule for.
+    ///
+    /// Unless specified explicitly, rules matching ICMP or ICMPv6 are generated for the IP version
+    /// of the protocol, and rules with a match-string only for IPv4.
+    pub(crate) fn ip_version(&self) -> IpVersion {
+        match (
+            self.ip_version,
+            self.protocol.and_then(Protocol::ip_version),
+        ) {
+            (Some(ip_version), _) | (None, Some(ip_version)) => ip_version,
+            (None, None) if self.has_matches => IpVersion::V4,
+            (None, None) => IpVersion::Both,
+        }
+    }
+
+    /// Restrict the IP version to the one of the protocol, since ICMP only exists for IPv4 and
+    /// ICMPv6 only for IPv6.
+    ///
+    /// Returns `None` if the protocol doesn't exist for the IP version.
+    pub(crate) fn restrict_ip_version(&self, ip_version: IpVersion) -> Option<IpVersion> {
+        match (self.protocol.and_then(Protocol::ip_version), ip_version) {
+            (Some(IpVersion::V4), IpVersion::V6) | (Some(IpVersion::V6), IpVersion::V4) => None,
+            (Some(protocol_ip_version), _) => Some(protocol_ip_version),
+            (None, ip_version) => Some(ip_version),
+        }
+    }
+}
+
+macro_rules! impl_typed_matches {
+    ($($rule:ty $(=> $ip_version:ident)?),*) => {
+        $(
+            impl $rule {
+                pub(crate) fn typed_matches(&self) -> TypedMatches<'_> {
+                    TypedMatches {
+                        protocol: self.protocol,
+                        src_port: self.src_port,
+                        dst_port: self.dst_port,
+                        dst_cidr_v4: self.dst_cidr_v4.as_deref(),
+                        dst_cidr_v6: self.dst_cidr_v6.as_deref(),
+                        icmp_type: self.icmp_type.as_deref(),
+                        ct_state: self.ct_state.as_deref(),
+                        ip_version: None$(.or(self.$ip_version))?,
+                        has_matches: self.matches.is_some(),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_typed_matches!(
+    ContainerToContainerRule => ip_version,
+    ContainerToWiderWorldRule,
+    ContainerToHostRule => ip_version
+);
+
+/// Resolve the selector a rule uses, either given directly or through a Compose or Swarm service.
+pub(crate) fn resolve_selector(
+    selector: Option<&ContainerSelector>,
+    service: Option<&ComposeService>,
+    swarm_service: Option<&SwarmService>,
+) -> Result<Option<ContainerSelector>> {
+    match (selector, service, swarm_service) {
+        (Some(selector), None, None) => Ok(Some(selector.clone())),
+        (None, Some(service), None) => Ok(Some(service.selector())),
+        (None, None, Some(swarm_service)) => Ok(Some(swarm_service.selector())),
+        (None, None, None) => Ok(None),
+        (Some(selector), Some(service), _) => bail!(
+            "compose service `{}/{}` can't be specified together with selector {:?}",
+            service.project,
+            service.service,
+            selector.0
+        ),
+        (_, _, Some(swarm_service)) => bail!(
+            "swarm service `{}` can't be specified together with a selector or compose service",
+            swarm_service.0
+        ),
+    }
+}
+
+/// Retrieve the IPv4 address (without the prefix length) of a container on a network.
+///
+/// Returns `None` if the container has no IPv4 address on the network, e.g. because the network
+/// is IPv6-only.
+pub(crate) fn get_container_ipv4_address(network_container: &NetworkContainer) -> Option<String> {
+    network_container
+        .ipv4_address
+        .as_deref()
+        .and_then(|ipv4_address| ipv4_address.split('/').next())
+        .filter(|ipv4_address| !ipv4_address.is_empty())
+        .map(ToOwned::to_owned)
+}
+
+/// Retrieve the IPv4 address of a container on a network, ensuring it has any address at all.
+///
+/// Returns `None` if the container only has an IPv6 address on the network, in which case no IPv4
+/// rules are generated for it, and fails if it has neither an IPv4 nor an IPv6 address.
+pub(crate) fn get_container_ipv4_address_checked(
+    network: &Network,
+    network_container: &NetworkContainer,
+) -> Result<Option<String>> {
+    let ipv4_address = get_container_ipv4_address(network_container);
+    if ipv4_address.is_none() && get_container_ipv6_address(network, network_container).is_none() {
+        bail!(
+            "container `{}` has neither an IPv4 nor an IPv6 address on network `{}`",
+            network_container.name.as_deref().unwrap_or_default(),
+            network.name.as_deref().unwrap_or_default()
+        );
+    }
+    Ok(ipv4_address)
+}
+
+/// Create a copy of every rule for every value, applying the value to the copy.
+///
+/// If no values are given, the rules are returned unchanged.
+pub(crate) fn expand_rules<R, F>(rules: Vec<R>, values: &[String], apply: F) -> Vec<R>
+where
+    R: Clone,
+    F: Fn(&mut R, &str),
+{
+    if values.is_empty() {
+        return rules;
+    }
+
+    let mut expanded_rules = Vec::with_capacity(rules.len() * values.len());
+    for rule in rules {
+        for value in values {
+            let mut expanded_rule = rule.clone();
+            apply(&mut expanded_rule, value);
+            expanded_rules.push(expanded_rule);
+        }
+    }
+    expanded_rules
+}
+
 pub(crate) fn get_container_map(containers: &[ContainerSummary]) -> Map<String, ContainerSummary> {
     let mut container_map: Map<String, ContainerSummary> = Map::new();
     for container in containers {
@@ -260,8 +1142,8 @@ pub(crate) fn get_container_map(containers: &[ContainerSummary]) -> Map<String,
     container_map
 }
 
-pub(crate) fn get_network_map(networks: &[Network]) -> Option<Map<String, Network>> {
-    let mut network_map: Map<String, Network> = Map::new();
+pub(crate) fn get_network_map(networks: &[Network]) -> Option<BTreeMap<String, Network>> {
+    let mut network_map: BTreeMap<String, Network> = BTreeMap::new();
     for network in networks {
         if let Some(name) = &network.name {
             network_map.insert(name.clone(), network.clone());
@@ -278,3 +1160,262 @@ pub(crate) fn get_network_map(networks: &[Network]) -> Option<Map<String, Networ
 pub(crate) fn generate_marker(components: &[&str]) -> String {
     format!("DFW-MARKER:{}", components.join(";"))
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bollard::models::{Ipam, IpamConfig};
+
+    fn bridge_network(name: &str, id: &str, bridge_name: Option<&str>) -> Network {
+        Network {
+            name: Some(name.to_owned()),
+            id: Some(id.to_owned()),
+            options: bridge_name.map(|bridge_name| {
+                hashmap! { BRIDGE_NAME_OPTION.to_owned() => bridge_name.to_owned() }
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn parent_network(driver: &str, parent: Option<&str>, subnets: &[&str]) -> Network {
+        Network {
+            name: Some("lan".to_owned()),
+            id: Some("0123456789abcdef".to_owned()),
+            driver: Some(driver.to_owned()),
+            options: parent
+                .map(|parent| hashmap! { PARENT_OPTION.to_owned() => parent.to_owned() }),
+            ipam: Some(Ipam {
+                config: Some(
+                    subnets
+                        .iter()
+                        .map(|subnet| IpamConfig {
+                            subnet: Some((*subnet).to_owned()),
+                            ..Default::default()
+                        })
+                        .collect(),
+                ),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn get_bridge_name_from_options() {
+        let network = bridge_network("custom", "0123456789abcdef", Some("dfw-custom"));
+        assert_eq!(
+            "dfw-custom",
+            get_bridge_name(&network, Runtime::Docker).unwrap()
+        );
+    }
+
+    #[test]
+    fn get_bridge_name_default_bridge() {
+        let network = bridge_network("bridge", "0123456789abcdef", Some("docker0"));
+        assert_eq!(
+            "docker0",
+            get_bridge_name(&network, Runtime::Docker).unwrap()
+        );
+
+        let network = bridge_network("bridge", "0123456789abcdef", None);
+        assert_eq!(
+            "docker0",
+            get_bridge_name(&network, Runtime::Docker).unwrap()
+        );
+    }
+
+    #[test]
+    fn get_bridge_name_from_id() {
+        let network = bridge_network("custom", "0123456789abcdef", None);
+        assert_eq!(
+            "br-0123456789ab",
+            get_bridge_name(&network, Runtime::Docker).unwrap()
+        );
+
+        let network = bridge_network("custom", "0123", None);
+        assert!(get_bridge_name(&network, Runtime::Docker).is_err());
+    }
+
+    #[test]
+    fn get_bridge_name_podman() {
+        let network = bridge_network("podman1", "0123456789abcdef", Some("podman1"));
+        assert_eq!(
+            "podman1",
+            get_bridge_name(&network, Runtime::Podman).unwrap()
+        );
+
+        // Podman lists its default network as `bridge`.
+        for name in &["bridge", "podman"] {
+            let network = bridge_network(name, "0123456789abcdef", None);
+            assert_eq!(
+                "podman0",
+                get_bridge_name(&network, Runtime::Podman).unwrap()
+            );
+        }
+
+        let network = bridge_network("custom", "0123456789abcdef", None);
+        assert!(get_bridge_name(&network, Runtime::Podman).is_err());
+    }
+
+    #[test]
+    fn runtime_event_filters() {
+        assert_eq!(
+            hashmap! { "type" => vec!["container"] },
+            Runtime::Docker.event_filters()
+        );
+
+        let filters = Runtime::Podman.event_filters();
+        assert_eq!(Some(&vec!["container"]), filters.get("type"));
+        for event in &["create", "remove", "start", "died", "stop"] {
+            assert!(filters["event"].contains(event));
+        }
+        assert!(!filters["event"].contains(&"cleanup"));
+    }
+
+    #[test]
+    fn get_network_interface_bridge() {
+        let network = bridge_network("custom", "0123456789abcdef", None);
+        assert_eq!(
+            NetworkInterface::Bridge("br-0123456789ab".to_owned()),
+            get_network_interface(&network, Runtime::Docker).unwrap()
+        );
+    }
+
+    #[test]
+    fn get_network_interface_parent() {
+        for driver in &["macvlan", "ipvlan"] {
+            let network = parent_network(driver, Some("eth0.10"), &["192.0.2.0/24"]);
+            let interface = get_network_interface(&network, Runtime::Docker).unwrap();
+            assert_eq!(NetworkInterface::Parent("eth0.10".to_owned()), interface);
+            assert!(interface.is_shared());
+            assert!(ensure_routed_network(&network, &interface).is_err());
+        }
+    }
+
+    #[test]
+    fn get_network_interface_parent_missing() {
+        let network = parent_network("macvlan", None, &["192.0.2.0/24"]);
+        assert!(get_network_interface(&network, Runtime::Docker).is_err());
+
+        let network = parent_network("macvlan", Some("eth0"), &[]);
+        assert!(get_network_interface(&network, Runtime::Docker).is_err());
+    }
+
+    #[test]
+    fn get_network_subnets_by_ip_version() {
+        let network = parent_network("macvlan", Some("eth0"), &["192.0.2.0/24", "2001:db8::/64"]);
+        assert_eq!(
+            vec!["192.0.2.0/24".to_owned()],
+            get_network_subnets(&network, IpVersion::V4)
+        );
+        assert_eq!(
+            vec!["2001:db8::/64".to_owned()],
+            get_network_subnets(&network, IpVersion::V6)
+        );
+        assert_eq!(2, get_network_subnets(&network, IpVersion::Both).len());
+    }
+
+    #[test]
+    fn get_rule_addresses_shared_interface() {
+        let network = parent_network("macvlan", Some("eth0"), &["192.0.2.0/24"]);
+        let interface = get_network_interface(&network, Runtime::Docker).unwrap();
+        assert_eq!(
+            Some(vec!["192.0.2.0/24".to_owned()]),
+            get_rule_addresses(&network, &interface, None, IpVersion::V4)
+        );
+
+        let network_container = NetworkContainer {
+            ipv4_address: Some("192.0.2.10/24".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(
+            Some(vec!["192.0.2.10".to_owned()]),
+            get_rule_addresses(
+                &network,
+                &interface,
+                Some(&[network_container]),
+                IpVersion::V4
+            )
+        );
+
+        let network = bridge_network("custom", "0123456789abcdef", None);
+        let interface = get_network_interface(&network, Runtime::Docker).unwrap();
+        assert_eq!(
+            None,
+            get_rule_addresses(&network, &interface, None, IpVersion::V4)
+        );
+    }
+
+    fn rulesets() -> Vec<Ruleset> {
+        vec![
+            Ruleset {
+                command: "iptables-restore".to_owned(),
+                lines: vec!["*filter".to_owned(), "COMMIT".to_owned()],
+            },
+            Ruleset {
+                command: "ip6tables-restore".to_owned(),
+                lines: vec!["*filter".to_owned(), "COMMIT".to_owned()],
+            },
+        ]
+    }
+
+    #[test]
+    fn write_rulesets_text() {
+        let mut output = Vec::new();
+        write_rulesets(&rulesets(), OutputFormat::Text, &mut output).unwrap();
+        assert_eq!(
+            "# iptables-restore\n*filter\nCOMMIT\n\n# ip6tables-restore\n*filter\nCOMMIT\n",
+            String::from_utf8(output).unwrap()
+        );
+    }
+
+    #[test]
+    fn write_rulesets_json() {
+        let mut output = Vec::new();
+        write_rulesets(&rulesets(), OutputFormat::Json, &mut output).unwrap();
+        let output: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(
+            serde_json::json!([
+                { "command": "iptables-restore", "lines": ["*filter", "COMMIT"] },
+                { "command": "ip6tables-restore", "lines": ["*filter", "COMMIT"] },
+            ]),
+            output
+        );
+    }
+
+    #[test]
+    fn restrict_ip_version_to_protocol() {
+        let matches = |protocol| TypedMatches {
+            protocol,
+            src_port: None,
+            dst_port: None,
+            dst_cidr_v4: None,
+            dst_cidr_v6: None,
+            icmp_type: None,
+            ct_state: None,
+            ip_version: None,
+            has_matches: false,
+        };
+
+        assert_eq!(
+            Some(IpVersion::Both),
+            matches(Some(Protocol::Tcp)).restrict_ip_version(IpVersion::Both)
+        );
+        assert_eq!(
+            Some(IpVersion::V4),
+            matches(Some(Protocol::Icmp)).restrict_ip_version(IpVersion::Both)
+        );
+        assert_eq!(
+            None,
+            matches(Some(Protocol::Icmp)).restrict_ip_version(IpVersion::V6)
+        );
+        assert_eq!(
+            Some(IpVersion::V6),
+            matches(Some(Protocol::Icmpv6)).restrict_ip_version(IpVersion::Both)
+        );
+        assert_eq!(
+            None,
+            matches(Some(Protocol::Icmpv6)).restrict_ip_version(IpVersion::V4)
+        );
+    }
+}
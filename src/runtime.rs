@@ -0,0 +1,323 @@
+// Copyright Pit Kleyersburg <pitkley@googlemail.com>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+//! This module abstracts the source of the containers and networks rules are generated for.
+//!
+//! [`ContainerRuntime`](trait.ContainerRuntime.html) is implemented for [`bollard::Docker`],
+//! talking to a Docker (or Docker-compatible) API, and by
+//! [`InMemoryRuntime`](struct.InMemoryRuntime.html), serving a fixed set of containers and
+//! networks, e.g. for tests.
+//!
+//! [`bollard::Docker`]: https://docs.rs/bollard/*/bollard/struct.Docker.html
+
+use crate::{errors::*, process::ContainerFilter, util::FutureExt};
+use bollard::{
+    container::ListContainersOptions,
+    models::{ContainerSummary, EventMessage, Network},
+    system::EventsOptions,
+    Docker,
+};
+use failure::format_err;
+use futures::stream::StreamExt;
+use maplit::hashmap;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap as Map,
+    io::{Read, Write},
+};
+
+/// Source of the containers and networks rules are generated for.
+pub trait ContainerRuntime {
+    /// List the containers matching the filter.
+    fn list_containers(&self, filter: &ContainerFilter) -> Result<Vec<ContainerSummary>>;
+
+    /// List all networks.
+    ///
+    /// The networks don't have to include the containers attached to them, these are retrieved
+    /// through [`inspect_network`](#tymethod.inspect_network).
+    fn list_networks(&self) -> Result<Vec<Network>>;
+
+    /// Inspect a network, including the containers attached to it.
+    fn inspect_network(&self, network_id: &str) -> Result<Network>;
+
+    /// Subscribe to the events of the runtime matching the filters, using the filters of the
+    /// Docker API (e.g. `type`).
+    ///
+    /// The returned iterator blocks until the next event is available.
+    fn events<'a>(
+        &'a self,
+        filters: Map<&'a str, Vec<&'a str>>,
+    ) -> Box<dyn Iterator<Item = Result<EventMessage>> + 'a>;
+}
+
+impl ContainerRuntime for Docker {
+    fn list_containers(&self, filter: &ContainerFilter) -> Result<Vec<ContainerSummary>> {
+        let list_containers_options = match filter {
+            ContainerFilter::All => None,
+            ContainerFilter::Running => Some(ListContainersOptions {
+                filters: hashmap! { "status" => vec!["running"]},
+                ..Default::default()
+            }),
+        };
+
+        Ok(Docker::list_containers(self, list_containers_options).sync()?)
+    }
+
+    fn list_networks(&self) -> Result<Vec<Network>> {
+        Ok(Docker::list_networks::<String>(self, None).sync()?)
+    }
+
+    fn inspect_network(&self, network_id: &str) -> Result<Network> {
+        Ok(Docker::inspect_network::<String>(self, network_id, None).sync()?)
+    }
+
+    fn events<'a>(
+        &'a self,
+        filters: Map<&'a str, Vec<&'a str>>,
+    ) -> Box<dyn Iterator<Item = Result<EventMessage>> + 'a> {
+        let mut stream = Box::pin(Docker::events(
+            self,
+            Some(EventsOptions {
+                filters,
+                ..Default::default()
+            }),
+        ));
+
+        Box::new(std::iter::from_fn(move || {
+            stream.next().sync().map(|event| event.map_err(Into::into))
+        }))
+    }
+}
+
+/// Container runtime serving a fixed set of containers, networks and events.
+///
+/// The networks have to include the containers attached to them, as they would be returned when
+/// inspecting them. The runtime can be deserialized from JSON, using the representation of the
+/// Docker API for the individual objects:
+///
+/// ```
+/// # use dfw::runtime::{ContainerRuntime, InMemoryRuntime};
+/// let runtime = InMemoryRuntime::from_json(
+///     r#"{
+///         "containers": [{ "Id": "7a52ef1a", "Names": ["/web"], "State": "running" }],
+///         "networks": [{
+///             "Name": "frontend",
+///             "Id": "ff2e4d8a",
+///             "Driver": "bridge",
+///             "Containers": {
+///                 "7a52ef1a": { "Name": "web", "IPv4Address": "172.18.0.2/16" }
+///             }
+///         }]
+///     }"#
+///     .as_bytes(),
+/// )
+/// .unwrap();
+/// assert_eq!(1, runtime.list_networks().unwrap().len());
+/// ```
+///
+/// A runtime serialized to JSON can be loaded again, which allows capturing the state of another
+/// runtime through [`capture`](#method.capture).
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct InMemoryRuntime {
+    #[serde(default)]
+    containers: Vec<ContainerSummary>,
+    #[serde(default)]
+    networks: Vec<Network>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    events: Vec<EventMessage>,
+}
+
+impl InMemoryRuntime {
+    /// Create a runtime serving the given containers and networks.
+    pub fn new(containers: Vec<ContainerSummary>, networks: Vec<Network>) -> InMemoryRuntime {
+        InMemoryRuntime {
+            containers,
+            networks,
+            events: Vec::new(),
+        }
+    }
+
+    /// Replay the given events when subscribing to the events of the runtime.
+    pub fn with_events(mut self, events: Vec<EventMessage>) -> InMemoryRuntime {
+        self.events = events;
+        self
+    }
+
+    /// Capture the containers matching the filter and all networks of another runtime.
+    ///
+    /// The networks are inspected, i.e. they include the containers attached to them.
+    pub fn capture(
+        runtime: &dyn ContainerRuntime,
+        filter: &ContainerFilter,
+    ) -> Result<InMemoryRuntime> {
+        let containers = runtime.list_containers(filter)?;
+        let networks = runtime
+            .list_networks()?
+            .into_iter()
+            .map(|network| match network.id {
+                Some(ref network_id) => runtime.inspect_network(network_id),
+                None => Ok(network),
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(InMemoryRuntime::new(containers, networks))
+    }
+
+    /// Deserialize a runtime from JSON, see [`InMemoryRuntime`](struct.InMemoryRuntime.html)
+    /// for the format.
+    pub fn from_json<R: Read>(reader: R) -> Result<InMemoryRuntime> {
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+    /// Serialize the runtime to JSON, in the format read by [`from_json`](#method.from_json).
+    pub fn to_json<W: Write>(&self, writer: W) -> Result<()> {
+        Ok(serde_json::to_writer_pretty(writer, self)?)
+    }
+}
+
+impl ContainerRuntime for InMemoryRuntime {
+    fn list_containers(&self, filter: &ContainerFilter) -> Result<Vec<ContainerSummary>> {
+        Ok(self
+            .containers
+            .iter()
+            .filter(|container| match filter {
+                ContainerFilter::All => true,
+                ContainerFilter::Running => container.state.as_deref() == Some("running"),
+            })
+            .cloned()
+            .collect())
+    }
+
+    fn list_networks(&self) -> Result<Vec<Network>> {
+        Ok(self.networks.clone())
+    }
+
+    fn inspect_network(&self, network_id: &str) -> Result<Network> {
+        // Like the Docker API, networks can be inspected by their ID or by their name.
+        self.networks
+            .iter()
+            .find(|network| {
+                network.id.as_deref() == Some(network_id)
+                    || network.name.as_deref() == Some(network_id)
+            })
+            .cloned()
+            .ok_or_else(|| format_err!("network {} not found", network_id))
+    }
+
+    fn events<'a>(
+        &'a self,
+        filters: Map<&'a str, Vec<&'a str>>,
+    ) -> Box<dyn Iterator<Item = Result<EventMessage>> + 'a> {
+        Box::new(
+            self.events
+                .iter()
+                .filter(move |event| {
+                    let matches = |key: &str, value: Option<String>| {
+                        filters.get(key).is_none_or(|values| {
+                            value.is_some_and(|value| values.contains(&&*value))
+                        })
+                    };
+                    matches("type", event.typ.map(|typ| typ.to_string()))
+                        && matches("event", event.action.clone())
+                })
+                .cloned()
+                .map(Ok),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bollard::models::{EventMessageTypeEnum, NetworkContainer};
+
+    fn runtime() -> InMemoryRuntime {
+        InMemoryRuntime::new(
+            vec![
+                ContainerSummary {
+                    id: Some("web".to_owned()),
+                    state: Some("running".to_owned()),
+                    ..Default::default()
+                },
+                ContainerSummary {
+                    id: Some("db".to_owned()),
+                    state: Some("exited".to_owned()),
+                    ..Default::default()
+                },
+            ],
+            vec![Network {
+                name: Some("frontend".to_owned()),
+                id: Some("0123456789abcdef".to_owned()),
+                containers: Some(hashmap! {
+                    "web".to_owned() => NetworkContainer {
+                        name: Some("web".to_owned()),
+                        ..Default::default()
+                    },
+                }),
+                ..Default::default()
+            }],
+        )
+    }
+
+    #[test]
+    fn list_containers_filter() {
+        let runtime = runtime();
+        assert_eq!(
+            2,
+            runtime
+                .list_containers(&ContainerFilter::All)
+                .unwrap()
+                .len()
+        );
+
+        let running = runtime.list_containers(&ContainerFilter::Running).unwrap();
+        assert_eq!(1, running.len());
+        assert_eq!(Some("web"), running[0].id.as_deref());
+    }
+
+    #[test]
+    fn inspect_network_by_id_or_name() {
+        let runtime = runtime();
+        for network_id in &["0123456789abcdef", "frontend"] {
+            let network = runtime.inspect_network(network_id).unwrap();
+            assert!(network.containers.unwrap().contains_key("web"));
+        }
+        assert!(runtime.inspect_network("backend").is_err());
+    }
+
+    #[test]
+    fn events_filter() {
+        let event = |typ, action: &str| EventMessage {
+            typ: Some(typ),
+            action: Some(action.to_owned()),
+            ..Default::default()
+        };
+        let runtime = runtime().with_events(vec![
+            event(EventMessageTypeEnum::CONTAINER, "start"),
+            event(EventMessageTypeEnum::NETWORK, "connect"),
+            event(EventMessageTypeEnum::CONTAINER, "exec_die"),
+        ]);
+
+        let actions = |filters| {
+            runtime
+                .events(filters)
+                .map(|event| event.unwrap().action.unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(3, actions(Map::new()).len());
+        assert_eq!(
+            vec!["start", "exec_die"],
+            actions(hashmap! { "type" => vec!["container"] })
+        );
+        assert_eq!(
+            vec!["start"],
+            actions(hashmap! { "type" => vec!["container"], "event" => vec!["start", "stop"] })
+        );
+    }
+}
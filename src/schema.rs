@@ -0,0 +1,72 @@
+// Copyright Pit Kleyersburg <pitkley@googlemail.com>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+//! JSON Schemas for the values accepted by the custom deserializers in [`de`](../de/index.html).
+
+use schemars::{
+    gen::SchemaGenerator,
+    schema::{ArrayValidation, InstanceType, Schema, SchemaObject, SubschemaValidation},
+    JsonSchema,
+};
+
+fn any_of(schemas: Vec<Schema>) -> Schema {
+    SchemaObject {
+        subschemas: Some(Box::new(SubschemaValidation {
+            any_of: Some(schemas),
+            ..Default::default()
+        })),
+        ..Default::default()
+    }
+    .into()
+}
+
+fn array_of(schema: Schema) -> Schema {
+    SchemaObject {
+        instance_type: Some(InstanceType::Array.into()),
+        array: Some(Box::new(ArrayValidation {
+            items: Some(schema.into()),
+            ..Default::default()
+        })),
+        ..Default::default()
+    }
+    .into()
+}
+
+/// Schema for types deserialized through `de::string_or_struct` that can also be given as a bare
+/// port, e.g. `80`.
+pub(crate) fn port_or_string_or_struct<T: JsonSchema + From<u16>>(
+    gen: &mut SchemaGenerator,
+) -> Schema {
+    any_of(vec![
+        gen.subschema_for::<u16>(),
+        gen.subschema_for::<String>(),
+        gen.subschema_for::<T>(),
+    ])
+}
+
+pub(crate) fn single_or_seq_port_or_string_or_struct<T: JsonSchema + From<u16>>(
+    gen: &mut SchemaGenerator,
+) -> Schema {
+    let single = port_or_string_or_struct::<T>(gen);
+    any_of(vec![single.clone(), array_of(single)])
+}
+
+pub(crate) fn string_or_seq_string(gen: &mut SchemaGenerator) -> Schema {
+    any_of(vec![
+        gen.subschema_for::<String>(),
+        gen.subschema_for::<Vec<String>>(),
+    ])
+}
+
+pub(crate) fn struct_or_seq_struct<T: JsonSchema>(gen: &mut SchemaGenerator) -> Schema {
+    any_of(vec![
+        gen.subschema_for::<T>(),
+        gen.subschema_for::<Vec<T>>(),
+    ])
+}
@@ -66,8 +66,12 @@
 
 use crate::{de::*, nftables, FirewallBackend, Process};
 use derive_builder::Builder;
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
 use serde::Deserialize;
-use std::str::FromStr;
+use std::{
+    collections::{BTreeMap, HashMap},
+    str::FromStr,
+};
 use strum::{Display, EnumString};
 
 const DEFAULT_PROTOCOL: &str = "tcp";
@@ -76,8 +80,9 @@ const DEFAULT_PROTOCOL: &str = "tcp";
 /// firewall rules.
 ///
 /// Every section is optional.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
 #[serde(deny_unknown_fields)]
+#[schemars(rename = "DFW", bound = "B::Defaults: JsonSchema")]
 pub struct DFW<B>
 where
     B: FirewallBackend,
@@ -116,10 +121,12 @@ where
     pub wider_world_to_container: Option<WiderWorldToContainer>,
     /// The `container_dnat` configuration section
     pub container_dnat: Option<ContainerDNAT>,
+    /// The `compose` configuration section
+    pub compose: Option<Compose>,
 }
 
 /// The default configuration section, used by DFW for rule processing.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash, Default)]
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq, Hash, Default)]
 #[serde(deny_unknown_fields)]
 pub struct GlobalDefaults {
     /// This defines the external network interfaces of the host to consider during building the
@@ -138,6 +145,7 @@ pub struct GlobalDefaults {
     /// # "#).unwrap();
     /// ```
     #[serde(default, deserialize_with = "option_string_or_seq_string")]
+    #[schemars(schema_with = "crate::schema::string_or_seq_string")]
     pub external_network_interfaces: Option<Vec<String>>,
 
     /// This defines whether the default Docker bridge (usually `docker0`) is allowed to access host
@@ -151,6 +159,57 @@ pub struct GlobalDefaults {
     #[serde(default)]
     pub default_docker_bridge_to_host_policy: ChainPolicy,
 
+    /// This defines whether DFW should additionally generate rules from the labels of the
+    /// containers. _(Default: false)_
+    ///
+    /// The following labels are supported (shown with the default prefix `dfw`):
+    ///
+    /// * `dfw.expose`: comma-separated list of ports to expose to the wider world, in the format
+    ///   accepted by [`ExposePort`](struct.ExposePort.html), e.g. `80/tcp,443:8443/tcp`.
+    ///   This generates a [wider-world-to-container rule].
+    /// * `dfw.egress`: verdict for traffic from the container to the wider world, e.g. `accept`.
+    ///   This generates a [container-to-wider-world rule].
+    /// * `dfw.allow-from`: comma-separated list of containers that are allowed to access the
+    ///   container, e.g. `backend`. This generates [container-to-container rules] for every
+    ///   network both containers are attached to.
+    /// * `dfw.network`: network the rules above should apply to. This is required for exposing a
+    ///   container that is attached to multiple networks.
+    ///
+    /// The rules generated from labels are appended to the rules of the configuration. Since the
+    /// container-to-container and container-to-wider-world sections define the default policies,
+    /// labels generating rules for these sections are only honored if the section is configured.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use dfw::types::*;
+    /// # use toml;
+    /// # toml::from_str::<GlobalDefaults>(r#"
+    /// container_label_rules = true
+    /// # "#).unwrap();
+    /// ```
+    ///
+    /// [wider-world-to-container rule]: struct.WiderWorldToContainerRule.html
+    /// [container-to-wider-world rule]: struct.ContainerToWiderWorldRule.html
+    /// [container-to-container rules]: struct.ContainerToContainerRule.html
+    #[serde(default)]
+    pub container_label_rules: bool,
+
+    /// The prefix of the container labels DFW generates rules from, see
+    /// [`container_label_rules`](#structfield.container_label_rules). _(Default: `dfw`)_
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use dfw::types::*;
+    /// # use toml;
+    /// # toml::from_str::<GlobalDefaults>(r#"
+    /// container_label_rules = true
+    /// container_label_prefix = "com.example.firewall"
+    /// # "#).unwrap();
+    /// ```
+    pub container_label_prefix: Option<String>,
+
     /// # This field is **DEPRECATED!**
     ///
     /// Provide the custom tables in the nftables backend-defaults section instead.
@@ -166,11 +225,46 @@ pub struct GlobalDefaults {
                 field will be removed with release 2.0.0."
     )]
     #[serde(default, deserialize_with = "option_struct_or_seq_struct")]
+    #[schemars(schema_with = "crate::schema::struct_or_seq_struct::<nftables::types::Table>")]
     pub custom_tables: Option<Vec<nftables::types::Table>>,
 }
 
+/// The compose section, defining how DFW handles containers managed by Docker Compose.
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(deny_unknown_fields)]
+pub struct Compose {
+    /// Isolate Compose projects from each other. _(Default: false)_
+    ///
+    /// If enabled, traffic between containers of different Compose projects that share a network
+    /// (e.g. an external network used by a reverse proxy) is dropped. The isolation is applied
+    /// after the container-to-container rules, which allows you to explicitly accept traffic
+    /// between specific services of different projects.
+    ///
+    /// This requires the container-to-container section to be configured.
+    ///
+    /// Note that a rule is generated for every pair of projects sharing a network, each of which
+    /// results in a rule for every pair of containers of the two projects. With many projects on a
+    /// shared network, consider enabling the
+    /// [`address_sets` option](../nftables/types/struct.Defaults.html#structfield.address_sets)
+    /// of the nftables backend, which matches the containers of a project through a set instead.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use dfw::nftables::Nftables;
+    /// # use dfw::types::*;
+    /// # use toml;
+    /// # toml::from_str::<DFW<Nftables>>(r#"
+    /// [compose]
+    /// isolate_projects = true
+    /// # "#).unwrap();
+    /// ```
+    #[serde(default)]
+    pub isolate_projects: bool,
+}
+
 /// The container-to-container section, defining how containers can communicate amongst each other.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq, Hash)]
 #[serde(deny_unknown_fields)]
 pub struct ContainerToContainer {
     /// The `default_policy` defines the default for when there is not a specific rule.
@@ -287,7 +381,7 @@ pub struct ContainerToContainer {
 }
 
 /// Definition for a rule to be used in the container-to-container section.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq, Hash)]
 #[serde(deny_unknown_fields)]
 pub struct ContainerToContainerRule {
     /// Common network between the source container and the destination container to apply the rule
@@ -295,9 +389,97 @@ pub struct ContainerToContainerRule {
     pub network: String,
     /// Source container to apply the rule to.
     pub src_container: Option<String>,
+    /// Labels identifying the source containers to apply the rule to, see
+    /// [`ContainerSelector`](struct.ContainerSelector.html).
+    ///
+    /// Cannot be combined with `src_container` or `src_service`.
+    pub src_selector: Option<ContainerSelector>,
+    /// Compose service to apply the rule to, in the format `<project>/<service>`, see
+    /// [`ComposeService`](struct.ComposeService.html).
+    ///
+    /// Cannot be combined with `src_container` or `src_selector`.
+    pub src_service: Option<ComposeService>,
+    /// Swarm service to apply the rule to, see [`SwarmService`](struct.SwarmService.html).
+    ///
+    /// Cannot be combined with `src_container`, `src_selector` or `src_service`.
+    pub src_swarm_service: Option<SwarmService>,
     /// Destination container to apply the rule to.
     pub dst_container: Option<String>,
-    /// Additional match-string, which will be added to the nftables command.
+    /// Labels identifying the destination containers to apply the rule to, see
+    /// [`ContainerSelector`](struct.ContainerSelector.html).
+    ///
+    /// Cannot be combined with `dst_container` or `dst_service`.
+    pub dst_selector: Option<ContainerSelector>,
+    /// Compose service to apply the rule to, in the format `<project>/<service>`, see
+    /// [`ComposeService`](struct.ComposeService.html).
+    ///
+    /// Cannot be combined with `dst_container` or `dst_selector`.
+    pub dst_service: Option<ComposeService>,
+    /// Swarm service to apply the rule to, see [`SwarmService`](struct.SwarmService.html).
+    ///
+    /// Cannot be combined with `dst_container`, `dst_selector` or `dst_service`.
+    pub dst_swarm_service: Option<SwarmService>,
+    /// Protocol to match, see [`Protocol`](enum.Protocol.html).
+    ///
+    /// This is required to match ports or ICMP types, `tcp` is used for ports if it is not
+    /// specified.
+    pub protocol: Option<Protocol>,
+    /// Source port to match, either a single port or a range of ports (e.g. `"8000-8010"`).
+    #[serde(default, deserialize_with = "option_string_or_struct")]
+    #[schemars(schema_with = "crate::schema::port_or_string_or_struct::<PortRange>")]
+    pub src_port: Option<PortRange>,
+    /// Destination port to match, either a single port or a range of ports (e.g. `"8000-8010"`).
+    #[serde(default, deserialize_with = "option_string_or_struct")]
+    #[schemars(schema_with = "crate::schema::port_or_string_or_struct::<PortRange>")]
+    pub dst_port: Option<PortRange>,
+    /// Destination CIDRs (IPv4) to match.
+    ///
+    /// This can be a single string or a list of strings.
+    #[serde(default, deserialize_with = "option_string_or_seq_string")]
+    #[schemars(schema_with = "crate::schema::string_or_seq_string")]
+    pub dst_cidr_v4: Option<Vec<String>>,
+    /// Destination CIDRs (IPv6) to match.
+    ///
+    /// This can be a single string or a list of strings. This cannot be combined with a
+    /// destination container, and only applies to the IPv6 addresses of source containers.
+    #[serde(default, deserialize_with = "option_string_or_seq_string")]
+    #[schemars(schema_with = "crate::schema::string_or_seq_string")]
+    pub dst_cidr_v6: Option<Vec<String>>,
+    /// ICMP type to match, e.g. `echo-request`. Requires `protocol` to be `icmp` or `icmpv6`.
+    pub icmp_type: Option<String>,
+    /// Connection tracking states to match, see [`ConnectionState`](enum.ConnectionState.html).
+    ///
+    /// This can be a single state or a list of states.
+    #[serde(default, deserialize_with = "option_single_or_seq_string_or_struct")]
+    #[schemars(schema_with = "crate::schema::struct_or_seq_struct::<ConnectionState>")]
+    pub ct_state: Option<Vec<ConnectionState>>,
+    /// IP versions to generate the rule for, see [`IpVersion`](enum.IpVersion.html).
+    ///
+    /// If not specified, this is derived from the rule: rules matching the `icmp` or `icmpv6`
+    /// protocol are only generated for IPv4 or IPv6 respectively, rules with a match-string
+    /// (`matches`) only for IPv4, since the match-string can't be checked for the IP version it
+    /// is written for. All other rules are generated for `both`. Specifying an IP version the
+    /// protocol doesn't exist for is an error.
+    ///
+    /// Rules matching container addresses are only generated for IPv6 if the containers have an
+    /// IPv6 address.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use dfw::types::*;
+    /// # use toml;
+    /// # toml::from_str::<ContainerToContainerRule>(r#"
+    /// # network = ""
+    /// # verdict = "accept"
+    /// ip_version = "v4"
+    /// # "#).unwrap();
+    /// ```
+    pub ip_version: Option<IpVersion>,
+    /// Additional match-string, which will be added to the firewall-backend command verbatim.
+    ///
+    /// Prefer the typed match fields above where possible, since the match-string is specific to
+    /// the firewall-backend used.
     pub matches: Option<String>,
     /// Verdict for rule (accept, drop or reject).
     #[serde(alias = "action")]
@@ -306,7 +488,7 @@ pub struct ContainerToContainerRule {
 
 /// The container-to-wider-world section, defining how containers can communicate with the wider
 /// world.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq, Hash)]
 #[serde(deny_unknown_fields)]
 pub struct ContainerToWiderWorld {
     /// The `default_policy` defines the default for when there is not a specific rule.
@@ -341,14 +523,65 @@ pub struct ContainerToWiderWorld {
 }
 
 /// Definition for a rule to be used in the container-to-wider-world section.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq, Hash)]
 #[serde(deny_unknown_fields)]
 pub struct ContainerToWiderWorldRule {
     /// Network of the source container to apply the rule to.
     pub network: Option<String>,
     /// Source container to apply the rule to.
     pub src_container: Option<String>,
-    /// Additional match-string, which will be added to the nftables command.
+    /// Labels identifying the source containers to apply the rule to, see
+    /// [`ContainerSelector`](struct.ContainerSelector.html).
+    ///
+    /// Cannot be combined with `src_container` or `src_service`.
+    pub src_selector: Option<ContainerSelector>,
+    /// Compose service to apply the rule to, in the format `<project>/<service>`, see
+    /// [`ComposeService`](struct.ComposeService.html).
+    ///
+    /// Cannot be combined with `src_container` or `src_selector`.
+    pub src_service: Option<ComposeService>,
+    /// Swarm service to apply the rule to, see [`SwarmService`](struct.SwarmService.html).
+    ///
+    /// Cannot be combined with `src_container`, `src_selector` or `src_service`.
+    pub src_swarm_service: Option<SwarmService>,
+    /// Protocol to match, see [`Protocol`](enum.Protocol.html).
+    ///
+    /// This is required to match ports or ICMP types, `tcp` is used for ports if it is not
+    /// specified.
+    pub protocol: Option<Protocol>,
+    /// Source port to match, either a single port or a range of ports (e.g. `"8000-8010"`).
+    #[serde(default, deserialize_with = "option_string_or_struct")]
+    #[schemars(schema_with = "crate::schema::port_or_string_or_struct::<PortRange>")]
+    pub src_port: Option<PortRange>,
+    /// Destination port to match, either a single port or a range of ports (e.g. `"8000-8010"`).
+    #[serde(default, deserialize_with = "option_string_or_struct")]
+    #[schemars(schema_with = "crate::schema::port_or_string_or_struct::<PortRange>")]
+    pub dst_port: Option<PortRange>,
+    /// Destination CIDRs (IPv4) to match.
+    ///
+    /// This can be a single string or a list of strings.
+    #[serde(default, deserialize_with = "option_string_or_seq_string")]
+    #[schemars(schema_with = "crate::schema::string_or_seq_string")]
+    pub dst_cidr_v4: Option<Vec<String>>,
+    /// Destination CIDRs (IPv6) to match.
+    ///
+    /// This can be a single string or a list of strings. Since source containers are addressed
+    /// through their IPv4 addresses, this cannot be combined with a source container.
+    #[serde(default, deserialize_with = "option_string_or_seq_string")]
+    #[schemars(schema_with = "crate::schema::string_or_seq_string")]
+    pub dst_cidr_v6: Option<Vec<String>>,
+    /// ICMP type to match, e.g. `echo-request`. Requires `protocol` to be `icmp` or `icmpv6`.
+    pub icmp_type: Option<String>,
+    /// Connection tracking states to match, see [`ConnectionState`](enum.ConnectionState.html).
+    ///
+    /// This can be a single state or a list of states.
+    #[serde(default, deserialize_with = "option_single_or_seq_string_or_struct")]
+    #[schemars(schema_with = "crate::schema::struct_or_seq_struct::<ConnectionState>")]
+    pub ct_state: Option<Vec<ConnectionState>>,
+    /// Additional match-string, which will be added to the firewall-backend command verbatim.
+    ///
+    /// Prefer the typed match fields above where possible, since the match-string is specific to
+    /// the firewall-backend used.
     pub matches: Option<String>,
     /// Verdict for rule (accept, drop or reject).
     #[serde(alias = "action")]
@@ -358,7 +591,7 @@ pub struct ContainerToWiderWorldRule {
 }
 
 /// The container-to-host section, defining how containers can communicate with the host.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq, Hash)]
 #[serde(deny_unknown_fields)]
 pub struct ContainerToHost {
     /// The `default_policy` defines the default for when there is not a specific rule.
@@ -395,14 +628,88 @@ pub struct ContainerToHost {
 }
 
 /// Definition for a rule to be used in the container-to-host section.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq, Hash)]
 #[serde(deny_unknown_fields)]
 pub struct ContainerToHostRule {
     /// Network of the source container to apply the rule to.
     pub network: String,
     /// Source container to apply the rule to.
     pub src_container: Option<String>,
-    /// Additional match-string, which will be added to the nftables command.
+    /// Labels identifying the source containers to apply the rule to, see
+    /// [`ContainerSelector`](struct.ContainerSelector.html).
+    ///
+    /// Cannot be combined with `src_container` or `src_service`.
+    pub src_selector: Option<ContainerSelector>,
+    /// Compose service to apply the rule to, in the format `<project>/<service>`, see
+    /// [`ComposeService`](struct.ComposeService.html).
+    ///
+    /// Cannot be combined with `src_container` or `src_selector`.
+    pub src_service: Option<ComposeService>,
+    /// Swarm service to apply the rule to, see [`SwarmService`](struct.SwarmService.html).
+    ///
+    /// Cannot be combined with `src_container`, `src_selector` or `src_service`.
+    pub src_swarm_service: Option<SwarmService>,
+    /// Protocol to match, see [`Protocol`](enum.Protocol.html).
+    ///
+    /// This is required to match ports or ICMP types, `tcp` is used for ports if it is not
+    /// specified.
+    pub protocol: Option<Protocol>,
+    /// Source port to match, either a single port or a range of ports (e.g. `"8000-8010"`).
+    #[serde(default, deserialize_with = "option_string_or_struct")]
+    #[schemars(schema_with = "crate::schema::port_or_string_or_struct::<PortRange>")]
+    pub src_port: Option<PortRange>,
+    /// Destination port to match, either a single port or a range of ports (e.g. `"8000-8010"`).
+    #[serde(default, deserialize_with = "option_string_or_struct")]
+    #[schemars(schema_with = "crate::schema::port_or_string_or_struct::<PortRange>")]
+    pub dst_port: Option<PortRange>,
+    /// Destination CIDRs (IPv4) to match.
+    ///
+    /// This can be a single string or a list of strings.
+    #[serde(default, deserialize_with = "option_string_or_seq_string")]
+    #[schemars(schema_with = "crate::schema::string_or_seq_string")]
+    pub dst_cidr_v4: Option<Vec<String>>,
+    /// Destination CIDRs (IPv6) to match.
+    ///
+    /// This can be a single string or a list of strings. Combined with a source container, this
+    /// only applies to the IPv6 address of the container.
+    #[serde(default, deserialize_with = "option_string_or_seq_string")]
+    #[schemars(schema_with = "crate::schema::string_or_seq_string")]
+    pub dst_cidr_v6: Option<Vec<String>>,
+    /// ICMP type to match, e.g. `echo-request`. Requires `protocol` to be `icmp` or `icmpv6`.
+    pub icmp_type: Option<String>,
+    /// Connection tracking states to match, see [`ConnectionState`](enum.ConnectionState.html).
+    ///
+    /// This can be a single state or a list of states.
+    #[serde(default, deserialize_with = "option_single_or_seq_string_or_struct")]
+    #[schemars(schema_with = "crate::schema::struct_or_seq_struct::<ConnectionState>")]
+    pub ct_state: Option<Vec<ConnectionState>>,
+    /// IP versions to generate the rule for, see [`IpVersion`](enum.IpVersion.html).
+    ///
+    /// If not specified, this is derived from the rule: rules matching the `icmp` or `icmpv6`
+    /// protocol are only generated for IPv4 or IPv6 respectively, rules with a match-string
+    /// (`matches`) only for IPv4, since the match-string can't be checked for the IP version it
+    /// is written for. All other rules are generated for `both`. Specifying an IP version the
+    /// protocol doesn't exist for is an error.
+    ///
+    /// Rules matching container addresses are only generated for IPv6 if the containers have an
+    /// IPv6 address.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use dfw::types::*;
+    /// # use toml;
+    /// # toml::from_str::<ContainerToHostRule>(r#"
+    /// # network = ""
+    /// # verdict = "accept"
+    /// ip_version = "v4"
+    /// # "#).unwrap();
+    /// ```
+    pub ip_version: Option<IpVersion>,
+    /// Additional match-string, which will be added to the firewall-backend command verbatim.
+    ///
+    /// Prefer the typed match fields above where possible, since the match-string is specific to
+    /// the firewall-backend used.
     pub matches: Option<String>,
     /// Verdict for rule (accept, drop or reject).
     #[serde(alias = "action")]
@@ -410,7 +717,7 @@ pub struct ContainerToHostRule {
 }
 
 /// The wider-world-to-container section, defining how containers can reached from the wider world.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq, Hash)]
 #[serde(deny_unknown_fields)]
 pub struct WiderWorldToContainer {
     /// An optional list of rules, see
@@ -444,14 +751,37 @@ pub struct WiderWorldToContainer {
 }
 
 /// Definition for a rule to be used in the wider-world-to-container section.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq, Hash)]
 #[serde(deny_unknown_fields)]
 pub struct WiderWorldToContainerRule {
     /// Network of the destination container to apply the rule to.
+    ///
+    /// On Docker Swarm nodes this can be the `ingress` network, in which case the exposed ports
+    /// are published through the routing mesh: traffic to them is accepted towards
+    /// `docker_gwbridge` and the destination is not resolved to a single container. Ports
+    /// published through the routing mesh can't be mapped to a different container port.
     pub network: String,
 
     /// Destination container to apply the rule to.
-    pub dst_container: String,
+    ///
+    /// Either this or `dst_selector` has to be specified.
+    pub dst_container: Option<String>,
+
+    /// Labels identifying the destination container to apply the rule to, see
+    /// [`ContainerSelector`](struct.ContainerSelector.html).
+    ///
+    /// Since traffic can only be forwarded to a single container, the selector has to match
+    /// exactly one container on the network. Cannot be combined with `dst_container`.
+    pub dst_selector: Option<ContainerSelector>,
+    /// Compose service to apply the rule to, in the format `<project>/<service>`, see
+    /// [`ComposeService`](struct.ComposeService.html).
+    ///
+    /// Cannot be combined with `dst_container` or `dst_selector`.
+    pub dst_service: Option<ComposeService>,
+    /// Swarm service to apply the rule to, see [`SwarmService`](struct.SwarmService.html).
+    ///
+    /// Cannot be combined with `dst_container`, `dst_selector` or `dst_service`.
+    pub dst_swarm_service: Option<SwarmService>,
 
     /// Ports to apply the rule to.
     ///
@@ -528,8 +858,23 @@ pub struct WiderWorldToContainerRule {
     ///     { host_port = 443, container_port = 8443 },
     /// ]
     /// # "#).unwrap();
+    ///
+    /// # toml::from_str::<WiderWorldToContainerRule>(r#"
+    /// ## Port ranges and multiple families are supported too
+    /// # network = ""
+    /// # dst_container = ""
+    /// expose_port = ["30000-30100/udp", "8000-8010:9000-9010", "53/tcp+udp"]
+    /// # "#).unwrap();
+    /// # toml::from_str::<WiderWorldToContainerRule>(r#"
+    /// # network = ""
+    /// # dst_container = ""
+    /// expose_port = { host_port = 8000, host_port_end = 8010, container_port = 9000 }
+    /// # "#).unwrap();
     /// ```
     #[serde(deserialize_with = "single_or_seq_string_or_struct")]
+    #[schemars(
+        schema_with = "crate::schema::single_or_seq_port_or_string_or_struct::<ExposePort>"
+    )]
     pub expose_port: Vec<ExposePort>,
 
     /// Specific external network interface to target.
@@ -537,6 +882,10 @@ pub struct WiderWorldToContainerRule {
 
     /// Configure if the container should be exposed via IPv6, too. _(Default: true)_.
     ///
+    /// If IPv6 is enabled for the network and the container has an IPv6 address on it, the
+    /// traffic is forwarded to this address. Otherwise the traffic is only accepted, relying on a
+    /// separate NAT setup to reach the container.
+    ///
     /// # Example
     ///
     /// ```
@@ -567,7 +916,7 @@ pub struct WiderWorldToContainerRule {
     ///
     /// * a list of strings
     ///
-    /// There is no validation whether the provided CIDRs are actually valid.
+    /// The CIDRs are only validated when checking the configuration using `--check-config`.
     ///
     /// # Example
     ///
@@ -595,6 +944,7 @@ pub struct WiderWorldToContainerRule {
         deserialize_with = "option_string_or_seq_string",
         alias = "source_cidr"
     )]
+    #[schemars(schema_with = "crate::schema::string_or_seq_string")]
     pub source_cidr_v4: Option<Vec<String>>,
 
     /// Source CIDRs (IPv6) to which incoming traffic should be restricted.
@@ -605,7 +955,7 @@ pub struct WiderWorldToContainerRule {
     ///
     /// * a list of strings
     ///
-    /// There is no validation whether the provided CIDRs are actually valid.
+    /// The CIDRs are only validated when checking the configuration using `--check-config`.
     ///
     /// # Example
     ///
@@ -629,6 +979,7 @@ pub struct WiderWorldToContainerRule {
     /// # "#).unwrap();
     /// ```
     #[serde(default, deserialize_with = "option_string_or_seq_string")]
+    #[schemars(schema_with = "crate::schema::string_or_seq_string")]
     pub source_cidr_v6: Option<Vec<String>>,
 }
 
@@ -637,33 +988,104 @@ fn default_wwtcr_expose_via_ipv6() -> bool {
 }
 
 /// Struct to hold a port definition to expose on the host/between containers.
-#[derive(Deserialize, Debug, Clone, Default, Builder, PartialEq, Eq, Hash)]
+///
+/// A port definition can span a range of ports, which are mapped 1:1 to a range of container
+/// ports of the same length, and it can apply to multiple families (e.g. `tcp+udp`).
+///
+/// _Note:_ mapping a range of host ports to a range of container ports starting at a different
+/// port requires iptables v1.8.7 (or newer) and Linux v5.13 (or newer) when using the iptables
+/// backend.
+#[derive(Deserialize, JsonSchema, Debug, Clone, Default, Builder, PartialEq, Eq, Hash)]
 #[serde(deny_unknown_fields)]
 pub struct ExposePort {
     /// Port the `container_port` should be exposed to on the host.
+    ///
+    /// If `host_port_end` is specified, this is the first port of the range of host ports.
     #[builder(field(public))]
     pub host_port: u16,
 
+    /// Last port (inclusive) of the range of host ports to expose, starting at `host_port`.
+    ///
+    /// This field is optional, if it is not specified only the single `host_port` is exposed.
+    #[serde(default)]
+    #[builder(field(public), default)]
+    pub host_port_end: Option<u16>,
+
     /// Port the `host_port` should map to into the container.
+    ///
+    /// If a range of host ports is exposed, this is the first port of the range of container
+    /// ports the host ports map to.
     #[builder(field(public), default = "self.default_container_port()")]
     pub container_port: Option<u16>,
 
     /// Family of the exposed port.
     ///
-    /// Can be left blank, `tcp` will be used as default.
+    /// Can be left blank, `tcp` will be used as default. Multiple families can be combined using
+    /// `+`, e.g. `tcp+udp`.
     #[serde(default = "default_expose_port_family")]
     #[builder(field(public), default = "self.default_family()")]
     pub family: String,
 }
 
+impl ExposePort {
+    /// Get the range of host ports, as inclusive start and end.
+    pub fn host_port_range(&self) -> Result<(u16, u16), String> {
+        let host_port_end = self.host_port_end.unwrap_or(self.host_port);
+        if host_port_end < self.host_port {
+            return Err(format!(
+                "port range {}-{} is invalid, the end has to be greater than the start",
+                self.host_port, host_port_end
+            ));
+        }
+        Ok((self.host_port, host_port_end))
+    }
+
+    /// Get the range of container ports the host ports map to, as inclusive start and end.
+    pub fn container_port_range(&self) -> Result<(u16, u16), String> {
+        let (host_port, host_port_end) = self.host_port_range()?;
+        let container_port = self.container_port.unwrap_or(host_port);
+        let container_port_end = container_port
+            .checked_add(host_port_end - host_port)
+            .ok_or_else(|| {
+                format!(
+                    "container port range starting at {} exceeds the maximum port",
+                    container_port
+                )
+            })?;
+        Ok((container_port, container_port_end))
+    }
+
+    /// Get the individual families of the exposed port.
+    pub fn families(&self) -> impl Iterator<Item = &str> {
+        self.family.split('+')
+    }
+}
+
 impl ExposePortBuilder {
     fn client_and_host_port(&mut self, value: &str) -> Result<&mut Self, String> {
         let split: Vec<&str> = value.split(':').collect();
         match split.len() {
-            1 => self.host_port = Some(split[0].parse().map_err(|e| format!("{}", e))?),
+            1 => {
+                let (host_port, host_port_end) = parse_port_range(split[0])?;
+                self.host_port = Some(host_port);
+                self.host_port_end = Some(host_port_end);
+            }
             2 => {
-                self.host_port = Some(split[0].parse().map_err(|e| format!("{}", e))?);
-                self.container_port = Some(Some(split[1].parse().map_err(|e| format!("{}", e))?));
+                let (host_port, host_port_end) = parse_port_range(split[0])?;
+                let (container_port, container_port_end) = parse_port_range(split[1])?;
+                // A container port range is optional, but if it is given it has to map 1:1.
+                if container_port_end.is_some()
+                    && container_port_end.map(|end| end - container_port)
+                        != host_port_end.map(|end| end - host_port)
+                {
+                    return Err(format!(
+                        "port ranges in '{}' have to be of the same length",
+                        value
+                    ));
+                }
+                self.host_port = Some(host_port);
+                self.host_port_end = Some(host_port_end);
+                self.container_port = Some(Some(container_port));
             }
             _ => return Err(format!("port string has invalid format '{}'", value)),
         }
@@ -679,6 +1101,36 @@ impl ExposePortBuilder {
     }
 }
 
+fn parse_port_range(value: &str) -> Result<(u16, Option<u16>), String> {
+    let split: Vec<&str> = value.split('-').collect();
+    match split.len() {
+        1 => Ok((split[0].parse().map_err(|e| format!("{}", e))?, None)),
+        2 => {
+            let start: u16 = split[0].parse().map_err(|e| format!("{}", e))?;
+            let end: u16 = split[1].parse().map_err(|e| format!("{}", e))?;
+            if end < start {
+                return Err(format!(
+                    "port range '{}' is invalid, the end has to be greater than the start",
+                    value
+                ));
+            }
+            Ok((start, Some(end)))
+        }
+        _ => Err(format!("port range has invalid format '{}'", value)),
+    }
+}
+
+impl From<u16> for ExposePort {
+    fn from(port: u16) -> Self {
+        ExposePort {
+            host_port: port,
+            host_port_end: None,
+            container_port: None,
+            family: DEFAULT_PROTOCOL.to_owned(),
+        }
+    }
+}
+
 impl FromStr for ExposePort {
     type Err = String;
 
@@ -688,6 +1140,10 @@ impl FromStr for ExposePort {
     /// `80:8080/tcp`. If you don't specify the container-port, it is assumed to be identical to the
     /// host-port.
     ///
+    /// Ports can be given as ranges (`<START>-<END>`), with the container-port range either being
+    /// of the same length as the host-port range or being just its start port. Multiple families
+    /// can be combined using `+`.
+    ///
     /// # Example
     ///
     /// ```
@@ -713,6 +1169,29 @@ impl FromStr for ExposePort {
     /// assert_eq!(port.container_port, Some(8080));
     /// assert_eq!(port.family, "tcp");
     /// ```
+    ///
+    /// ```
+    /// # use dfw::types::ExposePort;
+    /// let port: ExposePort = "30000-30100/udp".parse().unwrap();
+    /// assert_eq!(port.host_port, 30000);
+    /// assert_eq!(port.host_port_end, Some(30100));
+    /// assert_eq!(port.container_port, None);
+    /// assert_eq!(port.family, "udp");
+    /// ```
+    ///
+    /// ```
+    /// # use dfw::types::ExposePort;
+    /// let port: ExposePort = "8000-8010:9000-9010".parse().unwrap();
+    /// assert_eq!(port.host_port_range(), Ok((8000, 8010)));
+    /// assert_eq!(port.container_port_range(), Ok((9000, 9010)));
+    /// ```
+    ///
+    /// ```
+    /// # use dfw::types::ExposePort;
+    /// let port: ExposePort = "53/tcp+udp".parse().unwrap();
+    /// assert_eq!(port.host_port, 53);
+    /// assert_eq!(port.families().collect::<Vec<_>>(), vec!["tcp", "udp"]);
+    /// ```
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let split: Vec<&str> = s.split('/').collect();
         Ok(match split.len() {
@@ -720,11 +1199,13 @@ impl FromStr for ExposePort {
                 .client_and_host_port(split[0])?
                 .build()
                 .map_err(|error| format!("{}", error))?,
-            2 => ExposePortBuilder::default()
-                .client_and_host_port(split[0])?
-                .family(split[1].to_owned())
-                .build()
-                .map_err(|error| format!("{}", error))?,
+            2 if split[1].split('+').all(|family| !family.is_empty()) => {
+                ExposePortBuilder::default()
+                    .client_and_host_port(split[0])?
+                    .family(split[1].to_owned())
+                    .build()
+                    .map_err(|error| format!("{}", error))?
+            }
             _ => return Err(format!("port string has invalid format '{}'", s)),
         })
     }
@@ -732,7 +1213,7 @@ impl FromStr for ExposePort {
 
 /// The container-DNAT section, defining how containers can communicate with each other over
 /// non-common networks.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq, Hash)]
 #[serde(deny_unknown_fields)]
 pub struct ContainerDNAT {
     /// An optional list of rules, see
@@ -766,7 +1247,7 @@ pub struct ContainerDNAT {
 }
 
 /// Definition for a rule to be used in the container-DNAT section.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq, Hash)]
 #[serde(deny_unknown_fields)]
 pub struct ContainerDNATRule {
     /// Network of the source container to apply the rule to.
@@ -775,11 +1256,44 @@ pub struct ContainerDNATRule {
     /// Source container to apply the rule to.
     pub src_container: Option<String>,
 
+    /// Labels identifying the source containers to apply the rule to, see
+    /// [`ContainerSelector`](struct.ContainerSelector.html).
+    ///
+    /// Cannot be combined with `src_container` or `src_service`.
+    pub src_selector: Option<ContainerSelector>,
+    /// Compose service to apply the rule to, in the format `<project>/<service>`, see
+    /// [`ComposeService`](struct.ComposeService.html).
+    ///
+    /// Cannot be combined with `src_container` or `src_selector`.
+    pub src_service: Option<ComposeService>,
+    /// Swarm service to apply the rule to, see [`SwarmService`](struct.SwarmService.html).
+    ///
+    /// Cannot be combined with `src_container`, `src_selector` or `src_service`.
+    pub src_swarm_service: Option<SwarmService>,
+
     /// Network of the destination container to apply the rule to.
     pub dst_network: String,
 
     /// Destination container to apply the rule to.
-    pub dst_container: String,
+    ///
+    /// Either this or `dst_selector` has to be specified.
+    pub dst_container: Option<String>,
+
+    /// Labels identifying the destination container to apply the rule to, see
+    /// [`ContainerSelector`](struct.ContainerSelector.html).
+    ///
+    /// Since traffic can only be forwarded to a single container, the selector has to match
+    /// exactly one container on the network. Cannot be combined with `dst_container`.
+    pub dst_selector: Option<ContainerSelector>,
+    /// Compose service to apply the rule to, in the format `<project>/<service>`, see
+    /// [`ComposeService`](struct.ComposeService.html).
+    ///
+    /// Cannot be combined with `dst_container` or `dst_selector`.
+    pub dst_service: Option<ComposeService>,
+    /// Swarm service to apply the rule to, see [`SwarmService`](struct.SwarmService.html).
+    ///
+    /// Cannot be combined with `dst_container`, `dst_selector` or `dst_service`.
+    pub dst_swarm_service: Option<SwarmService>,
 
     /// Ports to apply the rule to.
     ///
@@ -856,11 +1370,307 @@ pub struct ContainerDNATRule {
     ///     { host_port = 443, container_port = 8443 },
     /// ]
     /// # "#).unwrap();
+    ///
+    /// # toml::from_str::<ContainerDNATRule>(r#"
+    /// ## Port ranges and multiple families are supported too
+    /// # dst_network = ""
+    /// # dst_container = ""
+    /// expose_port = ["30000-30100/udp", "8000-8010:9000-9010", "53/tcp+udp"]
+    /// # "#).unwrap();
+    /// # toml::from_str::<ContainerDNATRule>(r#"
+    /// # dst_network = ""
+    /// # dst_container = ""
+    /// expose_port = { host_port = 8000, host_port_end = 8010, container_port = 9000 }
+    /// # "#).unwrap();
     /// ```
     #[serde(deserialize_with = "single_or_seq_string_or_struct")]
+    #[schemars(
+        schema_with = "crate::schema::single_or_seq_port_or_string_or_struct::<ExposePort>"
+    )]
     pub expose_port: Vec<ExposePort>,
 }
 
+/// Selector identifying containers through their Docker labels.
+///
+/// A container is matched by the selector if it carries _all_ labels specified, with exactly the
+/// values specified. Every container matching the selector is considered when generating the
+/// rules, which allows you to reference containers whose names are not known in advance.
+///
+/// # Example
+///
+/// ```
+/// # use dfw::nftables::Nftables;
+/// # use dfw::types::*;
+/// # use toml;
+/// # toml::from_str::<DFW<Nftables>>(r#"
+/// # [container_to_container]
+/// # default_policy = "drop"
+/// [[container_to_container.rules]]
+/// network = "common_network"
+/// src_selector = { "app" = "web", "tier" = "frontend" }
+/// dst_selector = { "app" = "db" }
+/// verdict = "accept"
+/// # "#).unwrap();
+/// ```
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq, Hash, Default)]
+#[serde(transparent)]
+pub struct ContainerSelector(pub BTreeMap<String, String>);
+
+impl ContainerSelector {
+    /// Check whether the given container labels satisfy this selector.
+    ///
+    /// An empty selector matches every container, it is reported as an error by
+    /// [`validate`](../validate/fn.validate.html).
+    pub fn matches(&self, labels: Option<&HashMap<String, String>>) -> bool {
+        self.0.iter().all(|(key, value)| {
+            labels
+                .and_then(|labels| labels.get(key))
+                .map(|label_value| label_value == value)
+                .unwrap_or(false)
+        })
+    }
+}
+
+/// Label Docker Compose attaches to containers, identifying the project they belong to.
+pub const COMPOSE_PROJECT_LABEL: &str = "com.docker.compose.project";
+/// Label Docker Compose attaches to containers, identifying the service they belong to.
+pub const COMPOSE_SERVICE_LABEL: &str = "com.docker.compose.service";
+
+/// Reference to a Docker Compose service, in the format `<project>/<service>`.
+///
+/// The service is resolved through the labels Docker Compose attaches to its containers, which
+/// means that all replicas of a scaled service are considered when generating the rules.
+///
+/// # Example
+///
+/// ```
+/// # use dfw::nftables::Nftables;
+/// # use dfw::types::*;
+/// # use toml;
+/// # toml::from_str::<DFW<Nftables>>(r#"
+/// # [container_to_container]
+/// # default_policy = "drop"
+/// [[container_to_container.rules]]
+/// network = "myproj_default"
+/// src_service = "myproj/web"
+/// dst_service = "myproj/db"
+/// verdict = "accept"
+/// # "#).unwrap();
+/// ```
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(try_from = "String")]
+pub struct ComposeService {
+    /// Name of the Compose project.
+    pub project: String,
+    /// Name of the service within the Compose project.
+    pub service: String,
+}
+
+impl ComposeService {
+    /// Convert the service into a selector matching all of its containers.
+    pub fn selector(&self) -> ContainerSelector {
+        ContainerSelector(maplit::btreemap! {
+            COMPOSE_PROJECT_LABEL.to_owned() => self.project.clone(),
+            COMPOSE_SERVICE_LABEL.to_owned() => self.service.clone(),
+        })
+    }
+}
+
+impl FromStr for ComposeService {
+    type Err = String;
+
+    /// Convert a formatted string into a [`ComposeService`](struct.ComposeService.html).
+    ///
+    /// The string has to be in the format `<project>/<service>`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use dfw::types::ComposeService;
+    /// let service: ComposeService = "myproj/web".parse().unwrap();
+    /// assert_eq!(service.project, "myproj");
+    /// assert_eq!(service.service, "web");
+    /// ```
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split('/').collect::<Vec<_>>().as_slice() {
+            [project, service] if !project.is_empty() && !service.is_empty() => {
+                Ok(ComposeService {
+                    project: (*project).to_owned(),
+                    service: (*service).to_owned(),
+                })
+            }
+            _ => Err(format!(
+                "compose service has invalid format '{}', expected '<project>/<service>'",
+                s
+            )),
+        }
+    }
+}
+
+impl JsonSchema for ComposeService {
+    fn schema_name() -> String {
+        "ComposeService".to_owned()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        String::json_schema(gen)
+    }
+}
+
+impl TryFrom<String> for ComposeService {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+/// Label Docker Swarm attaches to the containers of the tasks of a service.
+pub const SWARM_SERVICE_LABEL: &str = "com.docker.swarm.service.name";
+
+/// Reference to a Docker Swarm service by its name.
+///
+/// The service is resolved through the labels Docker Swarm attaches to the containers of its
+/// tasks. Only the tasks scheduled on the local node have containers, which means that rules only
+/// apply to the replicas running on the node DFW runs on.
+///
+/// # Example
+///
+/// ```
+/// # use dfw::nftables::Nftables;
+/// # use dfw::types::*;
+/// # use toml;
+/// # toml::from_str::<DFW<Nftables>>(r#"
+/// # [container_to_wider_world]
+/// # default_policy = "drop"
+/// [[container_to_wider_world.rules]]
+/// network = "backend"
+/// src_swarm_service = "web"
+/// verdict = "accept"
+/// # "#).unwrap();
+/// ```
+#[derive(Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(transparent)]
+pub struct SwarmService(pub String);
+
+impl SwarmService {
+    /// Convert the service into a selector matching the containers of all of its local tasks.
+    pub fn selector(&self) -> ContainerSelector {
+        ContainerSelector(maplit::btreemap! {
+            SWARM_SERVICE_LABEL.to_owned() => self.0.clone(),
+        })
+    }
+}
+
+/// Range of ports, which can also consist of a single port.
+///
+/// A range can be given as a single port (`80`), as a string (`"80"` or `"8000-8010"`), or as a
+/// map (`{ start = 8000, end = 8010 }`).
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(deny_unknown_fields)]
+pub struct PortRange {
+    /// First port of the range.
+    pub start: u16,
+    /// Last port (inclusive) of the range.
+    pub end: u16,
+}
+
+impl From<u16> for PortRange {
+    fn from(port: u16) -> Self {
+        PortRange {
+            start: port,
+            end: port,
+        }
+    }
+}
+
+impl FromStr for PortRange {
+    type Err = String;
+
+    /// Convert a formatted string into a [`PortRange`](struct.PortRange.html).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use dfw::types::PortRange;
+    /// let range: PortRange = "8000-8010".parse().unwrap();
+    /// assert_eq!(range.start, 8000);
+    /// assert_eq!(range.end, 8010);
+    /// ```
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = parse_port_range(s)?;
+        Ok(PortRange {
+            start,
+            end: end.unwrap_or(start),
+        })
+    }
+}
+
+/// Representation of the protocols that can be matched.
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq, Hash, Display, EnumString)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum Protocol {
+    /// TCP
+    Tcp,
+    /// UDP
+    Udp,
+    /// UDP-Lite
+    Udplite,
+    /// SCTP
+    Sctp,
+    /// DCCP
+    Dccp,
+    /// ICMP (IPv4)
+    Icmp,
+    /// ICMPv6
+    Icmpv6,
+}
+
+impl Protocol {
+    /// The IP version the protocol is specific to, if any.
+    pub(crate) fn ip_version(self) -> Option<IpVersion> {
+        match self {
+            Protocol::Icmp => Some(IpVersion::V4),
+            Protocol::Icmpv6 => Some(IpVersion::V6),
+            _ => None,
+        }
+    }
+}
+
+/// Representation of connection tracking states that can be matched.
+#[derive(Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq, Hash, Display, EnumString)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+pub enum ConnectionState {
+    /// The packet starts a new connection.
+    New,
+    /// The packet belongs to an established connection.
+    Established,
+    /// The packet starts a new connection related to an established one (e.g. FTP data).
+    Related,
+    /// The packet could not be associated with a connection.
+    Invalid,
+    /// The packet is not tracked.
+    Untracked,
+}
+
+/// Representation of the IP versions a rule is generated for.
+#[derive(
+    Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Display, EnumString,
+)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum IpVersion {
+    /// Only generate the rule for IPv4.
+    V4,
+    /// Only generate the rule for IPv6.
+    V6,
+    /// Generate the rule for both IPv4 and IPv6.
+    #[default]
+    Both,
+}
+
 fn default_expose_port_family() -> String {
     DEFAULT_PROTOCOL.to_owned()
 }
@@ -871,7 +1681,9 @@ fn default_expose_port_family() -> String {
 ///
 /// Parts of the documentation have been taken from
 /// <https://wiki.nftables.org/wiki-nftables/index.php/Configuring_chains>.
-#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Display, EnumString)]
+#[derive(
+    Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Display, EnumString,
+)]
 #[serde(rename_all = "lowercase")]
 #[strum(serialize_all = "snake_case")]
 pub enum ChainPolicy {
@@ -904,7 +1716,9 @@ impl slog::Value for ChainPolicy {
 ///
 /// Parts of the documentation have been taken from
 /// <https://wiki.nftables.org/wiki-nftables/index.php/Configuring_chains>.
-#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Display, EnumString)]
+#[derive(
+    Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Display, EnumString,
+)]
 #[serde(rename_all = "lowercase")]
 #[strum(serialize_all = "snake_case")]
 pub enum RuleVerdict {
@@ -937,7 +1751,8 @@ impl slog::Value for RuleVerdict {
 }
 #[cfg(test)]
 mod test {
-    use super::{ChainPolicy, RuleVerdict};
+    use super::{ChainPolicy, ContainerSelector, RuleVerdict};
+    use maplit::{btreemap, hashmap};
     use std::str::FromStr;
 
     #[test]
@@ -970,4 +1785,27 @@ mod test {
         assert_eq!("drop", &RuleVerdict::Drop.to_string());
         assert_eq!("reject", &RuleVerdict::Reject.to_string());
     }
+
+    #[test]
+    fn containerselector_matches() {
+        let selector = ContainerSelector(btreemap! {
+            "app".to_owned() => "web".to_owned(),
+            "tier".to_owned() => "frontend".to_owned(),
+        });
+
+        assert!(selector.matches(Some(&hashmap! {
+            "app".to_owned() => "web".to_owned(),
+            "tier".to_owned() => "frontend".to_owned(),
+            "other".to_owned() => "label".to_owned(),
+        })));
+        assert!(!selector.matches(Some(&hashmap! {
+            "app".to_owned() => "web".to_owned(),
+        })));
+        assert!(!selector.matches(Some(&hashmap! {
+            "app".to_owned() => "db".to_owned(),
+            "tier".to_owned() => "frontend".to_owned(),
+        })));
+        assert!(!selector.matches(None));
+        assert!(ContainerSelector::default().matches(None));
+    }
 }
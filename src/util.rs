@@ -11,13 +11,22 @@
 
 use crate::errors::*;
 
+use failure::{bail, format_err, ResultExt};
 use glob::glob;
 use lazy_static::lazy_static;
+use schemars::{
+    gen::SchemaGenerator,
+    schema::{RootSchema, SchemaObject},
+    JsonSchema,
+};
 use serde::de::DeserializeOwned;
 use std::{
+    collections::{HashMap, HashSet},
     fs::File,
     future::Future,
     io::{prelude::*, BufReader},
+    ops::Range,
+    path::{Path, PathBuf},
 };
 use tokio::runtime::Runtime;
 
@@ -25,35 +34,524 @@ lazy_static! {
     static ref RUNTIME: Runtime = Runtime::new().unwrap();
 }
 
-/// Load single TOML-file from path and deserialize it into type `T`.
+/// Load single configuration file from path and deserialize it into type `T`.
+///
+/// The file can be written in TOML, JSON or YAML, the format being chosen by the file extension
+/// (`.json`, `.yaml` or `.yml`, falling back to TOML otherwise).
+///
+/// Files referenced through a top-level `include = [...]` directive are loaded and merged as
+/// described in [`load_path`](fn.load_path.html).
+///
+/// Errors in the configuration are reported as [`ConfigError`](../errors/struct.ConfigError.html),
+/// pointing at the file and line the error originates from.
 pub fn load_file<T>(file: &str) -> Result<T>
 where
     T: DeserializeOwned,
 {
-    let mut contents = String::new();
-    let mut file = BufReader::new(File::open(file)?);
-    file.read_to_string(&mut contents)?;
-    Ok(toml::from_str(&contents)?)
+    let mut config = MergedConfig::default();
+    config.load_file(Path::new(file))?;
+    config.deserialize()
 }
 
-/// Load all TOML-files from a path, concatenate their contents and deserialize the result into
-/// type `T`.
+/// Load all configuration files (`*.toml`, `*.json`, `*.yaml` and `*.yml`) from a path, merge
+/// their contents and deserialize the result into type `T`.
+///
+/// The files are loaded in lexicographical order of their names. Every file can include further
+/// files through a top-level `include = [...]` directive, the paths (which can be glob patterns)
+/// being relative to the including file. Included files are loaded before the contents of the
+/// including file, every file is only loaded once.
+///
+/// Tables are merged recursively and arrays of tables, i.e. the rule lists, are appended. A key
+/// that is set to different values in two files is reported as a conflict.
+///
+/// Errors in the configuration are reported as [`ConfigError`](../errors/struct.ConfigError.html),
+/// pointing at the file and line the error originates from.
 pub fn load_path<T>(path: &str) -> Result<T>
 where
     T: DeserializeOwned,
 {
-    let mut contents = String::new();
-    for entry in glob(&format!("{}/*.toml", path)).expect("Failed to read glob pattern") {
-        match entry {
-            Ok(path) => {
-                let mut file = BufReader::new(File::open(path)?);
-                file.read_to_string(&mut contents)?;
+    let mut files = Vec::new();
+    for extension in CONFIG_EXTENSIONS {
+        files.extend(glob_sorted(&format!("{}/*.{}", path, extension))?);
+    }
+    files.sort();
+
+    let mut config = MergedConfig::default();
+    for file in files {
+        config.load_file(&file)?;
+    }
+
+    config.deserialize()
+}
+
+/// Generate the JSON Schema of the configuration files deserialized into type `T`.
+///
+/// Next to the fields of `T`, the schema allows the top-level `include` directive described in
+/// [`load_path`](fn.load_path.html).
+pub fn config_schema<T>() -> RootSchema
+where
+    T: JsonSchema,
+{
+    let mut gen = SchemaGenerator::default();
+    let mut include = SchemaObject::from(crate::schema::string_or_seq_string(&mut gen));
+    include.metadata().description = Some(
+        "Paths (or glob patterns) of further configuration files to load, relative to this file."
+            .to_owned(),
+    );
+
+    let mut schema = gen.into_root_schema_for::<T>();
+    schema
+        .schema
+        .object()
+        .properties
+        .insert(INCLUDE_KEY.to_owned(), include.into());
+    schema
+}
+
+const INCLUDE_KEY: &str = "include";
+
+/// Extensions of the configuration files loaded from a directory by
+/// [`load_path`](fn.load_path.html).
+pub const CONFIG_EXTENSIONS: &[&str] = &["toml", "json", "yaml", "yml"];
+
+/// Formats configuration files can be written in, chosen by the extension of the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ConfigFormat {
+    Toml,
+    Json,
+    Yaml,
+}
+
+impl ConfigFormat {
+    /// Determine the format from the file extension, falling back to TOML.
+    pub(crate) fn from_path(file: &Path) -> Self {
+        match file
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase)
+            .as_deref()
+        {
+            Some("json") => ConfigFormat::Json,
+            Some("yaml") | Some("yml") => ConfigFormat::Yaml,
+            _ => ConfigFormat::Toml,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum PathSegment {
+    Key(String),
+    Index(usize),
+}
+
+fn format_path(path: &[PathSegment]) -> String {
+    let mut formatted = String::new();
+    for segment in path {
+        match segment {
+            PathSegment::Key(key) if formatted.is_empty() => formatted.push_str(key),
+            PathSegment::Key(key) => {
+                formatted.push('.');
+                formatted.push_str(key);
+            }
+            PathSegment::Index(index) => formatted.push_str(&format!("[{}]", index)),
+        }
+    }
+
+    formatted
+}
+
+/// The file a value of the merged configuration originates from, and its path within that file.
+#[derive(Debug, Clone)]
+struct Origin {
+    file: PathBuf,
+    path: Vec<PathSegment>,
+}
+
+/// Configuration merged from multiple files, remembering which file set which key.
+#[derive(Default)]
+struct MergedConfig {
+    value: toml::value::Table,
+    origins: HashMap<Vec<PathSegment>, Origin>,
+    contents: HashMap<PathBuf, String>,
+    loaded: HashSet<PathBuf>,
+    loading: Vec<PathBuf>,
+}
+
+impl MergedConfig {
+    fn load_file(&mut self, file: &Path) -> Result<()> {
+        let canonical = file
+            .canonicalize()
+            .with_context(|_| format!("failed to load configuration file {}", file.display()))?;
+        if self.loading.contains(&canonical) {
+            bail!("configuration file {} includes itself", file.display());
+        }
+        if !self.loaded.insert(canonical.clone()) {
+            return Ok(());
+        }
+
+        let contents = read_file(file)?;
+        let mut table = parse_file(file, &contents)?;
+        self.contents.insert(file.to_path_buf(), contents);
+
+        if let Some(include) = table.remove(INCLUDE_KEY) {
+            let base = file.parent().unwrap_or_else(|| Path::new(""));
+            self.loading.push(canonical);
+            for pattern in include_patterns(include, file)? {
+                let pattern = base.join(pattern);
+                let included_files = glob_sorted(&pattern.to_string_lossy())?;
+                if included_files.is_empty() {
+                    bail!(
+                        "include {} in configuration file {} doesn't match any file",
+                        pattern.display(),
+                        file.display()
+                    );
+                }
+                for included_file in included_files {
+                    self.load_file(&included_file)?;
+                }
+            }
+            self.loading.pop();
+        }
+
+        merge_table(&mut self.value, table, &[], file, &mut self.origins)
+    }
+
+    fn deserialize<T>(mut self) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        let value = toml::Value::Table(std::mem::take(&mut self.value));
+        serde_path_to_error::deserialize(value).map_err(|e| {
+            let path = e
+                .path()
+                .iter()
+                .filter_map(|segment| match segment {
+                    serde_path_to_error::Segment::Seq { index } => Some(PathSegment::Index(*index)),
+                    serde_path_to_error::Segment::Map { key } => {
+                        Some(PathSegment::Key(key.to_owned()))
+                    }
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            let message = e.inner().message().to_owned();
+
+            let origin = (0..=path.len()).rev().find_map(|length| {
+                self.origins.get(&path[..length]).map(|origin| Origin {
+                    file: origin.file.clone(),
+                    path: origin
+                        .path
+                        .iter()
+                        .chain(path[length..].iter())
+                        .cloned()
+                        .collect(),
+                })
+            });
+
+            match origin {
+                Some(origin) => {
+                    let contents = &self.contents[&origin.file];
+                    match ConfigFormat::from_path(&origin.file) {
+                        ConfigFormat::Toml => {
+                            let span = locate_toml(contents, &origin.path, &message);
+                            config_error(&origin.file, contents, span, message).into()
+                        }
+                        // Locations are only tracked for TOML, name at least the file and path.
+                        _ => format_err!(
+                            "{}: {} (at `{}`)",
+                            origin.file.display(),
+                            message,
+                            format_path(&origin.path)
+                        ),
+                    }
+                }
+                None if path.is_empty() => format_err!("{}", message),
+                None => format_err!("{} (at `{}`)", message, format_path(&path)),
+            }
+        })
+    }
+}
+
+/// Parse a configuration file according to its format.
+fn parse_file(file: &Path, contents: &str) -> Result<toml::value::Table> {
+    let value = match ConfigFormat::from_path(file) {
+        ConfigFormat::Toml => {
+            return toml::from_str(contents).map_err(|e| {
+                config_error(
+                    file,
+                    contents,
+                    e.span().unwrap_or(0..0),
+                    e.message().to_owned(),
+                )
+                .into()
+            })
+        }
+        ConfigFormat::Json => serde_json::from_str(contents)
+            .map_err(|e| config_error_at(file, contents, e.line(), e.column(), e.to_string()))?,
+        ConfigFormat::Yaml => serde_yaml::from_str(contents).map_err(|e| match e.location() {
+            Some(location) => config_error_at(
+                file,
+                contents,
+                location.line(),
+                location.column(),
+                e.to_string(),
+            ),
+            None => format_err!("{}: {}", file.display(), e),
+        })?,
+    };
+
+    match toml::Value::try_from(without_nulls(value))
+        .with_context(|_| format!("failed to parse configuration file {}", file.display()))?
+    {
+        toml::Value::Table(table) => Ok(table),
+        _ => bail!(
+            "configuration file {} has to contain a map at the top-level",
+            file.display()
+        ),
+    }
+}
+
+/// Remove all `null` values from maps, since TOML has no representation for them and they are
+/// equivalent to an unspecified value.
+pub(crate) fn without_nulls(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(map) => serde_json::Value::Object(
+            map.into_iter()
+                .filter(|(_, value)| !value.is_null())
+                .map(|(key, value)| (key, without_nulls(value)))
+                .collect(),
+        ),
+        serde_json::Value::Array(values) => {
+            serde_json::Value::Array(values.into_iter().map(without_nulls).collect())
+        }
+        value => value,
+    }
+}
+
+/// Locate the value at the path within the TOML document.
+fn locate_toml(contents: &str, path: &[PathSegment], message: &str) -> Range<usize> {
+    let document = match toml_edit::ImDocument::parse(contents) {
+        Ok(document) => document,
+        Err(_) => return 0..0,
+    };
+
+    let mut item = document.as_item();
+    let mut span = None;
+    for (index, segment) in path.iter().enumerate() {
+        let next = match segment {
+            PathSegment::Key(key) => {
+                // Unknown fields are best pointed out through their key.
+                if index == path.len() - 1 && message.starts_with("unknown field") {
+                    if let Some(key_span) = item
+                        .as_table_like()
+                        .and_then(|table| table.key(key))
+                        .and_then(toml_edit::Key::span)
+                    {
+                        span = Some(key_span);
+                        break;
+                    }
+                }
+                item.get(key.as_str())
+            }
+            PathSegment::Index(index) => item.get(*index),
+        };
+        match next {
+            Some(next) => {
+                item = next;
+                span = item.span().or(span);
+            }
+            None => break,
+        }
+    }
+
+    span.unwrap_or(0..0)
+}
+
+fn merge_table(
+    target: &mut toml::value::Table,
+    source: toml::value::Table,
+    prefix: &[PathSegment],
+    file: &Path,
+    origins: &mut HashMap<Vec<PathSegment>, Origin>,
+) -> Result<()> {
+    for (key, value) in source {
+        let mut key_path = prefix.to_vec();
+        key_path.push(PathSegment::Key(key.clone()));
+        match (target.get_mut(&key), value) {
+            (None, value) => {
+                origins.insert(
+                    key_path.clone(),
+                    Origin {
+                        file: file.to_path_buf(),
+                        path: key_path,
+                    },
+                );
+                target.insert(key, value);
+            }
+            (Some(toml::Value::Table(target)), toml::Value::Table(source)) => {
+                merge_table(target, source, &key_path, file, origins)?
+            }
+            (Some(toml::Value::Array(target)), toml::Value::Array(source))
+                if is_table_array(target) && is_table_array(&source) =>
+            {
+                for index in 0..source.len() {
+                    let mut merged_path = key_path.clone();
+                    merged_path.push(PathSegment::Index(target.len() + index));
+                    let mut path = key_path.clone();
+                    path.push(PathSegment::Index(index));
+                    origins.insert(
+                        merged_path,
+                        Origin {
+                            file: file.to_path_buf(),
+                            path,
+                        },
+                    );
+                }
+                target.extend(source)
+            }
+            (Some(existing), value) => {
+                if *existing != value {
+                    let origin = (1..=key_path.len())
+                        .rev()
+                        .find_map(|length| origins.get(&key_path[..length]));
+                    bail!(
+                        "conflicting values for `{}` in configuration files {} and {}",
+                        format_path(&key_path),
+                        origin.map_or_else(|| "<unknown>".into(), |o| o.file.display().to_string()),
+                        file.display()
+                    );
+                }
             }
-            Err(e) => println!("{:?}", e),
         }
     }
 
-    Ok(toml::from_str(&contents)?)
+    Ok(())
+}
+
+/// Build a [`ConfigError`] for the span of the file, suggesting the closest valid key or variant
+/// if the error names the expected ones.
+fn config_error(file: &Path, contents: &str, span: Range<usize>, message: String) -> ConfigError {
+    let start = span.start.min(contents.len());
+    let line_start = contents[..start].rfind('\n').map_or(0, |index| index + 1);
+    let line_end = contents[start..]
+        .find('\n')
+        .map_or(contents.len(), |index| start + index);
+    let line = contents[..start].matches('\n').count() + 1;
+    let column = contents[line_start..start].chars().count() + 1;
+
+    let line_contents = contents[line_start..line_end].trim_end_matches('\r');
+    let underline_length = contents[start..span.end.clamp(start, line_end)]
+        .chars()
+        .count()
+        .max(1);
+    let gutter = " ".repeat(line.to_string().len());
+    let snippet = format!(
+        "{gutter} |\n{line} | {line_contents}\n{gutter} | {padding}{underline}",
+        gutter = gutter,
+        line = line,
+        line_contents = line_contents,
+        padding = " ".repeat(column - 1),
+        underline = "^".repeat(underline_length),
+    );
+
+    let message = message.lines().next().unwrap_or_default().to_owned();
+    let suggestion = suggest(&message);
+
+    ConfigError {
+        file: file.display().to_string(),
+        line,
+        column,
+        message,
+        snippet,
+        suggestion,
+    }
+}
+
+/// Build a [`ConfigError`] for a one-based line and column of the file.
+fn config_error_at(
+    file: &Path,
+    contents: &str,
+    line: usize,
+    column: usize,
+    message: String,
+) -> failure::Error {
+    let line_offset: usize = contents
+        .split_inclusive('\n')
+        .take(line.saturating_sub(1))
+        .map(str::len)
+        .sum();
+    let offset = contents[line_offset..]
+        .char_indices()
+        .nth(column.saturating_sub(1))
+        .map_or(contents.len(), |(index, _)| line_offset + index);
+    // The messages of serde_json and serde_yaml repeat the location, which is already reported.
+    let message = match message.rfind(" at line ") {
+        Some(index) => message[..index].to_owned(),
+        None => message,
+    };
+
+    config_error(file, contents, offset..offset + 1, message).into()
+}
+
+/// Find the closest expected value for an unknown field or variant.
+///
+/// The error messages generated by serde quote the unknown value first, followed by the expected
+/// values, e.g. ``unknown field `expose_ports`, expected one of `network`, `expose_port` ``.
+fn suggest(message: &str) -> Option<String> {
+    if !message.starts_with("unknown field") && !message.starts_with("unknown variant") {
+        return None;
+    }
+    let mut quoted = message.split('`').skip(1).step_by(2);
+    let unknown = quoted.next()?;
+
+    quoted
+        .map(|expected| (strsim::levenshtein(unknown, expected), expected))
+        .filter(|(distance, expected)| *distance <= 2.max(expected.len() / 3))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, expected)| expected.to_owned())
+}
+
+fn is_table_array(array: &[toml::Value]) -> bool {
+    array.iter().all(toml::Value::is_table)
+}
+
+fn include_patterns(include: toml::Value, file: &Path) -> Result<Vec<String>> {
+    let patterns = match include {
+        toml::Value::String(pattern) => vec![pattern],
+        toml::Value::Array(patterns) => patterns
+            .into_iter()
+            .map(|pattern| match pattern {
+                toml::Value::String(pattern) => Ok(pattern),
+                _ => Err(format_err!(
+                    "`include` in configuration file {} has to be a list of paths",
+                    file.display()
+                )),
+            })
+            .collect::<Result<_>>()?,
+        _ => bail!(
+            "`include` in configuration file {} has to be a list of paths",
+            file.display()
+        ),
+    };
+
+    Ok(patterns)
+}
+
+fn glob_sorted(pattern: &str) -> Result<Vec<PathBuf>> {
+    let mut paths = glob(pattern)?.collect::<std::result::Result<Vec<_>, _>>()?;
+    paths.sort();
+
+    Ok(paths)
+}
+
+fn read_file(file: &Path) -> Result<String> {
+    let mut contents = String::new();
+    let mut reader = BufReader::new(
+        File::open(file)
+            .with_context(|_| format!("failed to open configuration file {}", file.display()))?,
+    );
+    reader.read_to_string(&mut contents)?;
+
+    Ok(contents)
 }
 
 /// An extension trait for `Future` allowing synchronized execution of the future.
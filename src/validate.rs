@@ -0,0 +1,757 @@
+// Copyright Pit Kleyersburg <pitkley@googlemail.com>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+//! Semantic validation of the configuration.
+//!
+//! The validation is performed offline, i.e. it neither requires Docker nor the firewall, and
+//! complements the checks performed while deserializing the configuration.
+
+use crate::{
+    process::{Process, TypedMatches},
+    types::*,
+    FirewallBackend,
+};
+use std::{
+    fmt,
+    net::{Ipv4Addr, Ipv6Addr},
+};
+use strum::Display;
+
+/// Maximum length of a network interface name, excluding the terminating null-byte (`IFNAMSIZ`).
+const MAX_INTERFACE_NAME_LENGTH: usize = 15;
+
+/// Severity of a finding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Display)]
+#[strum(serialize_all = "snake_case")]
+pub enum Severity {
+    /// The configuration works, but likely not as intended.
+    Warning,
+    /// The configuration can't be applied as is.
+    Error,
+}
+
+/// A single problem found in the configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    /// Severity of the finding.
+    pub severity: Severity,
+    /// Path of the offending configuration value, e.g. `wider_world_to_container.rules[1]`.
+    pub path: String,
+    /// Description of the problem.
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}: {}", self.severity, self.path, self.message)
+    }
+}
+
+/// Report of all problems found in the configuration.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+    /// The findings, in the order of the configuration sections they were found in.
+    pub findings: Vec<Finding>,
+}
+
+impl Report {
+    /// Check if any finding is an error.
+    pub fn has_errors(&self) -> bool {
+        self.findings
+            .iter()
+            .any(|finding| finding.severity == Severity::Error)
+    }
+
+    fn add(&mut self, severity: Severity, path: impl Into<String>, message: impl Into<String>) {
+        self.findings.push(Finding {
+            severity,
+            path: path.into(),
+            message: message.into(),
+        });
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for finding in &self.findings {
+            writeln!(f, "{}", finding)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Validate the configuration semantically, returning all problems found.
+pub fn validate<B>(dfw: &DFW<B>) -> Report
+where
+    B: FirewallBackend,
+    DFW<B>: Process<B>,
+{
+    let mut report = Report::default();
+
+    if let Some(interfaces) = &dfw.global_defaults.external_network_interfaces {
+        for (index, interface) in interfaces.iter().enumerate() {
+            validate_interface(
+                &mut report,
+                &format!("global_defaults.external_network_interfaces[{}]", index),
+                interface,
+            );
+        }
+    }
+
+    if let Some(rules) = dfw
+        .container_to_container
+        .as_ref()
+        .and_then(|section| section.rules.as_ref())
+    {
+        for (index, rule) in rules.iter().enumerate() {
+            let path = format!("container_to_container.rules[{}]", index);
+            validate_selector(&mut report, &path, "src_selector", &rule.src_selector);
+            validate_selector(&mut report, &path, "dst_selector", &rule.dst_selector);
+            validate_destination_cidrs(&mut report, &path, &rule.dst_cidr_v4, &rule.dst_cidr_v6);
+            validate_typed_matches(&mut report, &path, &rule.typed_matches());
+            if let Some(earlier) = rules[..index].iter().position(|earlier| {
+                earlier.network == rule.network
+                    && covers(&earlier.src_container, &rule.src_container)
+                    && covers(&earlier.src_selector, &rule.src_selector)
+                    && covers(&earlier.src_service, &rule.src_service)
+                    && covers(&earlier.src_swarm_service, &rule.src_swarm_service)
+                    && covers(&earlier.dst_container, &rule.dst_container)
+                    && covers(&earlier.dst_selector, &rule.dst_selector)
+                    && covers(&earlier.dst_service, &rule.dst_service)
+                    && covers(&earlier.dst_swarm_service, &rule.dst_swarm_service)
+                    && covers_typed_matches(&earlier.typed_matches(), &rule.typed_matches())
+                    && covers_ip_version(
+                        earlier.typed_matches().ip_version(),
+                        rule.typed_matches().ip_version(),
+                    )
+                    && covers(&earlier.matches, &rule.matches)
+            }) {
+                report_shadowed(&mut report, &path, "container_to_container", earlier);
+            }
+        }
+    }
+
+    if let Some(rules) = dfw
+        .container_to_wider_world
+        .as_ref()
+        .and_then(|section| section.rules.as_ref())
+    {
+        for (index, rule) in rules.iter().enumerate() {
+            let path = format!("container_to_wider_world.rules[{}]", index);
+            validate_selector(&mut report, &path, "src_selector", &rule.src_selector);
+            validate_destination_cidrs(&mut report, &path, &rule.dst_cidr_v4, &rule.dst_cidr_v6);
+            validate_typed_matches(&mut report, &path, &rule.typed_matches());
+            if let Some(interface) = &rule.external_network_interface {
+                validate_interface(
+                    &mut report,
+                    &format!("{}.external_network_interface", path),
+                    interface,
+                );
+            }
+            if let Some(earlier) = rules[..index].iter().position(|earlier| {
+                covers(&earlier.network, &rule.network)
+                    && covers(&earlier.src_container, &rule.src_container)
+                    && covers(&earlier.src_selector, &rule.src_selector)
+                    && covers(&earlier.src_service, &rule.src_service)
+                    && covers(&earlier.src_swarm_service, &rule.src_swarm_service)
+                    && covers(
+                        &earlier.external_network_interface,
+                        &rule.external_network_interface,
+                    )
+                    && covers_typed_matches(&earlier.typed_matches(), &rule.typed_matches())
+                    && covers(&earlier.matches, &rule.matches)
+            }) {
+                report_shadowed(&mut report, &path, "container_to_wider_world", earlier);
+            }
+        }
+    }
+
+    if let Some(rules) = dfw
+        .container_to_host
+        .as_ref()
+        .and_then(|section| section.rules.as_ref())
+    {
+        for (index, rule) in rules.iter().enumerate() {
+            let path = format!("container_to_host.rules[{}]", index);
+            validate_selector(&mut report, &path, "src_selector", &rule.src_selector);
+            validate_destination_cidrs(&mut report, &path, &rule.dst_cidr_v4, &rule.dst_cidr_v6);
+            validate_typed_matches(&mut report, &path, &rule.typed_matches());
+            if let Some(earlier) = rules[..index].iter().position(|earlier| {
+                earlier.network == rule.network
+                    && covers(&earlier.src_container, &rule.src_container)
+                    && covers(&earlier.src_selector, &rule.src_selector)
+                    && covers(&earlier.src_service, &rule.src_service)
+                    && covers(&earlier.src_swarm_service, &rule.src_swarm_service)
+                    && covers_typed_matches(&earlier.typed_matches(), &rule.typed_matches())
+                    && covers_ip_version(
+                        earlier.typed_matches().ip_version(),
+                        rule.typed_matches().ip_version(),
+                    )
+                    && covers(&earlier.matches, &rule.matches)
+            }) {
+                report_shadowed(&mut report, &path, "container_to_host", earlier);
+            }
+        }
+    }
+
+    if let Some(rules) = dfw
+        .wider_world_to_container
+        .as_ref()
+        .and_then(|section| section.rules.as_ref())
+    {
+        validate_wider_world_to_container(&mut report, rules);
+    }
+
+    if let Some(rules) = dfw
+        .container_dnat
+        .as_ref()
+        .and_then(|section| section.rules.as_ref())
+    {
+        for (index, rule) in rules.iter().enumerate() {
+            let path = format!("container_dnat.rules[{}]", index);
+            validate_selector(&mut report, &path, "src_selector", &rule.src_selector);
+            validate_selector(&mut report, &path, "dst_selector", &rule.dst_selector);
+            if rule.src_network.is_some()
+                && rule.src_container.is_none()
+                && rule.src_selector.is_none()
+                && rule.src_service.is_none()
+                && rule.src_swarm_service.is_none()
+            {
+                report.add(
+                    Severity::Error,
+                    &path,
+                    "`src_network` is set, but neither `src_container`, `src_selector`, \
+                     `src_service` nor `src_swarm_service` is",
+                );
+            }
+        }
+    }
+
+    report
+}
+
+fn validate_wider_world_to_container(report: &mut Report, rules: &[WiderWorldToContainerRule]) {
+    // Every exposed host port, identified by the index of the rule exposing it, the interface it
+    // is exposed on, its family and its inclusive port range.
+    type ExposedPort<'a> = (usize, &'a Option<String>, &'a str, (u16, u16));
+    let mut exposed_ports: Vec<ExposedPort> = Vec::new();
+
+    for (index, rule) in rules.iter().enumerate() {
+        let path = format!("wider_world_to_container.rules[{}]", index);
+        validate_selector(report, &path, "dst_selector", &rule.dst_selector);
+
+        if let Some(interface) = &rule.external_network_interface {
+            validate_interface(
+                report,
+                &format!("{}.external_network_interface", path),
+                interface,
+            );
+        }
+        for cidr in rule.source_cidr_v4.iter().flatten() {
+            if !is_valid_cidr_v4(cidr) {
+                report.add(
+                    Severity::Error,
+                    format!("{}.source_cidr_v4", path),
+                    format!("`{}` is not a valid IPv4 CIDR", cidr),
+                );
+            }
+        }
+        for cidr in rule.source_cidr_v6.iter().flatten() {
+            if !is_valid_cidr_v6(cidr) {
+                report.add(
+                    Severity::Error,
+                    format!("{}.source_cidr_v6", path),
+                    format!("`{}` is not a valid IPv6 CIDR", cidr),
+                );
+            }
+        }
+
+        for (port_index, expose_port) in rule.expose_port.iter().enumerate() {
+            // The container port range is derived from the host port range, validating both.
+            let host_ports = match expose_port
+                .container_port_range()
+                .and_then(|_| expose_port.host_port_range())
+            {
+                Ok(host_ports) => host_ports,
+                Err(error) => {
+                    report.add(
+                        Severity::Error,
+                        format!("{}.expose_port[{}]", path, port_index),
+                        error,
+                    );
+                    continue;
+                }
+            };
+            for family in expose_port.families() {
+                let conflict = exposed_ports.iter().find(
+                    |(other_index, other_interface, other_family, other_ports)| {
+                        let other = &rules[*other_index];
+                        *other_family == family
+                            && (other_interface.is_none()
+                                || rule.external_network_interface.is_none()
+                                || **other_interface == rule.external_network_interface)
+                            && other_ports.0 <= host_ports.1
+                            && host_ports.0 <= other_ports.1
+                            && (other.network != rule.network
+                                || other.dst_container != rule.dst_container
+                                || other.dst_selector != rule.dst_selector
+                                || other.dst_service != rule.dst_service
+                                || other.dst_swarm_service != rule.dst_swarm_service)
+                    },
+                );
+                if let Some((other_index, ..)) = conflict {
+                    report.add(
+                        Severity::Error,
+                        &path,
+                        format!(
+                            "host port {}/{} is already exposed by \
+                             wider_world_to_container.rules[{}]",
+                            format_ports(host_ports),
+                            family,
+                            other_index
+                        ),
+                    );
+                }
+                exposed_ports.push((index, &rule.external_network_interface, family, host_ports));
+            }
+        }
+    }
+}
+
+fn validate_selector(
+    report: &mut Report,
+    path: &str,
+    field: &str,
+    selector: &Option<ContainerSelector>,
+) {
+    if selector
+        .as_ref()
+        .is_some_and(|selector| selector.0.is_empty())
+    {
+        report.add(
+            Severity::Error,
+            format!("{}.{}", path, field),
+            "selector is empty and would match every container",
+        );
+    }
+}
+
+fn validate_interface(report: &mut Report, path: &str, interface: &str) {
+    if interface.len() > MAX_INTERFACE_NAME_LENGTH {
+        report.add(
+            Severity::Error,
+            path,
+            format!(
+                "interface name `{}` is longer than {} characters",
+                interface, MAX_INTERFACE_NAME_LENGTH
+            ),
+        );
+    }
+}
+
+fn validate_destination_cidrs(
+    report: &mut Report,
+    path: &str,
+    dst_cidr_v4: &Option<Vec<String>>,
+    dst_cidr_v6: &Option<Vec<String>>,
+) {
+    for cidr in dst_cidr_v4.iter().flatten() {
+        if !is_valid_cidr_v4(cidr) {
+            report.add(
+                Severity::Error,
+                format!("{}.dst_cidr_v4", path),
+                format!("`{}` is not a valid IPv4 CIDR", cidr),
+            );
+        }
+    }
+    for cidr in dst_cidr_v6.iter().flatten() {
+        if !is_valid_cidr_v6(cidr) {
+            report.add(
+                Severity::Error,
+                format!("{}.dst_cidr_v6", path),
+                format!("`{}` is not a valid IPv6 CIDR", cidr),
+            );
+        }
+    }
+}
+
+fn validate_typed_matches(report: &mut Report, path: &str, matches: &TypedMatches) {
+    if let Err(error) = matches.validate() {
+        report.add(Severity::Error, path, error.to_string());
+    }
+}
+
+fn report_shadowed(report: &mut Report, path: &str, section: &str, earlier: usize) {
+    report.add(
+        Severity::Warning,
+        path,
+        format!(
+            "rule is shadowed by {}.rules[{}] and will never match",
+            section, earlier
+        ),
+    );
+}
+
+/// Check if a match of an earlier rule covers the match of a later rule, i.e. the earlier rule
+/// either doesn't restrict the match at all or restricts it identically.
+fn covers<T: PartialEq>(earlier: &Option<T>, later: &Option<T>) -> bool {
+    earlier.is_none() || earlier == later
+}
+
+fn covers_ip_version(earlier: IpVersion, later: IpVersion) -> bool {
+    earlier == IpVersion::Both || earlier == later
+}
+
+fn covers_typed_matches(earlier: &TypedMatches, later: &TypedMatches) -> bool {
+    covers(&effective_protocol(earlier), &effective_protocol(later))
+        && covers(&earlier.src_port, &later.src_port)
+        && covers(&earlier.dst_port, &later.dst_port)
+        && covers(&earlier.dst_cidr_v4, &later.dst_cidr_v4)
+        && covers(&earlier.dst_cidr_v6, &later.dst_cidr_v6)
+        && covers(&earlier.icmp_type, &later.icmp_type)
+        && covers(&earlier.ct_state, &later.ct_state)
+}
+
+/// The protocol a rule matches, ports are matched for `tcp` if no protocol is specified.
+fn effective_protocol(matches: &TypedMatches) -> Option<Protocol> {
+    match matches.protocol {
+        None if matches.src_port.is_some() || matches.dst_port.is_some() => Some(Protocol::Tcp),
+        protocol => protocol,
+    }
+}
+
+fn format_ports((start, end): (u16, u16)) -> String {
+    if start == end {
+        start.to_string()
+    } else {
+        format!("{}-{}", start, end)
+    }
+}
+
+fn is_valid_cidr_v4(cidr: &str) -> bool {
+    is_valid_cidr::<Ipv4Addr>(cidr, 32)
+}
+
+fn is_valid_cidr_v6(cidr: &str) -> bool {
+    is_valid_cidr::<Ipv6Addr>(cidr, 128)
+}
+
+fn is_valid_cidr<A: std::str::FromStr>(cidr: &str, max_prefix_length: u8) -> bool {
+    let (address, prefix_length) = match cidr.split_once('/') {
+        Some((address, prefix_length)) => (address, Some(prefix_length)),
+        None => (cidr, None),
+    };
+
+    address.parse::<A>().is_ok()
+        && match prefix_length {
+            Some(prefix_length) => prefix_length
+                .parse::<u8>()
+                .is_ok_and(|prefix_length| prefix_length <= max_prefix_length),
+            None => true,
+        }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::nftables::Nftables;
+
+    fn findings(config: &str) -> Vec<(Severity, String)> {
+        let dfw: DFW<Nftables> = toml::from_str(config).unwrap();
+        validate(&dfw)
+            .findings
+            .into_iter()
+            .map(|finding| (finding.severity, finding.path))
+            .collect()
+    }
+
+    #[test]
+    fn valid_config() {
+        let config = r#"
+            [global_defaults]
+            external_network_interfaces = "eth0"
+
+            [[wider_world_to_container.rules]]
+            network = "network"
+            dst_container = "a"
+            expose_port = [80, 443]
+            source_cidr_v4 = "192.0.2.0/24"
+
+            [[wider_world_to_container.rules]]
+            network = "network"
+            dst_container = "b"
+            expose_port = "80/udp"
+            "#;
+
+        assert_eq!(findings(config), vec![]);
+    }
+
+    #[test]
+    fn invalid_source_cidrs() {
+        let config = r#"
+            [[wider_world_to_container.rules]]
+            network = "network"
+            dst_container = "a"
+            expose_port = 80
+            source_cidr_v4 = ["192.0.2.0/24", "192.0.2.0/40"]
+            source_cidr_v6 = "2001:db8::/g"
+            "#;
+
+        assert_eq!(
+            findings(config),
+            vec![
+                (
+                    Severity::Error,
+                    "wider_world_to_container.rules[0].source_cidr_v4".to_owned()
+                ),
+                (
+                    Severity::Error,
+                    "wider_world_to_container.rules[0].source_cidr_v6".to_owned()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn host_port_exposed_twice() {
+        let config = r#"
+            [[wider_world_to_container.rules]]
+            network = "network"
+            dst_container = "a"
+            expose_port = "8000-8010"
+
+            [[wider_world_to_container.rules]]
+            network = "network"
+            dst_container = "a"
+            expose_port = 8005
+            source_cidr_v4 = "192.0.2.0/24"
+
+            [[wider_world_to_container.rules]]
+            network = "network"
+            dst_container = "b"
+            expose_port = 8010
+            "#;
+
+        assert_eq!(
+            findings(config),
+            vec![(
+                Severity::Error,
+                "wider_world_to_container.rules[2]".to_owned()
+            )]
+        );
+    }
+
+    #[test]
+    fn invalid_host_port_range() {
+        let config = r#"
+            [[wider_world_to_container.rules]]
+            network = "network"
+            dst_container = "a"
+            expose_port = [80, { host_port = 8010, host_port_end = 8000 }]
+            "#;
+
+        assert_eq!(
+            findings(config),
+            vec![(
+                Severity::Error,
+                "wider_world_to_container.rules[0].expose_port[1]".to_owned()
+            )]
+        );
+    }
+
+    #[test]
+    fn dnat_without_source_container() {
+        let config = r#"
+            [[container_dnat.rules]]
+            src_network = "network"
+            dst_network = "network"
+            dst_container = "a"
+            expose_port = 80
+            "#;
+
+        assert_eq!(
+            findings(config),
+            vec![(Severity::Error, "container_dnat.rules[0]".to_owned())]
+        );
+    }
+
+    #[test]
+    fn interface_name_too_long() {
+        let config = r#"
+            [global_defaults]
+            external_network_interfaces = ["eth0", "an-interface-name"]
+            "#;
+
+        assert_eq!(
+            findings(config),
+            vec![(
+                Severity::Error,
+                "global_defaults.external_network_interfaces[1]".to_owned()
+            )]
+        );
+    }
+
+    #[test]
+    fn shadowed_rules() {
+        let config = r#"
+            [container_to_container]
+            default_policy = "drop"
+
+            [[container_to_container.rules]]
+            network = "network"
+            src_container = "a"
+            verdict = "accept"
+
+            [[container_to_container.rules]]
+            network = "network"
+            src_container = "a"
+            dst_container = "b"
+            protocol = "tcp"
+            verdict = "drop"
+
+            [[container_to_container.rules]]
+            network = "network"
+            src_container = "b"
+            verdict = "drop"
+
+            [[container_to_container.rules]]
+            network = "network"
+            src_container = "c"
+            dst_port = 53
+            verdict = "accept"
+
+            [[container_to_container.rules]]
+            network = "network"
+            src_container = "c"
+            protocol = "udp"
+            dst_port = 53
+            verdict = "accept"
+            "#;
+
+        // Ports without a protocol are matched for `tcp`, the `udp` rule isn't shadowed.
+        assert_eq!(
+            findings(config),
+            vec![(
+                Severity::Warning,
+                "container_to_container.rules[1]".to_owned()
+            )]
+        );
+    }
+
+    #[test]
+    fn shadowed_rules_ip_version() {
+        let config = r#"
+            [container_to_host]
+            default_policy = "drop"
+
+            [[container_to_host.rules]]
+            network = "network"
+            src_container = "a"
+            ip_version = "v4"
+            verdict = "accept"
+
+            [[container_to_host.rules]]
+            network = "network"
+            src_container = "a"
+            verdict = "drop"
+
+            [[container_to_host.rules]]
+            network = "network"
+            src_container = "a"
+            ip_version = "v6"
+            verdict = "drop"
+            "#;
+
+        assert_eq!(
+            findings(config),
+            vec![(Severity::Warning, "container_to_host.rules[2]".to_owned())]
+        );
+    }
+
+    #[test]
+    fn conflicting_ip_version() {
+        let config = r#"
+            [container_to_host]
+            default_policy = "drop"
+
+            [[container_to_host.rules]]
+            network = "network"
+            protocol = "icmp"
+            ip_version = "v6"
+            verdict = "accept"
+
+            [[container_to_host.rules]]
+            network = "network"
+            protocol = "icmpv6"
+            verdict = "accept"
+
+            [[container_to_host.rules]]
+            network = "network"
+            protocol = "icmpv6"
+            ip_version = "v6"
+            verdict = "drop"
+            "#;
+
+        // The ICMPv6 rule is only generated for IPv6, shadowing the last rule.
+        assert_eq!(
+            findings(config),
+            vec![
+                (Severity::Error, "container_to_host.rules[0]".to_owned()),
+                (Severity::Warning, "container_to_host.rules[2]".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_selectors() {
+        let config = r#"
+            [container_to_container]
+            default_policy = "drop"
+
+            [[container_to_container.rules]]
+            network = "network"
+            src_selector = {}
+            dst_selector = { app = "db" }
+            verdict = "accept"
+
+            [[wider_world_to_container.rules]]
+            network = "network"
+            dst_selector = {}
+            expose_port = 80
+            "#;
+
+        assert_eq!(
+            findings(config),
+            vec![
+                (
+                    Severity::Error,
+                    "container_to_container.rules[0].src_selector".to_owned()
+                ),
+                (
+                    Severity::Error,
+                    "wider_world_to_container.rules[0].dst_selector".to_owned()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn cidrs() {
+        assert!(is_valid_cidr_v4("192.0.2.0/24"));
+        assert!(is_valid_cidr_v4("192.0.2.1"));
+        assert!(!is_valid_cidr_v4("192.0.2.0/33"));
+        assert!(!is_valid_cidr_v4("192.0.2/24"));
+        assert!(!is_valid_cidr_v4("2001:db8::/32"));
+        assert!(is_valid_cidr_v6("2001:db8::/32"));
+        assert!(is_valid_cidr_v6("::1"));
+        assert!(!is_valid_cidr_v6("2001:db8::/129"));
+        assert!(!is_valid_cidr_v6("192.0.2.0/24"));
+    }
+}
@@ -17,7 +17,10 @@ use common::*;
 use dfw::{
     iptables::{Iptables, IptablesRuleDiscriminants},
     nftables::Nftables,
-    process::{ContainerFilter, Process, ProcessContext, ProcessingOptions},
+    process::{
+        ContainerFilter, OutputFormat, Process, ProcessContext, ProcessingOptions, RulesetOutput,
+        Runtime,
+    },
     types::*,
     util::FutureExt,
     FirewallBackend,
@@ -36,6 +39,11 @@ use std::{
 
 static PROCESSING_OPTIONS: ProcessingOptions = ProcessingOptions {
     container_filter: ContainerFilter::Running,
+    runtime: Runtime::Docker,
+    dry_run_output: RulesetOutput {
+        file: None,
+        format: OutputFormat::Text,
+    },
 };
 
 fn logger() -> Logger {
@@ -184,7 +192,7 @@ fn test_backend<B: FirewallBackend, F: FnOnce(&DFW<B>, ProcessContext<B>) -> ()>
         &project_name,
         || {
             let dfw =
-                ProcessContext::new(&docker, &toml, &PROCESSING_OPTIONS, &logger, true).unwrap();
+                ProcessContext::new(&*docker, &toml, &PROCESSING_OPTIONS, &logger, true).unwrap();
 
             // Test if container is available
             let container_name = format!("{}_a_1", project_name);
@@ -399,6 +407,7 @@ dfw_tests!(
     "05";
     "06";
     "07";
+    "08";
     "ctc-network-policies";
 
     R F "001_gh_166_01" "001-gh-166/01";
@@ -0,0 +1,115 @@
+// Copyright Pit Kleyersburg <pitkley@googlemail.com>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+//! Stub of the Docker API, replaying recorded responses.
+//!
+//! The recorded responses are stored as a JSON object, mapping the method and the path of a
+//! request (without the API version and the query) to the body of the response, e.g.
+//! `"GET /networks/<id>"`. Requests without a recorded response are answered with a 404.
+
+use bollard::{Docker, API_DEFAULT_VERSION};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap as Map,
+    fs::File,
+    io::{prelude::*, BufReader},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::Arc,
+    thread,
+};
+
+pub struct DockerApi {
+    address: SocketAddr,
+}
+
+impl DockerApi {
+    /// Serve the recorded responses of the given file on a random local port.
+    pub fn serve(recording: &str) -> DockerApi {
+        let responses: Map<String, Value> =
+            serde_json::from_reader(BufReader::new(File::open(recording).unwrap())).unwrap();
+        let responses = Arc::new(responses);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let responses = Arc::clone(&responses);
+                thread::spawn(move || respond(stream, &responses));
+            }
+        });
+
+        DockerApi { address }
+    }
+
+    /// Create a Docker client connected to the stub.
+    pub fn docker(&self) -> Docker {
+        Docker::connect_with_http(&format!("http://{}", self.address), 10, API_DEFAULT_VERSION)
+            .unwrap()
+    }
+}
+
+fn respond(mut stream: TcpStream, responses: &Map<String, Value>) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line).is_err() {
+        return;
+    }
+    // The requests of the client don't have a body, the headers can be skipped.
+    loop {
+        let mut header = String::new();
+        match reader.read_line(&mut header) {
+            Ok(0) | Err(_) => break,
+            Ok(_) if header == "\r\n" => break,
+            Ok(_) => {}
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let path = parts
+        .next()
+        .unwrap_or_default()
+        .split('?')
+        .next()
+        .unwrap_or_default();
+    let key = format!("{} {}", method, strip_version(path));
+    let (status, body) = match responses.get(&key) {
+        Some(body) => ("200 OK", body.to_string()),
+        None => (
+            "404 Not Found",
+            json!({ "message": format!("no recorded response for `{}`", key) }).to_string(),
+        ),
+    };
+
+    let _ = write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+}
+
+/// Strip the API version prefix (e.g. `/v1.43`) from the path.
+fn strip_version(path: &str) -> &str {
+    match path.strip_prefix("/v") {
+        Some(rest) => match rest.find('/') {
+            Some(index)
+                if rest[..index]
+                    .chars()
+                    .all(|c| c.is_ascii_digit() || c == '.') =>
+            {
+                &rest[index..]
+            }
+            _ => path,
+        },
+        None => path,
+    }
+}
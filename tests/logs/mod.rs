@@ -22,6 +22,7 @@ lazy_static! {
     static ref PATTERNS: Map<&'static str, &'static str> = {
         let mut m = Map::new();
         m.insert("ip", r"\d{1,3}\.\d{1,3}\.\d{1,3}\.\d{1,3}");
+        m.insert("ip6", r"[0-9a-f:]+");
         m.insert("bridge", r"br-[a-f0-9]{12}");
         m
     };
@@ -0,0 +1,140 @@
+// Copyright Pit Kleyersburg <pitkley@googlemail.com>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+//! Processing tests for Podman hosts, running against recorded responses of the Docker-compatible
+//! API of Podman.
+
+mod common;
+mod docker_api;
+mod logs;
+
+use common::*;
+use dfw::{
+    iptables::{Iptables, IptablesRuleDiscriminants},
+    nftables::Nftables,
+    process::{Process, ProcessContext, ProcessingOptions, Runtime},
+    types::*,
+    FirewallBackend,
+};
+use docker_api::DockerApi;
+use logs::*;
+use serde::de::DeserializeOwned;
+use slog::{o, Discard, Logger};
+use std::fs;
+
+fn load_config<T: DeserializeOwned>(path: &str, backend: &str) -> T {
+    let mut contents = String::new();
+    for file in &["conf.toml", &format!("{}/conf.toml", backend)] {
+        contents.push_str(
+            &fs::read_to_string(resource(&format!("podman/{}/{}", path, file)).unwrap()).unwrap(),
+        );
+    }
+    toml::from_str(&contents).unwrap()
+}
+
+fn process<B: FirewallBackend>(path: &str, dfw: &DFW<B>) -> dfw::errors::Result<Vec<B::Rule>>
+where
+    DFW<B>: Process<B>,
+{
+    let docker_api =
+        DockerApi::serve(&resource(&format!("podman/{}/docker-api.json", path)).unwrap());
+    let docker = docker_api.docker();
+    let processing_options = ProcessingOptions {
+        runtime: Runtime::Podman,
+        ..Default::default()
+    };
+    let logger = Logger::root(Discard, o!());
+    let ctx = ProcessContext::new(&docker, dfw, &processing_options, &logger, true)?;
+
+    Ok(Process::<B>::process(dfw, &ctx)?.unwrap_or_default())
+}
+
+fn loglines(commands: Vec<String>) -> Vec<LogLine> {
+    commands
+        .into_iter()
+        .map(|command| LogLine {
+            command,
+            regex: false,
+            eval: None,
+        })
+        .collect()
+}
+
+fn test_nftables(path: &str) {
+    let dfw: DFW<Nftables> = load_config(path, "nftables");
+    let rules = process(path, &dfw).unwrap();
+
+    let expected = load_loglines(
+        &resource(&format!("podman/{}/nftables/expected-nftables.txt", path)).unwrap(),
+    );
+    assert_eq!(
+        loglines(rules.iter().map(ToString::to_string).collect()),
+        expected
+    );
+}
+
+fn test_iptables(path: &str) {
+    let dfw: DFW<Iptables> = load_config(path, "iptables");
+    let rules = process(path, &dfw).unwrap();
+
+    for (rule_discriminant, file) in &[
+        (IptablesRuleDiscriminants::V4, "expected-iptables-v4.txt"),
+        (IptablesRuleDiscriminants::V6, "expected-iptables-v6.txt"),
+    ] {
+        let expected =
+            load_loglines(&resource(&format!("podman/{}/iptables/{}", path, file)).unwrap());
+        assert_eq!(
+            loglines(Iptables::get_rules(rules.clone(), *rule_discriminant)),
+            expected
+        );
+    }
+}
+
+#[test]
+fn test_nftables_01() {
+    test_nftables("01");
+}
+
+#[test]
+fn test_iptables_01() {
+    test_iptables("01");
+}
+
+#[test]
+fn default_network_bridge() {
+    let dfw: DFW<Nftables> = toml::from_str(
+        r#"
+        [global_defaults]
+        external_network_interfaces = "eni"
+
+        [container_to_wider_world]
+        default_policy = "accept"
+        "#,
+    )
+    .unwrap();
+
+    // Without the Podman runtime, the bridge of the default network is assumed to be `docker0`.
+    let docker_api = DockerApi::serve(&resource("podman/01/docker-api.json").unwrap());
+    let docker = docker_api.docker();
+    let logger = Logger::root(Discard, o!());
+    let processing_options = ProcessingOptions::default();
+    let ctx = ProcessContext::new(&docker, &dfw, &processing_options, &logger, true).unwrap();
+    let rules = Process::<Nftables>::process(&dfw, &ctx).unwrap().unwrap();
+    assert!(rules
+        .iter()
+        .any(|rule| rule.to_string().contains("docker0")));
+
+    let rules = process("01", &dfw).unwrap();
+    assert!(rules
+        .iter()
+        .all(|rule| !rule.to_string().contains("docker0")));
+    assert!(rules
+        .iter()
+        .any(|rule| rule.to_string().contains("podman0")));
+}
@@ -0,0 +1,191 @@
+// Copyright Pit Kleyersburg <pitkley@googlemail.com>
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+//! Processing tests running against an in-memory container runtime.
+
+mod common;
+mod docker_api;
+mod logs;
+
+use common::*;
+use dfw::{
+    iptables::Iptables,
+    nftables::Nftables,
+    process::{
+        ContainerFilter, OutputFormat, Process, ProcessContext, ProcessingOptions, RulesetOutput,
+    },
+    runtime::{ContainerRuntime, InMemoryRuntime},
+    types::*,
+    FirewallBackend, Ruleset,
+};
+use docker_api::DockerApi;
+use logs::*;
+use serde::de::DeserializeOwned;
+use slog::{o, Discard, Logger};
+use std::fs::{self, File};
+
+fn runtime(path: &str) -> InMemoryRuntime {
+    InMemoryRuntime::from_json(
+        File::open(resource(&format!("runtime/{}/runtime.json", path)).unwrap()).unwrap(),
+    )
+    .unwrap()
+}
+
+fn load_config<T: DeserializeOwned>(path: &str) -> T {
+    toml::from_str(&fs::read_to_string(resource(path).unwrap()).unwrap()).unwrap()
+}
+
+fn render<B: FirewallBackend>(runtime: &dyn ContainerRuntime, dfw: &DFW<B>) -> Vec<Ruleset>
+where
+    DFW<B>: Process<B>,
+{
+    let processing_options = ProcessingOptions {
+        container_filter: ContainerFilter::Running,
+        ..Default::default()
+    };
+    let logger = Logger::root(Discard, o!());
+
+    ProcessContext::new(runtime, dfw, &processing_options, &logger, true)
+        .unwrap()
+        .render()
+        .unwrap()
+}
+
+fn loglines(commands: Vec<String>) -> Vec<LogLine> {
+    commands
+        .into_iter()
+        .map(|command| LogLine {
+            command,
+            regex: false,
+            eval: None,
+        })
+        .collect()
+}
+
+#[test]
+fn test_nftables_01() {
+    let dfw: DFW<Nftables> = load_config("runtime/01/conf.toml");
+    let rulesets = render(&runtime("01"), &dfw);
+
+    assert_eq!(1, rulesets.len());
+    assert_eq!("nft", rulesets[0].command);
+    assert_eq!(
+        loglines(rulesets[0].lines.clone()),
+        load_loglines(&resource("runtime/01/expected-nftables.txt").unwrap())
+    );
+}
+
+#[test]
+fn test_iptables_01() {
+    let dfw: DFW<Iptables> = load_config("runtime/01/conf.toml");
+    let rulesets = render(&runtime("01"), &dfw);
+
+    assert_eq!(2, rulesets.len());
+    for (ruleset, (command, file)) in rulesets.iter().zip(&[
+        ("iptables-restore", "expected-iptables-v4.txt"),
+        ("ip6tables-restore", "expected-iptables-v6.txt"),
+    ]) {
+        assert_eq!(command, &ruleset.command);
+        assert_eq!(
+            loglines(ruleset.lines.clone()),
+            load_loglines(&resource(&format!("runtime/01/{}", file)).unwrap())
+        );
+    }
+}
+
+#[test]
+fn snapshot_renders_same_rules() {
+    let docker_api = DockerApi::serve(&resource("swarm/01/docker-api.json").unwrap());
+    let docker = docker_api.docker();
+
+    let mut snapshot = Vec::new();
+    InMemoryRuntime::capture(&docker, &ContainerFilter::Running)
+        .unwrap()
+        .to_json(&mut snapshot)
+        .unwrap();
+    let snapshot = InMemoryRuntime::from_json(&*snapshot).unwrap();
+
+    let dfw: DFW<Nftables> = toml::from_str(&format!(
+        "{}{}",
+        fs::read_to_string(resource("swarm/01/conf.toml").unwrap()).unwrap(),
+        fs::read_to_string(resource("swarm/01/nftables/conf.toml").unwrap()).unwrap(),
+    ))
+    .unwrap();
+    assert_eq!(render(&docker, &dfw), render(&snapshot, &dfw));
+}
+
+#[test]
+fn dry_run_writes_rules() {
+    let runtime = runtime("01");
+    let dfw: DFW<Iptables> = load_config("runtime/01/conf.toml");
+    let output = tempfile::NamedTempFile::new().unwrap();
+    let processing_options = ProcessingOptions {
+        container_filter: ContainerFilter::Running,
+        dry_run_output: RulesetOutput {
+            file: Some(output.path().to_owned()),
+            format: OutputFormat::Json,
+        },
+        ..Default::default()
+    };
+    let logger = Logger::root(Discard, o!());
+    let mut ctx = ProcessContext::new(&runtime, &dfw, &processing_options, &logger, true).unwrap();
+    ctx.process().unwrap();
+
+    let written: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(output.path()).unwrap()).unwrap();
+    assert_eq!(
+        serde_json::to_value(ctx.render().unwrap()).unwrap(),
+        written
+    );
+}
+
+#[test]
+fn test_nftables_01_address_sets() {
+    let dfw: DFW<Nftables> = toml::from_str(&format!(
+        "{}\n[backend_defaults]\naddress_sets = true\n",
+        fs::read_to_string(resource("runtime/01/conf.toml").unwrap()).unwrap(),
+    ))
+    .unwrap();
+    let rulesets = render(&runtime("01"), &dfw);
+
+    assert_eq!(1, rulesets.len());
+    assert_eq!(
+        loglines(rulesets[0].lines.clone()),
+        load_loglines(&resource("runtime/01/expected-nftables-address-sets.txt").unwrap())
+    );
+}
+
+#[test]
+fn test_nftables_02_ipv6_only() {
+    let dfw: DFW<Nftables> = load_config("runtime/02/conf.toml");
+    let rulesets = render(&runtime("02"), &dfw);
+
+    assert_eq!(1, rulesets.len());
+    assert_eq!(
+        loglines(rulesets[0].lines.clone()),
+        load_loglines(&resource("runtime/02/expected-nftables.txt").unwrap())
+    );
+}
+
+#[test]
+fn test_iptables_02_ipv6_only() {
+    let dfw: DFW<Iptables> = load_config("runtime/02/conf.toml");
+    let rulesets = render(&runtime("02"), &dfw);
+
+    assert_eq!(2, rulesets.len());
+    for (ruleset, file) in rulesets
+        .iter()
+        .zip(&["expected-iptables-v4.txt", "expected-iptables-v6.txt"])
+    {
+        assert_eq!(
+            loglines(ruleset.lines.clone()),
+            load_loglines(&resource(&format!("runtime/02/{}", file)).unwrap())
+        );
+    }
+}
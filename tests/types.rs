@@ -62,7 +62,21 @@ fn parse_conf_file() {
         rules: Some(vec![ContainerToContainerRule {
             network: "network".to_owned(),
             src_container: Some("src_container".to_owned()),
+            src_selector: None,
+            src_service: None,
+            src_swarm_service: None,
             dst_container: Some("dst_container".to_owned()),
+            dst_selector: None,
+            dst_service: None,
+            dst_swarm_service: None,
+            protocol: None,
+            src_port: None,
+            dst_port: None,
+            dst_cidr_v4: None,
+            dst_cidr_v6: None,
+            icmp_type: None,
+            ct_state: None,
+            ip_version: None,
             matches: Some("FILTER".to_owned()),
             verdict: RuleVerdict::Accept,
         }]),
@@ -72,6 +86,16 @@ fn parse_conf_file() {
         rules: Some(vec![ContainerToWiderWorldRule {
             network: Some("network".to_owned()),
             src_container: Some("src_container".to_owned()),
+            src_selector: None,
+            src_service: None,
+            src_swarm_service: None,
+            protocol: None,
+            src_port: None,
+            dst_port: None,
+            dst_cidr_v4: None,
+            dst_cidr_v6: None,
+            icmp_type: None,
+            ct_state: None,
             matches: Some("FILTER".to_owned()),
             verdict: RuleVerdict::Accept,
             external_network_interface: Some("eni".to_owned()),
@@ -82,6 +106,17 @@ fn parse_conf_file() {
         rules: Some(vec![ContainerToHostRule {
             network: "network".to_owned(),
             src_container: Some("src_container".to_owned()),
+            src_selector: None,
+            src_service: None,
+            src_swarm_service: None,
+            protocol: None,
+            src_port: None,
+            dst_port: None,
+            dst_cidr_v4: None,
+            dst_cidr_v6: None,
+            icmp_type: None,
+            ct_state: None,
+            ip_version: None,
             matches: Some("FILTER".to_owned()),
             verdict: RuleVerdict::Accept,
         }]),
@@ -90,9 +125,13 @@ fn parse_conf_file() {
         rules: Some(vec![
             WiderWorldToContainerRule {
                 network: "network".to_owned(),
-                dst_container: "dst_container".to_owned(),
+                dst_container: Some("dst_container".to_owned()),
+                dst_selector: None,
+                dst_service: None,
+                dst_swarm_service: None,
                 expose_port: vec![ExposePort {
                     host_port: 80,
+                    host_port_end: None,
                     container_port: None,
                     family: "tcp".to_owned(),
                 }],
@@ -103,9 +142,13 @@ fn parse_conf_file() {
             },
             WiderWorldToContainerRule {
                 network: "network".to_owned(),
-                dst_container: "dst_container".to_owned(),
+                dst_container: Some("dst_container".to_owned()),
+                dst_selector: None,
+                dst_service: None,
+                dst_swarm_service: None,
                 expose_port: vec![ExposePort {
                     host_port: 22,
+                    host_port_end: None,
                     container_port: None,
                     family: "tcp".to_owned(),
                 }],
@@ -123,10 +166,17 @@ fn parse_conf_file() {
         rules: Some(vec![ContainerDNATRule {
             src_network: Some("src_network".to_owned()),
             src_container: Some("src_container".to_owned()),
+            src_selector: None,
+            src_service: None,
+            src_swarm_service: None,
             dst_network: "dst_network".to_owned(),
-            dst_container: "dst_container".to_owned(),
+            dst_container: Some("dst_container".to_owned()),
+            dst_selector: None,
+            dst_service: None,
+            dst_swarm_service: None,
             expose_port: vec![ExposePort {
                 host_port: 80,
+                host_port_end: None,
                 container_port: None,
                 family: "tcp".to_owned(),
             }],
@@ -143,6 +193,7 @@ fn parse_conf_file() {
         container_to_host: Some(container_to_host),
         wider_world_to_container: Some(wider_world_to_container),
         container_dnat: Some(container_dnat),
+        compose: None,
     };
 
     let actual = load_file(&resource("conf-file.toml").unwrap()).unwrap();
@@ -166,7 +217,21 @@ fn parse_conf_path() {
         rules: Some(vec![ContainerToContainerRule {
             network: "network".to_owned(),
             src_container: Some("src_container".to_owned()),
+            src_selector: None,
+            src_service: None,
+            src_swarm_service: None,
             dst_container: Some("dst_container".to_owned()),
+            dst_selector: None,
+            dst_service: None,
+            dst_swarm_service: None,
+            protocol: None,
+            src_port: None,
+            dst_port: None,
+            dst_cidr_v4: None,
+            dst_cidr_v6: None,
+            icmp_type: None,
+            ct_state: None,
+            ip_version: None,
             matches: Some("FILTER".to_owned()),
             verdict: RuleVerdict::Accept,
         }]),
@@ -176,6 +241,16 @@ fn parse_conf_path() {
         rules: Some(vec![ContainerToWiderWorldRule {
             network: Some("network".to_owned()),
             src_container: Some("src_container".to_owned()),
+            src_selector: None,
+            src_service: None,
+            src_swarm_service: None,
+            protocol: None,
+            src_port: None,
+            dst_port: None,
+            dst_cidr_v4: None,
+            dst_cidr_v6: None,
+            icmp_type: None,
+            ct_state: None,
             matches: Some("FILTER".to_owned()),
             verdict: RuleVerdict::Accept,
             external_network_interface: Some("eni".to_owned()),
@@ -186,6 +261,17 @@ fn parse_conf_path() {
         rules: Some(vec![ContainerToHostRule {
             network: "network".to_owned(),
             src_container: Some("src_container".to_owned()),
+            src_selector: None,
+            src_service: None,
+            src_swarm_service: None,
+            protocol: None,
+            src_port: None,
+            dst_port: None,
+            dst_cidr_v4: None,
+            dst_cidr_v6: None,
+            icmp_type: None,
+            ct_state: None,
+            ip_version: None,
             matches: Some("FILTER".to_owned()),
             verdict: RuleVerdict::Accept,
         }]),
@@ -194,9 +280,13 @@ fn parse_conf_path() {
         rules: Some(vec![
             WiderWorldToContainerRule {
                 network: "network".to_owned(),
-                dst_container: "dst_container".to_owned(),
+                dst_container: Some("dst_container".to_owned()),
+                dst_selector: None,
+                dst_service: None,
+                dst_swarm_service: None,
                 expose_port: vec![ExposePort {
                     host_port: 80,
+                    host_port_end: None,
                     container_port: None,
                     family: "tcp".to_owned(),
                 }],
@@ -207,9 +297,13 @@ fn parse_conf_path() {
             },
             WiderWorldToContainerRule {
                 network: "network".to_owned(),
-                dst_container: "dst_container".to_owned(),
+                dst_container: Some("dst_container".to_owned()),
+                dst_selector: None,
+                dst_service: None,
+                dst_swarm_service: None,
                 expose_port: vec![ExposePort {
                     host_port: 22,
+                    host_port_end: None,
                     container_port: None,
                     family: "tcp".to_owned(),
                 }],
@@ -227,10 +321,17 @@ fn parse_conf_path() {
         rules: Some(vec![ContainerDNATRule {
             src_network: Some("src_network".to_owned()),
             src_container: Some("src_container".to_owned()),
+            src_selector: None,
+            src_service: None,
+            src_swarm_service: None,
             dst_network: "dst_network".to_owned(),
-            dst_container: "dst_container".to_owned(),
+            dst_container: Some("dst_container".to_owned()),
+            dst_selector: None,
+            dst_service: None,
+            dst_swarm_service: None,
             expose_port: vec![ExposePort {
                 host_port: 80,
+                host_port_end: None,
                 container_port: None,
                 family: "tcp".to_owned(),
             }],
@@ -247,6 +348,7 @@ fn parse_conf_path() {
         container_to_host: Some(container_to_host),
         wider_world_to_container: Some(wider_world_to_container),
         container_dnat: Some(container_dnat),
+        compose: None,
     };
 
     let actual = load_path(&resource("conf_path").unwrap()).unwrap();
@@ -254,6 +356,149 @@ fn parse_conf_path() {
     assert_eq!(expected, actual);
 }
 
+#[test]
+fn parse_conf_file_json() {
+    let expected: DFW<TestBackend> = load_file(&resource("conf-file.toml").unwrap()).unwrap();
+    let actual: DFW<TestBackend> = load_file(&resource("conf-file.json").unwrap()).unwrap();
+
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn parse_conf_file_yaml() {
+    let expected: DFW<TestBackend> = load_file(&resource("conf-file.toml").unwrap()).unwrap();
+    let actual: DFW<TestBackend> = load_file(&resource("conf-file.yaml").unwrap()).unwrap();
+
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn parse_json_fragment() {
+    let fragment = r#"{
+        "network": "network",
+        "dst_container": "dst_container",
+        "expose_port": [80, "443/tcp", { "host_port": 8080, "container_port": 80 }],
+        "external_network_interface": null
+    }"#;
+
+    let expected = vec![
+        ExposePort {
+            host_port: 80,
+            host_port_end: None,
+            container_port: None,
+            family: "tcp".to_owned(),
+        },
+        ExposePort {
+            host_port: 443,
+            host_port_end: None,
+            container_port: None,
+            family: "tcp".to_owned(),
+        },
+        ExposePort {
+            host_port: 8080,
+            host_port_end: None,
+            container_port: Some(80),
+            family: "tcp".to_owned(),
+        },
+    ];
+    let actual: WiderWorldToContainerRule = serde_json::from_str(fragment).unwrap();
+
+    assert_eq!(expected, actual.expose_port);
+    assert_eq!(None, actual.external_network_interface);
+}
+
+#[test]
+fn json_schema() {
+    let schema =
+        serde_json::to_value(dfw::util::config_schema::<DFW<dfw::nftables::Nftables>>()).unwrap();
+
+    for section in &[
+        "global_defaults",
+        "backend_defaults",
+        "container_to_container",
+        "container_to_wider_world",
+        "container_to_host",
+        "wider_world_to_container",
+        "container_dnat",
+    ] {
+        assert!(schema["properties"][section].is_object(), "{}", section);
+    }
+    assert!(schema["definitions"]["NftablesDefaults"].is_object());
+    assert_eq!(false, schema["additionalProperties"]);
+    assert!(schema["properties"]["include"].is_object());
+
+    // Port ranges accept a bare port, e.g. `dst_port = 80`.
+    let port_range = &schema["definitions"]["ContainerToHostRule"]["properties"]["dst_port"];
+    assert_eq!("integer", port_range["anyOf"][0]["type"]);
+}
+
+#[test]
+fn parse_conf_path_merged() {
+    let rule = |src: &str, dst: &str| ContainerToContainerRule {
+        network: "network".to_owned(),
+        src_container: Some(src.to_owned()),
+        src_selector: None,
+        src_service: None,
+        src_swarm_service: None,
+        dst_container: Some(dst.to_owned()),
+        dst_selector: None,
+        dst_service: None,
+        dst_swarm_service: None,
+        protocol: None,
+        src_port: None,
+        dst_port: None,
+        dst_cidr_v4: None,
+        dst_cidr_v6: None,
+        icmp_type: None,
+        ct_state: None,
+        ip_version: None,
+        matches: None,
+        verdict: RuleVerdict::Accept,
+    };
+    let expected = ContainerToContainer {
+        default_policy: ChainPolicy::Drop,
+        same_network_verdict: None,
+        rules: Some(vec![rule("a", "b"), rule("b", "c"), rule("c", "d")]),
+    };
+
+    let actual: DFW<TestBackend> = load_path(&resource("conf_path_merge").unwrap()).unwrap();
+
+    assert_eq!(Some(expected), actual.container_to_container);
+}
+
+#[test]
+fn parse_conf_path_conflict() {
+    let error = load_path::<DFW<TestBackend>>(&resource("conf_path_conflict").unwrap())
+        .unwrap_err()
+        .to_string();
+
+    assert!(error.contains("`container_to_container.default_policy`"));
+    assert!(error.contains("a.toml"));
+    assert!(error.contains("b.toml"));
+}
+
+#[test]
+fn parse_conf_path_error_location() {
+    let error = load_path::<DFW<TestBackend>>(&resource("conf_path_invalid").unwrap()).unwrap_err();
+    let error = error.downcast::<dfw::errors::ConfigError>().unwrap();
+
+    assert!(error.file.ends_with("b.toml"));
+    assert_eq!((error.line, error.column), (5, 1));
+    assert!(error.snippet.contains("expose_ports = 443"));
+    assert_eq!(error.suggestion, Some("expose_port".to_owned()));
+}
+
+#[test]
+fn parse_conf_file_error_location() {
+    let error =
+        load_file::<DFW<TestBackend>>(&resource("conf_path_invalid/b.toml").unwrap()).unwrap_err();
+    let error = error.downcast::<dfw::errors::ConfigError>().unwrap();
+
+    assert!(error.file.ends_with("b.toml"));
+    assert_eq!((error.line, error.column), (5, 1));
+    assert_eq!(error.suggestion, Some("expose_port".to_owned()));
+}
+
 #[test]
 fn parse_expose_port_single_int() {
     let fragment = r#"
@@ -264,9 +509,13 @@ fn parse_expose_port_single_int() {
 
     let expected = WiderWorldToContainerRule {
         network: "network".to_owned(),
-        dst_container: "dst_container".to_owned(),
+        dst_container: Some("dst_container".to_owned()),
+        dst_selector: None,
+        dst_service: None,
+        dst_swarm_service: None,
         expose_port: vec![ExposePort {
             host_port: 80,
+            host_port_end: None,
             container_port: None,
             family: "tcp".to_owned(),
         }],
@@ -290,15 +539,20 @@ fn parse_expose_port_seq_int() {
 
     let expected = WiderWorldToContainerRule {
         network: "network".to_owned(),
-        dst_container: "dst_container".to_owned(),
+        dst_container: Some("dst_container".to_owned()),
+        dst_selector: None,
+        dst_service: None,
+        dst_swarm_service: None,
         expose_port: vec![
             ExposePort {
                 host_port: 80,
+                host_port_end: None,
                 container_port: None,
                 family: "tcp".to_owned(),
             },
             ExposePort {
                 host_port: 81,
+                host_port_end: None,
                 container_port: None,
                 family: "tcp".to_owned(),
             },
@@ -327,9 +581,13 @@ fn parse_expose_port_single_string() {
 
         let expected = WiderWorldToContainerRule {
             network: "network".to_owned(),
-            dst_container: "dst_container".to_owned(),
+            dst_container: Some("dst_container".to_owned()),
+            dst_selector: None,
+            dst_service: None,
+            dst_swarm_service: None,
             expose_port: vec![ExposePort {
                 host_port: port.to_owned(),
+                host_port_end: None,
                 container_port: None,
                 family: family.to_owned(),
             }],
@@ -354,20 +612,26 @@ fn parse_expose_port_seq_string() {
 
     let expected = WiderWorldToContainerRule {
         network: "network".to_owned(),
-        dst_container: "dst_container".to_owned(),
+        dst_container: Some("dst_container".to_owned()),
+        dst_selector: None,
+        dst_service: None,
+        dst_swarm_service: None,
         expose_port: vec![
             ExposePort {
                 host_port: 80,
+                host_port_end: None,
                 container_port: None,
                 family: "tcp".to_owned(),
             },
             ExposePort {
                 host_port: 53,
+                host_port_end: None,
                 container_port: None,
                 family: "udp".to_owned(),
             },
             ExposePort {
                 host_port: 1234,
+                host_port_end: None,
                 container_port: None,
                 family: "other".to_owned(),
             },
@@ -399,9 +663,13 @@ fn parse_expose_port_single_struct() {
 
         let expected = WiderWorldToContainerRule {
             network: "network".to_owned(),
-            dst_container: "dst_container".to_owned(),
+            dst_container: Some("dst_container".to_owned()),
+            dst_selector: None,
+            dst_service: None,
+            dst_swarm_service: None,
             expose_port: vec![ExposePort {
                 host_port: 80,
+                host_port_end: None,
                 container_port: None,
                 family: "tcp".to_owned(),
             }],
@@ -431,25 +699,32 @@ fn parse_expose_port_seq_struct() {
 
     let expected = WiderWorldToContainerRule {
         network: "network".to_owned(),
-        dst_container: "dst_container".to_owned(),
+        dst_container: Some("dst_container".to_owned()),
+        dst_selector: None,
+        dst_service: None,
+        dst_swarm_service: None,
         expose_port: vec![
             ExposePort {
                 host_port: 80,
+                host_port_end: None,
                 container_port: None,
                 family: "tcp".to_owned(),
             },
             ExposePort {
                 host_port: 8080,
+                host_port_end: None,
                 container_port: Some(80),
                 family: "tcp".to_owned(),
             },
             ExposePort {
                 host_port: 8081,
+                host_port_end: None,
                 container_port: Some(81),
                 family: "udp".to_owned(),
             },
             ExposePort {
                 host_port: 8082,
+                host_port_end: None,
                 container_port: Some(82),
                 family: "other".to_owned(),
             },
@@ -464,6 +739,74 @@ fn parse_expose_port_seq_struct() {
     assert_eq!(expected, actual);
 }
 
+#[test]
+fn parse_expose_port_seq_string_ranges() {
+    let fragment = r#"
+        network = "network"
+        dst_container = "dst_container"
+        expose_port = ["30000-30100/udp", "8000-8010:9000-9010", "53/tcp+udp"]
+        "#;
+
+    let expected = WiderWorldToContainerRule {
+        network: "network".to_owned(),
+        dst_container: Some("dst_container".to_owned()),
+        dst_selector: None,
+        dst_service: None,
+        dst_swarm_service: None,
+        expose_port: vec![
+            ExposePort {
+                host_port: 30000,
+                host_port_end: Some(30100),
+                container_port: None,
+                family: "udp".to_owned(),
+            },
+            ExposePort {
+                host_port: 8000,
+                host_port_end: Some(8010),
+                container_port: Some(9000),
+                family: "tcp".to_owned(),
+            },
+            ExposePort {
+                host_port: 53,
+                host_port_end: None,
+                container_port: None,
+                family: "tcp+udp".to_owned(),
+            },
+        ],
+        external_network_interface: None,
+        expose_via_ipv6: true,
+        source_cidr_v4: None,
+        source_cidr_v6: None,
+    };
+    let actual: WiderWorldToContainerRule = toml::from_str(fragment).unwrap();
+
+    assert_eq!(expected, actual);
+}
+
+#[test]
+#[should_panic(expected = "have to be of the same length")]
+fn parse_expose_port_string_range_length_mismatch() {
+    let fragment = r#"
+        network = "network"
+        dst_container = "dst_container"
+        expose_port = "8000-8010:9000-9005"
+        "#;
+
+    toml::from_str::<WiderWorldToContainerRule>(fragment).unwrap();
+}
+
+#[test]
+#[should_panic(expected = "the end has to be greater than the start")]
+fn parse_expose_port_string_range_inverted() {
+    let fragment = r#"
+        network = "network"
+        dst_container = "dst_container"
+        expose_port = "8010-8000"
+        "#;
+
+    toml::from_str::<WiderWorldToContainerRule>(fragment).unwrap();
+}
+
 #[test]
 #[should_panic(expected = "port string has invalid format")]
 fn parse_expose_port_string_invalid_format() {
@@ -500,6 +843,203 @@ fn parse_expose_port_string_invalid_int2() {
     toml::from_str::<WiderWorldToContainerRule>(fragment).unwrap();
 }
 
+#[test]
+fn parse_container_selectors() {
+    let fragment = r#"
+        network = "network"
+        src_selector = { "app" = "web", "tier" = "frontend" }
+        dst_selector = { "app" = "db" }
+        verdict = "accept"
+        "#;
+
+    let expected = ContainerToContainerRule {
+        network: "network".to_owned(),
+        src_container: None,
+        src_selector: Some(ContainerSelector(maplit::btreemap! {
+            "app".to_owned() => "web".to_owned(),
+            "tier".to_owned() => "frontend".to_owned(),
+        })),
+        src_service: None,
+        src_swarm_service: None,
+        dst_container: None,
+        dst_selector: Some(ContainerSelector(maplit::btreemap! {
+            "app".to_owned() => "db".to_owned(),
+        })),
+        dst_service: None,
+        dst_swarm_service: None,
+        protocol: None,
+        src_port: None,
+        dst_port: None,
+        dst_cidr_v4: None,
+        dst_cidr_v6: None,
+        icmp_type: None,
+        ct_state: None,
+        ip_version: None,
+        matches: None,
+        verdict: RuleVerdict::Accept,
+    };
+    let actual: ContainerToContainerRule = toml::from_str(fragment).unwrap();
+
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn parse_typed_matches() {
+    let fragment = r#"
+        network = "network"
+        src_container = "src"
+        protocol = "tcp"
+        dst_port = "8000-8010"
+        dst_cidr_v4 = "10.0.0.0/8"
+        dst_cidr_v6 = ["fd00::/8", "fe80::/10"]
+        ct_state = ["new", "established"]
+        verdict = "accept"
+        "#;
+
+    let expected = ContainerToWiderWorldRule {
+        network: Some("network".to_owned()),
+        src_container: Some("src".to_owned()),
+        src_selector: None,
+        src_service: None,
+        src_swarm_service: None,
+        protocol: Some(Protocol::Tcp),
+        src_port: None,
+        dst_port: Some(PortRange {
+            start: 8000,
+            end: 8010,
+        }),
+        dst_cidr_v4: Some(vec!["10.0.0.0/8".to_owned()]),
+        dst_cidr_v6: Some(vec!["fd00::/8".to_owned(), "fe80::/10".to_owned()]),
+        icmp_type: None,
+        ct_state: Some(vec![ConnectionState::New, ConnectionState::Established]),
+        matches: None,
+        verdict: RuleVerdict::Accept,
+        external_network_interface: None,
+    };
+    let actual: ContainerToWiderWorldRule = toml::from_str(fragment).unwrap();
+
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn parse_typed_matches_single_port() {
+    let fragment = r#"
+        network = "network"
+        protocol = "udp"
+        src_port = 53
+        ct_state = "related"
+        verdict = "accept"
+        "#;
+
+    let actual: ContainerToHostRule = toml::from_str(fragment).unwrap();
+
+    assert_eq!(actual.src_port, Some(PortRange { start: 53, end: 53 }));
+    assert_eq!(actual.ct_state, Some(vec![ConnectionState::Related]));
+}
+
+#[test]
+fn parse_ip_version() {
+    let fragment = r#"
+        network = "network"
+        src_container = "src"
+        ip_version = "v6"
+        verdict = "accept"
+        "#;
+
+    let actual: ContainerToHostRule = toml::from_str(fragment).unwrap();
+
+    assert_eq!(actual.ip_version, Some(IpVersion::V6));
+}
+
+#[test]
+fn parse_ip_version_invalid() {
+    let fragment = r#"
+        network = "network"
+        ip_version = "v5"
+        verdict = "accept"
+        "#;
+
+    let actual: Result<ContainerToContainerRule, _> = toml::from_str(fragment);
+
+    assert!(actual.is_err());
+}
+
+#[test]
+fn parse_compose_services() {
+    let fragment = r#"
+        network = "myproj_default"
+        src_service = "myproj/web"
+        dst_service = "myproj/db"
+        verdict = "accept"
+        "#;
+
+    let expected = ContainerToContainerRule {
+        network: "myproj_default".to_owned(),
+        src_container: None,
+        src_selector: None,
+        src_service: Some(ComposeService {
+            project: "myproj".to_owned(),
+            service: "web".to_owned(),
+        }),
+        src_swarm_service: None,
+        dst_container: None,
+        dst_selector: None,
+        dst_service: Some(ComposeService {
+            project: "myproj".to_owned(),
+            service: "db".to_owned(),
+        }),
+        dst_swarm_service: None,
+        protocol: None,
+        src_port: None,
+        dst_port: None,
+        dst_cidr_v4: None,
+        dst_cidr_v6: None,
+        icmp_type: None,
+        ct_state: None,
+        ip_version: None,
+        matches: None,
+        verdict: RuleVerdict::Accept,
+    };
+    let actual: ContainerToContainerRule = toml::from_str(fragment).unwrap();
+
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn parse_swarm_service() {
+    let fragment = r#"
+        network = "backend"
+        src_swarm_service = "web"
+        verdict = "accept"
+        "#;
+
+    let actual: ContainerToWiderWorldRule = toml::from_str(fragment).unwrap();
+
+    assert_eq!(
+        Some(SwarmService("web".to_owned())),
+        actual.src_swarm_service
+    );
+    assert_eq!(
+        ContainerSelector(maplit::btreemap! {
+            SWARM_SERVICE_LABEL.to_owned() => "web".to_owned(),
+        }),
+        actual.src_swarm_service.unwrap().selector()
+    );
+}
+
+#[test]
+fn parse_compose_service_invalid_format() {
+    let fragment = r#"
+        network = "myproj_default"
+        src_service = "web"
+        verdict = "accept"
+        "#;
+
+    let actual: Result<ContainerToContainerRule, _> = toml::from_str(fragment);
+
+    assert!(actual.is_err());
+}
+
 #[test]
 fn parse_external_network_interfaces_single() {
     let fragment = r#"external_network_interfaces = "eni""#;